@@ -0,0 +1,335 @@
+mod title_bar;
+
+use crate::dialogs::alignment::AlignmentDialog;
+use crate::dialogs::cpa::CpaDialog;
+use crate::dialogs::dpa::DpaDialog;
+use crate::dialogs::filter::FilterDialog;
+use crate::dialogs::key_rank::KeyRankDialog;
+use crate::dialogs::mia::MiaDialog;
+use crate::dialogs::pipeline::PipelineDialog;
+use crate::dialogs::projection::ProjectionDialog;
+use crate::dialogs::snr::SnrDialog;
+use crate::dialogs::spectrum::SpectrumDialog;
+use crate::dialogs::template::TemplateDialog;
+use crate::dialogs::ttest::TTestDialog;
+use crate::dialogs::csv_import::CsvImportWizard;
+use crate::dialogs::dialog_box_ok;
+use crate::loaders::load_from_file;
+use crate::math::ttest::TVLA_THRESHOLD;
+use crate::trace_plotter::spectrogram_plotter::SpectrogramPlotter;
+use crate::trace_plotter::trace_plotter::TracePlotter;
+use crate::trace_set::{TraceSet, TraceSource};
+use eframe::egui::Frame;
+use egui::{CentralPanel, Color32};
+use egui_modal::{Icon};
+use log::error;
+use rand::distr::Alphanumeric;
+use rand::{rng, Rng};
+use std::sync::Arc;
+
+struct App {
+    trace_plotters: Vec<(TracePlotter, bool)>,
+    /// Title of the trace plotter that was selected last
+    active_trace_plotter: Option<String>,
+    csv_import_wizard: Option<CsvImportWizard>,
+    alignment_dialog: Option<AlignmentDialog>,
+    filter_dialog: Option<FilterDialog>,
+    pipeline_dialog: Option<PipelineDialog>,
+    cpa_dialog: Option<CpaDialog>,
+    dpa_dialog: Option<DpaDialog>,
+    mia_dialog: Option<MiaDialog>,
+    ttest_dialog: Option<TTestDialog>,
+    snr_dialog: Option<SnrDialog>,
+    spectrum_dialog: Option<SpectrumDialog>,
+    spectrogram_plotter: Option<SpectrogramPlotter>,
+    key_rank_dialog: Option<KeyRankDialog>,
+    template_dialog: Option<TemplateDialog>,
+    projection_dialog: Option<ProjectionDialog>,
+}
+
+impl eframe::App for App {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        let root_panel = Frame {
+            inner_margin: 0.0.into(),
+            fill: ctx.style().visuals.window_fill(),
+            rounding: 15.0.into(),
+            stroke: ctx.style().visuals.widgets.noninteractive.fg_stroke,
+            ..Default::default()
+        };
+
+        let content_panel = Frame {
+            inner_margin: 10.0.into(),
+            fill: Color32::TRANSPARENT,
+            ..Default::default()
+        };
+
+        CentralPanel::default().frame(root_panel).show(ctx, |ui| {
+            title_bar::custom_title_bar(ui, self);
+
+            CentralPanel::default()
+                .frame(content_panel)
+                .show_inside(ui, |ui| {
+                    ui.label("Hello from the root viewport");
+
+                    let err =
+                        dialog_box_ok(ui, "file_error", "Could not open the file", Icon::Warning);
+
+                    if ui.button("Open new Trace Plotter").clicked() {
+                        let file_path = "./data.bin";
+                        match load_from_file(file_path) {
+                            Ok(data) => {
+                                self.open_trace_plotter(Arc::new(data), generate_random_string(10))
+                            }
+                            Err(e) => {
+                                error!("Failed to open file: {:?}", e);
+                                err.open();
+                            }
+                        };
+                    }
+                });
+
+            if let Some(mut wizard) = self.csv_import_wizard.take() {
+                let mut open = true;
+                if let Some(data) = wizard.render(ctx, &mut open) {
+                    let title = format!("{} {}", wizard.file_name(), generate_random_string(4));
+                    self.open_trace_plotter(Arc::new(data), title);
+                    open = false;
+                }
+                if open {
+                    self.csv_import_wizard = Some(wizard);
+                }
+            }
+
+            if let Some(mut dialog) = self.alignment_dialog.take() {
+                let mut open = true;
+                if let Some(data) = dialog.render(ctx, &mut open) {
+                    let title = format!(
+                        "{} aligned {}",
+                        dialog.source_title(),
+                        generate_random_string(4)
+                    );
+                    self.open_trace_plotter(Arc::new(data), title);
+                }
+                if open {
+                    self.alignment_dialog = Some(dialog);
+                }
+            }
+
+            if let Some(mut dialog) = self.filter_dialog.take() {
+                let mut open = true;
+                if let Some(data) = dialog.render(ctx, &mut open) {
+                    let title = format!(
+                        "{} filtered {}",
+                        dialog.source_title(),
+                        generate_random_string(4)
+                    );
+                    self.open_trace_plotter(Arc::new(data), title);
+                }
+                if open {
+                    self.filter_dialog = Some(dialog);
+                }
+            }
+
+            if let Some(mut dialog) = self.pipeline_dialog.take() {
+                let mut open = true;
+                if let Some(source) = dialog.render(ctx, &mut open) {
+                    let title = format!(
+                        "{} processed {}",
+                        dialog.source_title(),
+                        generate_random_string(4)
+                    );
+                    self.open_trace_plotter(source, title);
+                }
+                if open {
+                    self.pipeline_dialog = Some(dialog);
+                }
+            }
+
+            if let Some(mut dialog) = self.cpa_dialog.take() {
+                let mut open = true;
+                if let Some((title, data)) = dialog.render(ctx, &mut open) {
+                    self.open_result_plotter(data, title, vec![]);
+                }
+                if open {
+                    self.cpa_dialog = Some(dialog);
+                }
+            }
+
+            if let Some(mut dialog) = self.dpa_dialog.take() {
+                let mut open = true;
+                if let Some((title, data)) = dialog.render(ctx, &mut open) {
+                    self.open_result_plotter(data, title, vec![]);
+                }
+                if open {
+                    self.dpa_dialog = Some(dialog);
+                }
+            }
+
+            if let Some(mut dialog) = self.mia_dialog.take() {
+                let mut open = true;
+                if let Some((title, data)) = dialog.render(ctx, &mut open) {
+                    self.open_result_plotter(data, title, vec![]);
+                }
+                if open {
+                    self.mia_dialog = Some(dialog);
+                }
+            }
+
+            if let Some(mut dialog) = self.ttest_dialog.take() {
+                let mut open = true;
+                if let Some((title, data)) = dialog.render(ctx, &mut open) {
+                    self.open_result_plotter(data, title, vec![-TVLA_THRESHOLD, TVLA_THRESHOLD]);
+                }
+                if open {
+                    self.ttest_dialog = Some(dialog);
+                }
+            }
+
+            if let Some(mut dialog) = self.snr_dialog.take() {
+                let mut open = true;
+                if let Some((title, data)) = dialog.render(ctx, &mut open) {
+                    self.open_result_plotter(data, title, vec![]);
+                }
+                if open {
+                    self.snr_dialog = Some(dialog);
+                }
+            }
+
+            if let Some(mut dialog) = self.spectrum_dialog.take() {
+                let mut open = true;
+                if let Some((title, data)) = dialog.render(ctx, &mut open) {
+                    self.open_result_plotter(data, title, vec![]);
+                }
+                if open {
+                    self.spectrum_dialog = Some(dialog);
+                }
+            }
+
+            if let Some(mut plotter) = self.spectrogram_plotter.take() {
+                let mut open = true;
+                if let Some((title, samples)) = plotter.render(ctx, &mut open) {
+                    if let Some((trace_plotter, _)) = self
+                        .trace_plotters
+                        .iter_mut()
+                        .find(|(trace_plotter, _)| trace_plotter.title() == title)
+                    {
+                        trace_plotter.select_samples(samples);
+                    }
+                }
+                if open {
+                    self.spectrogram_plotter = Some(plotter);
+                }
+            }
+
+            if let Some(mut dialog) = self.key_rank_dialog.take() {
+                let mut open = true;
+                if let Some((title, data)) = dialog.render(ctx, &mut open) {
+                    self.open_result_plotter(data, title, vec![]);
+                }
+                if open {
+                    self.key_rank_dialog = Some(dialog);
+                }
+            }
+
+            if let Some(mut dialog) = self.template_dialog.take() {
+                let mut open = true;
+                dialog.render(ctx, &mut open);
+                if open {
+                    self.template_dialog = Some(dialog);
+                }
+            }
+
+            if let Some(mut dialog) = self.projection_dialog.take() {
+                let mut open = true;
+                if let Some((title, data)) = dialog.render(ctx, &mut open) {
+                    self.open_result_plotter(data, title, vec![]);
+                }
+                if open {
+                    self.projection_dialog = Some(dialog);
+                }
+            }
+
+            self.trace_plotters.retain(|(_, show)| *show);
+
+            for (ref mut trace_plotter, ref mut show) in &mut self.trace_plotters {
+                trace_plotter.render(ctx, show);
+                if trace_plotter.is_selected() {
+                    self.active_trace_plotter = Some(trace_plotter.title().to_string());
+                }
+            }
+        });
+    }
+}
+
+pub(crate) fn generate_random_string(length: usize) -> String {
+    rng()
+        .sample_iter(&Alphanumeric)
+        .take(length)
+        .map(char::from)
+        .collect()
+}
+impl App {
+    fn new() -> Self {
+        App {
+            trace_plotters: vec![],
+            active_trace_plotter: None,
+            csv_import_wizard: None,
+            alignment_dialog: None,
+            filter_dialog: None,
+            pipeline_dialog: None,
+            cpa_dialog: None,
+            dpa_dialog: None,
+            mia_dialog: None,
+            ttest_dialog: None,
+            snr_dialog: None,
+            spectrum_dialog: None,
+            spectrogram_plotter: None,
+            key_rank_dialog: None,
+            template_dialog: None,
+            projection_dialog: None,
+        }
+    }
+
+    fn active_trace_plotter(&self) -> Option<&TracePlotter> {
+        let title = self.active_trace_plotter.as_ref()?;
+
+        self.trace_plotters
+            .iter()
+            .map(|(trace_plotter, _)| trace_plotter)
+            .find(|trace_plotter| trace_plotter.title() == title)
+    }
+
+    fn open_trace_plotter(&mut self, trace_data: Arc<dyn TraceSource>, title: String) {
+        let trace_plotter = TracePlotter::new(trace_data, title);
+
+        self.trace_plotters.push((trace_plotter, true));
+    }
+
+    /// Opens analysis results, such as correlation traces, showing every trace at once
+    fn open_result_plotter(&mut self, data: TraceSet, title: String, threshold_lines: Vec<f64>) {
+        let traces = 0..data.len();
+        let title = format!("{} {}", title, generate_random_string(4));
+        let trace_plotter = TracePlotter::new(Arc::new(data), title)
+            .with_plot_range(traces)
+            .with_threshold_lines(threshold_lines);
+
+        self.trace_plotters.push((trace_plotter, true));
+    }
+}
+
+/// Opens the main window, returning once it is closed
+pub fn run() -> Result<(), eframe::Error> {
+    let options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default()
+            .with_decorations(true)
+            .with_inner_size([1250.0, 750.0])
+            .with_transparent(true),
+        ..Default::default()
+    };
+
+    eframe::run_native(
+        "SC-Analysis",
+        options,
+        Box::new(|_cc| Ok(Box::new(App::new()))),
+    )
+}
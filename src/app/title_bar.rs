@@ -0,0 +1,351 @@
+use crate::dialogs::alignment::AlignmentDialog;
+use crate::dialogs::cpa::CpaDialog;
+use crate::dialogs::dpa::DpaDialog;
+use crate::dialogs::filter::FilterDialog;
+use crate::dialogs::key_rank::KeyRankDialog;
+use crate::dialogs::mia::MiaDialog;
+use crate::dialogs::pipeline::PipelineDialog;
+use crate::dialogs::projection::ProjectionDialog;
+use crate::dialogs::snr::SnrDialog;
+use crate::dialogs::spectrum::SpectrumDialog;
+use crate::trace_plotter::spectrogram_plotter::SpectrogramPlotter;
+use crate::dialogs::template::TemplateDialog;
+use crate::dialogs::ttest::TTestDialog;
+use crate::dialogs::csv_import::CsvImportWizard;
+use crate::dialogs::{dialog_box_ok, open_csv_explorer, open_file_explorer, save_file_explorer};
+use crate::loaders::{open_trace_source, write_trace_source};
+use super::{generate_random_string, App};
+use eframe::emath::Align;
+use egui::{
+    Button, Direction, Id, Layout, PointerButton, RichText, Sense,
+    Ui, ViewportCommand,
+};
+use egui_modal::Icon;
+use log::error;
+use std::path::Path;
+
+pub fn custom_title_bar(ui: &mut Ui, app: &mut App) {
+    let side_margin = 10.0;
+    let title_bar_height = 40.0;
+
+    let title_bar_rect = {
+        let mut r = ui.max_rect();
+        r.max.y = r.min.y + title_bar_height;
+        r
+    };
+
+    let drag_response = ui.interact(title_bar_rect, Id::new("drag_bar"), Sense::click_and_drag());
+
+    if drag_response.double_clicked() {
+        let is_maximized = ui.input(|i| i.viewport().maximized.unwrap_or(false));
+        ui.ctx()
+            .send_viewport_cmd(ViewportCommand::Maximized(!is_maximized));
+    }
+
+    if drag_response.drag_started_by(PointerButton::Primary) {
+        ui.ctx().send_viewport_cmd(ViewportCommand::StartDrag);
+    }
+
+    ui.allocate_ui_at_rect(title_bar_rect, |ui| {
+        ui.with_layout(Layout::centered_and_justified(Direction::TopDown), |ui| {
+            ui.horizontal(|ui| {
+                ui.add_space(side_margin);
+
+                ui.with_layout(Layout::left_to_right(Align::Center), |ui| {
+                    ui.menu_button("File", |ui| {
+                        file_dropdown_buttons(ui, app);
+                    });
+                    ui.menu_button("View", |ui| {
+                        ui.button("Side Bar").clicked();
+                    });
+                    ui.menu_button("Tools", |ui| {
+                        tools_dropdown_buttons(ui, app);
+                    })
+                });
+
+                ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+                    ui.add_space(side_margin);
+
+                    minimize_maximize_close(ui);
+                });
+            });
+        });
+    });
+}
+
+fn file_dropdown_buttons(ui: &mut Ui, app: &mut App) {
+    let err = dialog_box_ok(
+        ui,
+        "file_error",
+        "Error trying to open file.",
+        Icon::Warning,
+    );
+
+    let open_button = Button::new("Open");
+    let import_csv_button = Button::new("Import CSV");
+    let export_button = Button::new("Export");
+    let exit_button = Button::new("Exit");
+
+    if ui.add(open_button).clicked() {
+        if let Some(path) = open_file_explorer() {
+            match open_trace_source(&path) {
+                Ok(data) => {
+                    let file_name = Path::new(&path)
+                        .file_name()
+                        .map_or(path.clone(), |name| name.to_string_lossy().into_owned());
+                    app.open_trace_plotter(
+                        data,
+                        format!("{} {}", file_name, generate_random_string(4)),
+                    );
+                }
+                Err(e) => {
+                    error!("Failed to open file: {:?}", e);
+                    err.open();
+                }
+            };
+        } else {
+            error!("Path doesnt exist.");
+            err.open();
+        }
+    }
+
+    if ui.add(import_csv_button).clicked() {
+        if let Some(path) = open_csv_explorer() {
+            app.csv_import_wizard = Some(CsvImportWizard::new(path));
+        }
+    }
+
+    if ui
+        .add_enabled(app.active_trace_plotter().is_some(), export_button)
+        .on_hover_text("Save the traces of the last selected plotter")
+        .clicked()
+    {
+        if let (Some(path), Some(trace_plotter)) =
+            (save_file_explorer(), app.active_trace_plotter())
+        {
+            // The save dialog already confirmed replacing the file the companions belong to
+            if let Err(e) = write_trace_source(trace_plotter.source().as_ref(), &path, true) {
+                error!("Failed to write file: {:?}", e);
+                err.open();
+            }
+        }
+    }
+
+    if ui.add(exit_button).clicked() {
+        ui.ctx().send_viewport_cmd(ViewportCommand::Close);
+    }
+}
+
+fn tools_dropdown_buttons(ui: &mut Ui, app: &mut App) {
+    let align_button = Button::new("Align traces");
+
+    if ui
+        .add_enabled(app.active_trace_plotter().is_some(), align_button)
+        .on_hover_text("Align the traces of the last selected plotter")
+        .clicked()
+    {
+        if let Some(trace_plotter) = app.active_trace_plotter() {
+            app.alignment_dialog = Some(AlignmentDialog::new(trace_plotter));
+        }
+        ui.close_menu();
+    }
+
+    let filter_button = Button::new("Filter traces");
+
+    if ui
+        .add_enabled(app.active_trace_plotter().is_some(), filter_button)
+        .on_hover_text("Filter the traces of the last selected plotter")
+        .clicked()
+    {
+        if let Some(trace_plotter) = app.active_trace_plotter() {
+            app.filter_dialog = Some(FilterDialog::new(trace_plotter));
+        }
+        ui.close_menu();
+    }
+
+    let pipeline_button = Button::new("Preprocessing pipeline");
+
+    if ui
+        .add_enabled(app.active_trace_plotter().is_some(), pipeline_button)
+        .on_hover_text("Run a saved sequence of preprocessing steps on the last selected plotter")
+        .clicked()
+    {
+        if let Some(trace_plotter) = app.active_trace_plotter() {
+            app.pipeline_dialog = Some(PipelineDialog::new(trace_plotter));
+        }
+        ui.close_menu();
+    }
+
+    ui.separator();
+
+    let cpa_button = Button::new("CPA (AES-128)");
+
+    if ui
+        .add_enabled(app.active_trace_plotter().is_some(), cpa_button)
+        .on_hover_text("Correlation power analysis of the last selected plotter")
+        .clicked()
+    {
+        if let Some(trace_plotter) = app.active_trace_plotter() {
+            app.cpa_dialog = Some(CpaDialog::new(trace_plotter));
+        }
+        ui.close_menu();
+    }
+
+    let dpa_button = Button::new("DPA (difference of means)");
+
+    if ui
+        .add_enabled(app.active_trace_plotter().is_some(), dpa_button)
+        .on_hover_text("Differential power analysis of the last selected plotter")
+        .clicked()
+    {
+        if let Some(trace_plotter) = app.active_trace_plotter() {
+            app.dpa_dialog = Some(DpaDialog::new(trace_plotter));
+        }
+        ui.close_menu();
+    }
+
+    let mia_button = Button::new("MIA (mutual information)");
+
+    if ui
+        .add_enabled(app.active_trace_plotter().is_some(), mia_button)
+        .on_hover_text("Mutual information analysis of the last selected plotter")
+        .clicked()
+    {
+        if let Some(trace_plotter) = app.active_trace_plotter() {
+            app.mia_dialog = Some(MiaDialog::new(trace_plotter));
+        }
+        ui.close_menu();
+    }
+
+    let key_rank_button = Button::new("Key rank, GE and SR");
+
+    if ui
+        .add_enabled(app.active_trace_plotter().is_some(), key_rank_button)
+        .on_hover_text("How far the full key of the last selected plotter is from recovery")
+        .clicked()
+    {
+        if let Some(trace_plotter) = app.active_trace_plotter() {
+            app.key_rank_dialog = Some(KeyRankDialog::new(trace_plotter));
+        }
+        ui.close_menu();
+    }
+
+    let projection_button = Button::new("PCA / LDA");
+
+    if ui
+        .add_enabled(app.active_trace_plotter().is_some(), projection_button)
+        .on_hover_text("Compress the last selected plotter onto a few components")
+        .clicked()
+    {
+        if let Some(trace_plotter) = app.active_trace_plotter() {
+            app.projection_dialog = Some(ProjectionDialog::new(trace_plotter));
+        }
+        ui.close_menu();
+    }
+
+    let template_button = Button::new("Template attack");
+
+    if ui
+        .add_enabled(app.active_trace_plotter().is_some(), template_button)
+        .on_hover_text("Profile the last selected plotter and attack any open trace set")
+        .clicked()
+    {
+        let attack_sets = app
+            .trace_plotters
+            .iter()
+            .map(|(plotter, _)| (plotter.title().to_string(), plotter.source().clone()))
+            .collect();
+        if let Some(trace_plotter) = app.active_trace_plotter() {
+            app.template_dialog = Some(TemplateDialog::new(trace_plotter, attack_sets));
+        }
+        ui.close_menu();
+    }
+
+    let ttest_button = Button::new("Welch t-test (TVLA)");
+
+    if ui
+        .add_enabled(app.active_trace_plotter().is_some(), ttest_button)
+        .on_hover_text("Leakage assessment of the last selected plotter")
+        .clicked()
+    {
+        if let Some(trace_plotter) = app.active_trace_plotter() {
+            app.ttest_dialog = Some(TTestDialog::new(trace_plotter));
+        }
+        ui.close_menu();
+    }
+
+    let snr_button = Button::new("SNR / NICV");
+
+    if ui
+        .add_enabled(app.active_trace_plotter().is_some(), snr_button)
+        .on_hover_text("Find points of interest in the last selected plotter")
+        .clicked()
+    {
+        if let Some(trace_plotter) = app.active_trace_plotter() {
+            app.snr_dialog = Some(SnrDialog::new(trace_plotter));
+        }
+        ui.close_menu();
+    }
+
+    let spectrum_button = Button::new("Spectrum");
+
+    if ui
+        .add_enabled(app.active_trace_plotter().is_some(), spectrum_button)
+        .on_hover_text("Frequency spectra of the traces shown in the last selected plotter")
+        .clicked()
+    {
+        if let Some(trace_plotter) = app.active_trace_plotter() {
+            app.spectrum_dialog = Some(SpectrumDialog::new(trace_plotter));
+        }
+        ui.close_menu();
+    }
+
+    let spectrogram_button = Button::new("Spectrogram");
+
+    if ui
+        .add_enabled(app.active_trace_plotter().is_some(), spectrogram_button)
+        .on_hover_text("Power over time and frequency of the first trace shown in the last selected plotter")
+        .clicked()
+    {
+        if let Some(trace_plotter) = app.active_trace_plotter() {
+            app.spectrogram_plotter = Some(SpectrogramPlotter::new(trace_plotter));
+        }
+        ui.close_menu();
+    }
+}
+
+fn minimize_maximize_close(ui: &mut Ui) {
+    let close_response = ui
+        .add(Button::new(RichText::new("❌")))
+        .on_hover_text("Close Window");
+
+    if close_response.clicked() {
+        ui.ctx().send_viewport_cmd(ViewportCommand::Close);
+    }
+
+    let is_maximized = ui.input(|i| i.viewport().maximized.unwrap_or(false));
+
+    if is_maximized {
+        let maximized_response = ui
+            .add(Button::new(RichText::new("🗗")))
+            .on_hover_text("Restore Window");
+        if maximized_response.clicked() {
+            ui.ctx()
+                .send_viewport_cmd(ViewportCommand::Maximized(false));
+        }
+    } else {
+        let maximized_response = ui
+            .add(Button::new(RichText::new("🗗")))
+            .on_hover_text("Maximize Window");
+        if maximized_response.clicked() {
+            ui.ctx().send_viewport_cmd(ViewportCommand::Maximized(true));
+        }
+    }
+
+    let minimized_response = ui
+        .add(Button::new(RichText::new("🗕")))
+        .on_hover_text("Minimize Window");
+    if minimized_response.clicked() {
+        ui.ctx().send_viewport_cmd(ViewportCommand::Minimized(true));
+    }
+}
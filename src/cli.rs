@@ -0,0 +1,575 @@
+use crate::loaders::{open_trace_source, write_trace_file, write_trace_source};
+use crate::math::cpa::{cpa, CpaModel, KEY_BYTES};
+use crate::math::pipeline::Pipeline;
+use crate::math::snr::{snr, top_peaks, write_poi_csv, SnrClass};
+use crate::math::ttest::{distinct_labels, welch_t_test, TVLA_THRESHOLD};
+use crate::math::{elastic_align, static_align, write_shift_report};
+use crate::trace_set::TraceSource;
+use clap::{Parser, Subcommand, ValueEnum};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::error::Error;
+use std::ops::Range;
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Exit code of `ttest --fail-on-leakage` when a sample exceeds the threshold
+const LEAKAGE_DETECTED: u8 = 3;
+
+/// Resolution of the progress bars, the analyses report fractions
+const PROGRESS_STEPS: u64 = 1000;
+
+/// Side-channel trace analysis. Starts the GUI when no command is given.
+#[derive(Parser)]
+#[command(version)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Replaces the files written next to an output, like the per-trace arrays of .npy files
+    #[arg(long, global = true)]
+    pub force: bool,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Converts a trace file to another format, picked from the output extension
+    Convert { input: String, output: String },
+    /// Aligns the traces on a window of a reference trace
+    Align {
+        input: String,
+        output: String,
+        /// Samples of the reference trace to align on, as START..END
+        #[arg(long, value_parser = parse_window)]
+        window: Range<usize>,
+        /// Trace the others are aligned onto
+        #[arg(long, default_value_t = 0)]
+        reference: usize,
+        /// Largest shift tried, in samples
+        #[arg(long, default_value_t = 100)]
+        max_shift: usize,
+        /// Traces correlating less than this after shifting are dropped
+        #[arg(long, default_value_t = 0.5, allow_negative_numbers = true)]
+        threshold: f64,
+        /// Writes the shift and correlation of every trace as CSV
+        #[arg(long, value_name = "CSV")]
+        report: Option<String>,
+        /// Warps the window with FastDTW of this radius instead of shifting the traces
+        #[arg(long, value_name = "RADIUS", conflicts_with_all = ["max_shift", "threshold", "report"])]
+        elastic: Option<usize>,
+    },
+    /// Runs a TVLA Welch t-test between two groups of labelled traces and writes the t-traces
+    Ttest {
+        input: String,
+        output: String,
+        /// Samples to test, as START..END, every sample by default
+        #[arg(long, value_parser = parse_window)]
+        window: Option<Range<usize>>,
+        /// Labels of the two groups, needed unless the traces have exactly two labels
+        #[arg(long, num_args = 2, value_names = ["FIRST", "SECOND"])]
+        labels: Option<Vec<String>>,
+        /// Highest order tested, every order up to it gets a t-trace
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=3))]
+        order: u8,
+        /// |t| above which a sample is leaking
+        #[arg(long, default_value_t = TVLA_THRESHOLD)]
+        threshold: f64,
+        /// Exits with code 3 when any sample leaks
+        #[arg(long)]
+        fail_on_leakage: bool,
+    },
+    /// Runs CPA on the first AES round and writes the ranking of every key byte as CSV
+    Cpa {
+        input: String,
+        output: String,
+        /// Samples to analyse, as START..END, every sample by default
+        #[arg(long, value_parser = parse_window)]
+        window: Option<Range<usize>>,
+        /// Key bytes to attack, as a comma separated list, every byte by default
+        #[arg(long, value_delimiter = ',')]
+        bytes: Option<Vec<usize>>,
+        #[arg(long, value_enum, default_value_t = CpaModelArg::Hw)]
+        model: CpaModelArg,
+        /// S-box output bit predicted by the `bit` model
+        #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u8).range(0..8))]
+        bit: u8,
+        /// Guesses written per key byte
+        #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u16).range(1..=256))]
+        candidates: u16,
+    },
+    /// Computes the SNR (or NICV) of an intermediate byte and writes it as a trace
+    Snr {
+        input: String,
+        output: String,
+        /// Samples to analyse, as START..END, every sample by default
+        #[arg(long, value_parser = parse_window)]
+        window: Option<Range<usize>>,
+        #[arg(long, value_enum, default_value_t = SnrClassArg::Sbox)]
+        class: SnrClassArg,
+        /// Byte the traces are grouped by
+        #[arg(long, default_value_t = 0)]
+        byte: usize,
+        /// Writes the NICV instead of the SNR
+        #[arg(long)]
+        nicv: bool,
+        /// Writes the highest peaks as CSV
+        #[arg(long, value_name = "CSV")]
+        pois: Option<String>,
+        /// Number of peaks written to the POI file
+        #[arg(long, default_value_t = 10)]
+        num_pois: usize,
+        /// Fewest samples between two peaks
+        #[arg(long, default_value_t = 5)]
+        min_distance: usize,
+    },
+    /// Runs a preprocessing pipeline saved from the GUI
+    Pipeline {
+        /// Pipeline file, as saved by the pipeline window
+        pipeline: String,
+        input: String,
+        /// Output file, streamed when it is a chunked container (.sct)
+        output: String,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum CpaModelArg {
+    /// Hamming weight of the S-box output
+    Hw,
+    /// One bit of the S-box output, see --bit
+    Bit,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum SnrClassArg {
+    Plaintext,
+    Ciphertext,
+    /// First-round S-box output, using the key stored with the traces
+    Sbox,
+    /// Hamming weight of the first-round S-box output
+    SboxHw,
+}
+
+/// Runs a command, printing its error and returning a failure code if it fails
+pub fn run(command: Command, force: bool) -> ExitCode {
+    let result = match command {
+        Command::Convert { input, output } => convert(&input, &output, force),
+        Command::Align {
+            input,
+            output,
+            window,
+            reference,
+            max_shift,
+            threshold,
+            report,
+            elastic,
+        } => match elastic {
+            Some(radius) => align_elastic(&input, &output, window, reference, radius, force),
+            None => align_static(
+                &input, &output, window, reference, max_shift, threshold, report, force,
+            ),
+        },
+        Command::Ttest {
+            input,
+            output,
+            window,
+            labels,
+            order,
+            threshold,
+            fail_on_leakage,
+        } => ttest(
+            &input,
+            &output,
+            window,
+            labels,
+            order as usize,
+            threshold,
+            fail_on_leakage,
+            force,
+        ),
+        Command::Cpa {
+            input,
+            output,
+            window,
+            bytes,
+            model,
+            bit,
+            candidates,
+        } => {
+            let model = match model {
+                CpaModelArg::Hw => CpaModel::SboxHammingWeight,
+                CpaModelArg::Bit => CpaModel::SboxBit(bit),
+            };
+            let bytes = bytes.unwrap_or_else(|| (0..KEY_BYTES).collect());
+            run_cpa(&input, &output, window, &bytes, model, candidates as usize)
+        }
+        Command::Snr {
+            input,
+            output,
+            window,
+            class,
+            byte,
+            nicv,
+            pois,
+            num_pois,
+            min_distance,
+        } => {
+            let class = match class {
+                SnrClassArg::Plaintext => SnrClass::PlaintextByte(byte),
+                SnrClassArg::Ciphertext => SnrClass::CiphertextByte(byte),
+                SnrClassArg::Sbox => SnrClass::SboxOutput(byte),
+                SnrClassArg::SboxHw => SnrClass::SboxOutputHammingWeight(byte),
+            };
+            run_snr(
+                &input,
+                &output,
+                window,
+                class,
+                nicv,
+                pois,
+                num_pois,
+                min_distance,
+                force,
+            )
+        }
+        Command::Pipeline {
+            pipeline,
+            input,
+            output,
+        } => run_pipeline(&pipeline, &input, &output, force),
+    };
+
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Parses a sample window written as `START..END`
+fn parse_window(text: &str) -> Result<Range<usize>, String> {
+    let (start, end) = text
+        .split_once("..")
+        .ok_or_else(|| format!("Expected START..END, got '{}'", text))?;
+    let start = start
+        .trim()
+        .parse()
+        .map_err(|e| format!("Bad start: {}", e))?;
+    let end = end.trim().parse().map_err(|e| format!("Bad end: {}", e))?;
+    if start >= end {
+        return Err(format!("The window {}..{} is empty", start, end));
+    }
+    Ok(start..end)
+}
+
+/// Progress bar driven by the fraction callbacks of the analyses
+fn progress_bar(message: &'static str) -> ProgressBar {
+    let bar = ProgressBar::new(PROGRESS_STEPS);
+    bar.set_style(
+        ProgressStyle::with_template("{msg:12} {wide_bar} {percent:>3}% [{elapsed}, eta {eta}]")
+            .unwrap(),
+    );
+    bar.set_message(message);
+    bar
+}
+
+/// Spinner for steps that don't report their progress
+fn spinner(message: &'static str) -> ProgressBar {
+    let bar = ProgressBar::new_spinner();
+    bar.set_message(message);
+    bar.enable_steady_tick(Duration::from_millis(100));
+    bar
+}
+
+fn set_fraction(bar: &ProgressBar, fraction: f32) {
+    bar.set_position((fraction.clamp(0.0, 1.0) * PROGRESS_STEPS as f32) as u64);
+}
+
+fn open(input: &str) -> Result<Arc<dyn TraceSource>, Box<dyn Error>> {
+    open_trace_source(input).map_err(|e| format!("Failed to open {}: {}", input, e).into())
+}
+
+fn convert(input: &str, output: &str, force: bool) -> Result<ExitCode, Box<dyn Error>> {
+    let source = open(input)?;
+    let bar = spinner("Converting");
+    write_trace_source(source.as_ref(), output, force)?;
+    bar.finish_and_clear();
+
+    println!("Wrote {} traces to {}", source.len(), output);
+    Ok(ExitCode::SUCCESS)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn align_static(
+    input: &str,
+    output: &str,
+    window: Range<usize>,
+    reference: usize,
+    max_shift: usize,
+    threshold: f64,
+    report: Option<String>,
+    force: bool,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let source = open(input)?;
+    let bar = spinner("Aligning");
+    let alignment = static_align(reference, source.as_ref(), window, max_shift, threshold)?;
+    bar.finish_and_clear();
+
+    if let Some(report_path) = report {
+        write_shift_report(&alignment.report, &report_path)?;
+    }
+    if alignment.traces.is_empty() {
+        return Err(format!("No trace reached the correlation threshold {}", threshold).into());
+    }
+    write_trace_file(&alignment.traces, output, force)?;
+
+    println!(
+        "Kept {} of {} traces, wrote them to {}",
+        alignment.traces.len(),
+        source.len(),
+        output
+    );
+    Ok(ExitCode::SUCCESS)
+}
+
+fn align_elastic(
+    input: &str,
+    output: &str,
+    window: Range<usize>,
+    reference: usize,
+    radius: usize,
+    force: bool,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let source = open(input)?;
+    let bar = progress_bar("Warping");
+    let traces = elastic_align(
+        reference,
+        source.as_ref(),
+        window,
+        radius,
+        &mut |fraction| set_fraction(&bar, fraction),
+    )?;
+    bar.finish_and_clear();
+
+    write_trace_file(&traces, output, force)?;
+    println!("Wrote {} warped traces to {}", traces.len(), output);
+    Ok(ExitCode::SUCCESS)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn ttest(
+    input: &str,
+    output: &str,
+    window: Option<Range<usize>>,
+    labels: Option<Vec<String>>,
+    max_order: usize,
+    threshold: f64,
+    fail_on_leakage: bool,
+    force: bool,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let source = open(input)?;
+    let labels = match labels {
+        Some(labels) => labels,
+        None => {
+            let bar = spinner("Reading labels");
+            let labels = distinct_labels(source.as_ref())?;
+            bar.finish_and_clear();
+            if labels.len() != 2 {
+                return Err(format!(
+                    "The traces have {} labels, pick two with --labels: {:?}",
+                    labels.len(),
+                    labels
+                )
+                .into());
+            }
+            labels
+        }
+    };
+
+    let bar = progress_bar("t-test");
+    let result = welch_t_test(
+        source.as_ref(),
+        window.unwrap_or(0..source.num_samples()),
+        &labels[0],
+        &labels[1],
+        max_order,
+        &mut |fraction| set_fraction(&bar, fraction),
+    )?;
+    bar.finish_and_clear();
+
+    write_trace_file(&result.t, output, force)?;
+
+    println!(
+        "{} traces labelled '{}', {} labelled '{}'",
+        result.counts[0], labels[0], result.counts[1], labels[1]
+    );
+    let mut leaking = false;
+    for order in 1..=result.t.len() {
+        let samples = result.leaking_samples(order, threshold);
+        leaking |= samples > 0;
+        println!(
+            "Order {}: max |t| = {:.2}, {} samples above {}",
+            order,
+            result.max_abs_t(order),
+            samples,
+            threshold
+        );
+    }
+
+    if leaking && fail_on_leakage {
+        Ok(ExitCode::from(LEAKAGE_DETECTED))
+    } else {
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+fn run_cpa(
+    input: &str,
+    output: &str,
+    window: Option<Range<usize>>,
+    bytes: &[usize],
+    model: CpaModel,
+    candidates: usize,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let source = open(input)?;
+    let window = window.unwrap_or(0..source.num_samples());
+    let bar = progress_bar("CPA");
+    let result = cpa(
+        source.as_ref(),
+        window.clone(),
+        bytes,
+        model,
+        &mut |fraction| set_fraction(&bar, fraction),
+    )?;
+    bar.finish_and_clear();
+
+    let mut writer = csv::Writer::from_path(output)?;
+    writer.write_record(["byte", "rank", "guess", "correlation", "sample"])?;
+    let mut best_key = Vec::with_capacity(bytes.len());
+    for &byte in bytes {
+        let ranking = result
+            .ranking(byte)
+            .ok_or_else(|| format!("Key byte {} wasn't attacked", byte))?;
+        best_key.push(ranking[0].guess);
+        for (rank, candidate) in ranking.iter().take(candidates).enumerate() {
+            writer.write_record([
+                byte.to_string(),
+                rank.to_string(),
+                format!("{:02x}", candidate.guess),
+                candidate.correlation.to_string(),
+                (window.start + candidate.sample).to_string(),
+            ])?;
+        }
+    }
+    writer.flush()?;
+
+    println!("{} traces", result.num_traces);
+    println!("Best guess: {}", hex(&best_key));
+    if let Some(key) = &result.known_key {
+        let key: Vec<u8> = bytes.iter().map(|&byte| key[byte]).collect();
+        let correct = key.iter().zip(&best_key).filter(|(a, b)| a == b).count();
+        println!(
+            "Known key:  {} ({} of {} bytes found)",
+            hex(&key),
+            correct,
+            bytes.len()
+        );
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_snr(
+    input: &str,
+    output: &str,
+    window: Option<Range<usize>>,
+    class: SnrClass,
+    nicv: bool,
+    pois: Option<String>,
+    num_pois: usize,
+    min_distance: usize,
+    force: bool,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let source = open(input)?;
+    let window = window.unwrap_or(0..source.num_samples());
+    let bar = progress_bar("SNR");
+    let result = snr(source.as_ref(), window.clone(), class, &mut |fraction| {
+        set_fraction(&bar, fraction)
+    })?;
+    bar.finish_and_clear();
+
+    let traces = if nicv { &result.nicv } else { &result.snr };
+    write_trace_file(traces, output, force)?;
+
+    let counts = &result.counts;
+    println!(
+        "{} traces in {} classes",
+        counts.iter().sum::<u64>(),
+        counts.iter().filter(|&&count| count > 0).count()
+    );
+    if let Some(pois_path) = pois {
+        let peaks = top_peaks(traces.trace(0), num_pois, min_distance);
+        write_poi_csv(&peaks, window.start, &source.time(), &pois_path)?;
+        for poi in &peaks {
+            println!("Sample {}: {:.4}", window.start + poi.sample, poi.value);
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn run_pipeline(
+    pipeline: &str,
+    input: &str,
+    output: &str,
+    force: bool,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let pipeline = Pipeline::load(pipeline)
+        .map_err(|e| format!("Failed to load the pipeline {}: {}", pipeline, e))?;
+    let source = open(input)?;
+
+    let bar = progress_bar("Processing");
+    let mut progress = |fraction| set_fraction(&bar, fraction);
+    let streamed = Path::new(output)
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("sct"));
+    if streamed {
+        pipeline.run_to_file(source.as_ref(), output, &mut progress)?;
+    } else {
+        write_trace_file(
+            &pipeline.run(source.as_ref(), &mut progress)?,
+            output,
+            force,
+        )?;
+    }
+    bar.finish_and_clear();
+
+    println!("Processed {} traces into {}", source.len(), output);
+    Ok(ExitCode::SUCCESS)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_windows() {
+        assert_eq!(parse_window("10..250"), Ok(10..250));
+        assert_eq!(parse_window(" 0 .. 1 "), Ok(0..1));
+
+        // Reversed or empty
+        assert!(parse_window("250..10").is_err());
+        assert!(parse_window("7..7").is_err());
+
+        for malformed in [
+            "", "10", "10-250", "..250", "10..", "-1..5", "a..b", "1..2..3",
+        ] {
+            assert!(parse_window(malformed).is_err(), "{}", malformed);
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use crate::dialogs::BackgroundTask;
+use crate::math::{elastic_align, static_align, write_shift_report, TraceShift};
+use crate::trace_plotter::trace_plotter::TracePlotter;
+use crate::trace_set::{TraceSet, TraceSource};
+use egui::{Color32, Context, DragValue, Grid, ProgressBar, ScrollArea, Spinner, Window};
+use log::error;
+use rfd::FileDialog;
+use std::ops::Range;
+use std::sync::Arc;
+
+/// Number of report rows shown before the rest is only available in the saved report
+const REPORT_ROWS: usize = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum AlignmentMode {
+    /// Shift every trace as a whole, see `math::static_align`
+    Static,
+    /// Warp every trace with FastDTW, see `math::elastic_align`
+    Elastic,
+}
+
+/// Aligned traces and, for static alignment, the shift of every trace
+type AlignmentResult = Result<(TraceSet, Vec<TraceShift>), String>;
+
+/// Window for aligning the traces of a plotter on a reference window.
+pub struct AlignmentDialog {
+    source: Arc<dyn TraceSource>,
+    source_title: String,
+    mode: AlignmentMode,
+    reference: usize,
+    window: Range<usize>,
+    max_distance: usize,
+    correlation_threshold: f64,
+    radius: usize,
+    task: Option<BackgroundTask<AlignmentResult>>,
+    report: Vec<TraceShift>,
+    error: Option<String>,
+}
+
+impl AlignmentDialog {
+    /// Opens the dialog for the traces of `trace_plotter`, using its first shown trace as the
+    /// reference and its selection as the reference window
+    pub fn new(trace_plotter: &TracePlotter) -> Self {
+        let source = trace_plotter.source().clone();
+        let window = trace_plotter
+            .selected_sample_range()
+            .unwrap_or(0..source.num_samples().min(100));
+
+        AlignmentDialog {
+            source_title: trace_plotter.title().to_string(),
+            mode: AlignmentMode::Static,
+            reference: trace_plotter.selected_plot_range().start,
+            window,
+            max_distance: 100,
+            correlation_threshold: 0.5,
+            radius: 1,
+            task: None,
+            report: vec![],
+            error: None,
+            source,
+        }
+    }
+
+    pub fn source_title(&self) -> &str {
+        &self.source_title
+    }
+
+    /// Renders the dialog, returning the aligned traces once an alignment has finished.
+    pub fn render(&mut self, ctx: &Context, open: &mut bool) -> Option<TraceSet> {
+        let mut result = None;
+
+        Window::new(format!("Align traces: {}", self.source_title))
+            .open(open)
+            .show(ctx, |ui| {
+                let running = self.task.is_some();
+                ui.add_enabled_ui(!running, |ui| {
+                    self.render_options(ui);
+                });
+
+                ui.separator();
+
+                if let Some(task) = &self.task {
+                    match self.mode {
+                        AlignmentMode::Static => {
+                            ui.horizontal(|ui| {
+                                ui.add(Spinner::new());
+                                ui.label("Aligning traces...");
+                            });
+                        }
+                        AlignmentMode::Elastic => {
+                            ui.add(ProgressBar::new(task.progress()).show_percentage());
+                        }
+                    }
+                    ctx.request_repaint();
+                } else if ui.button("Align").clicked() {
+                    self.start_alignment();
+                }
+
+                if let Some(error) = &self.error {
+                    ui.colored_label(Color32::RED, error);
+                }
+
+                if !self.report.is_empty() {
+                    self.render_report(ui);
+                }
+            });
+
+        if self.task.as_ref().is_some_and(|t| t.is_finished()) {
+            match self.task.take().unwrap().join() {
+                Ok(Ok((traces, report))) => {
+                    self.report = report;
+                    result = Some(traces);
+                }
+                Ok(Err(e)) | Err(e) => self.error = Some(e),
+            }
+        }
+
+        result
+    }
+
+    fn render_options(&mut self, ui: &mut egui::Ui) {
+        let num_samples = self.source.num_samples();
+
+        Grid::new("alignment_options")
+            .num_columns(2)
+            .show(ui, |ui| {
+                ui.label("Mode:");
+                ui.horizontal(|ui| {
+                    ui.radio_value(&mut self.mode, AlignmentMode::Static, "Static shift");
+                    ui.radio_value(&mut self.mode, AlignmentMode::Elastic, "Elastic (FastDTW)");
+                });
+                ui.end_row();
+
+                ui.label("Reference trace:");
+                ui.add(
+                    DragValue::new(&mut self.reference)
+                        .range(0..=self.source.len().saturating_sub(1)),
+                );
+                ui.end_row();
+
+                ui.label("Reference window:");
+                ui.horizontal(|ui| {
+                    ui.add(DragValue::new(&mut self.window.start).range(0..=num_samples));
+                    ui.label("to");
+                    ui.add(DragValue::new(&mut self.window.end).range(0..=num_samples));
+                });
+                ui.end_row();
+
+                match self.mode {
+                    AlignmentMode::Static => {
+                        ui.label("Max shift (samples):");
+                        ui.add(DragValue::new(&mut self.max_distance).range(0..=num_samples));
+                        ui.end_row();
+
+                        ui.label("Correlation threshold:");
+                        ui.add(
+                            DragValue::new(&mut self.correlation_threshold)
+                                .range(-1.0..=1.0)
+                                .speed(0.01),
+                        );
+                        ui.end_row();
+                    }
+                    AlignmentMode::Elastic => {
+                        ui.label("Radius:");
+                        ui.add(DragValue::new(&mut self.radius).range(0..=num_samples))
+                            .on_hover_text("Larger radii are closer to exact DTW but slower");
+                        ui.end_row();
+                    }
+                }
+            });
+    }
+
+    fn render_report(&mut self, ui: &mut egui::Ui) {
+        ui.separator();
+
+        let kept = self.report.iter().filter(|shift| shift.kept).count();
+        ui.horizontal(|ui| {
+            ui.label(format!("Kept {} of {} traces", kept, self.report.len()));
+            if ui.button("Save report").clicked() {
+                if let Some(path) = FileDialog::new().add_filter("csv", &["csv"]).save_file() {
+                    if let Err(e) = write_shift_report(&self.report, &path.to_string_lossy()) {
+                        error!("Failed to write the alignment report: {:?}", e);
+                        self.error = Some(e.to_string());
+                    }
+                }
+            }
+        });
+
+        ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+            Grid::new("alignment_report")
+                .num_columns(3)
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.strong("Trace");
+                    ui.strong("Shift");
+                    ui.strong("Correlation");
+                    ui.end_row();
+
+                    for shift in self.report.iter().take(REPORT_ROWS) {
+                        let color = if shift.kept {
+                            ui.visuals().text_color()
+                        } else {
+                            Color32::RED
+                        };
+                        ui.colored_label(color, shift.trace.to_string());
+                        ui.colored_label(color, shift.shift.to_string());
+                        ui.colored_label(color, format!("{:.4}", shift.correlation));
+                        ui.end_row();
+                    }
+                });
+        });
+    }
+
+    fn start_alignment(&mut self) {
+        let source = self.source.clone();
+        let reference = self.reference;
+        let window = self.window.clone();
+        let max_distance = self.max_distance;
+        let correlation_threshold = self.correlation_threshold;
+        let radius = self.radius;
+        let mode = self.mode;
+
+        self.error = None;
+        self.report.clear();
+        self.task = Some(BackgroundTask::spawn(move |progress| match mode {
+            AlignmentMode::Static => static_align(
+                reference,
+                source.as_ref(),
+                window,
+                max_distance,
+                correlation_threshold,
+            )
+            .map(|alignment| (alignment.traces, alignment.report)),
+            AlignmentMode::Elastic => elastic_align(
+                reference,
+                source.as_ref(),
+                window,
+                radius,
+                &mut |fraction| progress.set(fraction),
+            )
+            .map(|traces| (traces, vec![])),
+        }));
+    }
+}
@@ -0,0 +1,256 @@
+use crate::dialogs::key_rank::rank_text;
+use crate::dialogs::BackgroundTask;
+use crate::math::cpa::{cpa, CpaModel, CpaResult, KeyCandidate, KEY_BYTES};
+use crate::math::rank::{
+    correlation_log_probabilities, estimate_key_rank, KeyRank, DEFAULT_RANK_BINS,
+};
+use crate::trace_plotter::trace_plotter::TracePlotter;
+use crate::trace_set::{TraceSet, TraceSource};
+use egui::{Color32, Context, DragValue, Grid, ProgressBar, RichText, Window};
+use std::ops::{Range, RangeInclusive};
+use std::sync::Arc;
+
+/// Number of ranked guesses shown for every key byte
+const SHOWN_CANDIDATES: usize = 8;
+
+/// Window for running CPA on the traces of a plotter and browsing the ranked key guesses.
+pub struct CpaDialog {
+    source: Arc<dyn TraceSource>,
+    source_title: String,
+    window: Range<usize>,
+    /// Attacked key bytes, inclusive
+    bytes: RangeInclusive<usize>,
+    model: CpaModel,
+    /// First sample of the window the result was computed on
+    result_offset: usize,
+    task: Option<BackgroundTask<Result<CpaResult, String>>>,
+    result: Option<CpaResult>,
+    /// Ranked guesses of every attacked byte of the result
+    rankings: Vec<Vec<KeyCandidate>>,
+    /// Rank of the known key among all keys, if there is one
+    key_rank: Option<KeyRank>,
+    error: Option<String>,
+}
+
+impl CpaDialog {
+    /// Opens the dialog for the traces of `trace_plotter`, analysing its selection if there is one
+    pub fn new(trace_plotter: &TracePlotter) -> Self {
+        let source = trace_plotter.source().clone();
+
+        CpaDialog {
+            source_title: trace_plotter.title().to_string(),
+            window: trace_plotter
+                .selected_sample_range()
+                .unwrap_or(0..source.num_samples()),
+            bytes: 0..=KEY_BYTES - 1,
+            model: CpaModel::SboxHammingWeight,
+            result_offset: 0,
+            task: None,
+            result: None,
+            rankings: vec![],
+            key_rank: None,
+            error: None,
+            source,
+        }
+    }
+
+    /// Renders the dialog, returning the title and correlation traces of a key byte when they
+    /// should be plotted.
+    pub fn render(&mut self, ctx: &Context, open: &mut bool) -> Option<(String, TraceSet)> {
+        let mut plot = None;
+
+        Window::new(format!("CPA: {}", self.source_title))
+            .open(open)
+            .show(ctx, |ui| {
+                ui.add_enabled_ui(self.task.is_none(), |ui| {
+                    self.render_options(ui);
+                });
+
+                ui.separator();
+
+                if let Some(task) = &self.task {
+                    ui.add(ProgressBar::new(task.progress()).show_percentage());
+                    ctx.request_repaint();
+                } else if ui.button("Run").clicked() {
+                    self.start_cpa();
+                }
+
+                if let Some(error) = &self.error {
+                    ui.colored_label(Color32::RED, error);
+                }
+
+                if self.result.is_some() {
+                    plot = self.render_result(ui);
+                }
+            });
+
+        if self.task.as_ref().is_some_and(|t| t.is_finished()) {
+            match self.task.take().unwrap().join() {
+                Ok(Ok(result)) => {
+                    self.rankings = result.bytes.iter().filter_map(|&b| result.ranking(b)).collect();
+                    self.key_rank = self.estimate_key_rank(&result);
+                    self.result = Some(result);
+                }
+                Ok(Err(e)) | Err(e) => self.error = Some(e),
+            }
+        }
+
+        plot
+    }
+
+    fn render_options(&mut self, ui: &mut egui::Ui) {
+        let num_samples = self.source.num_samples();
+
+        Grid::new("cpa_options").num_columns(2).show(ui, |ui| {
+            ui.label("Samples:");
+            ui.horizontal(|ui| {
+                ui.add(DragValue::new(&mut self.window.start).range(0..=num_samples));
+                ui.label("to");
+                ui.add(DragValue::new(&mut self.window.end).range(0..=num_samples));
+            });
+            ui.end_row();
+
+            ui.label("Key bytes:");
+            ui.horizontal(|ui| {
+                let (mut first, mut last) = self.bytes.clone().into_inner();
+                ui.add(DragValue::new(&mut first).range(0..=KEY_BYTES - 1));
+                ui.label("to");
+                ui.add(DragValue::new(&mut last).range(first..=KEY_BYTES - 1));
+                self.bytes = first..=last.max(first);
+            });
+            ui.end_row();
+
+            ui.label("Leakage model:");
+            ui.horizontal(|ui| {
+                let hamming_weight = self.model == CpaModel::SboxHammingWeight;
+                if ui.radio(hamming_weight, "S-box HW").clicked() {
+                    self.model = CpaModel::SboxHammingWeight;
+                }
+                if ui.radio(!hamming_weight, "S-box bit").clicked() && hamming_weight {
+                    self.model = CpaModel::SboxBit(0);
+                }
+                if let CpaModel::SboxBit(bit) = &mut self.model {
+                    ui.add(DragValue::new(bit).range(0..=7));
+                }
+            });
+            ui.end_row();
+        });
+    }
+
+    fn render_result(&self, ui: &mut egui::Ui) -> Option<(String, TraceSet)> {
+        let result = self.result.as_ref()?;
+        let mut plot = None;
+
+        ui.separator();
+        ui.label(format!("{} traces", result.num_traces));
+
+        let key_text = |key: &[u8]| {
+            key.iter()
+                .map(|b| format!("{:02X}", b))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let best_key: Vec<u8> = self
+            .rankings
+            .iter()
+            .map(|ranking| ranking[0].guess)
+            .collect();
+        ui.label(RichText::new(format!("Best key:  {}", key_text(&best_key))).monospace());
+        if let Some(known_key) = &result.known_key {
+            let known_key: Vec<u8> = result.bytes.iter().map(|&byte| known_key[byte]).collect();
+            ui.label(RichText::new(format!("Known key: {}", key_text(&known_key))).monospace());
+        }
+        if let Some(key_rank) = &self.key_rank {
+            ui.label(rank_text(key_rank));
+        }
+
+        Grid::new("cpa_ranking").striped(true).show(ui, |ui| {
+            ui.strong("Rank");
+            for byte in &result.bytes {
+                ui.strong(byte.to_string());
+            }
+            ui.end_row();
+
+            for rank in 0..SHOWN_CANDIDATES {
+                ui.label((rank + 1).to_string());
+                for (&byte, ranking) in result.bytes.iter().zip(&self.rankings) {
+                    let candidate = ranking[rank];
+                    let correct = result
+                        .known_key
+                        .as_ref()
+                        .is_some_and(|key| key[byte] == candidate.guess);
+                    let text = RichText::new(format!("{:02X}", candidate.guess)).monospace();
+                    let text = if correct {
+                        text.color(Color32::GREEN)
+                    } else {
+                        text
+                    };
+                    ui.label(text).on_hover_text(format!(
+                        "Correlation {:.4} at sample {}",
+                        candidate.correlation,
+                        self.result_offset + candidate.sample
+                    ));
+                }
+                ui.end_row();
+            }
+
+            ui.label("");
+            for (&byte, correlations) in result.bytes.iter().zip(&result.correlations) {
+                if ui
+                    .small_button("Plot")
+                    .on_hover_text("Show the correlation traces of every guess")
+                    .clicked()
+                {
+                    plot = Some((
+                        format!("CPA {} byte {}", self.source_title, byte),
+                        correlations.clone(),
+                    ));
+                }
+            }
+            ui.end_row();
+        });
+
+        plot
+    }
+
+    /// Ranks the attacked bytes of the known key with the correlation peak of every guess
+    fn estimate_key_rank(&self, result: &CpaResult) -> Option<KeyRank> {
+        let key = result.known_key.as_ref()?;
+        let log_probabilities: Vec<Vec<f64>> = self
+            .rankings
+            .iter()
+            .map(|ranking| {
+                let mut peaks = vec![0.0; ranking.len()];
+                for candidate in ranking {
+                    peaks[candidate.guess as usize] = candidate.correlation as f64;
+                }
+                correlation_log_probabilities(&peaks, result.num_traces)
+            })
+            .collect();
+        let key: Vec<usize> = result
+            .bytes
+            .iter()
+            .map(|&byte| key[byte] as usize)
+            .collect();
+
+        estimate_key_rank(&log_probabilities, &key, DEFAULT_RANK_BINS).ok()
+    }
+
+    fn start_cpa(&mut self) {
+        let source = self.source.clone();
+        let window = self.window.clone();
+        let bytes: Vec<usize> = self.bytes.clone().collect();
+        let model = self.model;
+
+        self.result_offset = window.start;
+        self.error = None;
+        self.result = None;
+        self.rankings.clear();
+        self.key_rank = None;
+        self.task = Some(BackgroundTask::spawn(move |progress| {
+            cpa(source.as_ref(), window, &bytes, model, &mut |fraction| {
+                progress.set(fraction)
+            })
+        }));
+    }
+}
@@ -0,0 +1,194 @@
+use crate::dialogs::BackgroundTask;
+use crate::loaders::csv_import::{
+    detect_csv_options, read_csv, CsvError, CsvOptions, CsvOrientation, CsvTime,
+};
+use crate::trace_set::TraceSet;
+use egui::{Color32, ComboBox, Context, DragValue, Grid, ProgressBar, RichText, Window};
+use log::error;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+const PREVIEW_LINES: usize = 12;
+
+const DELIMITERS: [(u8, &str); 4] = [
+    (b',', "Comma"),
+    (b';', "Semicolon"),
+    (b'\t', "Tab"),
+    (b' ', "Space"),
+];
+
+/// Window for choosing how a CSV file is read before importing it in the background.
+pub struct CsvImportWizard {
+    file_path: String,
+    options: CsvOptions,
+    preview: Vec<String>,
+    time_index: usize,
+    sample_rate: f64,
+    import: Option<BackgroundTask<Result<TraceSet, CsvError>>>,
+    error: Option<String>,
+}
+
+impl CsvImportWizard {
+    /// Opens the wizard for `file_path`, starting from the detected delimiter and header rows
+    pub fn new(file_path: String) -> Self {
+        let options = detect_csv_options(&file_path).unwrap_or_else(|e| {
+            error!("Failed to detect the CSV layout: {}", e);
+            CsvOptions::default()
+        });
+
+        let preview = File::open(&file_path)
+            .map(|file| {
+                BufReader::new(file)
+                    .lines()
+                    .take(options.skip_rows + PREVIEW_LINES)
+                    .map_while(Result::ok)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        CsvImportWizard {
+            file_path,
+            options,
+            preview,
+            time_index: 0,
+            sample_rate: 1.0e9,
+            import: None,
+            error: None,
+        }
+    }
+
+    pub fn file_name(&self) -> String {
+        Path::new(&self.file_path)
+            .file_name()
+            .map_or(self.file_path.clone(), |name| {
+                name.to_string_lossy().into_owned()
+            })
+    }
+
+    /// Renders the wizard, returning the trace set once an import has finished.
+    pub fn render(&mut self, ctx: &Context, open: &mut bool) -> Option<TraceSet> {
+        let mut result = None;
+
+        Window::new("Import CSV").open(open).show(ctx, |ui| {
+            ui.label(&self.file_path);
+            ui.separator();
+
+            let importing = self.import.is_some();
+            ui.add_enabled_ui(!importing, |ui| {
+                self.render_options(ui);
+            });
+
+            ui.separator();
+            ui.label("Preview:");
+            for (i, line) in self.preview.iter().enumerate() {
+                let text = RichText::new(line).monospace();
+                if i < self.options.skip_rows {
+                    ui.label(text.color(Color32::GRAY).strikethrough());
+                } else {
+                    ui.label(text);
+                }
+            }
+            ui.separator();
+
+            if let Some(import) = &self.import {
+                ui.add(ProgressBar::new(import.progress()).show_percentage());
+                ctx.request_repaint();
+            } else if ui.button("Import").clicked() {
+                self.start_import();
+            }
+
+            if let Some(error) = &self.error {
+                ui.colored_label(Color32::RED, error);
+            }
+        });
+
+        if self.import.as_ref().is_some_and(|i| i.is_finished()) {
+            match self.import.take().unwrap().join() {
+                Ok(Ok(trace_set)) => result = Some(trace_set),
+                Ok(Err(e)) => self.error = Some(e.to_string()),
+                Err(e) => self.error = Some(e),
+            }
+        }
+
+        result
+    }
+
+    fn render_options(&mut self, ui: &mut egui::Ui) {
+        Grid::new("csv_import_options")
+            .num_columns(2)
+            .show(ui, |ui| {
+                ui.label("Delimiter:");
+                let selected = DELIMITERS
+                    .iter()
+                    .find(|(d, _)| *d == self.options.delimiter)
+                    .map_or("Other", |(_, name)| name);
+                ComboBox::from_id_source("csv_delimiter")
+                    .selected_text(selected)
+                    .show_ui(ui, |ui| {
+                        for (delimiter, name) in DELIMITERS {
+                            ui.selectable_value(&mut self.options.delimiter, delimiter, name);
+                        }
+                    });
+                ui.end_row();
+
+                ui.label("Header rows to skip:");
+                ui.add(DragValue::new(&mut self.options.skip_rows).range(0..=10_000));
+                ui.end_row();
+
+                ui.label("Layout:");
+                ui.horizontal(|ui| {
+                    ui.radio_value(
+                        &mut self.options.orientation,
+                        CsvOrientation::TracePerColumn,
+                        "Trace per column",
+                    );
+                    ui.radio_value(
+                        &mut self.options.orientation,
+                        CsvOrientation::TracePerRow,
+                        "Trace per row",
+                    );
+                });
+                ui.end_row();
+
+                let time_label = match self.options.orientation {
+                    CsvOrientation::TracePerColumn => "Time column",
+                    CsvOrientation::TracePerRow => "Time row",
+                };
+                ui.label("Time:");
+                ui.horizontal(|ui| {
+                    let from_file = matches!(self.options.time, CsvTime::Index(_));
+                    if ui.radio(from_file, time_label).clicked() {
+                        self.options.time = CsvTime::Index(self.time_index);
+                    }
+                    if ui.radio(!from_file, "Sample rate (Hz)").clicked() {
+                        self.options.time = CsvTime::SampleRate(self.sample_rate);
+                    }
+                });
+                ui.end_row();
+
+                ui.label("");
+                match &mut self.options.time {
+                    CsvTime::Index(index) => {
+                        ui.add(DragValue::new(index).range(0..=usize::MAX));
+                        self.time_index = *index;
+                    }
+                    CsvTime::SampleRate(rate) => {
+                        ui.add(DragValue::new(rate).range(1.0..=f64::MAX).speed(1.0e6));
+                        self.sample_rate = *rate;
+                    }
+                }
+                ui.end_row();
+            });
+    }
+
+    fn start_import(&mut self) {
+        let file_path = self.file_path.clone();
+        let options = self.options;
+
+        self.error = None;
+        self.import = Some(BackgroundTask::spawn(move |progress| {
+            read_csv(&file_path, &options, &mut |fraction| progress.set(fraction))
+        }));
+    }
+}
@@ -0,0 +1,200 @@
+use crate::dialogs::{filter_spec_picker, BackgroundTask};
+use crate::math::filter::{filter_traces, Filter, FilterBand, FilterFamily, FilterSpec};
+use crate::trace_plotter::trace_plotter::TracePlotter;
+use crate::trace_set::{TraceSet, TraceSource};
+use egui::{Color32, Context, DragValue, ProgressBar, Ui, Window};
+use egui_plot::{Legend, Line, Plot, PlotPoints};
+use std::ops::Range;
+use std::sync::Arc;
+
+/// Points drawn per preview line, the trace is subsampled above this
+const PREVIEW_POINTS: usize = 20_000;
+
+/// Preview of the filter on one trace, with the settings it was computed for
+struct Preview {
+    spec: FilterSpec,
+    trace: usize,
+    original: Vec<f32>,
+    /// The filtered trace, or why the filter couldn't be designed
+    filtered: Result<Vec<f32>, String>,
+}
+
+/// Window for designing a filter on the traces of a plotter, previewing it on one trace while
+/// the settings change, then filtering every trace.
+pub struct FilterDialog {
+    source: Arc<dyn TraceSource>,
+    source_title: String,
+    /// Trace the preview is drawn for
+    trace: usize,
+    /// Samples shown in the preview
+    shown: Range<usize>,
+    spec: FilterSpec,
+    preview: Option<Preview>,
+    task: Option<BackgroundTask<Result<TraceSet, String>>>,
+    error: Option<String>,
+}
+
+impl FilterDialog {
+    /// Opens the dialog for the traces of `trace_plotter`, previewing its first shown trace over
+    /// its selection if there is one
+    pub fn new(trace_plotter: &TracePlotter) -> Self {
+        let source = trace_plotter.source().clone();
+        let sample_rate = 1.0 / source.time().interval;
+
+        FilterDialog {
+            source_title: trace_plotter.title().to_string(),
+            trace: trace_plotter.selected_plot_range().start,
+            shown: trace_plotter
+                .selected_sample_range()
+                .unwrap_or(0..source.num_samples()),
+            spec: FilterSpec {
+                family: FilterFamily::Butterworth,
+                band: FilterBand::LowPass(sample_rate / 10.0),
+                order: 4,
+            },
+            preview: None,
+            task: None,
+            error: None,
+            source,
+        }
+    }
+
+    pub fn source_title(&self) -> &str {
+        &self.source_title
+    }
+
+    /// Renders the dialog, returning the filtered traces once filtering has finished.
+    pub fn render(&mut self, ctx: &Context, open: &mut bool) -> Option<TraceSet> {
+        let mut result = None;
+
+        Window::new(format!("Filter traces: {}", self.source_title))
+            .open(open)
+            .show(ctx, |ui| {
+                ui.add_enabled_ui(self.task.is_none(), |ui| {
+                    self.render_options(ui);
+                });
+
+                self.update_preview();
+                self.render_preview(ui);
+
+                ui.separator();
+
+                if let Some(task) = &self.task {
+                    ui.add(ProgressBar::new(task.progress()).show_percentage());
+                    ctx.request_repaint();
+                } else if ui
+                    .button("Filter all traces")
+                    .on_hover_text("Open the filtered traces in a new plotter")
+                    .clicked()
+                {
+                    self.start_filter();
+                }
+
+                if let Some(error) = &self.error {
+                    ui.colored_label(Color32::RED, error);
+                }
+            });
+
+        if self.task.as_ref().is_some_and(|t| t.is_finished()) {
+            match self.task.take().unwrap().join() {
+                Ok(Ok(traces)) => result = Some(traces),
+                Ok(Err(e)) | Err(e) => self.error = Some(e),
+            }
+        }
+
+        result
+    }
+
+    fn render_options(&mut self, ui: &mut Ui) {
+        let sample_rate = 1.0 / self.source.time().interval;
+
+        ui.horizontal(|ui| {
+            ui.label("Preview trace:");
+            ui.add(DragValue::new(&mut self.trace).range(0..=self.source.len().saturating_sub(1)));
+        });
+        filter_spec_picker(ui, "filter_spec", &mut self.spec, sample_rate);
+
+        ui.label(format!(
+            "Sample rate {:.4e}, applied forward and backward so the trace isn't delayed",
+            sample_rate
+        ));
+    }
+
+    /// Filters the preview trace again when the settings or the trace changed
+    fn update_preview(&mut self) {
+        let spec = self.spec;
+        if let Some(preview) = &self.preview {
+            if preview.spec == spec && preview.trace == self.trace {
+                return;
+            }
+        }
+
+        let original = match self.source.read_traces(self.trace..self.trace + 1) {
+            Ok(traces) if !traces.is_empty() => traces.trace(0).to_vec(),
+            Ok(_) => vec![],
+            Err(e) => {
+                self.error = Some(e.to_string());
+                vec![]
+            }
+        };
+        let filtered = Filter::design(&spec, 1.0 / self.source.time().interval)
+            .map(|filter| filter.apply(&original));
+
+        self.preview = Some(Preview {
+            spec,
+            trace: self.trace,
+            original,
+            filtered,
+        });
+    }
+
+    fn render_preview(&self, ui: &mut Ui) {
+        let Some(preview) = &self.preview else {
+            return;
+        };
+        if let Err(e) = &preview.filtered {
+            ui.colored_label(Color32::RED, e);
+        }
+
+        let time = self.source.time();
+        let shown = self.shown.start.min(preview.original.len())
+            ..self.shown.end.min(preview.original.len());
+        let step = shown.len().div_ceil(PREVIEW_POINTS).max(1);
+        let points = |trace: &[f32]| -> PlotPoints {
+            shown
+                .clone()
+                .step_by(step)
+                .map(|i| [time.x(i), trace[i] as f64])
+                .collect()
+        };
+
+        Plot::new("filter_preview")
+            .legend(Legend::default())
+            .width(800.0)
+            .view_aspect(2.5)
+            .show(ui, |plot_ui| {
+                plot_ui.line(Line::new(points(&preview.original)).name("Original"));
+                if let Ok(filtered) = &preview.filtered {
+                    plot_ui.line(Line::new(points(filtered)).name("Filtered"));
+                }
+            });
+    }
+
+    fn start_filter(&mut self) {
+        let source = self.source.clone();
+
+        self.error = None;
+        let filter = match Filter::design(&self.spec, 1.0 / source.time().interval) {
+            Ok(filter) => filter,
+            Err(e) => {
+                self.error = Some(e);
+                return;
+            }
+        };
+        self.task = Some(BackgroundTask::spawn(move |progress| {
+            filter_traces(source.as_ref(), &filter, &mut |fraction| {
+                progress.set(fraction)
+            })
+        }));
+    }
+}
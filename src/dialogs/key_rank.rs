@@ -0,0 +1,353 @@
+use crate::dialogs::BackgroundTask;
+use crate::math::cpa::KEY_BYTES;
+use crate::math::leakage::{AesSboxOutput, Leakage, LeakageModel};
+use crate::math::rank::{
+    correlation_log_probabilities, estimate_key_rank, normalize_log_probabilities, rank_curves,
+    read_score_csv, KeyRank, RankCurves, DEFAULT_RANK_BINS,
+};
+use crate::trace_plotter::trace_plotter::TracePlotter;
+use crate::trace_set::{TraceSet, TraceSource};
+use egui::{Color32, Context, DragValue, Grid, ProgressBar, RichText, Window};
+use log::error;
+use rfd::FileDialog;
+use std::ops::Range;
+use std::sync::Arc;
+
+/// What the numbers of an imported score table are
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ScoreKind {
+    LogProbability,
+    /// Correlation peaks, turned into log-probabilities with the number of traces
+    Correlation,
+}
+
+/// Window for estimating the rank of a known key from a score table, and for the guessing
+/// entropy and success rate of CPA on the traces of a plotter.
+pub struct KeyRankDialog {
+    source: Arc<dyn TraceSource>,
+    source_title: String,
+    /// Known key as hex
+    key_text: String,
+    scores: Option<Vec<Vec<f64>>>,
+    score_kind: ScoreKind,
+    /// Number of traces the imported correlations were computed on
+    score_traces: usize,
+    rank: Option<KeyRank>,
+    window: Range<usize>,
+    max_traces: usize,
+    steps: usize,
+    experiments: usize,
+    task: Option<BackgroundTask<Result<RankCurves, String>>>,
+    curves: Option<RankCurves>,
+    error: Option<String>,
+}
+
+impl KeyRankDialog {
+    /// Opens the dialog for the traces of `trace_plotter`, using the key stored with its first
+    /// trace if there is one
+    pub fn new(trace_plotter: &TracePlotter) -> Self {
+        let source = trace_plotter.source().clone();
+        let key_text = source
+            .read_window(0..source.len().min(1), 0..0)
+            .ok()
+            .and_then(|first| first.data.first().map(|data| hex(&data.key)))
+            .unwrap_or_default();
+
+        KeyRankDialog {
+            source_title: trace_plotter.title().to_string(),
+            key_text,
+            scores: None,
+            score_kind: ScoreKind::Correlation,
+            score_traces: source.len(),
+            rank: None,
+            window: trace_plotter
+                .selected_sample_range()
+                .unwrap_or(0..source.num_samples()),
+            max_traces: source.len(),
+            steps: 10,
+            experiments: 10,
+            task: None,
+            curves: None,
+            error: None,
+            source,
+        }
+    }
+
+    /// Renders the dialog, returning the title and a curve when it should be plotted.
+    pub fn render(&mut self, ctx: &Context, open: &mut bool) -> Option<(String, TraceSet)> {
+        let mut plot = None;
+
+        Window::new(format!("Key rank: {}", self.source_title))
+            .open(open)
+            .show(ctx, |ui| {
+                Grid::new("key_rank_key").num_columns(2).show(ui, |ui| {
+                    ui.label("Known key:");
+                    ui.text_edit_singleline(&mut self.key_text);
+                    ui.end_row();
+                });
+
+                ui.separator();
+                ui.strong("Score table");
+                self.render_score_table(ui);
+
+                ui.separator();
+                ui.strong("Guessing entropy and success rate");
+                ui.add_enabled_ui(self.task.is_none(), |ui| {
+                    self.render_curve_options(ui);
+                });
+
+                if let Some(task) = &self.task {
+                    ui.add(ProgressBar::new(task.progress()).show_percentage());
+                    ctx.request_repaint();
+                } else if ui
+                    .button("Run")
+                    .on_hover_text("CPA with the S-box HW model on growing random subsets")
+                    .clicked()
+                {
+                    self.start_curves();
+                }
+
+                if self.curves.is_some() {
+                    plot = self.render_curves(ui);
+                }
+
+                if let Some(error) = &self.error {
+                    ui.colored_label(Color32::RED, error);
+                }
+            });
+
+        if self.task.as_ref().is_some_and(|t| t.is_finished()) {
+            match self.task.take().unwrap().join() {
+                Ok(Ok(curves)) => {
+                    match curves.trace_sets() {
+                        Ok((entropy, _)) => plot = Some((self.plot_title("GE"), entropy)),
+                        Err(e) => self.error = Some(e),
+                    }
+                    self.curves = Some(curves);
+                }
+                Ok(Err(e)) | Err(e) => self.error = Some(e),
+            }
+        }
+
+        plot
+    }
+
+    fn render_score_table(&mut self, ui: &mut egui::Ui) {
+        Grid::new("key_rank_scores").num_columns(2).show(ui, |ui| {
+            ui.label("Scores:");
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut self.score_kind, ScoreKind::Correlation, "Correlations");
+                ui.radio_value(
+                    &mut self.score_kind,
+                    ScoreKind::LogProbability,
+                    "Log-probabilities",
+                );
+            });
+            ui.end_row();
+
+            if self.score_kind == ScoreKind::Correlation {
+                ui.label("Traces:");
+                ui.add(DragValue::new(&mut self.score_traces).range(4..=usize::MAX));
+                ui.end_row();
+            }
+        });
+
+        ui.horizontal(|ui| {
+            if ui
+                .button("Import CSV")
+                .on_hover_text("One row per key byte, one column per guess")
+                .clicked()
+            {
+                if let Some(path) = FileDialog::new().add_filter("csv", &["csv"]).pick_file() {
+                    match read_score_csv(&path.to_string_lossy()) {
+                        Ok(scores) => {
+                            self.scores = Some(scores);
+                            self.rank = None;
+                            self.error = None;
+                        }
+                        Err(e) => {
+                            error!("Failed to read the score table: {:?}", e);
+                            self.error = Some(e.to_string());
+                        }
+                    }
+                }
+            }
+
+            if let Some(scores) = &self.scores {
+                ui.label(format!(
+                    "{} bytes of {} guesses",
+                    scores.len(),
+                    scores.first().map_or(0, |row| row.len())
+                ));
+                if ui.button("Estimate rank").clicked() {
+                    match self.estimate_rank() {
+                        Ok(rank) => {
+                            self.rank = Some(rank);
+                            self.error = None;
+                        }
+                        Err(e) => self.error = Some(e),
+                    }
+                }
+            }
+        });
+
+        if let Some(rank) = &self.rank {
+            ui.label(rank_text(rank));
+        }
+    }
+
+    fn render_curve_options(&mut self, ui: &mut egui::Ui) {
+        let num_samples = self.source.num_samples();
+        let num_traces = self.source.len();
+
+        Grid::new("key_rank_curves").num_columns(2).show(ui, |ui| {
+            ui.label("Samples:");
+            ui.horizontal(|ui| {
+                ui.add(DragValue::new(&mut self.window.start).range(0..=num_samples));
+                ui.label("to");
+                ui.add(DragValue::new(&mut self.window.end).range(0..=num_samples));
+            });
+            ui.end_row();
+
+            ui.label("Traces:");
+            ui.add(DragValue::new(&mut self.max_traces).range(2..=num_traces));
+            ui.end_row();
+
+            ui.label("Steps:");
+            ui.add(DragValue::new(&mut self.steps).range(1..=100));
+            ui.end_row();
+
+            ui.label("Experiments:");
+            ui.add(DragValue::new(&mut self.experiments).range(1..=1000));
+            ui.end_row();
+        });
+    }
+
+    fn render_curves(&mut self, ui: &mut egui::Ui) -> Option<(String, TraceSet)> {
+        let curves = self.curves.as_ref()?;
+        let mut plot = None;
+        let mut export_error = None;
+
+        if let (Some(entropy), Some(success)) =
+            (curves.guessing_entropy.last(), curves.success_rate.last())
+        {
+            ui.label(format!(
+                "With {} traces: guessing entropy {:.1} bits, success rate {:.0}%",
+                curves.num_traces().last().unwrap_or(0),
+                entropy,
+                success * 100.0
+            ));
+        }
+
+        ui.horizontal(|ui| {
+            if let Ok((entropy, success)) = curves.trace_sets() {
+                if ui.button("Plot GE").clicked() {
+                    plot = Some((self.plot_title("GE"), entropy));
+                }
+                if ui.button("Plot SR").clicked() {
+                    plot = Some((self.plot_title("SR"), success));
+                }
+            }
+
+            if ui.button("Export CSV").clicked() {
+                if let Some(path) = FileDialog::new().add_filter("csv", &["csv"]).save_file() {
+                    if let Err(e) = curves.write_csv(&path.to_string_lossy()) {
+                        error!("Failed to write the rank curves: {:?}", e);
+                        export_error = Some(e.to_string());
+                    }
+                }
+            }
+        });
+
+        if export_error.is_some() {
+            self.error = export_error;
+        }
+
+        plot
+    }
+
+    fn estimate_rank(&self) -> Result<KeyRank, String> {
+        let key = parse_hex(&self.key_text)?;
+        let scores = self.scores.as_ref().ok_or("Import a score table first")?;
+
+        let log_probabilities: Vec<Vec<f64>> = scores
+            .iter()
+            .map(|row| match self.score_kind {
+                ScoreKind::LogProbability => normalize_log_probabilities(row),
+                ScoreKind::Correlation => correlation_log_probabilities(row, self.score_traces),
+            })
+            .collect();
+        let key: Vec<usize> = key.iter().map(|&b| b as usize).collect();
+
+        estimate_key_rank(&log_probabilities, &key, DEFAULT_RANK_BINS)
+    }
+
+    fn plot_title(&self, name: &str) -> String {
+        format!("{} {}", name, self.source_title)
+    }
+
+    fn start_curves(&mut self) {
+        let source = self.source.clone();
+        let window = self.window.clone();
+        let (max_traces, steps, experiments) = (self.max_traces, self.steps, self.experiments);
+
+        self.error = None;
+        self.curves = None;
+        self.task = Some(BackgroundTask::spawn(move |progress| {
+            let models: Vec<Box<dyn LeakageModel>> = (0..KEY_BYTES)
+                .map(|byte| {
+                    Box::new(AesSboxOutput {
+                        byte,
+                        leakage: Leakage::HammingWeight,
+                    }) as Box<dyn LeakageModel>
+                })
+                .collect();
+            rank_curves(
+                source.as_ref(),
+                window,
+                &models,
+                max_traces,
+                steps,
+                experiments,
+                &mut |fraction| progress.set(fraction),
+            )
+        }));
+    }
+}
+
+/// Describes an estimated key rank and its bounds
+pub fn rank_text(rank: &KeyRank) -> RichText {
+    let text = format!(
+        "Estimated key rank 2^{:.1}, between 2^{:.1} and 2^{:.1}",
+        rank.log2_rank, rank.log2_lower, rank.log2_upper
+    );
+    if rank.log2_upper < 1.0 {
+        RichText::new(text).color(Color32::GREEN)
+    } else {
+        RichText::new(text)
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Reads hex bytes, ignoring whitespace
+fn parse_hex(text: &str) -> Result<Vec<u8>, String> {
+    let digits: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+    if digits.is_empty() || !digits.len().is_multiple_of(2) {
+        return Err(format!("'{}' is no key in hex", text));
+    }
+
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&digits[i..i + 2], 16)
+                .map_err(|_| format!("'{}' is no key in hex", text))
+        })
+        .collect()
+}
@@ -0,0 +1,214 @@
+use crate::dialogs::{intermediate_picker, BackgroundTask};
+use crate::math::leakage::{Intermediate, Leakage};
+use crate::math::mia::{mia, MiaCandidate, MiaEstimator, MiaResult};
+use crate::trace_plotter::trace_plotter::TracePlotter;
+use crate::trace_set::{TraceSet, TraceSource};
+use egui::{Color32, Context, DragValue, Grid, ProgressBar, RichText, Window};
+use std::ops::Range;
+use std::sync::Arc;
+
+/// Number of ranked guesses shown
+const SHOWN_CANDIDATES: usize = 10;
+
+/// Window for a mutual information analysis on the traces of a plotter.
+pub struct MiaDialog {
+    source: Arc<dyn TraceSource>,
+    source_title: String,
+    window: Range<usize>,
+    target: Intermediate,
+    leakage: Leakage,
+    kernel: bool,
+    bins: usize,
+    /// First sample of the window the result was computed on
+    result_offset: usize,
+    /// Target the result was computed for
+    result_target: Intermediate,
+    task: Option<BackgroundTask<Result<MiaResult, String>>>,
+    result: Option<MiaResult>,
+    ranking: Vec<MiaCandidate>,
+    error: Option<String>,
+}
+
+impl MiaDialog {
+    /// Opens the dialog for the traces of `trace_plotter`, analysing its selection if there is one
+    pub fn new(trace_plotter: &TracePlotter) -> Self {
+        let source = trace_plotter.source().clone();
+
+        MiaDialog {
+            source_title: trace_plotter.title().to_string(),
+            window: trace_plotter
+                .selected_sample_range()
+                .unwrap_or(0..source.num_samples()),
+            target: Intermediate::AesSboxOutput(0),
+            leakage: Leakage::HammingWeight,
+            kernel: false,
+            bins: 9,
+            result_offset: 0,
+            result_target: Intermediate::AesSboxOutput(0),
+            task: None,
+            result: None,
+            ranking: vec![],
+            error: None,
+            source,
+        }
+    }
+
+    /// Renders the dialog, returning the title and information traces when they should be plotted.
+    pub fn render(&mut self, ctx: &Context, open: &mut bool) -> Option<(String, TraceSet)> {
+        let mut plot = None;
+
+        Window::new(format!("MIA: {}", self.source_title))
+            .open(open)
+            .show(ctx, |ui| {
+                ui.add_enabled_ui(self.task.is_none(), |ui| {
+                    self.render_options(ui);
+                });
+
+                ui.separator();
+
+                if let Some(task) = &self.task {
+                    ui.add(ProgressBar::new(task.progress()).show_percentage());
+                    ctx.request_repaint();
+                } else if ui.button("Run").clicked() {
+                    self.start_mia();
+                }
+
+                if let Some(error) = &self.error {
+                    ui.colored_label(Color32::RED, error);
+                }
+
+                if self.result.is_some() {
+                    plot = self.render_result(ui);
+                }
+            });
+
+        if self.task.as_ref().is_some_and(|t| t.is_finished()) {
+            match self.task.take().unwrap().join() {
+                Ok(Ok(result)) => {
+                    self.ranking = result.ranking();
+                    self.result = Some(result);
+                }
+                Ok(Err(e)) | Err(e) => self.error = Some(e),
+            }
+        }
+
+        plot
+    }
+
+    fn render_options(&mut self, ui: &mut egui::Ui) {
+        let num_samples = self.source.num_samples();
+
+        Grid::new("mia_options").num_columns(2).show(ui, |ui| {
+            ui.label("Samples:");
+            ui.horizontal(|ui| {
+                ui.add(DragValue::new(&mut self.window.start).range(0..=num_samples));
+                ui.label("to");
+                ui.add(DragValue::new(&mut self.window.end).range(0..=num_samples));
+            });
+            ui.end_row();
+
+            ui.label("Target:");
+            intermediate_picker(ui, "mia_target", &mut self.target);
+            ui.end_row();
+
+            ui.label("Leakage:");
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut self.leakage, Leakage::HammingWeight, "Hamming weight");
+                ui.radio_value(&mut self.leakage, Leakage::Identity, "Value")
+                    .on_hover_text("Useless for a bijective target such as the AES S-box output");
+            });
+            ui.end_row();
+
+            ui.label("Densities:");
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut self.kernel, false, "Histogram");
+                ui.radio_value(&mut self.kernel, true, "Gaussian kernel");
+            });
+            ui.end_row();
+
+            if !self.kernel {
+                ui.label("Bins:");
+                ui.add(DragValue::new(&mut self.bins).range(2..=256));
+                ui.end_row();
+            }
+        });
+    }
+
+    fn render_result(&self, ui: &mut egui::Ui) -> Option<(String, TraceSet)> {
+        let result = self.result.as_ref()?;
+        let mut plot = None;
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.label(format!("{} traces", result.num_traces));
+            if ui
+                .button("Plot")
+                .on_hover_text("Show the mutual information traces of every guess")
+                .clicked()
+            {
+                plot = Some((self.plot_title(), result.information.clone()));
+            }
+        });
+
+        Grid::new("mia_ranking").striped(true).show(ui, |ui| {
+            ui.strong("Rank");
+            ui.strong("Guess");
+            ui.strong("Information (bits)");
+            ui.strong("Sample");
+            ui.end_row();
+
+            for (rank, candidate) in self.ranking.iter().take(SHOWN_CANDIDATES).enumerate() {
+                let text = RichText::new(format!("{:02X}", candidate.guess)).monospace();
+                let text = if result.known_guess == Some(candidate.guess) {
+                    text.color(Color32::GREEN)
+                } else {
+                    text
+                };
+
+                ui.label((rank + 1).to_string());
+                ui.label(text);
+                ui.label(format!("{:.4e}", candidate.information));
+                ui.label((self.result_offset + candidate.sample).to_string());
+                ui.end_row();
+            }
+        });
+
+        if let Some(known_guess) = result.known_guess {
+            if let Some(rank) = self.ranking.iter().position(|c| c.guess == known_guess) {
+                ui.label(format!("Known key {:02X} ranks {}", known_guess, rank + 1));
+            }
+        }
+
+        plot
+    }
+
+    fn plot_title(&self) -> String {
+        format!("MIA {} {}", self.source_title, self.result_target)
+    }
+
+    fn start_mia(&mut self) {
+        let source = self.source.clone();
+        let window = self.window.clone();
+        let model = self.target.model(self.leakage);
+        let estimator = if self.kernel {
+            MiaEstimator::Kernel
+        } else {
+            MiaEstimator::Histogram(self.bins)
+        };
+
+        self.result_offset = window.start;
+        self.result_target = self.target;
+        self.error = None;
+        self.result = None;
+        self.ranking.clear();
+        self.task = Some(BackgroundTask::spawn(move |progress| {
+            mia(
+                source.as_ref(),
+                window,
+                model.as_ref(),
+                estimator,
+                &mut |fraction| progress.set(fraction),
+            )
+        }));
+    }
+}
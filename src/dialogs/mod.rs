@@ -0,0 +1,286 @@
+pub mod alignment;
+pub mod cpa;
+pub mod csv_import;
+pub mod dpa;
+pub mod filter;
+pub mod key_rank;
+pub mod mia;
+pub mod pipeline;
+pub mod projection;
+pub mod snr;
+pub mod spectrum;
+pub mod template;
+pub mod ttest;
+
+use crate::math::fft::WindowFunction;
+use crate::math::filter::{FilterBand, FilterFamily, FilterSpec, MAX_IIR_ORDER};
+use crate::math::leakage::Intermediate;
+use eframe::epaint::Color32;
+use egui::{Align, ComboBox, DragValue, Ui};
+use egui_modal::{Icon, Modal, ModalStyle};
+use rfd::FileDialog;
+use std::mem::discriminant;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+pub fn open_file_explorer() -> Option<String> {
+    FileDialog::new()
+        .add_filter("trace_set", &["sct", "bin", "trs", "npy", "npz"])
+        .set_directory("/")
+        .pick_file()
+        .map(|x| x.to_string_lossy().into_owned())
+}
+
+pub fn open_csv_explorer() -> Option<String> {
+    FileDialog::new()
+        .add_filter("csv", &["csv", "txt"])
+        .set_directory("/")
+        .pick_file()
+        .map(|x| x.to_string_lossy().into_owned())
+}
+
+pub fn save_file_explorer() -> Option<String> {
+    FileDialog::new()
+        .add_filter("chunked", &["sct"])
+        .add_filter("trace_set", &["bin"])
+        .add_filter("inspector", &["trs"])
+        .add_filter("numpy", &["npy"])
+        .set_directory("/")
+        .save_file()
+        .map(|x| x.to_string_lossy().into_owned())
+}
+
+pub fn dialog_box_ok(ui: &mut Ui, id: &str, message: &str, message_type: Icon) -> Modal {
+    let style = ModalStyle {
+        body_margin: 30.0,
+        frame_margin: 0.0,
+        icon_margin: 10.0,
+        icon_size: 40.0,
+        overlay_color: Default::default(),
+        caution_button_fill: Default::default(),
+        suggested_button_fill: Default::default(),
+        caution_button_text_color: Default::default(),
+        suggested_button_text_color: Default::default(),
+        dialog_ok_text: "".to_string(),
+        info_icon_color: Color32::LIGHT_BLUE,
+        warning_icon_color: Color32::YELLOW,
+        success_icon_color: Color32::GREEN,
+        error_icon_color: Color32::RED,
+        default_width: Some(ui.max_rect().max.x / 2.0),
+        default_height: Some(ui.max_rect().max.y / 2.0),
+        body_alignment: Align::Center,
+    };
+
+    let error_dialog = Modal::new(ui.ctx(), id).with_style(&style);
+
+    error_dialog.show(|ui| {
+        error_dialog.frame(ui, |ui| {
+            error_dialog.body_and_icon(ui, message, message_type);
+        });
+        error_dialog.buttons(ui, |ui| {
+            if ui.button("Ok").clicked() {
+                error_dialog.close();
+            }
+        });
+    });
+
+    error_dialog
+}
+
+/// Fraction of a background task that is done, stored as the bits of an `f32`
+#[derive(Clone)]
+pub struct Progress(Arc<AtomicU32>);
+
+impl Progress {
+    pub fn set(&self, fraction: f32) {
+        self.0.store(fraction.to_bits(), Ordering::Relaxed)
+    }
+
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+}
+
+/// Work running on its own thread so the windows keep rendering.
+pub struct BackgroundTask<T> {
+    progress: Progress,
+    handle: JoinHandle<T>,
+}
+
+impl<T: Send + 'static> BackgroundTask<T> {
+    pub fn spawn(task: impl FnOnce(Progress) -> T + Send + 'static) -> Self {
+        let progress = Progress(Arc::new(AtomicU32::new(0.0f32.to_bits())));
+        let thread_progress = progress.clone();
+
+        BackgroundTask {
+            progress,
+            handle: std::thread::spawn(move || task(thread_progress)),
+        }
+    }
+
+    pub fn progress(&self) -> f32 {
+        self.progress.get()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+
+    /// Waits for the task, returning an error when its thread panicked
+    pub fn join(self) -> Result<T, String> {
+        self.handle
+            .join()
+            .map_err(|_| "The background thread panicked".to_string())
+    }
+}
+
+/// Picks a built-in intermediate value and the key byte or S-box it depends on
+pub(crate) fn intermediate_picker(ui: &mut Ui, id: &str, intermediate: &mut Intermediate) {
+    let index = intermediate.index();
+
+    ui.horizontal(|ui| {
+        ComboBox::from_id_source(id)
+            .selected_text(intermediate.kind())
+            .show_ui(ui, |ui| {
+                for option in [
+                    Intermediate::AesSboxOutput(index),
+                    Intermediate::AesLastRound(index),
+                    Intermediate::DesSboxOutput(index),
+                ] {
+                    let option = option.with_index(index);
+                    ui.selectable_value(intermediate, option, option.kind());
+                }
+            });
+
+        // DES S-boxes are numbered from 1
+        let (label, first) = match intermediate {
+            Intermediate::DesSboxOutput(_) => ("S-box", 1),
+            _ => ("byte", 0),
+        };
+        let mut shown = intermediate.index() + first;
+        ui.label(label);
+        let range = first..=intermediate.num_indices() - 1 + first;
+        if ui.add(DragValue::new(&mut shown).range(range)).changed() {
+            *intermediate = intermediate.with_index(shown - first);
+        }
+    });
+}
+
+/// Edits the design, band and order of a filter for traces sampled at `sample_rate`
+pub(crate) fn filter_spec_picker(ui: &mut Ui, id: &str, spec: &mut FilterSpec, sample_rate: f64) {
+    let nyquist = sample_rate / 2.0;
+    let (low, high) = match spec.band {
+        FilterBand::LowPass(cutoff) | FilterBand::HighPass(cutoff) => {
+            (cutoff, (2.0 * cutoff).min(nyquist))
+        }
+        FilterBand::BandPass(low, high) | FilterBand::BandStop(low, high) => (low, high),
+    };
+    let family_name = |family: &FilterFamily| match family {
+        FilterFamily::Butterworth => "Butterworth",
+        FilterFamily::Chebyshev { .. } => "Chebyshev",
+        FilterFamily::Fir { .. } => "FIR (windowed sinc)",
+    };
+    let band_name = |band: &FilterBand| match band {
+        FilterBand::LowPass(_) => "Low-pass",
+        FilterBand::HighPass(_) => "High-pass",
+        FilterBand::BandPass(..) => "Band-pass",
+        FilterBand::BandStop(..) => "Band-stop (notch)",
+    };
+
+    ui.horizontal(|ui| {
+        ComboBox::from_id_source((id, "family"))
+            .selected_text(family_name(&spec.family))
+            .show_ui(ui, |ui| {
+                for family in [
+                    FilterFamily::Butterworth,
+                    FilterFamily::Chebyshev { ripple: 1.0 },
+                    FilterFamily::Fir {
+                        window: WindowFunction::Hann,
+                    },
+                ] {
+                    let selected = discriminant(&spec.family) == discriminant(&family);
+                    if ui
+                        .selectable_label(selected, family_name(&family))
+                        .clicked()
+                        && !selected
+                    {
+                        // FIR filters need a much higher order than IIR filters
+                        match (spec.family, family) {
+                            (FilterFamily::Fir { .. }, _) => spec.order = 4,
+                            (_, FilterFamily::Fir { .. }) => spec.order = 100,
+                            _ => {}
+                        }
+                        spec.family = family;
+                    }
+                }
+            });
+
+        ComboBox::from_id_source((id, "band"))
+            .selected_text(band_name(&spec.band))
+            .show_ui(ui, |ui| {
+                for band in [
+                    FilterBand::LowPass(low),
+                    FilterBand::HighPass(low),
+                    FilterBand::BandPass(low, high),
+                    FilterBand::BandStop(low, high),
+                ] {
+                    let selected = discriminant(&spec.band) == discriminant(&band);
+                    if ui.selectable_label(selected, band_name(&band)).clicked() {
+                        spec.band = band;
+                    }
+                }
+            });
+    });
+
+    ui.horizontal(|ui| {
+        let frequency = |ui: &mut Ui, value: &mut f64| {
+            ui.add(
+                DragValue::new(value)
+                    .range(0.0..=nyquist)
+                    .speed(nyquist / 1000.0),
+            );
+        };
+        match &mut spec.band {
+            FilterBand::LowPass(cutoff) | FilterBand::HighPass(cutoff) => {
+                ui.label("Cutoff:");
+                frequency(ui, cutoff);
+            }
+            FilterBand::BandPass(low, high) | FilterBand::BandStop(low, high) => {
+                ui.label("From:");
+                frequency(ui, low);
+                ui.label("to");
+                frequency(ui, high);
+            }
+        }
+
+        ui.label("Order:");
+        match &mut spec.family {
+            FilterFamily::Fir { window } => {
+                ui.add(DragValue::new(&mut spec.order).range(2..=10_000))
+                    .on_hover_text("The filter has one tap more than its order");
+                ComboBox::from_id_source((id, "window"))
+                    .selected_text(window.to_string())
+                    .show_ui(ui, |ui| {
+                        for function in [
+                            WindowFunction::Rectangular,
+                            WindowFunction::Hann,
+                            WindowFunction::Blackman,
+                        ] {
+                            ui.selectable_value(window, function, function.to_string());
+                        }
+                    });
+            }
+            FilterFamily::Chebyshev { ripple } => {
+                ui.add(DragValue::new(&mut spec.order).range(1..=MAX_IIR_ORDER))
+                    .on_hover_text("Band filters have twice as many poles");
+                ui.label("Ripple (dB):");
+                ui.add(DragValue::new(ripple).range(0.01..=10.0).speed(0.01));
+            }
+            FilterFamily::Butterworth => {
+                ui.add(DragValue::new(&mut spec.order).range(1..=MAX_IIR_ORDER))
+                    .on_hover_text("Band filters have twice as many poles");
+            }
+        }
+    });
+}
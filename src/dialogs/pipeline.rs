@@ -0,0 +1,359 @@
+use crate::dialogs::{filter_spec_picker, BackgroundTask};
+use crate::loaders::open_trace_source;
+use crate::math::filter::{FilterBand, FilterFamily, FilterSpec};
+use crate::math::pipeline::{Normalization, Pipeline, Transform};
+use crate::trace_plotter::trace_plotter::TracePlotter;
+use crate::trace_set::{TraceSet, TraceSource};
+use egui::{Button, Color32, ComboBox, Context, DragValue, Grid, ProgressBar, Ui, Window};
+use log::error;
+use rfd::FileDialog;
+use std::ops::Range;
+use std::sync::Arc;
+
+/// Where a finished run put the processed traces
+enum PipelineOutput {
+    Traces(TraceSet),
+    File(String),
+}
+
+/// Change to the list of steps requested by the buttons of one step
+enum StepAction {
+    Up(usize),
+    Down(usize),
+    Remove(usize),
+}
+
+/// Window for building a preprocessing pipeline, saving or loading it and running it on the
+/// traces of a plotter.
+pub struct PipelineDialog {
+    source: Arc<dyn TraceSource>,
+    source_title: String,
+    pipeline: Pipeline,
+    /// Defaults of new alignment and crop steps, from the plotter
+    reference: usize,
+    window: Range<usize>,
+    task: Option<BackgroundTask<Result<PipelineOutput, String>>>,
+    error: Option<String>,
+}
+
+impl PipelineDialog {
+    /// Opens the dialog for the traces of `trace_plotter`, new steps defaulting to its first shown
+    /// trace and its selection
+    pub fn new(trace_plotter: &TracePlotter) -> Self {
+        let source = trace_plotter.source().clone();
+
+        PipelineDialog {
+            source_title: trace_plotter.title().to_string(),
+            pipeline: Pipeline::default(),
+            reference: trace_plotter.selected_plot_range().start,
+            window: trace_plotter
+                .selected_sample_range()
+                .unwrap_or(0..source.num_samples().min(100)),
+            task: None,
+            error: None,
+            source,
+        }
+    }
+
+    pub fn source_title(&self) -> &str {
+        &self.source_title
+    }
+
+    /// Renders the dialog, returning the processed traces once a run has finished.
+    pub fn render(&mut self, ctx: &Context, open: &mut bool) -> Option<Arc<dyn TraceSource>> {
+        let mut result = None;
+
+        Window::new(format!("Pipeline: {}", self.source_title))
+            .open(open)
+            .show(ctx, |ui| {
+                ui.add_enabled_ui(self.task.is_none(), |ui| {
+                    self.render_steps(ui);
+                    ui.separator();
+                    self.render_files(ui);
+                });
+
+                ui.separator();
+
+                if let Some(task) = &self.task {
+                    ui.add(ProgressBar::new(task.progress()).show_percentage());
+                    ctx.request_repaint();
+                } else {
+                    ui.add_enabled_ui(!self.pipeline.steps.is_empty(), |ui| {
+                        ui.horizontal(|ui| {
+                            if ui
+                                .button("Run")
+                                .on_hover_text("Open the processed traces in a new plotter")
+                                .clicked()
+                            {
+                                self.start(None);
+                            }
+                            if ui
+                                .button("Run to file")
+                                .on_hover_text(
+                                    "Stream the processed traces into a chunked container, \
+                                     for trace sets that don't fit in memory",
+                                )
+                                .clicked()
+                            {
+                                if let Some(path) = FileDialog::new()
+                                    .add_filter("chunked", &["sct"])
+                                    .save_file()
+                                {
+                                    self.start(Some(path.to_string_lossy().into_owned()));
+                                }
+                            }
+                        });
+                    });
+                }
+
+                if let Some(error) = &self.error {
+                    ui.colored_label(Color32::RED, error);
+                }
+            });
+
+        if self.task.as_ref().is_some_and(|t| t.is_finished()) {
+            match self.task.take().unwrap().join() {
+                Ok(Ok(PipelineOutput::Traces(traces))) => result = Some(Arc::new(traces) as _),
+                Ok(Ok(PipelineOutput::File(path))) => match open_trace_source(&path) {
+                    Ok(source) => result = Some(source),
+                    Err(e) => {
+                        error!("Failed to open {}: {:?}", path, e);
+                        self.error = Some(e.to_string());
+                    }
+                },
+                Ok(Err(e)) | Err(e) => self.error = Some(e),
+            }
+        }
+
+        result
+    }
+
+    fn render_steps(&mut self, ui: &mut Ui) {
+        let num_samples = self.source.num_samples();
+        let num_traces = self.source.len();
+        let sample_rate = 1.0 / self.source.time().interval;
+        let mut action = None;
+
+        if self.pipeline.steps.is_empty() {
+            ui.label("Add the steps to run on every trace, in order");
+        }
+
+        let num_steps = self.pipeline.steps.len();
+        for (index, step) in self.pipeline.steps.iter_mut().enumerate() {
+            ui.push_id(index, |ui| {
+                ui.horizontal(|ui| {
+                    ui.strong(format!("{}.", index + 1));
+                    if ui.add_enabled(index > 0, Button::new("⬆")).clicked() {
+                        action = Some(StepAction::Up(index));
+                    }
+                    if ui
+                        .add_enabled(index + 1 < num_steps, Button::new("⬇"))
+                        .clicked()
+                    {
+                        action = Some(StepAction::Down(index));
+                    }
+                    if ui.button("🗙").on_hover_text("Remove this step").clicked() {
+                        action = Some(StepAction::Remove(index));
+                    }
+                    ui.label(step.to_string());
+                });
+                ui.indent("step", |ui| {
+                    step_editor(ui, step, num_traces, num_samples, sample_rate);
+                });
+            });
+        }
+
+        match action {
+            Some(StepAction::Up(index)) => self.pipeline.steps.swap(index - 1, index),
+            Some(StepAction::Down(index)) => self.pipeline.steps.swap(index, index + 1),
+            Some(StepAction::Remove(index)) => {
+                self.pipeline.steps.remove(index);
+            }
+            None => {}
+        }
+
+        let defaults = [
+            Transform::Crop {
+                samples: self.window.clone(),
+            },
+            Transform::Filter {
+                spec: FilterSpec {
+                    family: FilterFamily::Butterworth,
+                    band: FilterBand::LowPass(sample_rate / 10.0),
+                    order: 4,
+                },
+            },
+            Transform::StaticAlign {
+                reference: self.reference,
+                window: self.window.clone(),
+                max_shift: 100,
+                threshold: 0.5,
+            },
+            Transform::ElasticAlign {
+                reference: self.reference,
+                window: self.window.clone(),
+                radius: 1,
+            },
+            Transform::Resample { factor: 2 },
+            Transform::Normalize {
+                method: Normalization::ZScore,
+            },
+        ];
+        ComboBox::from_id_source("pipeline_add_step")
+            .selected_text("Add step")
+            .show_ui(ui, |ui| {
+                for step in defaults {
+                    if ui.selectable_label(false, step_name(&step)).clicked() {
+                        self.pipeline.steps.push(step);
+                    }
+                }
+            });
+    }
+
+    fn render_files(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            if ui.button("Load pipeline").clicked() {
+                if let Some(path) = FileDialog::new()
+                    .add_filter("pipeline", &["json"])
+                    .pick_file()
+                {
+                    match Pipeline::load(&path.to_string_lossy()) {
+                        Ok(pipeline) => {
+                            self.pipeline = pipeline;
+                            self.error = None;
+                        }
+                        Err(e) => {
+                            error!("Failed to load the pipeline: {:?}", e);
+                            self.error = Some(e.to_string());
+                        }
+                    }
+                }
+            }
+            if ui.button("Save pipeline").clicked() {
+                if let Some(path) = FileDialog::new()
+                    .add_filter("pipeline", &["json"])
+                    .save_file()
+                {
+                    if let Err(e) = self.pipeline.save(&path.to_string_lossy()) {
+                        error!("Failed to save the pipeline: {:?}", e);
+                        self.error = Some(e.to_string());
+                    }
+                }
+            }
+        });
+    }
+
+    /// Runs the pipeline in the background, into `file_path` if given and in memory otherwise
+    fn start(&mut self, file_path: Option<String>) {
+        let source = self.source.clone();
+        let pipeline = self.pipeline.clone();
+
+        self.error = None;
+        self.task = Some(BackgroundTask::spawn(move |progress| {
+            let mut progress = |fraction| progress.set(fraction);
+            match file_path {
+                Some(path) => pipeline
+                    .run_to_file(source.as_ref(), &path, &mut progress)
+                    .map(|_| PipelineOutput::File(path)),
+                None => pipeline
+                    .run(source.as_ref(), &mut progress)
+                    .map(PipelineOutput::Traces),
+            }
+        }));
+    }
+}
+
+fn step_name(step: &Transform) -> &'static str {
+    match step {
+        Transform::Crop { .. } => "Crop",
+        Transform::Filter { .. } => "Filter",
+        Transform::StaticAlign { .. } => "Static align",
+        Transform::ElasticAlign { .. } => "Elastic align (FastDTW)",
+        Transform::Resample { .. } => "Resample",
+        Transform::Normalize { .. } => "Normalize",
+    }
+}
+
+/// Edits the settings of one step, bounded by the shape of the input traces
+fn step_editor(
+    ui: &mut Ui,
+    step: &mut Transform,
+    num_traces: usize,
+    num_samples: usize,
+    sample_rate: f64,
+) {
+    let window_picker = |ui: &mut Ui, label: &str, window: &mut Range<usize>| {
+        ui.label(label);
+        ui.horizontal(|ui| {
+            ui.add(DragValue::new(&mut window.start).range(0..=num_samples));
+            ui.label("to");
+            ui.add(DragValue::new(&mut window.end).range(0..=num_samples));
+        });
+        ui.end_row();
+    };
+    let reference_picker = |ui: &mut Ui, reference: &mut usize| {
+        ui.label("Reference trace:");
+        ui.add(DragValue::new(reference).range(0..=num_traces.saturating_sub(1)));
+        ui.end_row();
+    };
+
+    match step {
+        Transform::Filter { spec } => {
+            filter_spec_picker(ui, "pipeline_filter", spec, sample_rate);
+        }
+        Transform::Normalize { method } => {
+            ui.horizontal(|ui| {
+                for option in [
+                    Normalization::Mean,
+                    Normalization::ZScore,
+                    Normalization::MinMax,
+                ] {
+                    ui.radio_value(method, option, option.to_string());
+                }
+            });
+        }
+        step => {
+            Grid::new("step_options")
+                .num_columns(2)
+                .show(ui, |ui| match step {
+                    Transform::Crop { samples } => window_picker(ui, "Samples:", samples),
+                    Transform::StaticAlign {
+                        reference,
+                        window,
+                        max_shift,
+                        threshold,
+                    } => {
+                        reference_picker(ui, reference);
+                        window_picker(ui, "Reference window:", window);
+
+                        ui.label("Max shift (samples):");
+                        ui.add(DragValue::new(max_shift).range(0..=num_samples));
+                        ui.end_row();
+
+                        ui.label("Correlation threshold:");
+                        ui.add(DragValue::new(threshold).range(-1.0..=1.0).speed(0.01));
+                        ui.end_row();
+                    }
+                    Transform::ElasticAlign {
+                        reference,
+                        window,
+                        radius,
+                    } => {
+                        reference_picker(ui, reference);
+                        window_picker(ui, "Reference window:", window);
+
+                        ui.label("Radius:");
+                        ui.add(DragValue::new(radius).range(0..=num_samples));
+                        ui.end_row();
+                    }
+                    Transform::Resample { factor } => {
+                        ui.label("Factor:");
+                        ui.add(DragValue::new(factor).range(1..=num_samples.max(1)))
+                            .on_hover_text("Every new sample is the average of this many samples");
+                        ui.end_row();
+                    }
+                    Transform::Filter { .. } | Transform::Normalize { .. } => {}
+                });
+        }
+    }
+}
@@ -0,0 +1,295 @@
+use crate::dialogs::{intermediate_picker, BackgroundTask};
+use crate::math::leakage::{Intermediate, Leakage};
+use crate::math::projection::{lda, pca, project, Projection};
+use crate::trace_plotter::trace_plotter::TracePlotter;
+use crate::trace_set::{TraceSet, TraceSource};
+use egui::{Button, Color32, Context, DragValue, Grid, ProgressBar, Window};
+use log::error;
+use rfd::FileDialog;
+use std::ops::Range;
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Method {
+    Pca,
+    Lda,
+}
+
+/// Window for finding PCA or LDA components of the traces of a plotter and projecting traces
+/// onto them.
+pub struct ProjectionDialog {
+    source: Arc<dyn TraceSource>,
+    source_title: String,
+    window: Range<usize>,
+    method: Method,
+    components: usize,
+    /// Classes of LDA
+    intermediate: Intermediate,
+    leakage: Leakage,
+    fit_task: Option<BackgroundTask<Result<Projection, String>>>,
+    projection: Option<Projection>,
+    project_task: Option<BackgroundTask<Result<TraceSet, String>>>,
+    error: Option<String>,
+}
+
+impl ProjectionDialog {
+    /// Opens the dialog for the traces of `trace_plotter`, using its selection if there is one
+    pub fn new(trace_plotter: &TracePlotter) -> Self {
+        let source = trace_plotter.source().clone();
+
+        ProjectionDialog {
+            source_title: trace_plotter.title().to_string(),
+            window: trace_plotter
+                .selected_sample_range()
+                .unwrap_or(0..source.num_samples()),
+            method: Method::Pca,
+            components: 10,
+            intermediate: Intermediate::AesSboxOutput(0),
+            leakage: Leakage::HammingWeight,
+            fit_task: None,
+            projection: None,
+            project_task: None,
+            error: None,
+            source,
+        }
+    }
+
+    /// Renders the dialog, returning a title and the projected traces or a plot of the
+    /// projection when they should be opened.
+    pub fn render(&mut self, ctx: &Context, open: &mut bool) -> Option<(String, TraceSet)> {
+        let mut plot = None;
+
+        Window::new(format!("PCA / LDA: {}", self.source_title))
+            .open(open)
+            .show(ctx, |ui| {
+                let busy = self.fit_task.is_some() || self.project_task.is_some();
+
+                ui.add_enabled_ui(!busy, |ui| {
+                    self.render_options(ui);
+                });
+
+                if let Some(task) = &self.fit_task {
+                    ui.add(ProgressBar::new(task.progress()).show_percentage());
+                    ctx.request_repaint();
+                } else {
+                    ui.add_enabled_ui(!busy, |ui| {
+                        ui.horizontal(|ui| {
+                            if ui.button("Run").clicked() {
+                                self.start_fit();
+                            }
+                            if ui.button("Load projection").clicked() {
+                                self.load_projection();
+                            }
+                        });
+                    });
+                }
+
+                if self.projection.is_some() {
+                    ui.separator();
+                    plot = self.render_projection(ui);
+                }
+
+                if let Some(task) = &self.project_task {
+                    ui.add(ProgressBar::new(task.progress()).show_percentage());
+                    ctx.request_repaint();
+                }
+
+                if let Some(error) = &self.error {
+                    ui.colored_label(Color32::RED, error);
+                }
+            });
+
+        if self.fit_task.as_ref().is_some_and(|t| t.is_finished()) {
+            match self.fit_task.take().unwrap().join() {
+                Ok(Ok(projection)) => {
+                    match projection.explained_variance() {
+                        Ok(variance) => plot = Some((self.plot_title(&projection), variance)),
+                        Err(e) => self.error = Some(e),
+                    }
+                    self.projection = Some(projection);
+                }
+                Ok(Err(e)) | Err(e) => self.error = Some(e),
+            }
+        }
+
+        if self.project_task.as_ref().is_some_and(|t| t.is_finished()) {
+            match self.project_task.take().unwrap().join() {
+                Ok(Ok(projected)) => {
+                    let method = self.projection.as_ref().map_or("", |p| p.method.as_str());
+                    plot = Some((format!("{} {}", method, self.source_title), projected));
+                }
+                Ok(Err(e)) | Err(e) => self.error = Some(e),
+            }
+        }
+
+        plot
+    }
+
+    fn render_options(&mut self, ui: &mut egui::Ui) {
+        let num_samples = self.source.num_samples();
+
+        Grid::new("projection_options")
+            .num_columns(2)
+            .show(ui, |ui| {
+                ui.label("Samples:");
+                ui.horizontal(|ui| {
+                    ui.add(DragValue::new(&mut self.window.start).range(0..=num_samples));
+                    ui.label("to");
+                    ui.add(DragValue::new(&mut self.window.end).range(0..=num_samples));
+                });
+                ui.end_row();
+
+                ui.label("Method:");
+                ui.horizontal(|ui| {
+                    ui.radio_value(&mut self.method, Method::Pca, "PCA")
+                        .on_hover_text("Directions of largest variance");
+                    ui.radio_value(&mut self.method, Method::Lda, "LDA")
+                        .on_hover_text("Directions separating the classes of an intermediate");
+                });
+                ui.end_row();
+
+                ui.label("Components:");
+                ui.add(DragValue::new(&mut self.components).range(1..=num_samples.max(1)));
+                ui.end_row();
+
+                if self.method == Method::Lda {
+                    ui.label("Classes:");
+                    intermediate_picker(ui, "projection_intermediate", &mut self.intermediate);
+                    ui.end_row();
+
+                    ui.label("Leakage:");
+                    ui.horizontal(|ui| {
+                        ui.radio_value(&mut self.leakage, Leakage::HammingWeight, "Hamming weight");
+                        ui.radio_value(&mut self.leakage, Leakage::Identity, "Value");
+                    });
+                    ui.end_row();
+                }
+            });
+    }
+
+    fn render_projection(&mut self, ui: &mut egui::Ui) -> Option<(String, TraceSet)> {
+        let projection = self.projection.as_ref()?;
+        let mut plot = None;
+        let mut error = None;
+
+        let window = projection.window();
+        let explained: f64 = projection.eigenvalues.iter().sum::<f64>() / projection.total;
+        ui.label(format!(
+            "{}: {} components of samples {} to {}, explaining {:.1}%",
+            projection.method,
+            projection.components.len(),
+            window.start,
+            window.end,
+            explained * 100.0
+        ));
+
+        ui.horizontal(|ui| {
+            if ui.button("Plot explained variance").clicked() {
+                match projection.explained_variance() {
+                    Ok(variance) => plot = Some((self.plot_title(projection), variance)),
+                    Err(e) => error = Some(e),
+                }
+            }
+            if ui.button("Plot components").clicked() {
+                match projection.component_traces(self.source.time()) {
+                    Ok(components) => {
+                        let title =
+                            format!("{} components {}", projection.method, self.source_title);
+                        plot = Some((title, components));
+                    }
+                    Err(e) => error = Some(e),
+                }
+            }
+            if ui.button("Save projection").clicked() {
+                if let Some(path) = FileDialog::new()
+                    .add_filter("projection", &["proj"])
+                    .save_file()
+                {
+                    if let Err(e) = projection.save(&path.to_string_lossy()) {
+                        error!("Failed to save the projection: {:?}", e);
+                        error = Some(e.to_string());
+                    }
+                }
+            }
+        });
+
+        let project_clicked = ui
+            .add_enabled(self.project_task.is_none(), Button::new("Project traces"))
+            .on_hover_text("Open the traces of this plotter projected onto the components")
+            .clicked();
+
+        if error.is_some() {
+            self.error = error;
+        }
+        if project_clicked {
+            self.start_project();
+        }
+
+        plot
+    }
+
+    fn plot_title(&self, projection: &Projection) -> String {
+        format!(
+            "{} explained variance {}",
+            projection.method, self.source_title
+        )
+    }
+
+    fn load_projection(&mut self) {
+        let Some(path) = FileDialog::new()
+            .add_filter("projection", &["proj"])
+            .pick_file()
+        else {
+            return;
+        };
+        match Projection::load(&path.to_string_lossy()) {
+            Ok(projection) => {
+                self.window = projection.window();
+                self.projection = Some(projection);
+                self.error = None;
+            }
+            Err(e) => {
+                error!("Failed to load the projection: {:?}", e);
+                self.error = Some(e.to_string());
+            }
+        }
+    }
+
+    fn start_project(&mut self) {
+        let Some(projection) = self.projection.clone() else {
+            return;
+        };
+        let source = self.source.clone();
+
+        self.error = None;
+        self.project_task = Some(BackgroundTask::spawn(move |progress| {
+            project(source.as_ref(), &projection, &mut |fraction| {
+                progress.set(fraction)
+            })
+        }));
+    }
+
+    fn start_fit(&mut self) {
+        let source = self.source.clone();
+        let window = self.window.clone();
+        let components = self.components;
+        let method = self.method;
+        let model = self.intermediate.model(self.leakage);
+
+        self.error = None;
+        self.projection = None;
+        self.fit_task = Some(BackgroundTask::spawn(move |progress| {
+            let mut progress = |fraction| progress.set(fraction);
+            match method {
+                Method::Pca => pca(source.as_ref(), window, components, &mut progress),
+                Method::Lda => lda(
+                    source.as_ref(),
+                    window,
+                    model.as_ref(),
+                    components,
+                    &mut progress,
+                ),
+            }
+        }));
+    }
+}
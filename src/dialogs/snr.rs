@@ -0,0 +1,272 @@
+use crate::dialogs::BackgroundTask;
+use crate::math::cpa::KEY_BYTES;
+use crate::math::snr::{snr, top_peaks, write_poi_csv, PointOfInterest, SnrClass, SnrResult};
+use crate::trace_plotter::trace_plotter::TracePlotter;
+use crate::trace_set::{TraceSet, TraceSource};
+use egui::{Color32, ComboBox, Context, DragValue, Grid, ProgressBar, ScrollArea, Window};
+use log::error;
+use rfd::FileDialog;
+use std::ops::Range;
+use std::sync::Arc;
+
+/// Window for finding points of interest with the SNR and NICV of the traces of a plotter.
+pub struct SnrDialog {
+    source: Arc<dyn TraceSource>,
+    source_title: String,
+    window: Range<usize>,
+    class: SnrClass,
+    /// First sample of the window the result was computed on
+    result_offset: usize,
+    /// Classes the result was computed with
+    result_class: SnrClass,
+    task: Option<BackgroundTask<Result<SnrResult, String>>>,
+    result: Option<SnrResult>,
+    num_pois: usize,
+    min_distance: usize,
+    /// Highest SNR peaks of the result, for the current POI settings
+    pois: Vec<PointOfInterest>,
+    error: Option<String>,
+}
+
+impl SnrDialog {
+    /// Opens the dialog for the traces of `trace_plotter`, analysing its selection if there is one
+    pub fn new(trace_plotter: &TracePlotter) -> Self {
+        let source = trace_plotter.source().clone();
+
+        SnrDialog {
+            source_title: trace_plotter.title().to_string(),
+            window: trace_plotter
+                .selected_sample_range()
+                .unwrap_or(0..source.num_samples()),
+            class: SnrClass::SboxOutput(0),
+            result_offset: 0,
+            result_class: SnrClass::SboxOutput(0),
+            task: None,
+            result: None,
+            num_pois: 10,
+            min_distance: 5,
+            pois: vec![],
+            error: None,
+            source,
+        }
+    }
+
+    /// Renders the dialog, returning the title and SNR or NICV trace when it should be plotted.
+    pub fn render(&mut self, ctx: &Context, open: &mut bool) -> Option<(String, TraceSet)> {
+        let mut plot = None;
+
+        Window::new(format!("SNR: {}", self.source_title))
+            .open(open)
+            .show(ctx, |ui| {
+                ui.add_enabled_ui(self.task.is_none(), |ui| {
+                    self.render_options(ui);
+                });
+
+                ui.separator();
+
+                if let Some(task) = &self.task {
+                    ui.add(ProgressBar::new(task.progress()).show_percentage());
+                    ctx.request_repaint();
+                } else if ui.button("Run").clicked() {
+                    self.start_snr();
+                }
+
+                if let Some(error) = &self.error {
+                    ui.colored_label(Color32::RED, error);
+                }
+
+                if self.result.is_some() {
+                    plot = self.render_result(ui);
+                }
+            });
+
+        if self.task.as_ref().is_some_and(|t| t.is_finished()) {
+            match self.task.take().unwrap().join() {
+                Ok(Ok(result)) => {
+                    plot = Some((self.plot_title("SNR"), result.snr.clone()));
+                    self.result = Some(result);
+                    self.update_pois();
+                }
+                Ok(Err(e)) | Err(e) => self.error = Some(e),
+            }
+        }
+
+        plot
+    }
+
+    fn render_options(&mut self, ui: &mut egui::Ui) {
+        let num_samples = self.source.num_samples();
+
+        Grid::new("snr_options").num_columns(2).show(ui, |ui| {
+            ui.label("Samples:");
+            ui.horizontal(|ui| {
+                ui.add(DragValue::new(&mut self.window.start).range(0..=num_samples));
+                ui.label("to");
+                ui.add(DragValue::new(&mut self.window.end).range(0..=num_samples));
+            });
+            ui.end_row();
+
+            let byte = match self.class {
+                SnrClass::PlaintextByte(byte)
+                | SnrClass::CiphertextByte(byte)
+                | SnrClass::SboxOutput(byte)
+                | SnrClass::SboxOutputHammingWeight(byte) => byte,
+            };
+
+            ui.label("Classes:");
+            ui.horizontal(|ui| {
+                ComboBox::from_id_source("snr_class")
+                    .selected_text(class_name(&self.class))
+                    .show_ui(ui, |ui| {
+                        for class in [
+                            SnrClass::PlaintextByte(byte),
+                            SnrClass::CiphertextByte(byte),
+                            SnrClass::SboxOutput(byte),
+                            SnrClass::SboxOutputHammingWeight(byte),
+                        ] {
+                            ui.selectable_value(&mut self.class, class, class_name(&class));
+                        }
+                    });
+
+                let mut byte = byte;
+                ui.label("byte");
+                if ui
+                    .add(DragValue::new(&mut byte).range(0..=KEY_BYTES - 1))
+                    .changed()
+                {
+                    self.class = match self.class {
+                        SnrClass::PlaintextByte(_) => SnrClass::PlaintextByte(byte),
+                        SnrClass::CiphertextByte(_) => SnrClass::CiphertextByte(byte),
+                        SnrClass::SboxOutput(_) => SnrClass::SboxOutput(byte),
+                        SnrClass::SboxOutputHammingWeight(_) => {
+                            SnrClass::SboxOutputHammingWeight(byte)
+                        }
+                    };
+                }
+            })
+            .response
+            .on_hover_text("S-box classes use the key stored with the traces");
+            ui.end_row();
+        });
+    }
+
+    fn render_result(&mut self, ui: &mut egui::Ui) -> Option<(String, TraceSet)> {
+        let result = self.result.as_ref()?;
+        let mut plot = None;
+
+        ui.separator();
+        let counts = &result.counts;
+        ui.label(format!(
+            "{} traces in {} classes",
+            counts.iter().sum::<u64>(),
+            counts.iter().filter(|&&count| count > 0).count()
+        ));
+
+        ui.horizontal(|ui| {
+            if ui.button("Plot SNR").clicked() {
+                plot = Some((self.plot_title("SNR"), result.snr.clone()));
+            }
+            if ui.button("Plot NICV").clicked() {
+                plot = Some((self.plot_title("NICV"), result.nicv.clone()));
+            }
+        });
+
+        ui.separator();
+
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            ui.label("Points of interest:");
+            changed |= ui
+                .add(DragValue::new(&mut self.num_pois).range(1..=1000))
+                .changed();
+            ui.label("at least");
+            changed |= ui
+                .add(DragValue::new(&mut self.min_distance).range(1..=10000))
+                .changed();
+            ui.label("samples apart");
+        });
+        if changed {
+            self.update_pois();
+        }
+
+        ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+            Grid::new("snr_pois").striped(true).show(ui, |ui| {
+                ui.strong("Sample");
+                ui.strong("Time");
+                ui.strong("SNR");
+                ui.end_row();
+
+                let time = self.source.time();
+                for poi in &self.pois {
+                    let sample = self.result_offset + poi.sample;
+                    ui.label(sample.to_string());
+                    ui.label(format!("{:.6e}", time.x(sample)));
+                    ui.label(format!("{:.4}", poi.value));
+                    ui.end_row();
+                }
+            });
+        });
+
+        if ui.button("Export POIs").clicked() {
+            if let Some(path) = FileDialog::new().add_filter("csv", &["csv"]).save_file() {
+                let time = self.source.time();
+                let path = path.to_string_lossy();
+                if let Err(e) = write_poi_csv(&self.pois, self.result_offset, &time, &path) {
+                    error!("Failed to write the points of interest: {:?}", e);
+                    self.error = Some(e.to_string());
+                }
+            }
+        }
+
+        plot
+    }
+
+    fn update_pois(&mut self) {
+        self.pois = match &self.result {
+            Some(result) => top_peaks(result.snr.trace(0), self.num_pois, self.min_distance),
+            None => vec![],
+        };
+    }
+
+    fn plot_title(&self, name: &str) -> String {
+        let byte = match self.result_class {
+            SnrClass::PlaintextByte(byte)
+            | SnrClass::CiphertextByte(byte)
+            | SnrClass::SboxOutput(byte)
+            | SnrClass::SboxOutputHammingWeight(byte) => byte,
+        };
+        format!(
+            "{} {} {} byte {}",
+            name,
+            self.source_title,
+            class_name(&self.result_class),
+            byte
+        )
+    }
+
+    fn start_snr(&mut self) {
+        let source = self.source.clone();
+        let window = self.window.clone();
+        let class = self.class;
+
+        self.result_offset = window.start;
+        self.result_class = class;
+        self.error = None;
+        self.result = None;
+        self.pois.clear();
+        self.task = Some(BackgroundTask::spawn(move |progress| {
+            snr(source.as_ref(), window, class, &mut |fraction| {
+                progress.set(fraction)
+            })
+        }));
+    }
+}
+
+fn class_name(class: &SnrClass) -> &'static str {
+    match class {
+        SnrClass::PlaintextByte(_) => "Plaintext",
+        SnrClass::CiphertextByte(_) => "Ciphertext",
+        SnrClass::SboxOutput(_) => "S-box output",
+        SnrClass::SboxOutputHammingWeight(_) => "S-box output HW",
+    }
+}
@@ -0,0 +1,181 @@
+use crate::dialogs::BackgroundTask;
+use crate::math::fft::{average_spectrum, spectra, WindowFunction};
+use crate::trace_plotter::trace_plotter::TracePlotter;
+use crate::trace_set::{TraceSet, TraceSource};
+use egui::{Color32, ComboBox, Context, DragValue, Grid, ProgressBar, Window};
+use std::ops::Range;
+use std::sync::Arc;
+
+/// Window for the frequency spectra of the traces shown in a plotter. The spectra open in a
+/// plotter of their own, over the frequency.
+pub struct SpectrumDialog {
+    source: Arc<dyn TraceSource>,
+    source_title: String,
+    traces: Range<usize>,
+    window: Range<usize>,
+    function: WindowFunction,
+    decibels: bool,
+    /// Title of the plot the running task computes
+    task_title: String,
+    task: Option<BackgroundTask<Result<TraceSet, String>>>,
+    error: Option<String>,
+}
+
+impl SpectrumDialog {
+    /// Opens the dialog for the traces shown in `trace_plotter`, cropped to its selection if there
+    /// is one
+    pub fn new(trace_plotter: &TracePlotter) -> Self {
+        let source = trace_plotter.source().clone();
+
+        SpectrumDialog {
+            source_title: trace_plotter.title().to_string(),
+            traces: trace_plotter.selected_plot_range(),
+            window: trace_plotter
+                .selected_sample_range()
+                .unwrap_or(0..source.num_samples()),
+            function: WindowFunction::Hann,
+            decibels: true,
+            task_title: String::new(),
+            task: None,
+            error: None,
+            source,
+        }
+    }
+
+    /// Renders the dialog, returning the title and spectra when they should be plotted.
+    pub fn render(&mut self, ctx: &Context, open: &mut bool) -> Option<(String, TraceSet)> {
+        let mut plot = None;
+
+        Window::new(format!("Spectrum: {}", self.source_title))
+            .open(open)
+            .show(ctx, |ui| {
+                ui.add_enabled_ui(self.task.is_none(), |ui| {
+                    self.render_options(ui);
+                });
+
+                ui.separator();
+
+                if let Some(task) = &self.task {
+                    ui.add(ProgressBar::new(task.progress()).show_percentage());
+                    ctx.request_repaint();
+                } else {
+                    ui.horizontal(|ui| {
+                        if ui
+                            .button("Plot spectra")
+                            .on_hover_text("One spectrum per trace")
+                            .clicked()
+                        {
+                            self.start(false);
+                        }
+                        if ui
+                            .button("Plot average")
+                            .on_hover_text("Mean power spectrum of the traces")
+                            .clicked()
+                        {
+                            self.start(true);
+                        }
+                    });
+                }
+
+                if let Some(error) = &self.error {
+                    ui.colored_label(Color32::RED, error);
+                }
+            });
+
+        if self.task.as_ref().is_some_and(|t| t.is_finished()) {
+            match self.task.take().unwrap().join() {
+                Ok(Ok(spectra)) => plot = Some((self.task_title.clone(), spectra)),
+                Ok(Err(e)) | Err(e) => self.error = Some(e),
+            }
+        }
+
+        plot
+    }
+
+    fn render_options(&mut self, ui: &mut egui::Ui) {
+        let num_samples = self.source.num_samples();
+        let num_traces = self.source.len();
+
+        Grid::new("spectrum_options").num_columns(2).show(ui, |ui| {
+            ui.label("Traces:");
+            ui.horizontal(|ui| {
+                ui.add(DragValue::new(&mut self.traces.start).range(0..=num_traces));
+                ui.label("to");
+                ui.add(DragValue::new(&mut self.traces.end).range(0..=num_traces));
+            });
+            ui.end_row();
+
+            ui.label("Samples:");
+            ui.horizontal(|ui| {
+                ui.add(DragValue::new(&mut self.window.start).range(0..=num_samples));
+                ui.label("to");
+                ui.add(DragValue::new(&mut self.window.end).range(0..=num_samples));
+            });
+            ui.end_row();
+
+            ui.label("Window:");
+            ComboBox::from_id_source("spectrum_window")
+                .selected_text(self.function.to_string())
+                .show_ui(ui, |ui| {
+                    for function in [
+                        WindowFunction::Rectangular,
+                        WindowFunction::Hann,
+                        WindowFunction::Blackman,
+                    ] {
+                        ui.selectable_value(&mut self.function, function, function.to_string());
+                    }
+                });
+            ui.end_row();
+
+            ui.label("Magnitude:");
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut self.decibels, true, "Power (dB)");
+                ui.radio_value(&mut self.decibels, false, "Amplitude");
+            });
+            ui.end_row();
+        });
+
+        let frequency = 1.0 / self.source.time().interval;
+        ui.label(format!(
+            "Sample rate {:.4e}, resolution {:.4e}",
+            frequency,
+            frequency / self.window.len().max(1) as f64
+        ));
+    }
+
+    fn start(&mut self, average: bool) {
+        let source = self.source.clone();
+        let (traces, window) = (self.traces.clone(), self.window.clone());
+        let (function, decibels) = (self.function, self.decibels);
+
+        let kind = if average {
+            "Average spectrum"
+        } else {
+            "Spectra"
+        };
+        self.task_title = format!("{} {} {}", kind, function, self.source_title);
+        self.error = None;
+        self.task = Some(BackgroundTask::spawn(move |progress| {
+            let mut progress = |fraction| progress.set(fraction);
+            if average {
+                average_spectrum(
+                    source.as_ref(),
+                    traces,
+                    window,
+                    function,
+                    decibels,
+                    &mut progress,
+                )
+            } else {
+                spectra(
+                    source.as_ref(),
+                    traces,
+                    window,
+                    function,
+                    decibels,
+                    &mut progress,
+                )
+            }
+        }));
+    }
+}
@@ -0,0 +1,327 @@
+use crate::dialogs::{intermediate_picker, BackgroundTask};
+use crate::math::leakage::{Intermediate, Leakage};
+use crate::math::snr::read_poi_csv;
+use crate::math::template::{build_templates, match_templates, TemplateResult, Templates};
+use crate::trace_plotter::trace_plotter::TracePlotter;
+use crate::trace_set::TraceSource;
+use egui::{Color32, ComboBox, Context, Grid, ProgressBar, RichText, Window};
+use log::error;
+use rfd::FileDialog;
+use std::sync::Arc;
+
+/// Number of ranked guesses shown
+const SHOWN_CANDIDATES: usize = 10;
+
+/// Window for building Gaussian templates on a profiling set and matching them on an attack set.
+pub struct TemplateDialog {
+    profiling: Arc<dyn TraceSource>,
+    profiling_title: String,
+    /// Every open trace set the templates can be matched on
+    attack_sets: Vec<(String, Arc<dyn TraceSource>)>,
+    attack_set: usize,
+    /// Sample indices of the points of interest, separated by commas
+    pois_text: String,
+    intermediate: Intermediate,
+    leakage: Leakage,
+    build_task: Option<BackgroundTask<Result<Templates, String>>>,
+    templates: Option<Templates>,
+    match_task: Option<BackgroundTask<Result<TemplateResult, String>>>,
+    result: Option<TemplateResult>,
+    ranking: Vec<(usize, f64)>,
+    error: Option<String>,
+}
+
+impl TemplateDialog {
+    /// Opens the dialog with the traces of `trace_plotter` as profiling set and any of
+    /// `attack_sets` as attack set
+    pub fn new(
+        trace_plotter: &TracePlotter,
+        attack_sets: Vec<(String, Arc<dyn TraceSource>)>,
+    ) -> Self {
+        let pois_text = trace_plotter
+            .selected_sample_range()
+            .map(|range| {
+                range
+                    .map(|sample| sample.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            })
+            .unwrap_or_default();
+
+        TemplateDialog {
+            profiling: trace_plotter.source().clone(),
+            profiling_title: trace_plotter.title().to_string(),
+            attack_sets,
+            attack_set: 0,
+            pois_text,
+            intermediate: Intermediate::AesSboxOutput(0),
+            leakage: Leakage::Identity,
+            build_task: None,
+            templates: None,
+            match_task: None,
+            result: None,
+            ranking: vec![],
+            error: None,
+        }
+    }
+
+    pub fn render(&mut self, ctx: &Context, open: &mut bool) {
+        Window::new(format!("Template attack: {}", self.profiling_title))
+            .open(open)
+            .show(ctx, |ui| {
+                let busy = self.build_task.is_some() || self.match_task.is_some();
+
+                ui.strong("Profiling");
+                ui.add_enabled_ui(!busy, |ui| {
+                    self.render_profiling(ui);
+                });
+                if let Some(task) = &self.build_task {
+                    ui.add(ProgressBar::new(task.progress()).show_percentage());
+                    ctx.request_repaint();
+                }
+
+                if self.templates.is_some() {
+                    ui.separator();
+                    ui.strong("Attack");
+                    ui.add_enabled_ui(!busy, |ui| {
+                        self.render_attack(ui);
+                    });
+                    if let Some(task) = &self.match_task {
+                        ui.add(ProgressBar::new(task.progress()).show_percentage());
+                        ctx.request_repaint();
+                    }
+                }
+
+                if self.result.is_some() {
+                    self.render_result(ui);
+                }
+
+                if let Some(error) = &self.error {
+                    ui.colored_label(Color32::RED, error);
+                }
+            });
+
+        if self.build_task.as_ref().is_some_and(|t| t.is_finished()) {
+            match self.build_task.take().unwrap().join() {
+                Ok(Ok(templates)) => self.templates = Some(templates),
+                Ok(Err(e)) | Err(e) => self.error = Some(e),
+            }
+        }
+
+        if self.match_task.as_ref().is_some_and(|t| t.is_finished()) {
+            match self.match_task.take().unwrap().join() {
+                Ok(Ok(result)) => {
+                    self.ranking = result.ranking();
+                    self.result = Some(result);
+                }
+                Ok(Err(e)) | Err(e) => self.error = Some(e),
+            }
+        }
+    }
+
+    fn render_profiling(&mut self, ui: &mut egui::Ui) {
+        Grid::new("template_profiling")
+            .num_columns(2)
+            .show(ui, |ui| {
+                ui.label("Points of interest:");
+                ui.horizontal(|ui| {
+                    ui.text_edit_singleline(&mut self.pois_text)
+                        .on_hover_text("Sample indices, separated by commas");
+                    if ui
+                        .button("Load")
+                        .on_hover_text("Read the samples of a POI list exported from the SNR")
+                        .clicked()
+                    {
+                        self.load_pois();
+                    }
+                });
+                ui.end_row();
+
+                ui.label("Classes:");
+                intermediate_picker(ui, "template_intermediate", &mut self.intermediate);
+                ui.end_row();
+
+                ui.label("Leakage:");
+                ui.horizontal(|ui| {
+                    ui.radio_value(&mut self.leakage, Leakage::Identity, "Value");
+                    ui.radio_value(&mut self.leakage, Leakage::HammingWeight, "Hamming weight");
+                });
+                ui.end_row();
+            });
+
+        ui.horizontal(|ui| {
+            if ui.button("Build templates").clicked() {
+                self.start_build();
+            }
+            if ui.button("Load templates").clicked() {
+                if let Some(path) = FileDialog::new()
+                    .add_filter("templates", &["tpl"])
+                    .pick_file()
+                {
+                    match Templates::load(&path.to_string_lossy()) {
+                        Ok(templates) => {
+                            self.pois_text = poi_text(&templates.pois);
+                            self.templates = Some(templates);
+                            self.result = None;
+                            self.error = None;
+                        }
+                        Err(e) => {
+                            error!("Failed to load templates: {:?}", e);
+                            self.error = Some(e.to_string());
+                        }
+                    }
+                }
+            }
+            if let Some(templates) = &self.templates {
+                if ui.button("Save templates").clicked() {
+                    if let Some(path) = FileDialog::new()
+                        .add_filter("templates", &["tpl"])
+                        .save_file()
+                    {
+                        if let Err(e) = templates.save(&path.to_string_lossy()) {
+                            error!("Failed to save templates: {:?}", e);
+                            self.error = Some(e.to_string());
+                        }
+                    }
+                }
+            }
+        });
+
+        if let Some(templates) = &self.templates {
+            ui.label(format!(
+                "Templates of {} classes of {} on {} points of interest, from {} traces",
+                templates.num_classes(),
+                templates.model,
+                templates.pois.len(),
+                templates.counts.iter().sum::<u64>()
+            ));
+        }
+    }
+
+    fn render_attack(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Attack traces:");
+            let selected = self
+                .attack_sets
+                .get(self.attack_set)
+                .map_or("", |(title, _)| title.as_str());
+            ComboBox::from_id_source("template_attack_set")
+                .selected_text(selected)
+                .show_ui(ui, |ui| {
+                    for (index, (title, _)) in self.attack_sets.iter().enumerate() {
+                        ui.selectable_value(&mut self.attack_set, index, title);
+                    }
+                });
+
+            if ui.button("Match").clicked() {
+                self.start_match();
+            }
+        });
+    }
+
+    fn render_result(&self, ui: &mut egui::Ui) {
+        let Some(result) = &self.result else {
+            return;
+        };
+
+        ui.separator();
+        ui.label(format!("{} attack traces", result.num_traces));
+
+        Grid::new("template_ranking").striped(true).show(ui, |ui| {
+            ui.strong("Rank");
+            ui.strong("Guess");
+            ui.strong("Log-likelihood");
+            ui.end_row();
+
+            for (rank, &(guess, log_likelihood)) in
+                self.ranking.iter().take(SHOWN_CANDIDATES).enumerate()
+            {
+                let text = RichText::new(format!("{:02X}", guess)).monospace();
+                let text = if result.known_guess == Some(guess) {
+                    text.color(Color32::GREEN)
+                } else {
+                    text
+                };
+
+                ui.label((rank + 1).to_string());
+                ui.label(text);
+                ui.label(format!("{:.2}", log_likelihood));
+                ui.end_row();
+            }
+        });
+
+        if let Some(known_guess) = result.known_guess {
+            if let Some(rank) = self.ranking.iter().position(|&(g, _)| g == known_guess) {
+                ui.label(format!("Known key {:02X} ranks {}", known_guess, rank + 1));
+            }
+        }
+    }
+
+    fn load_pois(&mut self) {
+        let Some(path) = FileDialog::new().add_filter("csv", &["csv"]).pick_file() else {
+            return;
+        };
+        match read_poi_csv(&path.to_string_lossy()) {
+            Ok(pois) => self.pois_text = poi_text(&pois),
+            Err(e) => {
+                error!("Failed to read the points of interest: {:?}", e);
+                self.error = Some(e.to_string());
+            }
+        }
+    }
+
+    fn start_build(&mut self) {
+        let pois: Result<Vec<usize>, _> = self
+            .pois_text
+            .split(',')
+            .filter(|poi| !poi.trim().is_empty())
+            .map(|poi| poi.trim().parse::<usize>())
+            .collect();
+        let Ok(pois) = pois else {
+            self.error = Some(format!("'{}' are no sample indices", self.pois_text));
+            return;
+        };
+
+        let source = self.profiling.clone();
+        let model = self.intermediate.model(self.leakage);
+
+        self.error = None;
+        self.templates = None;
+        self.result = None;
+        self.build_task = Some(BackgroundTask::spawn(move |progress| {
+            build_templates(source.as_ref(), &pois, model.as_ref(), &mut |fraction| {
+                progress.set(fraction)
+            })
+        }));
+    }
+
+    fn start_match(&mut self) {
+        let (Some(templates), Some((_, source))) = (
+            self.templates.clone(),
+            self.attack_sets.get(self.attack_set),
+        ) else {
+            return;
+        };
+        let source = source.clone();
+        let model = self.intermediate.model(self.leakage);
+
+        self.error = None;
+        self.result = None;
+        self.ranking.clear();
+        self.match_task = Some(BackgroundTask::spawn(move |progress| {
+            match_templates(
+                source.as_ref(),
+                &templates,
+                model.as_ref(),
+                &mut |fraction| progress.set(fraction),
+            )
+        }));
+    }
+}
+
+fn poi_text(pois: &[usize]) -> String {
+    pois.iter()
+        .map(|poi| poi.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
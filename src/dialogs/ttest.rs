@@ -0,0 +1,198 @@
+use crate::dialogs::BackgroundTask;
+use crate::math::ttest::{distinct_labels, welch_t_test, TTestResult, TVLA_THRESHOLD};
+use crate::trace_plotter::trace_plotter::TracePlotter;
+use crate::trace_set::{TraceSet, TraceSource};
+use egui::{Color32, ComboBox, Context, DragValue, Grid, ProgressBar, Spinner, Window};
+use std::ops::Range;
+use std::sync::Arc;
+
+/// Highest order offered for univariate higher-order t-tests
+const MAX_ORDER: usize = 3;
+
+/// Window for a TVLA t-test between two groups of traces, chosen by their labels.
+pub struct TTestDialog {
+    source: Arc<dyn TraceSource>,
+    source_title: String,
+    window: Range<usize>,
+    labels: Vec<String>,
+    labels_task: Option<BackgroundTask<Result<Vec<String>, String>>>,
+    first_label: String,
+    second_label: String,
+    max_order: usize,
+    task: Option<BackgroundTask<Result<TTestResult, String>>>,
+    result: Option<TTestResult>,
+    error: Option<String>,
+}
+
+impl TTestDialog {
+    /// Opens the dialog for the traces of `trace_plotter`, testing its selection if there is one
+    pub fn new(trace_plotter: &TracePlotter) -> Self {
+        let source = trace_plotter.source().clone();
+        let labels_source = source.clone();
+
+        TTestDialog {
+            source_title: trace_plotter.title().to_string(),
+            window: trace_plotter
+                .selected_sample_range()
+                .unwrap_or(0..source.num_samples()),
+            labels: vec![],
+            labels_task: Some(BackgroundTask::spawn(move |_| {
+                distinct_labels(labels_source.as_ref())
+            })),
+            first_label: "fixed".to_string(),
+            second_label: "random".to_string(),
+            max_order: 1,
+            task: None,
+            result: None,
+            error: None,
+            source,
+        }
+    }
+
+    /// Renders the dialog, returning the title and t-trace when it should be plotted.
+    pub fn render(&mut self, ctx: &Context, open: &mut bool) -> Option<(String, TraceSet)> {
+        let mut plot = None;
+
+        Window::new(format!("Welch t-test: {}", self.source_title))
+            .open(open)
+            .show(ctx, |ui| {
+                ui.add_enabled_ui(self.task.is_none(), |ui| {
+                    self.render_options(ui);
+                });
+
+                ui.separator();
+
+                if let Some(task) = &self.task {
+                    ui.add(ProgressBar::new(task.progress()).show_percentage());
+                    ctx.request_repaint();
+                } else if ui.button("Run").clicked() {
+                    self.start_test();
+                }
+
+                if let Some(error) = &self.error {
+                    ui.colored_label(Color32::RED, error);
+                }
+
+                if let Some(result) = &self.result {
+                    ui.separator();
+                    ui.label(format!(
+                        "'{}': {} traces, '{}': {} traces",
+                        self.first_label, result.counts[0], self.second_label, result.counts[1]
+                    ));
+
+                    for order in 1..=result.t.len() {
+                        let leaking = result.leaking_samples(order, TVLA_THRESHOLD);
+                        let text = format!(
+                            "Order {}: max |t| {:.2}, {} samples exceed |t| > {}",
+                            order,
+                            result.max_abs_t(order),
+                            leaking,
+                            TVLA_THRESHOLD
+                        );
+                        let color = if leaking > 0 {
+                            Color32::RED
+                        } else {
+                            Color32::GREEN
+                        };
+                        ui.colored_label(color, text);
+                    }
+
+                    if ui.button("Plot").clicked() {
+                        plot = Some((self.plot_title(), result.t.clone()));
+                    }
+                }
+            });
+
+        if self.labels_task.as_ref().is_some_and(|t| t.is_finished()) {
+            match self.labels_task.take().unwrap().join() {
+                Ok(Ok(labels)) => self.labels = labels,
+                Ok(Err(e)) | Err(e) => self.error = Some(e),
+            }
+        }
+
+        if self.task.as_ref().is_some_and(|t| t.is_finished()) {
+            match self.task.take().unwrap().join() {
+                Ok(Ok(result)) => {
+                    plot = Some((self.plot_title(), result.t.clone()));
+                    self.result = Some(result);
+                }
+                Ok(Err(e)) | Err(e) => self.error = Some(e),
+            }
+        }
+
+        plot
+    }
+
+    fn render_options(&mut self, ui: &mut egui::Ui) {
+        let num_samples = self.source.num_samples();
+
+        Grid::new("ttest_options").num_columns(2).show(ui, |ui| {
+            ui.label("Samples:");
+            ui.horizontal(|ui| {
+                ui.add(DragValue::new(&mut self.window.start).range(0..=num_samples));
+                ui.label("to");
+                ui.add(DragValue::new(&mut self.window.end).range(0..=num_samples));
+            });
+            ui.end_row();
+
+            for (name, label) in [
+                ("First group label:", &mut self.first_label),
+                ("Second group label:", &mut self.second_label),
+            ] {
+                ui.label(name);
+                ui.horizontal(|ui| {
+                    ui.text_edit_singleline(label);
+                    if self.labels_task.is_some() {
+                        ui.add(Spinner::new());
+                    } else {
+                        ComboBox::from_id_source(name)
+                            .selected_text("Labels")
+                            .show_ui(ui, |ui| {
+                                for option in &self.labels {
+                                    ui.selectable_value(label, option.clone(), option);
+                                }
+                            });
+                    }
+                });
+                ui.end_row();
+            }
+
+            ui.label("Orders:");
+            ui.add(
+                DragValue::new(&mut self.max_order)
+                    .range(1..=MAX_ORDER)
+                    .prefix("1 to "),
+            )
+            .on_hover_text("Higher orders test masked implementations, in the same pass");
+            ui.end_row();
+        });
+    }
+
+    fn plot_title(&self) -> String {
+        format!(
+            "t-test {} '{}' vs '{}'",
+            self.source_title, self.first_label, self.second_label
+        )
+    }
+
+    fn start_test(&mut self) {
+        let source = self.source.clone();
+        let window = self.window.clone();
+        let first_label = self.first_label.clone();
+        let second_label = self.second_label.clone();
+        let max_order = self.max_order;
+
+        self.error = None;
+        self.result = None;
+        self.task = Some(BackgroundTask::spawn(move |progress| {
+            welch_t_test(
+                source.as_ref(),
+                window,
+                &first_label,
+                &second_label,
+                max_order,
+                &mut |fraction| progress.set(fraction),
+            )
+        }));
+    }
+}
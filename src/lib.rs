@@ -0,0 +1,24 @@
+//! Side-channel trace analysis: the trace model, loaders for the supported file formats and the
+//! analysis algorithms, shared by the GUI and the command line tools.
+//!
+//! ```no_run
+//! use softcore_sc_analysis::loaders::open_trace_source;
+//! use softcore_sc_analysis::math::cpa::{cpa, CpaModel};
+//!
+//! let traces = open_trace_source("traces.sct")?;
+//! let result = cpa(
+//!     traces.as_ref(),
+//!     0..traces.num_samples(),
+//!     &[0],
+//!     CpaModel::SboxHammingWeight,
+//!     &mut |_| {},
+//! )?;
+//! println!("Best guess for byte 0: {:02x}", result.ranking(0).unwrap()[0].guess);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+pub mod loaders;
+pub mod math;
+pub mod trace_set;
+
+pub use trace_set::{read_chunks, SampleCoding, TimeAxis, TraceData, TraceSet, TraceSource};
@@ -0,0 +1,442 @@
+use crate::trace_set::{
+    check_read_ranges, SampleCoding, TimeAxis, TraceData, TraceSet, TraceSource,
+};
+use bincode::{config, Decode, Encode};
+use memmap2::Mmap;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Seek, Write};
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+const MAGIC: &[u8; 8] = b"SCTRACES";
+const VERSION: u32 = 1;
+
+/// Size of the footer: index offset, index length and the closing magic
+const FOOTER_SIZE: usize = 8 + 8 + MAGIC.len();
+
+/// Number of decompressed blocks kept in memory by a `ChunkedTraceFile`
+const CACHED_BLOCKS: usize = 4;
+
+/// Uncompressed size a block is aimed at when no block size is given
+const TARGET_BLOCK_BYTES: usize = 16 * 1024 * 1024;
+
+/// Settings shared by every block of the container
+#[derive(Clone, Debug, Encode, Decode)]
+struct ContainerHeader {
+    time: TimeAxis,
+    coding: SampleCoding,
+    scale: f32,
+    num_samples: u64,
+    parameters: BTreeMap<String, String>,
+}
+
+/// One independently compressed block of traces
+#[derive(Encode, Decode)]
+struct StoredBlock {
+    data: Vec<TraceData>,
+    samples: Vec<u8>,
+}
+
+/// Location of a block in the file
+#[derive(Clone, Copy, Debug, Encode, Decode)]
+struct BlockIndex {
+    offset: u64,
+    length: u64,
+    /// Index of the first trace in the block
+    first_trace: u64,
+    traces: u64,
+}
+
+fn invalid_data(message: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Picks a block size so that a block holds about `TARGET_BLOCK_BYTES` of samples
+pub fn default_block_size(num_samples: usize, coding: SampleCoding) -> usize {
+    (TARGET_BLOCK_BYTES / (num_samples * coding.size()).max(1)).max(1)
+}
+
+/// Writes traces into a chunked container, one compressed block at a time.
+///
+/// The container holds a header, the blocks of `block_size` traces, each compressed with zstd on
+/// its own, and an index of the blocks at the end of the file. Traces can be written in any
+/// number of calls, so a container can be filled without holding all traces in memory.
+pub struct ChunkedWriter {
+    writer: BufWriter<File>,
+    header: ContainerHeader,
+    block_size: usize,
+    pending: TraceSet,
+    index: Vec<BlockIndex>,
+    written_traces: u64,
+}
+
+impl ChunkedWriter {
+    /// Creates a container using the time axis, coding and parameters of `template`.
+    pub fn create(file_path: &str, template: &TraceSet, block_size: usize) -> io::Result<Self> {
+        let header = ContainerHeader {
+            time: template.time,
+            coding: template.coding,
+            scale: template.scale,
+            num_samples: template.num_samples() as u64,
+            parameters: template.parameters.clone(),
+        };
+
+        let encoded = bincode::encode_to_vec(&header, config::standard()).map_err(invalid_data)?;
+
+        let mut writer = BufWriter::new(File::create(file_path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&(encoded.len() as u32).to_le_bytes())?;
+        writer.write_all(&encoded)?;
+
+        let mut pending = TraceSet::new(template.time, template.num_samples());
+        pending.coding = template.coding;
+        pending.scale = template.scale;
+
+        Ok(ChunkedWriter {
+            writer,
+            header,
+            block_size: block_size.max(1),
+            pending,
+            index: vec![],
+            written_traces: 0,
+        })
+    }
+
+    /// Writes every full block of traces to the file, only keeping the traces of the last
+    /// incomplete block until more arrive.
+    pub fn write_traces(&mut self, traces: &TraceSet) -> io::Result<()> {
+        let num_samples = self.pending.num_samples();
+        if traces.num_samples() != num_samples {
+            return Err(invalid_data(format!(
+                "Trace set has {} samples but {} were expected",
+                traces.num_samples(),
+                num_samples
+            )));
+        }
+
+        // Completes the pending block first, so blocks keep their size
+        let mut start = 0;
+        if !self.pending.is_empty() {
+            start = (self.block_size - self.pending.len()).min(traces.len());
+            self.pending
+                .append(&traces.select(0..start, 0..num_samples))
+                .map_err(invalid_data)?;
+            if self.pending.len() < self.block_size {
+                return Ok(());
+            }
+
+            let empty = self.pending.empty_like();
+            let block = std::mem::replace(&mut self.pending, empty);
+            self.write_block(&block.data, block.samples())?;
+        }
+
+        while traces.len() - start >= self.block_size {
+            let end = start + self.block_size;
+            self.write_block(
+                &traces.data[start..end],
+                &traces.samples()[start * num_samples..end * num_samples],
+            )?;
+            start = end;
+        }
+
+        self.pending
+            .append(&traces.select(start..traces.len(), 0..num_samples))
+            .map_err(invalid_data)
+    }
+
+    fn write_block(&mut self, data: &[TraceData], samples: &[f32]) -> io::Result<()> {
+        let stored = StoredBlock {
+            data: data.to_vec(),
+            samples: self.header.coding.encode(samples, self.header.scale),
+        };
+
+        let encoded = bincode::encode_to_vec(&stored, config::standard()).map_err(invalid_data)?;
+        let compressed = zstd::encode_all(&encoded[..], 0)?;
+
+        let offset = self.writer.stream_position()?;
+        self.writer.write_all(&compressed)?;
+
+        self.index.push(BlockIndex {
+            offset,
+            length: compressed.len() as u64,
+            first_trace: self.written_traces,
+            traces: data.len() as u64,
+        });
+        self.written_traces += data.len() as u64;
+
+        Ok(())
+    }
+
+    /// Writes the remaining traces and the block index.
+    pub fn finish(mut self) -> io::Result<()> {
+        if !self.pending.is_empty() {
+            let empty = self.pending.empty_like();
+            let block = std::mem::replace(&mut self.pending, empty);
+            self.write_block(&block.data, block.samples())?;
+        }
+
+        let encoded =
+            bincode::encode_to_vec(&self.index, config::standard()).map_err(invalid_data)?;
+        let index_offset = self.writer.stream_position()?;
+        self.writer.write_all(&encoded)?;
+        self.writer.write_all(&index_offset.to_le_bytes())?;
+        self.writer
+            .write_all(&(encoded.len() as u64).to_le_bytes())?;
+        self.writer.write_all(MAGIC)?;
+        self.writer.flush()
+    }
+}
+
+/// Writes a whole trace set as a chunked container.
+pub fn write_chunked(data: &TraceSet, file_path: &str) -> io::Result<()> {
+    let block_size = default_block_size(data.num_samples(), data.coding);
+    let mut writer = ChunkedWriter::create(file_path, data, block_size)?;
+    writer.write_traces(data)?;
+    writer.finish()
+}
+
+/// A memory-mapped chunked container, only decompressing the blocks that are read.
+pub struct ChunkedTraceFile {
+    mmap: Mmap,
+    header: ContainerHeader,
+    index: Vec<BlockIndex>,
+    num_traces: usize,
+    cache: Mutex<Vec<(usize, Arc<TraceSet>)>>,
+}
+
+impl ChunkedTraceFile {
+    pub fn open(file_path: &str) -> io::Result<Self> {
+        let file = File::open(file_path)?;
+        // Safety: the file is only read, and must not be modified while it is open
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        if mmap.len() < MAGIC.len() + 8 + FOOTER_SIZE || &mmap[..MAGIC.len()] != MAGIC {
+            return Err(invalid_data("Not a chunked trace container"));
+        }
+
+        let read_u32 = |at: usize| u32::from_le_bytes(mmap[at..at + 4].try_into().unwrap());
+        let read_u64 =
+            |at: usize| u64::from_le_bytes(mmap[at..at + 8].try_into().unwrap()) as usize;
+
+        let version = read_u32(MAGIC.len());
+        if version != VERSION {
+            return Err(invalid_data(format!(
+                "Unsupported container version {}",
+                version
+            )));
+        }
+
+        let header_start = MAGIC.len() + 8;
+        let header_end = header_start + read_u32(MAGIC.len() + 4) as usize;
+        let (header, _): (ContainerHeader, usize) = bincode::decode_from_slice(
+            mmap.get(header_start..header_end)
+                .ok_or_else(|| invalid_data("Truncated container header"))?,
+            config::standard(),
+        )
+        .map_err(invalid_data)?;
+
+        let footer = mmap.len() - FOOTER_SIZE;
+        if &mmap[footer + 16..] != MAGIC {
+            return Err(invalid_data(
+                "Container has no block index, it may be incomplete",
+            ));
+        }
+        let index_offset = read_u64(footer);
+        let index_end = index_offset
+            .checked_add(read_u64(footer + 8))
+            .ok_or_else(|| invalid_data("Truncated container index"))?;
+        let (index, _): (Vec<BlockIndex>, usize) = bincode::decode_from_slice(
+            mmap.get(index_offset..index_end)
+                .ok_or_else(|| invalid_data("Truncated container index"))?,
+            config::standard(),
+        )
+        .map_err(invalid_data)?;
+
+        let num_traces = index.iter().map(|block| block.traces as usize).sum();
+
+        Ok(ChunkedTraceFile {
+            mmap,
+            header,
+            index,
+            num_traces,
+            cache: Mutex::new(vec![]),
+        })
+    }
+
+    /// Returns the decompressed block, from the cache when it was read recently.
+    fn block(&self, block_index: usize) -> io::Result<Arc<TraceSet>> {
+        if let Some((_, block)) = self
+            .cache
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(i, _)| *i == block_index)
+        {
+            return Ok(block.clone());
+        }
+
+        let location = self.index[block_index];
+        let start = location.offset as usize;
+        let compressed = start
+            .checked_add(location.length as usize)
+            .and_then(|end| self.mmap.get(start..end))
+            .ok_or_else(|| invalid_data("Truncated container block"))?;
+
+        let decompressed = zstd::decode_all(compressed)?;
+        let (stored, _): (StoredBlock, usize) =
+            bincode::decode_from_slice(&decompressed, config::standard()).map_err(invalid_data)?;
+
+        let samples = self
+            .header
+            .coding
+            .decode(&stored.samples, self.header.scale);
+        let mut block = TraceSet::from_samples(
+            self.header.time,
+            self.header.num_samples as usize,
+            samples,
+            stored.data,
+        )
+        .map_err(invalid_data)?;
+        block.coding = self.header.coding;
+        block.scale = self.header.scale;
+        let block = Arc::new(block);
+
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= CACHED_BLOCKS {
+            cache.remove(0);
+        }
+        cache.push((block_index, block.clone()));
+
+        Ok(block)
+    }
+}
+
+impl TraceSource for ChunkedTraceFile {
+    fn len(&self) -> usize {
+        self.num_traces
+    }
+
+    fn num_samples(&self) -> usize {
+        self.header.num_samples as usize
+    }
+
+    fn time(&self) -> TimeAxis {
+        self.header.time
+    }
+
+    fn read_window(&self, traces: Range<usize>, window: Range<usize>) -> io::Result<TraceSet> {
+        check_read_ranges(self, &traces, &window)?;
+
+        let time = self.time();
+        let mut result = TraceSet::new(
+            TimeAxis::new(time.x(window.start), time.interval),
+            window.len(),
+        );
+        result.coding = self.header.coding;
+        result.scale = self.header.scale;
+        result.parameters = self.header.parameters.clone();
+
+        // Blocks are sorted by their first trace, so only the overlapping ones are decompressed
+        let first_block = self
+            .index
+            .partition_point(|block| (block.first_trace + block.traces) as usize <= traces.start);
+
+        for (block_index, location) in self.index.iter().enumerate().skip(first_block) {
+            let block_start = location.first_trace as usize;
+            if block_start >= traces.end {
+                break;
+            }
+
+            let block = self.block(block_index)?;
+            let start = traces.start.max(block_start) - block_start;
+            let end = traces.end.min(block_start + block.len()) - block_start;
+            result
+                .append(&block.select(start..end, window.clone()))
+                .map_err(invalid_data)?;
+        }
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_traces(len: usize) -> TraceSet {
+        let mut traces = TraceSet::new(TimeAxis::new(0.5, 0.25), 4);
+        traces.coding = SampleCoding::Short;
+        traces.scale = 0.5;
+        traces
+            .parameters
+            .insert("title".to_string(), "test".to_string());
+        for i in 0..len {
+            let data = TraceData {
+                input: vec![i as u8],
+                label: format!("trace {}", i),
+                ..Default::default()
+            };
+            let samples: Vec<f32> = (0..4).map(|j| (i * 4 + j) as f32 * 0.5).collect();
+            traces.push(&samples, data).unwrap();
+        }
+        traces
+    }
+
+    #[test]
+    fn sct_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("traces.sct");
+        let path = path.to_str().unwrap();
+        let traces = test_traces(12);
+
+        // Writes that end in the middle of blocks, larger and smaller than a block
+        let mut writer = ChunkedWriter::create(path, &traces, 3).unwrap();
+        for range in [0..2, 2..9, 9..10, 10..12] {
+            writer.write_traces(&traces.select(range, 0..4)).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let file = ChunkedTraceFile::open(path).unwrap();
+        assert!(file.index.iter().all(|block| block.traces == 3));
+        assert_eq!(file.read_traces(0..12).unwrap(), traces);
+        assert_eq!(
+            file.read_window(4..8, 1..3).unwrap(),
+            traces.select(4..8, 1..3)
+        );
+        #[allow(clippy::reversed_empty_ranges)]
+        let reversed = file.read_window(4..8, 3..1).unwrap_err();
+        assert_eq!(reversed.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn rejects_mismatched_traces() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("traces.sct");
+        let traces = test_traces(2);
+
+        let mut writer = ChunkedWriter::create(path.to_str().unwrap(), &traces, 3).unwrap();
+        let narrow = traces.select(0..2, 0..3);
+        assert!(writer.write_traces(&narrow).is_err());
+    }
+
+    #[test]
+    fn rejects_corrupt_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("traces.sct");
+        write_chunked(&test_traces(5), path.to_str().unwrap()).unwrap();
+
+        // An index length that overflows the end of the index
+        let mut bytes = std::fs::read(&path).unwrap();
+        let length_at = bytes.len() - FOOTER_SIZE + 8;
+        bytes[length_at..length_at + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        std::fs::write(&path, bytes).unwrap();
+
+        let error = ChunkedTraceFile::open(path.to_str().unwrap())
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,393 @@
+use crate::trace_set::{TimeAxis, TraceData, TraceSet};
+use csv::{ReaderBuilder, StringRecord};
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+/// Delimiters tried when detecting the layout of a file
+const DELIMITERS: [u8; 4] = [b',', b';', b'\t', b' '];
+
+/// Number of lines looked at when detecting the layout of a file
+const DETECTION_LINES: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CsvOrientation {
+    /// Every column is a trace and every row a sample
+    TracePerColumn,
+    /// Every row is a trace and every column a sample
+    TracePerRow,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CsvTime {
+    /// Time values are read from this column, or this row for the `TracePerRow` orientation.
+    /// It isn't imported as a trace, and its values must be evenly spaced.
+    Index(usize),
+    /// The file holds no time values, samples are taken at this rate in Hz
+    SampleRate(f64),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CsvOptions {
+    pub delimiter: u8,
+    /// Number of header or metadata rows before the samples
+    pub skip_rows: usize,
+    pub orientation: CsvOrientation,
+    pub time: CsvTime,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            delimiter: b',',
+            skip_rows: 0,
+            orientation: CsvOrientation::TracePerColumn,
+            time: CsvTime::Index(0),
+        }
+    }
+}
+
+/// An error while importing a CSV file, with the 1-based row and column it occurred at.
+#[derive(Debug)]
+pub struct CsvError {
+    pub row: u64,
+    pub column: usize,
+    pub message: String,
+}
+
+impl CsvError {
+    fn new(row: u64, column: usize, message: impl ToString) -> Self {
+        CsvError {
+            row,
+            column,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for CsvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.row == 0 {
+            write!(f, "{}", self.message)
+        } else {
+            write!(
+                f,
+                "Row {}, column {}: {}",
+                self.row, self.column, self.message
+            )
+        }
+    }
+}
+
+impl Error for CsvError {}
+
+fn is_numeric_row(fields: &[&str]) -> bool {
+    !fields.is_empty()
+        && fields
+            .iter()
+            .all(|field| field.trim().parse::<f64>().is_ok())
+}
+
+fn split_line(line: &str, delimiter: u8) -> Vec<&str> {
+    if delimiter == b' ' {
+        line.split_whitespace().collect()
+    } else {
+        line.split(delimiter as char).collect()
+    }
+}
+
+/// Guesses the delimiter and number of header rows from the start of a file.
+///
+/// The delimiter chosen is the one splitting the numeric rows into the most consistent and
+/// largest number of fields, and every row before the first fully numeric row is skipped.
+pub fn detect_csv_options(file_path: &str) -> Result<CsvOptions, CsvError> {
+    let file = File::open(file_path).map_err(|e| CsvError::new(0, 0, e))?;
+    let lines: Vec<String> = BufReader::new(file)
+        .lines()
+        .take(DETECTION_LINES)
+        .collect::<Result<_, _>>()
+        .map_err(|e| CsvError::new(0, 0, e))?;
+
+    let mut options = CsvOptions::default();
+    let mut best_score = 0;
+
+    for delimiter in DELIMITERS {
+        let Some(skip_rows) = lines
+            .iter()
+            .position(|line| is_numeric_row(&split_line(line, delimiter)))
+        else {
+            continue;
+        };
+
+        let field_counts: Vec<usize> = lines[skip_rows..]
+            .iter()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| split_line(line, delimiter).len())
+            .collect();
+        let consistent = field_counts.windows(2).all(|pair| pair[0] == pair[1]);
+        let score = field_counts.first().copied().unwrap_or(0) * if consistent { 2 } else { 1 };
+
+        if score > best_score {
+            best_score = score;
+            options.delimiter = delimiter;
+            options.skip_rows = skip_rows;
+        }
+    }
+
+    Ok(options)
+}
+
+/// Reads a CSV file into a trace set, one record at a time.
+///
+/// `progress` is called with the fraction of the file read so far.
+pub fn read_csv(
+    file_path: &str,
+    options: &CsvOptions,
+    progress: &mut dyn FnMut(f32),
+) -> Result<TraceSet, CsvError> {
+    let file = File::open(file_path).map_err(|e| CsvError::new(0, 0, e))?;
+    let file_size = file.metadata().map(|m| m.len()).unwrap_or(0).max(1);
+
+    let mut rdr = ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .delimiter(options.delimiter)
+        .from_reader(file);
+
+    let mut time: Vec<f64> = Vec::new();
+    // Traces read from rows go straight into the trace set, traces in columns are read sample by
+    // sample and transposed at the end
+    let mut trace_set: Option<TraceSet> = None;
+    let mut columns: Vec<f32> = Vec::new();
+    let mut num_columns = 0;
+    let mut record = StringRecord::new();
+    let mut skipped_rows = 0;
+    let mut data_row = 0;
+
+    loop {
+        let row = rdr.position().line();
+        match rdr.read_record(&mut record) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => return Err(CsvError::new(row, 0, e)),
+        }
+
+        if skipped_rows < options.skip_rows {
+            skipped_rows += 1;
+            continue;
+        }
+
+        // Space delimited exports often pad with repeated spaces, which show up as empty fields
+        let fields: Vec<(usize, &str)> = record
+            .iter()
+            .enumerate()
+            .filter(|(_, field)| !(options.delimiter == b' ' && field.is_empty()))
+            .collect();
+        if fields.is_empty() {
+            continue;
+        }
+
+        let parse = |column: usize, field: &str| {
+            field
+                .parse::<f64>()
+                .map_err(|_| CsvError::new(row, column + 1, format!("'{}' is not a number", field)))
+        };
+
+        match options.orientation {
+            CsvOrientation::TracePerColumn => {
+                if let CsvTime::Index(index) = options.time {
+                    if index >= fields.len() {
+                        return Err(CsvError::new(
+                            row,
+                            fields.len() + 1,
+                            format!("Row has no time column {}", index + 1),
+                        ));
+                    }
+                }
+
+                let mut trace_index = 0;
+                for (i, (column, field)) in fields.iter().enumerate() {
+                    let value = parse(*column, field)?;
+                    if options.time == CsvTime::Index(i) {
+                        time.push(value);
+                        continue;
+                    }
+
+                    if data_row > 0 && trace_index == num_columns {
+                        return Err(CsvError::new(
+                            row,
+                            column + 1,
+                            "Row has more columns than the first row",
+                        ));
+                    }
+                    columns.push(value as f32);
+                    trace_index += 1;
+                }
+
+                if data_row == 0 {
+                    num_columns = trace_index;
+                } else if trace_index < num_columns {
+                    return Err(CsvError::new(
+                        row,
+                        fields.len() + 1,
+                        "Row has fewer columns than the first row",
+                    ));
+                }
+            }
+            CsvOrientation::TracePerRow => {
+                let values = fields
+                    .iter()
+                    .map(|(column, field)| parse(*column, field).map(|value| value as f32))
+                    .collect::<Result<Vec<f32>, CsvError>>()?;
+
+                if options.time == CsvTime::Index(data_row) {
+                    time = values.iter().map(|&value| value as f64).collect();
+                } else {
+                    let trace_set = trace_set
+                        .get_or_insert_with(|| TraceSet::new(TimeAxis::default(), values.len()));
+                    if trace_set.num_samples() != values.len() {
+                        return Err(CsvError::new(
+                            row,
+                            values.len().min(trace_set.num_samples()) + 1,
+                            format!(
+                                "Row has {} samples but the first trace has {}",
+                                values.len(),
+                                trace_set.num_samples()
+                            ),
+                        ));
+                    }
+                    trace_set
+                        .push(&values, TraceData::default())
+                        .map_err(|e| CsvError::new(row, 0, e))?;
+                }
+            }
+        }
+
+        data_row += 1;
+        if data_row % 1024 == 0 {
+            progress(rdr.position().byte() as f32 / file_size as f32);
+        }
+    }
+
+    let time_axis = match options.time {
+        CsvTime::SampleRate(rate) => TimeAxis::new(0.0, 1.0 / rate),
+        CsvTime::Index(index) => {
+            if options.orientation == CsvOrientation::TracePerRow && index >= data_row {
+                return Err(CsvError::new(
+                    0,
+                    0,
+                    format!("The file has no time row {}", index + 1),
+                ));
+            }
+            TimeAxis::from_times(&time).map_err(|e| CsvError::new(0, 0, e))?
+        }
+    };
+
+    let mut trace_set = match options.orientation {
+        CsvOrientation::TracePerRow => trace_set.unwrap_or_else(|| TraceSet::new(time_axis, 0)),
+        CsvOrientation::TracePerColumn => {
+            let num_samples = data_row;
+            let mut samples = Vec::with_capacity(columns.len());
+            for trace in 0..num_columns {
+                samples
+                    .extend((0..num_samples).map(|sample| columns[sample * num_columns + trace]));
+            }
+            TraceSet::from_samples(
+                time_axis,
+                num_samples,
+                samples,
+                vec![TraceData::default(); num_columns],
+            )
+            .map_err(|e| CsvError::new(0, 0, e))?
+        }
+    };
+    trace_set.time = time_axis;
+
+    progress(1.0);
+    Ok(trace_set)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_text(text: &str, options: &CsvOptions) -> Result<TraceSet, CsvError> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("traces.csv");
+        std::fs::write(&path, text).unwrap();
+        read_csv(path.to_str().unwrap(), options, &mut |_| {})
+    }
+
+    #[test]
+    fn reads_traces_in_columns() {
+        let text = "time;a;b\n0.5;1;4\n0.75;2;5\n1.0;3;6\n";
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("traces.csv");
+        std::fs::write(&path, text).unwrap();
+        let path = path.to_str().unwrap();
+
+        let options = detect_csv_options(path).unwrap();
+        assert_eq!(options.delimiter, b';');
+        assert_eq!(options.skip_rows, 1);
+
+        let traces = read_csv(path, &options, &mut |_| {}).unwrap();
+        assert_eq!(traces.time, TimeAxis::new(0.5, 0.25));
+        assert_eq!(traces.len(), 2);
+        assert_eq!(traces.trace(0), &[1.0, 2.0, 3.0]);
+        assert_eq!(traces.trace(1), &[4.0, 5.0, 6.0]);
+    }
+
+    #[test]
+    fn reads_traces_in_rows() {
+        let options = CsvOptions {
+            orientation: CsvOrientation::TracePerRow,
+            time: CsvTime::Index(1),
+            ..Default::default()
+        };
+        let traces = read_text("1,2,3\n0,2,4\n4,5,6\n", &options).unwrap();
+        assert_eq!(traces.time, TimeAxis::new(0.0, 2.0));
+        assert_eq!(traces.len(), 2);
+        assert_eq!(traces.trace(1), &[4.0, 5.0, 6.0]);
+
+        let options = CsvOptions {
+            time: CsvTime::SampleRate(1000.0),
+            ..options
+        };
+        let traces = read_text("1,2,3\n4,5,6\n", &options).unwrap();
+        assert_eq!(traces.time, TimeAxis::new(0.0, 1e-3));
+        assert_eq!(traces.len(), 2);
+    }
+
+    #[test]
+    fn rejects_uneven_time() {
+        let error = read_text("0,1\n1,2\n3,3\n", &CsvOptions::default()).unwrap_err();
+        assert!(error.message.contains("evenly spaced"));
+    }
+
+    #[test]
+    fn rejects_missing_time_index() {
+        let options = CsvOptions {
+            time: CsvTime::Index(2),
+            ..Default::default()
+        };
+        let error = read_text("0,1\n1,2\n", &options).unwrap_err();
+        assert_eq!((error.row, error.column), (1, 3));
+
+        let options = CsvOptions {
+            orientation: CsvOrientation::TracePerRow,
+            ..options
+        };
+        assert!(read_text("0,1\n1,2\n", &options).is_err());
+    }
+
+    #[test]
+    fn rejects_ragged_rows() {
+        let error = read_text("0,1,2\n1,2\n", &CsvOptions::default()).unwrap_err();
+        assert_eq!(error.row, 2);
+
+        let error = read_text("0,1\n1,2,3\n", &CsvOptions::default()).unwrap_err();
+        assert_eq!((error.row, error.column), (2, 3));
+    }
+}
@@ -0,0 +1,284 @@
+pub mod container;
+pub mod csv_import;
+pub mod npy;
+pub mod trs;
+
+use crate::loaders::container::{
+    default_block_size, write_chunked, ChunkedTraceFile, ChunkedWriter,
+};
+use crate::loaders::csv_import::{read_csv, CsvOptions};
+use crate::loaders::npy::{load_npy_traces, load_npz, write_npy_traces, NpyCompanions};
+use crate::loaders::trs::{load_trs, write_trs};
+use crate::trace_set::{read_chunks, SampleCoding, TimeAxis, TraceData, TraceSet, TraceSource};
+use bincode::{config, Decode, Encode};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+use zstd::encode_all;
+
+/// Start of `.bin` files, older files without it hold bare `(time, value)` pairs
+const BIN_MAGIC: &[u8; 8] = b"SCTRCBIN";
+const BIN_VERSION: u32 = 1;
+
+/// On-disk layout of a trace set, with the samples stored in their sample coding
+#[derive(Encode, Decode)]
+struct StoredTraceSet {
+    time: TimeAxis,
+    coding: SampleCoding,
+    scale: f32,
+    num_samples: u64,
+    data: Vec<TraceData>,
+    parameters: BTreeMap<String, String>,
+    samples: Vec<u8>,
+}
+
+fn has_extension(file_path: &str, extension: &str) -> bool {
+    Path::new(file_path)
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case(extension))
+}
+
+/// Opens a trace file for reading, keeping chunked containers on disk and loading every other
+/// format into memory
+pub fn open_trace_source(file_path: &str) -> Result<Arc<dyn TraceSource>, io::Error> {
+    if has_extension(file_path, "sct") {
+        Ok(Arc::new(ChunkedTraceFile::open(file_path)?))
+    } else {
+        Ok(Arc::new(load_trace_file(file_path)?))
+    }
+}
+
+/// Loads a trace set, picking the format from the file extension
+pub fn load_trace_file(file_path: &str) -> Result<TraceSet, io::Error> {
+    if has_extension(file_path, "sct") {
+        let source = ChunkedTraceFile::open(file_path)?;
+        source.read_traces(0..source.len())
+    } else if has_extension(file_path, "trs") {
+        load_trs(file_path, None)
+    } else if has_extension(file_path, "npy") {
+        load_npy_traces(file_path, &NpyCompanions::find(file_path))
+    } else if has_extension(file_path, "npz") {
+        load_npz(file_path)
+    } else {
+        load_from_file(file_path)
+    }
+}
+
+/// Writes a trace set, picking the format from the file extension.
+///
+/// `replace_companions` allows replacing the files some formats write next to `file_path`, like
+/// the per-trace data arrays of `.npy` files.
+pub fn write_trace_file(
+    data: &TraceSet,
+    file_path: &str,
+    replace_companions: bool,
+) -> io::Result<()> {
+    if has_extension(file_path, "sct") {
+        write_chunked(data, file_path)
+    } else if has_extension(file_path, "trs") {
+        write_trs(data, file_path)
+    } else if has_extension(file_path, "npy") {
+        write_npy_traces(data, file_path, replace_companions)
+    } else {
+        write_to_file(data, file_path)
+    }
+}
+
+/// Writes the traces of a source, streaming them block by block into chunked containers so the
+/// source never has to be held in memory. See `write_trace_file` for `replace_companions`.
+pub fn write_trace_source(
+    source: &dyn TraceSource,
+    file_path: &str,
+    replace_companions: bool,
+) -> io::Result<()> {
+    if !has_extension(file_path, "sct") {
+        return write_trace_file(
+            &source.read_traces(0..source.len())?,
+            file_path,
+            replace_companions,
+        );
+    }
+
+    let template = source.read_traces(0..source.len().min(1))?;
+    let block_size = default_block_size(source.num_samples(), template.coding);
+    let mut writer = ChunkedWriter::create(file_path, &template, block_size)?;
+    for chunk in read_chunks(source, block_size, 0..source.num_samples()) {
+        writer.write_traces(&chunk?)?;
+    }
+    writer.finish()
+}
+
+/// Loads a `.bin` trace set, including the files of earlier versions that stored every trace as
+/// `(time, value)` pairs
+pub fn load_from_file(file_path: &str) -> Result<TraceSet, io::Error> {
+    let config = config::standard();
+
+    let mut file = File::open(file_path)?;
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer)?;
+
+    let Some(compressed) = buffer.strip_prefix(BIN_MAGIC) else {
+        return load_legacy_bin(&buffer);
+    };
+    let version = compressed
+        .get(..4)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| invalid_data("Truncated trace set header"))?;
+    if version != BIN_VERSION {
+        return Err(invalid_data(format!(
+            "Unsupported trace set version {}",
+            version
+        )));
+    }
+
+    // Measure the execution time of loading data from a binary file
+    let start_bin = Instant::now();
+    let decompressed = zstd::decode_all(&compressed[4..])?;
+
+    let duration_bin = start_bin.elapsed();
+    log::debug!("Time taken to load binary file: {:?}", duration_bin);
+
+    let (stored, _): (StoredTraceSet, usize) =
+        bincode::decode_from_slice(&decompressed, config).map_err(invalid_data)?;
+
+    let samples = stored.coding.decode(&stored.samples, stored.scale);
+    let mut data = TraceSet::from_samples(
+        stored.time,
+        stored.num_samples as usize,
+        samples,
+        stored.data,
+    )
+    .map_err(invalid_data)?;
+    data.coding = stored.coding;
+    data.scale = stored.scale;
+    data.parameters = stored.parameters;
+
+    Ok(data)
+}
+
+/// Decodes the original `.bin` layout, one `Vec<(time, value)>` per trace, which has to share an
+/// evenly spaced time axis
+fn load_legacy_bin(buffer: &[u8]) -> Result<TraceSet, io::Error> {
+    let decompressed = zstd::decode_all(buffer)?;
+    let (traces, _): (Vec<Vec<(f64, f64)>>, usize) =
+        bincode::decode_from_slice(&decompressed, config::standard())
+            .map_err(|_| invalid_data("Not a trace set file"))?;
+
+    let times: Vec<f64> = traces
+        .first()
+        .map(|trace| trace.iter().map(|&(x, _)| x).collect())
+        .unwrap_or_default();
+    let time = TimeAxis::from_times(&times).map_err(invalid_data)?;
+
+    let mut data = TraceSet::new(time, times.len());
+    for trace in &traces {
+        if trace.iter().map(|&(x, _)| x).ne(times.iter().copied()) {
+            return Err(invalid_data("The traces have different time values"));
+        }
+        let samples: Vec<f32> = trace.iter().map(|&(_, y)| y as f32).collect();
+        data.push(&samples, TraceData::default())
+            .map_err(invalid_data)?;
+    }
+
+    Ok(data)
+}
+
+pub fn write_to_file(data: &TraceSet, file_path: &str) -> io::Result<()> {
+    let config = config::standard();
+
+    let stored = StoredTraceSet {
+        time: data.time,
+        coding: data.coding,
+        scale: data.scale,
+        num_samples: data.num_samples() as u64,
+        data: data.data.clone(),
+        parameters: data.parameters.clone(),
+        samples: data.coding.encode(data.samples(), data.scale),
+    };
+
+    let encoded: Vec<u8> = bincode::encode_to_vec(&stored, config).map_err(invalid_data)?;
+    let mut file = File::create(file_path)?;
+    file.write_all(BIN_MAGIC)?;
+    file.write_all(&BIN_VERSION.to_le_bytes())?;
+    let compressed = encode_all(&encoded[..], 0)?; // Default compression level
+    file.write_all(&compressed)?;
+    Ok(())
+}
+
+fn invalid_data(message: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Loads a CSV file with the time in the first column and one trace per following column
+pub fn load_csv(file_path: &str) -> Result<TraceSet, Box<dyn Error>> {
+    Ok(read_csv(file_path, &CsvOptions::default(), &mut |_| {})?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bin_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("traces.bin");
+        let path = path.to_str().unwrap();
+
+        let mut traces = TraceSet::new(TimeAxis::new(1e-6, 2e-9), 3);
+        traces
+            .push(
+                &[1.0, -2.5, 3.25],
+                TraceData {
+                    input: vec![1, 2],
+                    key: vec![3],
+                    label: "fixed".to_string(),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        traces
+            .push(&[0.0, 4.0, -1.0], TraceData::default())
+            .unwrap();
+        traces
+            .parameters
+            .insert("title".to_string(), "test".to_string());
+
+        write_to_file(&traces, path).unwrap();
+        assert_eq!(load_from_file(path).unwrap(), traces);
+    }
+
+    #[test]
+    fn loads_legacy_bin() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("legacy.bin");
+
+        let legacy: Vec<Vec<(f64, f64)>> = vec![
+            vec![(0.5, 1.0), (0.75, 2.0), (1.0, 3.0)],
+            vec![(0.5, -1.0), (0.75, -2.0), (1.0, -3.0)],
+        ];
+        let encoded = bincode::encode_to_vec(&legacy, config::standard()).unwrap();
+        std::fs::write(&path, encode_all(&encoded[..], 0).unwrap()).unwrap();
+
+        let traces = load_from_file(path.to_str().unwrap()).unwrap();
+        assert_eq!(traces.time, TimeAxis::new(0.5, 0.25));
+        assert_eq!(traces.len(), 2);
+        assert_eq!(traces.trace(1), &[-1.0, -2.0, -3.0]);
+    }
+
+    #[test]
+    fn rejects_uneven_legacy_bin() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("legacy.bin");
+
+        let legacy: Vec<Vec<(f64, f64)>> = vec![vec![(0.0, 1.0), (1.0, 2.0), (5.0, 3.0)]];
+        let encoded = bincode::encode_to_vec(&legacy, config::standard()).unwrap();
+        std::fs::write(&path, encode_all(&encoded[..], 0).unwrap()).unwrap();
+
+        assert!(load_from_file(path.to_str().unwrap()).is_err());
+    }
+}
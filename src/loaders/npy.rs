@@ -0,0 +1,577 @@
+use crate::trace_set::{TimeAxis, TraceData, TraceSet};
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use zip::ZipArchive;
+
+const MAGIC: &[u8; 6] = b"\x93NUMPY";
+
+/// Accessor for one of the per-trace byte fields
+type DataField = fn(&TraceData) -> &Vec<u8>;
+
+/// A numeric NumPy array, with the values converted to `f64` in row-major order.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NpyArray {
+    pub shape: Vec<usize>,
+    pub values: Vec<f64>,
+}
+
+impl NpyArray {
+    /// Number of rows, the first dimension of the array
+    pub fn rows(&self) -> usize {
+        self.shape.first().copied().unwrap_or(1)
+    }
+
+    /// Number of values per row, the product of every dimension but the first
+    pub fn row_length(&self) -> usize {
+        self.shape.iter().skip(1).product()
+    }
+
+    pub fn row(&self, index: usize) -> &[f64] {
+        let length = self.row_length();
+        &self.values[index * length..(index + 1) * length]
+    }
+}
+
+/// Companion arrays holding the per-trace data, one row per trace.
+#[derive(Clone, Debug, Default)]
+pub struct NpyCompanions {
+    pub input: Option<String>,
+    pub output: Option<String>,
+    pub key: Option<String>,
+}
+
+impl NpyCompanions {
+    /// Finds the `<stem>_textin.npy`, `<stem>_textout.npy` and `<stem>_keylist.npy` files written
+    /// by `write_npy_traces`, falling back to the ChipWhisperer names when there are none.
+    pub fn find(traces_path: &str) -> Self {
+        let find = |suffix: &str| {
+            let path = companion_path(traces_path, suffix);
+            path.exists().then(|| path.to_string_lossy().into_owned())
+        };
+
+        match (find("textin"), find("textout"), find("keylist")) {
+            (None, None, None) => NpyCompanions::chipwhisperer(traces_path),
+            (input, output, key) => NpyCompanions { input, output, key },
+        }
+    }
+
+    /// Finds the `textin.npy`, `textout.npy` and `keylist.npy` files ChipWhisperer saves next to
+    /// the traces.
+    pub fn chipwhisperer(traces_path: &str) -> Self {
+        let directory = Path::new(traces_path).parent().unwrap_or(Path::new("."));
+        let find = |name: &str| {
+            let path = directory.join(name);
+            path.exists().then(|| path.to_string_lossy().into_owned())
+        };
+
+        NpyCompanions {
+            input: find("textin.npy"),
+            output: find("textout.npy"),
+            key: find("keylist.npy"),
+        }
+    }
+}
+
+/// Path of the companion array `<stem>_<suffix>.npy` next to the traces
+fn companion_path(traces_path: &str, suffix: &str) -> PathBuf {
+    let path = Path::new(traces_path);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}_{}.npy", stem, suffix))
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Returns the text following `'key':` in the header dictionary
+fn header_value<'a>(header: &'a str, key: &str) -> io::Result<&'a str> {
+    let pattern = format!("'{}':", key);
+    let start = header
+        .find(&pattern)
+        .ok_or_else(|| invalid_data(format!("NPY header has no '{}' entry", key)))?;
+    Ok(header[start + pattern.len()..].trim_start())
+}
+
+/// Parses the `descr`, `fortran_order` and `shape` entries of the header dictionary
+fn parse_header(header: &str) -> io::Result<(String, bool, Vec<usize>)> {
+    let descr = header_value(header, "descr")?;
+    let descr = descr
+        .strip_prefix('\'')
+        .and_then(|d| d.split('\'').next())
+        .ok_or_else(|| invalid_data(format!("Unsupported NPY dtype {}", descr)))?;
+
+    let fortran_order = header_value(header, "fortran_order")?.starts_with("True");
+
+    let shape = header_value(header, "shape")?;
+    let shape = shape
+        .strip_prefix('(')
+        .and_then(|s| s.split(')').next())
+        .ok_or_else(|| invalid_data(format!("Invalid NPY shape {}", shape)))?;
+    let shape = shape
+        .split(',')
+        .map(str::trim)
+        .filter(|dimension| !dimension.is_empty())
+        .map(|dimension| {
+            dimension
+                .trim_end_matches('L')
+                .parse()
+                .map_err(|_| invalid_data(format!("Invalid NPY dimension {}", dimension)))
+        })
+        .collect::<io::Result<Vec<usize>>>()?;
+
+    Ok((descr.to_string(), fortran_order, shape))
+}
+
+/// Converts raw array data of the given dtype, e.g. `<f4` or `|u1`, to `f64` values
+fn decode_values(descr: &str, bytes: &[u8]) -> io::Result<Vec<f64>> {
+    let (byte_order, kind) = descr
+        .get(..1)
+        .zip(descr.get(1..))
+        .ok_or_else(|| invalid_data(format!("Unsupported NPY dtype {}", descr)))?;
+    let big_endian = byte_order == ">";
+
+    // Copies `N` bytes per value in little-endian order
+    fn chunks<const N: usize>(
+        bytes: &[u8],
+        big_endian: bool,
+    ) -> impl Iterator<Item = [u8; N]> + '_ {
+        bytes.chunks_exact(N).map(move |chunk| {
+            let mut value: [u8; N] = chunk.try_into().unwrap();
+            if big_endian {
+                value.reverse();
+            }
+            value
+        })
+    }
+
+    let values = match kind {
+        "b1" | "u1" => bytes.iter().map(|&b| b as f64).collect(),
+        "i1" => bytes.iter().map(|&b| b as i8 as f64).collect(),
+        "u2" => chunks(bytes, big_endian)
+            .map(|b| u16::from_le_bytes(b) as f64)
+            .collect(),
+        "i2" => chunks(bytes, big_endian)
+            .map(|b| i16::from_le_bytes(b) as f64)
+            .collect(),
+        "u4" => chunks(bytes, big_endian)
+            .map(|b| u32::from_le_bytes(b) as f64)
+            .collect(),
+        "i4" => chunks(bytes, big_endian)
+            .map(|b| i32::from_le_bytes(b) as f64)
+            .collect(),
+        "u8" => chunks(bytes, big_endian)
+            .map(|b| u64::from_le_bytes(b) as f64)
+            .collect(),
+        "i8" => chunks(bytes, big_endian)
+            .map(|b| i64::from_le_bytes(b) as f64)
+            .collect(),
+        "f4" => chunks(bytes, big_endian)
+            .map(|b| f32::from_le_bytes(b) as f64)
+            .collect(),
+        "f8" => chunks(bytes, big_endian).map(f64::from_le_bytes).collect(),
+        _ => return Err(invalid_data(format!("Unsupported NPY dtype {}", descr))),
+    };
+
+    Ok(values)
+}
+
+/// Reverses the axes of a column-major array so its values are in row-major order
+fn fortran_to_row_major(shape: &[usize], values: Vec<f64>) -> Vec<f64> {
+    if shape.len() < 2 {
+        return values;
+    }
+
+    let mut reordered = vec![0.0; values.len()];
+    let mut index = vec![0usize; shape.len()];
+    for value in values {
+        // `index` walks the array in column-major order, the first axis changing fastest
+        let row_major = index
+            .iter()
+            .zip(shape)
+            .fold(0, |offset, (&i, &dimension)| offset * dimension + i);
+        reordered[row_major] = value;
+
+        for (i, &dimension) in index.iter_mut().zip(shape) {
+            *i += 1;
+            if *i < dimension {
+                break;
+            }
+            *i = 0;
+        }
+    }
+
+    reordered
+}
+
+/// Reads a single NumPy array from an `.npy` stream
+pub fn read_npy(reader: &mut impl Read) -> io::Result<NpyArray> {
+    let mut preamble = [0u8; 8];
+    reader.read_exact(&mut preamble)?;
+    if &preamble[..6] != MAGIC {
+        return Err(invalid_data("Not an NPY file".to_string()));
+    }
+
+    // Version 1 stores the header length in two bytes, later versions in four
+    let header_length = if preamble[6] == 1 {
+        let mut length = [0u8; 2];
+        reader.read_exact(&mut length)?;
+        u16::from_le_bytes(length) as usize
+    } else {
+        let mut length = [0u8; 4];
+        reader.read_exact(&mut length)?;
+        u32::from_le_bytes(length) as usize
+    };
+
+    let mut header = vec![0u8; header_length];
+    reader.read_exact(&mut header)?;
+    let (descr, fortran_order, shape) = parse_header(&String::from_utf8_lossy(&header))?;
+
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let mut values = decode_values(&descr, &bytes)?;
+
+    let expected: usize = shape.iter().product();
+    if values.len() < expected {
+        return Err(invalid_data(format!(
+            "NPY array has {} values but its shape needs {}",
+            values.len(),
+            expected
+        )));
+    }
+    values.truncate(expected);
+
+    if fortran_order {
+        values = fortran_to_row_major(&shape, values);
+    }
+
+    Ok(NpyArray { shape, values })
+}
+
+pub fn load_npy(file_path: &str) -> io::Result<NpyArray> {
+    read_npy(&mut BufReader::new(File::open(file_path)?))
+}
+
+/// Writes a row-major array of `f4` or `u1` values
+fn write_npy_raw(
+    writer: &mut impl Write,
+    descr: &str,
+    shape: &[usize],
+    bytes: &[u8],
+) -> io::Result<()> {
+    let shape = match shape {
+        [dimension] => format!("({},)", dimension),
+        _ => format!(
+            "({})",
+            shape
+                .iter()
+                .map(usize::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    let mut header = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}",
+        descr, shape
+    );
+
+    // The header is padded with spaces and a newline so the data starts 64-byte aligned
+    let unpadded = MAGIC.len() + 4 + header.len() + 1;
+    header.push_str(&" ".repeat((64 - unpadded % 64) % 64));
+    header.push('\n');
+
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[1, 0])?;
+    writer.write_all(&(header.len() as u16).to_le_bytes())?;
+    writer.write_all(header.as_bytes())?;
+    writer.write_all(bytes)
+}
+
+pub fn write_npy_f32(file_path: &str, shape: &[usize], values: &[f32]) -> io::Result<()> {
+    let bytes: Vec<u8> = values
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect();
+    let mut writer = BufWriter::new(File::create(file_path)?);
+    write_npy_raw(&mut writer, "<f4", shape, &bytes)?;
+    writer.flush()
+}
+
+pub fn write_npy_u8(file_path: &str, shape: &[usize], values: &[u8]) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(file_path)?);
+    write_npy_raw(&mut writer, "|u1", shape, values)?;
+    writer.flush()
+}
+
+/// Builds a trace set from a traces array and optional companion arrays of per-trace bytes
+fn build_trace_set(
+    traces: NpyArray,
+    input: Option<NpyArray>,
+    output: Option<NpyArray>,
+    key: Option<NpyArray>,
+) -> io::Result<TraceSet> {
+    // A single trace may be saved without the trace dimension
+    let traces = match traces.shape[..] {
+        [num_samples] => NpyArray {
+            shape: vec![1, num_samples],
+            ..traces
+        },
+        [] => return Err(invalid_data("The traces array is a scalar".to_string())),
+        _ => traces,
+    };
+
+    let rows = traces.rows();
+    for companion in [&input, &output, &key].into_iter().flatten() {
+        if companion.rows() != rows {
+            return Err(invalid_data(format!(
+                "Companion array has {} rows but there are {} traces",
+                companion.rows(),
+                rows
+            )));
+        }
+    }
+
+    let bytes = |array: &Option<NpyArray>, index: usize| -> Vec<u8> {
+        array.as_ref().map_or(vec![], |array| {
+            array.row(index).iter().map(|&value| value as u8).collect()
+        })
+    };
+
+    let data = (0..rows)
+        .map(|i| TraceData {
+            input: bytes(&input, i),
+            output: bytes(&output, i),
+            key: bytes(&key, i),
+            label: String::new(),
+        })
+        .collect();
+    let samples = traces.values.iter().map(|&value| value as f32).collect();
+
+    TraceSet::from_samples(TimeAxis::default(), traces.row_length(), samples, data)
+        .map_err(invalid_data)
+}
+
+/// Loads a `(traces, samples)` array as a trace set, pairing the companion arrays as the
+/// per-trace input, output and key bytes.
+pub fn load_npy_traces(traces_path: &str, companions: &NpyCompanions) -> io::Result<TraceSet> {
+    let load = |path: &Option<String>| path.as_deref().map(load_npy).transpose();
+
+    build_trace_set(
+        load_npy(traces_path)?,
+        load(&companions.input)?,
+        load(&companions.output)?,
+        load(&companions.key)?,
+    )
+}
+
+fn read_npz_entry<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    names: &[&str],
+) -> io::Result<Option<NpyArray>> {
+    for name in names {
+        if let Ok(mut entry) = archive.by_name(&format!("{}.npy", name)) {
+            return read_npy(&mut entry).map(Some);
+        }
+    }
+
+    Ok(None)
+}
+
+/// Loads an `.npz` archive, using the `traces` (or `wave`) array as samples and the `textin`,
+/// `textout` and `keylist` (or `key`) arrays as per-trace data when present.
+pub fn load_npz(file_path: &str) -> io::Result<TraceSet> {
+    let mut archive = ZipArchive::new(BufReader::new(File::open(file_path)?))?;
+
+    let traces = read_npz_entry(&mut archive, &["traces", "wave"])?
+        .ok_or_else(|| invalid_data("NPZ archive has no traces array".to_string()))?;
+    let input = read_npz_entry(&mut archive, &["textin", "plaintext"])?;
+    let output = read_npz_entry(&mut archive, &["textout", "ciphertext"])?;
+    let key = read_npz_entry(&mut archive, &["keylist", "key"])?;
+
+    build_trace_set(traces, input, output, key)
+}
+
+/// Writes the samples to `traces_path` and any per-trace data as `<stem>_textin.npy`,
+/// `<stem>_textout.npy` and `<stem>_keylist.npy` next to it.
+///
+/// Existing companion files are only replaced, or removed when the trace set has no such data,
+/// with `replace_companions`, otherwise nothing is written.
+pub fn write_npy_traces(
+    data: &TraceSet,
+    traces_path: &str,
+    replace_companions: bool,
+) -> io::Result<()> {
+    let fields: [(&str, DataField); 3] = [
+        ("textin", |d| &d.input),
+        ("textout", |d| &d.output),
+        ("keylist", |d| &d.key),
+    ];
+    let companions = fields.map(|(suffix, field)| (companion_path(traces_path, suffix), field));
+
+    if !replace_companions {
+        if let Some((path, _)) = companions.iter().find(|(path, _)| path.exists()) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", path.display()),
+            ));
+        }
+    }
+
+    write_npy_f32(
+        traces_path,
+        &[data.len(), data.num_samples()],
+        data.samples(),
+    )?;
+
+    for (path, field) in companions {
+        let length = data.data.first().map_or(0, |d| field(d).len());
+        if length == 0 || data.data.iter().any(|d| field(d).len() != length) {
+            // A stale companion would be paired with these traces when they are loaded
+            if path.exists() {
+                std::fs::remove_file(&path)?;
+            }
+            continue;
+        }
+
+        let values: Vec<u8> = data.data.iter().flat_map(|d| field(d).clone()).collect();
+        write_npy_u8(&path.to_string_lossy(), &[data.len(), length], &values)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    fn npy_bytes(descr: &str, shape: &[usize], bytes: &[u8]) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        write_npy_raw(&mut buffer, descr, shape, bytes).unwrap();
+        buffer
+    }
+
+    fn test_traces() -> TraceSet {
+        let mut traces = TraceSet::new(TimeAxis::default(), 3);
+        for i in 0..2u8 {
+            let data = TraceData {
+                input: vec![i, i + 1],
+                key: vec![0xAA],
+                ..Default::default()
+            };
+            traces.push(&[i as f32, -1.5, 2.0], data).unwrap();
+        }
+        traces
+    }
+
+    #[test]
+    fn npy_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("traces.npy");
+        let path = path.to_str().unwrap();
+        let traces = test_traces();
+
+        write_npy_traces(&traces, path, false).unwrap();
+        assert!(dir.path().join("traces_textin.npy").exists());
+        assert!(!dir.path().join("traces_textout.npy").exists());
+        assert!(!dir.path().join("textin.npy").exists());
+
+        let companions = NpyCompanions::find(path);
+        assert_eq!(load_npy_traces(path, &companions).unwrap(), traces);
+    }
+
+    #[test]
+    fn keeps_existing_companions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("traces.npy");
+        let path = path.to_str().unwrap();
+        let traces = test_traces();
+
+        write_npy_traces(&traces, path, false).unwrap();
+        let error = write_npy_traces(&traces, path, false).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+
+        // Replacing drops the companions the new trace set has no data for
+        let mut without_key = traces.empty_like();
+        for (trace, data) in traces.traces().zip(&traces.data) {
+            let data = TraceData {
+                key: vec![],
+                ..data.clone()
+            };
+            without_key.push(trace, data).unwrap();
+        }
+        write_npy_traces(&without_key, path, true).unwrap();
+        assert!(!dir.path().join("traces_keylist.npy").exists());
+        let companions = NpyCompanions::find(path);
+        assert_eq!(load_npy_traces(path, &companions).unwrap(), without_key);
+    }
+
+    #[test]
+    fn reads_fortran_order_and_big_endian() {
+        let values: Vec<u8> = [1i16, 2, 3, 4, 5, 6]
+            .iter()
+            .flat_map(|v| v.to_be_bytes())
+            .collect();
+        let mut bytes = npy_bytes(">i2", &[2, 3], &values);
+        let header = String::from_utf8_lossy(&bytes[10..]).replace("False", "True ");
+        bytes.truncate(10);
+        bytes.extend(header.as_bytes());
+
+        // Column-major, so the values run down the columns
+        let array = read_npy(&mut &bytes[..]).unwrap();
+        assert_eq!(array.shape, vec![2, 3]);
+        assert_eq!(array.values, vec![1.0, 3.0, 5.0, 2.0, 4.0, 6.0]);
+    }
+
+    #[test]
+    fn rejects_bad_dtypes() {
+        for descr in ["", "é4", "<c8"] {
+            let bytes = npy_bytes(descr, &[1], &[0; 8]);
+            let error = read_npy(&mut &bytes[..]).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn loads_one_dimensional_traces_as_one_trace() {
+        let array = NpyArray {
+            shape: vec![4],
+            values: vec![1.0, 2.0, 3.0, 4.0],
+        };
+        let traces = build_trace_set(array, None, None, None).unwrap();
+        assert_eq!(traces.len(), 1);
+        assert_eq!(traces.trace(0), &[1.0, 2.0, 3.0, 4.0]);
+    }
+
+    #[test]
+    fn npz_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("traces.npz");
+        let traces = test_traces();
+
+        let samples: Vec<u8> = traces
+            .samples()
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let input: Vec<u8> = traces.data.iter().flat_map(|d| d.input.clone()).collect();
+        let key: Vec<u8> = traces.data.iter().flat_map(|d| d.key.clone()).collect();
+
+        let mut archive = ZipWriter::new(File::create(&path).unwrap());
+        for (name, array) in [
+            ("traces.npy", npy_bytes("<f4", &[2, 3], &samples)),
+            ("textin.npy", npy_bytes("|u1", &[2, 2], &input)),
+            ("key.npy", npy_bytes("|u1", &[2, 1], &key)),
+        ] {
+            archive
+                .start_file(name, SimpleFileOptions::default())
+                .unwrap();
+            archive.write_all(&array).unwrap();
+        }
+        archive.finish().unwrap();
+
+        assert_eq!(load_npz(path.to_str().unwrap()).unwrap(), traces);
+    }
+}
@@ -0,0 +1,345 @@
+use crate::trace_set::{SampleCoding, TimeAxis, TraceData, TraceSet};
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+// Header tags of the Riscure Inspector trace set format
+const NUMBER_OF_TRACES: u8 = 0x41;
+const NUMBER_OF_SAMPLES: u8 = 0x42;
+const SAMPLE_CODING: u8 = 0x43;
+const DATA_LENGTH: u8 = 0x44;
+const TITLE_SPACE: u8 = 0x45;
+const GLOBAL_TITLE: u8 = 0x46;
+const DESCRIPTION: u8 = 0x47;
+const OFFSET_X: u8 = 0x48;
+const LABEL_X: u8 = 0x49;
+const LABEL_Y: u8 = 0x4A;
+const SCALE_X: u8 = 0x4B;
+const SCALE_Y: u8 = 0x4C;
+const TRACE_BLOCK: u8 = 0x5F;
+
+/// Trace set parameters that are mapped to the string tags of the header
+const STRING_TAGS: [(u8, &str); 4] = [
+    (GLOBAL_TITLE, "title"),
+    (DESCRIPTION, "description"),
+    (LABEL_X, "x_label"),
+    (LABEL_Y, "y_label"),
+];
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn coding_from_byte(coding: u8) -> io::Result<SampleCoding> {
+    match coding {
+        0x01 => Ok(SampleCoding::Byte),
+        0x02 => Ok(SampleCoding::Short),
+        0x04 => Ok(SampleCoding::Int),
+        0x14 => Ok(SampleCoding::Float),
+        _ => Err(invalid_data(format!(
+            "Unsupported sample coding 0x{:02X}",
+            coding
+        ))),
+    }
+}
+
+fn coding_to_byte(coding: SampleCoding) -> u8 {
+    match coding {
+        SampleCoding::Byte => 0x01,
+        SampleCoding::Short => 0x02,
+        SampleCoding::Int => 0x04,
+        SampleCoding::Float => 0x14,
+    }
+}
+
+/// Reads a little-endian integer of up to 8 bytes
+fn read_uint(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .take(8)
+        .enumerate()
+        .fold(0, |value, (i, &b)| value | (b as u64) << (8 * i))
+}
+
+fn read_f32(bytes: &[u8]) -> io::Result<f32> {
+    let bytes: [u8; 4] = bytes
+        .try_into()
+        .map_err(|_| invalid_data("Expected a 4 byte float in the header".to_string()))?;
+    Ok(f32::from_le_bytes(bytes))
+}
+
+/// Reads one tag-length-value header object, returning `None` at the end of the file
+fn read_tag(reader: &mut impl Read) -> io::Result<Option<(u8, Vec<u8>)>> {
+    let mut tag_and_length = [0u8; 2];
+    match reader.read_exact(&mut tag_and_length) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let [tag, length] = tag_and_length;
+
+    // Lengths above 127 are stored in the following (length & 0x7F) bytes
+    let length = if length & 0x80 != 0 {
+        let mut length_bytes = vec![0u8; (length & 0x7F) as usize];
+        reader.read_exact(&mut length_bytes)?;
+        read_uint(&length_bytes) as usize
+    } else {
+        length as usize
+    };
+
+    let mut value = vec![0u8; length];
+    reader.read_exact(&mut value)?;
+    Ok(Some((tag, value)))
+}
+
+fn write_tag(writer: &mut impl Write, tag: u8, value: &[u8]) -> io::Result<()> {
+    writer.write_all(&[tag])?;
+    if value.len() < 0x80 {
+        writer.write_all(&[value.len() as u8])?;
+    } else {
+        writer.write_all(&[0x84])?;
+        writer.write_all(&(value.len() as u32).to_le_bytes())?;
+    }
+    writer.write_all(value)
+}
+
+/// Loads a Riscure Inspector `.trs` trace set.
+///
+/// The data bytes of every trace are split into the input and output fields, with the first
+/// `input_length` bytes as input. When `input_length` is `None` the data is split in half, which
+/// matches the usual plaintext followed by ciphertext layout.
+pub fn load_trs(file_path: &str, input_length: Option<usize>) -> io::Result<TraceSet> {
+    let mut reader = BufReader::new(File::open(file_path)?);
+
+    let mut number_of_traces = 0;
+    let mut number_of_samples = 0;
+    let mut coding = SampleCoding::Byte;
+    let mut data_length = 0;
+    let mut title_space = 0;
+    let mut offset_x = 0;
+    let mut scale_x = 1.0;
+    let mut scale_y = 1.0;
+    let mut parameters = Vec::new();
+
+    loop {
+        let Some((tag, value)) = read_tag(&mut reader)? else {
+            return Err(invalid_data(
+                "File ended before the trace block".to_string(),
+            ));
+        };
+
+        match tag {
+            NUMBER_OF_TRACES => number_of_traces = read_uint(&value) as usize,
+            NUMBER_OF_SAMPLES => number_of_samples = read_uint(&value) as usize,
+            SAMPLE_CODING => coding = coding_from_byte(read_uint(&value) as u8)?,
+            DATA_LENGTH => data_length = read_uint(&value) as usize,
+            TITLE_SPACE => title_space = read_uint(&value) as usize,
+            OFFSET_X => offset_x = read_uint(&value) as i32,
+            SCALE_X => scale_x = read_f32(&value)?,
+            SCALE_Y => scale_y = read_f32(&value)?,
+            TRACE_BLOCK => break,
+            _ => {
+                if let Some((_, name)) = STRING_TAGS.iter().find(|(t, _)| *t == tag) {
+                    let text = String::from_utf8_lossy(&value).trim_end().to_string();
+                    parameters.push((name.to_string(), text));
+                } else {
+                    log::debug!("Skipping TRS header tag 0x{:02X}", tag);
+                }
+            }
+        }
+    }
+
+    let input_length = input_length.unwrap_or(data_length / 2).min(data_length);
+    let time = TimeAxis::new(offset_x as f64 * scale_x as f64, scale_x as f64);
+
+    let mut trace_set = TraceSet::new(time, number_of_samples);
+    trace_set.coding = coding;
+    trace_set.scale = scale_y;
+    trace_set.parameters.extend(parameters);
+
+    let mut title = vec![0u8; title_space];
+    let mut data = vec![0u8; data_length];
+    let mut samples = vec![0u8; number_of_samples * coding.size()];
+
+    // A trace count of zero is written by interrupted acquisitions, read until the end instead
+    let mut index = 0;
+    while number_of_traces == 0 || index < number_of_traces {
+        match reader.read_exact(&mut title) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && number_of_traces == 0 => break,
+            Err(e) => return Err(e),
+        }
+        reader.read_exact(&mut data)?;
+        reader.read_exact(&mut samples)?;
+
+        let trace_data = TraceData {
+            input: data[..input_length].to_vec(),
+            output: data[input_length..].to_vec(),
+            key: vec![],
+            label: String::from_utf8_lossy(&title)
+                .trim_end_matches(['\0', ' '])
+                .to_string(),
+        };
+
+        trace_set
+            .push(&coding.decode(&samples, scale_y), trace_data)
+            .map_err(invalid_data)?;
+        index += 1;
+    }
+
+    Ok(trace_set)
+}
+
+/// Writes a trace set in the Riscure Inspector `.trs` format.
+///
+/// The input and output fields of each trace are concatenated into its data bytes, padded with
+/// zeros to the longest entry, and the label is stored as the trace title.
+pub fn write_trs(data: &TraceSet, file_path: &str) -> io::Result<()> {
+    let data_length = data
+        .data
+        .iter()
+        .map(|d| d.input.len() + d.output.len())
+        .max()
+        .unwrap_or(0);
+    let data_length = u16::try_from(data_length).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "TRS files hold at most {} bytes of data per trace, a trace has {}",
+                u16::MAX,
+                data_length
+            ),
+        )
+    })?;
+    let too_large = |what: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Too many {} for a TRS file", what),
+        )
+    };
+    let num_traces = u32::try_from(data.len()).map_err(|_| too_large("traces"))?;
+    let num_samples = u32::try_from(data.num_samples()).map_err(|_| too_large("samples"))?;
+    let title_space = data
+        .data
+        .iter()
+        .map(|d| d.label.len())
+        .max()
+        .unwrap_or(0)
+        .min(u8::MAX as usize);
+    let scale_y = if data.coding == SampleCoding::Float {
+        1.0
+    } else {
+        data.scale
+    };
+
+    let mut writer = BufWriter::new(File::create(file_path)?);
+
+    write_tag(&mut writer, NUMBER_OF_TRACES, &num_traces.to_le_bytes())?;
+    write_tag(&mut writer, NUMBER_OF_SAMPLES, &num_samples.to_le_bytes())?;
+    write_tag(&mut writer, SAMPLE_CODING, &[coding_to_byte(data.coding)])?;
+    write_tag(&mut writer, DATA_LENGTH, &data_length.to_le_bytes())?;
+    write_tag(&mut writer, TITLE_SPACE, &[title_space as u8])?;
+    for (tag, name) in STRING_TAGS {
+        if let Some(text) = data.parameters.get(name) {
+            write_tag(&mut writer, tag, text.as_bytes())?;
+        }
+    }
+    let offset_x = (data.time.offset / data.time.interval).round() as i32;
+    write_tag(&mut writer, OFFSET_X, &offset_x.to_le_bytes())?;
+    write_tag(
+        &mut writer,
+        SCALE_X,
+        &(data.time.interval as f32).to_le_bytes(),
+    )?;
+    write_tag(&mut writer, SCALE_Y, &scale_y.to_le_bytes())?;
+    write_tag(&mut writer, TRACE_BLOCK, &[])?;
+
+    for (trace, trace_data) in data.traces().zip(&data.data) {
+        let mut title = truncate_at_char(&trace_data.label, title_space)
+            .as_bytes()
+            .to_vec();
+        title.resize(title_space, b' ');
+
+        let mut bytes = [&trace_data.input[..], &trace_data.output[..]].concat();
+        bytes.resize(data_length as usize, 0);
+
+        writer.write_all(&title)?;
+        writer.write_all(&bytes)?;
+        writer.write_all(&data.coding.encode(trace, scale_y))?;
+    }
+
+    writer.flush()
+}
+
+/// Longest start of `text` that fits in `max_len` bytes without splitting a character
+fn truncate_at_char(text: &str, max_len: usize) -> &str {
+    let mut end = text.len().min(max_len);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trace_data(input: Vec<u8>, output: Vec<u8>, label: &str) -> TraceData {
+        TraceData {
+            input,
+            output,
+            label: label.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn trs_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("traces.trs");
+        let path = path.to_str().unwrap();
+
+        let mut traces = TraceSet::new(TimeAxis::new(1.0, 0.5), 3);
+        traces.coding = SampleCoding::Float;
+        traces
+            .push(
+                &[1.0, -2.5, 3.25],
+                trace_data(vec![1, 2], vec![3, 4], "fixed"),
+            )
+            .unwrap();
+        traces
+            .push(
+                &[0.0, 4.0, -1.0],
+                trace_data(vec![5, 6], vec![7, 8], "random"),
+            )
+            .unwrap();
+        traces
+            .parameters
+            .insert("title".to_string(), "test".to_string());
+
+        write_trs(&traces, path).unwrap();
+        assert_eq!(load_trs(path, None).unwrap(), traces);
+    }
+
+    #[test]
+    fn rejects_oversized_data() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("traces.trs");
+
+        let mut traces = TraceSet::new(TimeAxis::default(), 1);
+        traces
+            .push(&[0.0], trace_data(vec![0; 40000], vec![0; 40000], ""))
+            .unwrap();
+
+        let error = write_trs(&traces, path.to_str().unwrap()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn truncates_titles_at_char_boundaries() {
+        assert_eq!(truncate_at_char("aé", 2), "a");
+        assert_eq!(truncate_at_char("aé", 3), "aé");
+        assert_eq!(truncate_at_char("abc", 10), "abc");
+    }
+}
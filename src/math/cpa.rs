@@ -0,0 +1,440 @@
+use crate::math::aes::SBOX;
+use crate::math::leakage::Leakage;
+use crate::math::MAX_ACCUMULATOR_BYTES;
+use crate::trace_set::{read_chunks, TimeAxis, TraceData, TraceSet, TraceSource};
+use rayon::prelude::*;
+use std::ops::Range;
+
+/// Number of key bytes of AES-128
+pub const KEY_BYTES: usize = 16;
+
+const GUESSES: usize = 256;
+
+/// Number of traces read from the source at once
+const CPA_CHUNK_SIZE: usize = 1024;
+
+/// Power model predicting the leakage of a key byte guess from the matching plaintext byte
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CpaModel {
+    /// Hamming weight of the first-round S-box output
+    SboxHammingWeight,
+    /// One bit of the first-round S-box output
+    SboxBit(u8),
+}
+
+impl CpaModel {
+    /// Leakage of the S-box output the model predicts
+    pub fn leakage(&self) -> Leakage {
+        match *self {
+            CpaModel::SboxHammingWeight => Leakage::HammingWeight,
+            CpaModel::SboxBit(bit) => Leakage::Bit(bit),
+        }
+    }
+
+    /// Predicted leakage, zero for a bit the S-box output doesn't have
+    pub fn predict(&self, plaintext: u8, guess: u8) -> f64 {
+        self.leakage()
+            .of(SBOX[(plaintext ^ guess) as usize] as u32)
+            .unwrap_or(0.0)
+    }
+}
+
+/// One-pass accumulators for CPA on some key bytes of AES-128.
+///
+/// The prediction of a guess only depends on one plaintext byte, so instead of a cross sum for
+/// every guess the traces are summed per value of each attacked plaintext byte. The sums, sums of
+/// squares and cross sums of the correlation all follow from these when the result is computed,
+/// and traces can be added in any number of batches.
+pub struct CpaAccumulator {
+    model: CpaModel,
+    /// Attacked key bytes, in the order of the sums
+    bytes: Vec<usize>,
+    num_samples: usize,
+    num_traces: u64,
+    sum: Vec<f64>,
+    sum_squares: Vec<f64>,
+    /// Sum of the traces with every value of every attacked byte, indexed [byte][value][sample]
+    value_sums: Vec<f64>,
+    /// Number of traces with every value of every attacked byte, indexed [byte][value]
+    value_counts: Vec<u64>,
+}
+
+impl CpaAccumulator {
+    /// Prepares CPA on the `bytes` key bytes, failing when a byte is listed twice, the model
+    /// predicts a bit the S-box output doesn't have, or the sums would take more than
+    /// `MAX_ACCUMULATOR_BYTES` of memory.
+    pub fn new(model: CpaModel, bytes: &[usize], num_samples: usize) -> Result<Self, String> {
+        if bytes.is_empty() {
+            return Err("No key bytes to attack".to_string());
+        }
+        if let Some(byte) = bytes.iter().find(|&&byte| byte >= KEY_BYTES) {
+            return Err(format!("There is no key byte {}", byte));
+        }
+        if let Some(byte) = bytes
+            .iter()
+            .enumerate()
+            .find_map(|(position, byte)| bytes[..position].contains(byte).then_some(byte))
+        {
+            return Err(format!("Key byte {} is listed twice", byte));
+        }
+        if let CpaModel::SboxBit(bit @ 8..) = model {
+            return Err(format!("The S-box output has no bit {}, only 0 to 7", bit));
+        }
+
+        let size = (bytes.len() * GUESSES)
+            .checked_mul(num_samples)
+            .and_then(|values| values.checked_mul(size_of::<f64>()))
+            .filter(|&size| size <= MAX_ACCUMULATOR_BYTES)
+            .ok_or_else(|| {
+                format!(
+                    "CPA on {} samples of {} key bytes needs more than the {} MiB allowed, \
+                     select a smaller window or fewer key bytes",
+                    num_samples,
+                    bytes.len(),
+                    MAX_ACCUMULATOR_BYTES >> 20
+                )
+            })?;
+
+        Ok(CpaAccumulator {
+            model,
+            bytes: bytes.to_vec(),
+            num_samples,
+            num_traces: 0,
+            sum: vec![0.0; num_samples],
+            sum_squares: vec![0.0; num_samples],
+            value_sums: vec![0.0; size / size_of::<f64>()],
+            value_counts: vec![0; bytes.len() * GUESSES],
+        })
+    }
+
+    pub fn num_traces(&self) -> u64 {
+        self.num_traces
+    }
+
+    /// Adds traces, using the first 16 input bytes of every trace as the plaintext.
+    pub fn add(&mut self, traces: &TraceSet) -> Result<(), String> {
+        if traces.num_samples() != self.num_samples {
+            return Err(format!(
+                "Expected traces of {} samples, got {}",
+                self.num_samples,
+                traces.num_samples()
+            ));
+        }
+        if let Some(index) = traces.data.iter().position(|d| d.input.len() < KEY_BYTES) {
+            return Err(format!(
+                "Trace {} has no {} byte plaintext in its input",
+                index, KEY_BYTES
+            ));
+        }
+
+        for trace in traces.traces() {
+            for ((sum, sum_squares), &y) in self
+                .sum
+                .iter_mut()
+                .zip(self.sum_squares.iter_mut())
+                .zip(trace)
+            {
+                *sum += y as f64;
+                *sum_squares += y as f64 * y as f64;
+            }
+        }
+
+        let num_samples = self.num_samples;
+        self.value_sums
+            .par_chunks_mut(GUESSES * num_samples)
+            .zip(self.value_counts.par_chunks_mut(GUESSES))
+            .zip(&self.bytes)
+            .for_each(|((sums, counts), &byte)| {
+                for (trace, data) in traces.traces().zip(&traces.data) {
+                    let value = data.input[byte] as usize;
+                    counts[value] += 1;
+
+                    let row = &mut sums[value * num_samples..(value + 1) * num_samples];
+                    for (sum, &y) in row.iter_mut().zip(trace) {
+                        *sum += y as f64;
+                    }
+                }
+            });
+
+        self.num_traces += traces.len() as u64;
+        Ok(())
+    }
+
+    /// Pearson correlation of every guess at every sample, one row per guess, for the attacked
+    /// byte at `position` in `bytes`
+    fn correlations(&self, position: usize) -> Vec<Vec<f32>> {
+        let n = self.num_traces as f64;
+        let num_samples = self.num_samples;
+        let sums = &self.value_sums[position * GUESSES * num_samples..][..GUESSES * num_samples];
+        let counts = &self.value_counts[position * GUESSES..][..GUESSES];
+
+        let trace_deviation: Vec<f64> = self
+            .sum
+            .iter()
+            .zip(&self.sum_squares)
+            .map(|(sum, sum_squares)| (n * sum_squares - sum * sum).max(0.0).sqrt())
+            .collect();
+
+        (0..GUESSES)
+            .into_par_iter()
+            .map(|guess| {
+                let mut sum_h = 0.0;
+                let mut sum_hh = 0.0;
+                let mut cross_sums = vec![0.0; num_samples];
+
+                for value in (0..GUESSES).filter(|&value| counts[value] > 0) {
+                    let h = self.model.predict(value as u8, guess as u8);
+                    sum_h += counts[value] as f64 * h;
+                    sum_hh += counts[value] as f64 * h * h;
+
+                    let row = &sums[value * num_samples..(value + 1) * num_samples];
+                    for (cross_sum, sum) in cross_sums.iter_mut().zip(row) {
+                        *cross_sum += h * sum;
+                    }
+                }
+
+                let guess_deviation = (n * sum_hh - sum_h * sum_h).max(0.0).sqrt();
+
+                cross_sums
+                    .iter()
+                    .zip(&self.sum)
+                    .zip(&trace_deviation)
+                    .map(|((cross_sum, sum), deviation)| {
+                        let r = (n * cross_sum - sum_h * sum) / (guess_deviation * deviation);
+                        if r.is_finite() {
+                            r as f32
+                        } else {
+                            0.0
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Computes the correlation traces of every attacked byte, on the time axis of the added
+    /// traces.
+    pub fn result(&self, time: TimeAxis) -> Result<CpaResult, String> {
+        let correlations = self
+            .bytes
+            .iter()
+            .enumerate()
+            .map(|(position, byte)| {
+                let mut guesses = TraceSet::new(time, self.num_samples);
+                guesses
+                    .parameters
+                    .insert("title".to_string(), format!("CPA key byte {}", byte));
+                for (guess, correlation) in self.correlations(position).iter().enumerate() {
+                    let data = TraceData {
+                        label: format!("0x{:02X}", guess),
+                        ..Default::default()
+                    };
+                    guesses.push(correlation, data)?;
+                }
+                Ok(guesses)
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        Ok(CpaResult {
+            num_traces: self.num_traces as usize,
+            bytes: self.bytes.clone(),
+            correlations,
+            known_key: None,
+        })
+    }
+}
+
+/// A key byte guess with its highest absolute correlation
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyCandidate {
+    pub guess: u8,
+    pub correlation: f32,
+    /// Sample of the peak, relative to the analysed window
+    pub sample: usize,
+}
+
+pub struct CpaResult {
+    pub num_traces: usize,
+    /// Attacked key bytes
+    pub bytes: Vec<usize>,
+    /// One trace set per attacked byte, holding the correlation trace of every guess in order
+    pub correlations: Vec<TraceSet>,
+    /// Key stored with the traces, if any
+    pub known_key: Option<Vec<u8>>,
+}
+
+impl CpaResult {
+    /// Correlation traces of a key byte, if it was attacked
+    pub fn byte_correlations(&self, byte: usize) -> Option<&TraceSet> {
+        let position = self.bytes.iter().position(|&b| b == byte)?;
+        self.correlations.get(position)
+    }
+
+    /// Guesses for one key byte, ordered by the height of their correlation peak, if it was
+    /// attacked
+    pub fn ranking(&self, byte: usize) -> Option<Vec<KeyCandidate>> {
+        let correlations = self.byte_correlations(byte)?;
+        let mut candidates: Vec<KeyCandidate> = correlations
+            .traces()
+            .enumerate()
+            .map(|(guess, trace)| {
+                let (sample, correlation) = trace
+                    .iter()
+                    .enumerate()
+                    .max_by(|(_, a), (_, b)| a.abs().total_cmp(&b.abs()))
+                    .map_or((0, 0.0), |(sample, &r)| (sample, r));
+                KeyCandidate {
+                    guess: guess as u8,
+                    correlation,
+                    sample,
+                }
+            })
+            .collect();
+
+        candidates.sort_by(|a, b| b.correlation.abs().total_cmp(&a.correlation.abs()));
+        Some(candidates)
+    }
+}
+
+/// Runs CPA on the `bytes` key bytes over the `window` samples of every trace of the source,
+/// reading it a chunk at a time.
+///
+/// `progress` is called with the fraction of traces processed so far.
+pub fn cpa(
+    traces: &dyn TraceSource,
+    window: Range<usize>,
+    bytes: &[usize],
+    model: CpaModel,
+    progress: &mut dyn FnMut(f32),
+) -> Result<CpaResult, String> {
+    if window.is_empty() || window.end > traces.num_samples() {
+        return Err(format!("Invalid sample window {:?}", window));
+    }
+
+    let mut accumulator = CpaAccumulator::new(model, bytes, window.len())?;
+    let mut known_key = None;
+
+    for chunk in read_chunks(traces, CPA_CHUNK_SIZE, window.clone()) {
+        let chunk = chunk.map_err(|e| e.to_string())?;
+        if known_key.is_none() {
+            known_key = chunk
+                .data
+                .first()
+                .filter(|data| data.key.len() >= KEY_BYTES)
+                .map(|data| data.key[..KEY_BYTES].to_vec());
+        }
+
+        accumulator.add(&chunk)?;
+        progress(accumulator.num_traces() as f32 / traces.len() as f32);
+    }
+
+    let time = traces.time();
+    let mut result = accumulator.result(TimeAxis::new(time.x(window.start), time.interval))?;
+    result.known_key = known_key;
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const KEY: [u8; KEY_BYTES] = [
+        0x2B, 0x7E, 0x15, 0x16, 0x28, 0xAE, 0xD2, 0xA6, 0xAB, 0xF7, 0x15, 0x88, 0x09, 0xCF, 0x4F,
+        0x3C,
+    ];
+
+    /// Traces leaking the Hamming weight of the S-box output of byte `i` at sample `2 * i`, with
+    /// Gaussian-like noise
+    fn leaking_traces(num_traces: usize) -> TraceSet {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut traces = TraceSet::new(TimeAxis::default(), 2 * KEY_BYTES);
+        for _ in 0..num_traces {
+            let plaintext: Vec<u8> = (0..KEY_BYTES).map(|_| rng.random()).collect();
+            let samples: Vec<f32> = (0..2 * KEY_BYTES)
+                .map(|sample| {
+                    let noise: f32 = (0..4).map(|_| rng.random_range(-1.0..1.0)).sum();
+                    let byte = sample / 2;
+                    let leak = if sample % 2 == 0 {
+                        CpaModel::SboxHammingWeight.predict(plaintext[byte], KEY[byte]) as f32
+                    } else {
+                        0.0
+                    };
+                    leak + noise
+                })
+                .collect();
+            let data = TraceData {
+                input: plaintext,
+                key: KEY.to_vec(),
+                ..Default::default()
+            };
+            traces.push(&samples, data).unwrap();
+        }
+        traces
+    }
+
+    #[test]
+    fn recovers_key() {
+        let traces = leaking_traces(500);
+        let bytes: Vec<usize> = (0..KEY_BYTES).collect();
+        let result = cpa(
+            &traces,
+            0..traces.num_samples(),
+            &bytes,
+            CpaModel::SboxHammingWeight,
+            &mut |_| {},
+        )
+        .unwrap();
+
+        assert_eq!(result.num_traces, 500);
+        assert_eq!(result.known_key.as_deref(), Some(&KEY[..]));
+        for (byte, &key) in KEY.iter().enumerate() {
+            let best = result.ranking(byte).unwrap()[0];
+            assert_eq!(best.guess, key, "byte {}", byte);
+            assert_eq!(best.sample, 2 * byte);
+        }
+    }
+
+    #[test]
+    fn attacks_some_bytes_in_batches() {
+        let traces = leaking_traces(300);
+        let mut accumulator =
+            CpaAccumulator::new(CpaModel::SboxHammingWeight, &[3, 9], traces.num_samples())
+                .unwrap();
+        accumulator
+            .add(&traces.select(0..100, 0..traces.num_samples()))
+            .unwrap();
+        accumulator
+            .add(&traces.select(100..300, 0..traces.num_samples()))
+            .unwrap();
+
+        let result = accumulator.result(traces.time).unwrap();
+        assert_eq!(result.correlations.len(), 2);
+        assert_eq!(result.ranking(3).unwrap()[0].guess, KEY[3]);
+        assert_eq!(result.ranking(9).unwrap()[0].guess, KEY[9]);
+        assert!(result.byte_correlations(0).is_none());
+        assert!(result.ranking(0).is_none());
+    }
+
+    #[test]
+    fn rejects_oversized_windows() {
+        let error = CpaAccumulator::new(CpaModel::SboxHammingWeight, &[0, 1], usize::MAX / 64)
+            .err()
+            .unwrap();
+        assert!(error.contains("MiB"));
+        assert!(CpaAccumulator::new(CpaModel::SboxHammingWeight, &[0, 1], 1 << 20).is_err());
+        assert!(CpaAccumulator::new(CpaModel::SboxHammingWeight, &[16], 10).is_err());
+    }
+
+    #[test]
+    fn rejects_duplicate_bytes_and_missing_bits() {
+        let error = CpaAccumulator::new(CpaModel::SboxHammingWeight, &[2, 5, 2], 10)
+            .err()
+            .unwrap();
+        assert!(error.contains("twice"), "{}", error);
+        assert!(CpaAccumulator::new(CpaModel::SboxBit(7), &[0], 10).is_ok());
+        assert!(CpaAccumulator::new(CpaModel::SboxBit(8), &[0], 10).is_err());
+    }
+}
@@ -0,0 +1,320 @@
+use crate::math::leakage::{Intermediate, Leakage, LeakageModel};
+use crate::trace_set::{read_chunks, TimeAxis, TraceData, TraceSet, TraceSource};
+use rayon::prelude::*;
+use std::ops::Range;
+
+/// Number of traces read from the source at once
+const DPA_CHUNK_SIZE: usize = 1024;
+
+/// One-pass accumulators for difference-of-means DPA with several selection functions.
+///
+/// Only the sum of the traces each selection function puts in the set partition is kept for
+/// every guess, the sum of the other partition follows from the sum of all traces.
+pub struct DpaAccumulator {
+    /// Selection functions, each predicting one bit
+    selections: Vec<Box<dyn LeakageModel>>,
+    num_guesses: usize,
+    num_samples: usize,
+    num_traces: u64,
+    sum: Vec<f64>,
+    /// Sum of the traces with the selected bit set, indexed [selection][guess][sample]
+    set_sums: Vec<f64>,
+    /// Number of traces with the selected bit set, indexed [selection][guess]
+    set_counts: Vec<u64>,
+}
+
+impl DpaAccumulator {
+    /// Accumulates for the `selections`, which must share their number of guesses. A trace goes
+    /// in the set partition of a guess when its prediction is above 0.5.
+    pub fn new(selections: Vec<Box<dyn LeakageModel>>, num_samples: usize) -> Self {
+        let num_guesses = selections.first().map_or(0, |model| model.num_guesses());
+        let partitions = selections.len() * num_guesses;
+
+        DpaAccumulator {
+            selections,
+            num_guesses,
+            num_samples,
+            num_traces: 0,
+            sum: vec![0.0; num_samples],
+            set_sums: vec![0.0; partitions * num_samples],
+            set_counts: vec![0; partitions],
+        }
+    }
+
+    pub fn num_traces(&self) -> u64 {
+        self.num_traces
+    }
+
+    /// Adds traces, skipping the ones the selection functions can't predict.
+    pub fn add(&mut self, traces: &TraceSet) -> Result<(), String> {
+        if traces.num_samples() != self.num_samples {
+            return Err(format!(
+                "Expected traces of {} samples, got {}",
+                self.num_samples,
+                traces.num_samples()
+            ));
+        }
+        let Some(first) = self.selections.first() else {
+            return Err("DPA needs at least one selection function".to_string());
+        };
+
+        let predicted: Vec<usize> = (0..traces.len())
+            .filter(|&index| first.predict(traces.data(index), 0).is_some())
+            .collect();
+
+        for &index in &predicted {
+            for (sum, &y) in self.sum.iter_mut().zip(traces.trace(index)) {
+                *sum += y as f64;
+            }
+        }
+
+        let (num_guesses, selections) = (self.num_guesses, &self.selections);
+        self.set_sums
+            .par_chunks_mut(self.num_samples)
+            .zip(self.set_counts.par_iter_mut())
+            .enumerate()
+            .for_each(|(partition, (sums, count))| {
+                let model = &selections[partition / num_guesses];
+                let guess = partition % num_guesses;
+
+                for &index in &predicted {
+                    if model.predict(traces.data(index), guess).unwrap_or(0.0) > 0.5 {
+                        *count += 1;
+                        for (sum, &y) in sums.iter_mut().zip(traces.trace(index)) {
+                            *sum += y as f64;
+                        }
+                    }
+                }
+            });
+
+        self.num_traces += predicted.len() as u64;
+        Ok(())
+    }
+
+    /// Difference between the mean of the set and the clear partition of every guess, averaged
+    /// over the selection functions. Zero where a partition is empty.
+    fn differences(&self, guess: usize) -> Vec<f32> {
+        let mut differences = vec![0.0; self.num_samples];
+
+        for selection in 0..self.selections.len() {
+            let partition = selection * self.num_guesses + guess;
+            let set_count = self.set_counts[partition];
+            let clear_count = self.num_traces - set_count;
+            if set_count == 0 || clear_count == 0 {
+                continue;
+            }
+
+            let set_sums = &self.set_sums[partition * self.num_samples..][..self.num_samples];
+            for ((difference, set_sum), sum) in differences.iter_mut().zip(set_sums).zip(&self.sum)
+            {
+                *difference += set_sum / set_count as f64 - (sum - set_sum) / clear_count as f64;
+            }
+        }
+
+        let num_selections = self.selections.len().max(1) as f64;
+        differences
+            .into_iter()
+            .map(|difference| (difference / num_selections) as f32)
+            .collect()
+    }
+
+    /// Computes the difference traces of every guess, on the time axis of the added traces.
+    pub fn result(&self, time: TimeAxis) -> Result<DpaResult, String> {
+        let title = match self.selections.as_slice() {
+            [model] => format!("DPA {}", model.name()),
+            selections => format!("DPA over {} selection functions", selections.len()),
+        };
+
+        let mut differences = TraceSet::new(time, self.num_samples);
+        differences.parameters.insert("title".to_string(), title);
+        for guess in 0..self.num_guesses {
+            let data = TraceData {
+                label: format!("0x{:02X}", guess),
+                ..Default::default()
+            };
+            differences.push(&self.differences(guess), data)?;
+        }
+
+        Ok(DpaResult {
+            num_traces: self.num_traces as usize,
+            differences,
+            known_guess: None,
+        })
+    }
+}
+
+/// A key guess with its highest absolute difference of means
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DpaCandidate {
+    pub guess: usize,
+    pub difference: f32,
+    /// Sample of the peak, relative to the analysed window
+    pub sample: usize,
+}
+
+pub struct DpaResult {
+    pub num_traces: usize,
+    /// Difference trace of every guess in order
+    pub differences: TraceSet,
+    /// Guess matching the key stored with the traces, if any
+    pub known_guess: Option<usize>,
+}
+
+impl DpaResult {
+    /// Guesses ordered by the height of their difference peak
+    pub fn ranking(&self) -> Vec<DpaCandidate> {
+        let mut candidates: Vec<DpaCandidate> = self
+            .differences
+            .traces()
+            .enumerate()
+            .map(|(guess, trace)| {
+                let (sample, difference) = trace
+                    .iter()
+                    .enumerate()
+                    .max_by(|(_, a), (_, b)| a.abs().total_cmp(&b.abs()))
+                    .map_or((0, 0.0), |(sample, &d)| (sample, d));
+                DpaCandidate {
+                    guess,
+                    difference,
+                    sample,
+                }
+            })
+            .collect();
+
+        candidates.sort_by(|a, b| b.difference.abs().total_cmp(&a.difference.abs()));
+        candidates
+    }
+}
+
+/// Runs difference-of-means DPA on the `window` samples, partitioning the traces with the
+/// selected `bits` of `target`. With several bits the difference traces are averaged.
+///
+/// `progress` is called with the fraction of traces processed so far.
+pub fn dpa(
+    traces: &dyn TraceSource,
+    window: Range<usize>,
+    target: Intermediate,
+    bits: &[u8],
+    progress: &mut dyn FnMut(f32),
+) -> Result<DpaResult, String> {
+    if window.is_empty() || window.end > traces.num_samples() {
+        return Err(format!("Invalid sample window {:?}", window));
+    }
+    if bits.is_empty() {
+        return Err("Select at least one bit".to_string());
+    }
+
+    let selections = bits
+        .iter()
+        .map(|&bit| target.model(Leakage::Bit(bit)))
+        .collect();
+    let mut accumulator = DpaAccumulator::new(selections, window.len());
+    let known_guess_model = target.model(Leakage::Bit(bits[0]));
+    let mut known_guess = None;
+    let mut read = 0;
+
+    for chunk in read_chunks(traces, DPA_CHUNK_SIZE, window.clone()) {
+        let chunk = chunk.map_err(|e| e.to_string())?;
+        if known_guess.is_none() {
+            known_guess = chunk
+                .data
+                .first()
+                .and_then(|data| known_guess_model.known_guess(data));
+        }
+
+        accumulator.add(&chunk)?;
+        read += chunk.len();
+        progress(read as f32 / traces.len() as f32);
+    }
+
+    if accumulator.num_traces() < 2 {
+        return Err(format!(
+            "{} can only predict {} of the traces",
+            target,
+            accumulator.num_traces()
+        ));
+    }
+
+    let time = traces.time();
+    let mut result = accumulator.result(TimeAxis::new(time.x(window.start), time.interval))?;
+    result.known_guess = known_guess;
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::aes::SBOX;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const KEY: u8 = 0x4D;
+
+    /// Traces whose sample 1 leaks bit 0 of the first S-box output and sample 2 its bit 3
+    fn leaking_traces(num_traces: usize) -> TraceSet {
+        let mut rng = StdRng::seed_from_u64(15);
+        let mut traces = TraceSet::new(TimeAxis::default(), 3);
+        for _ in 0..num_traces {
+            let plaintext: u8 = rng.random();
+            let output = SBOX[(plaintext ^ KEY) as usize];
+            let mut samples: Vec<f32> = (0..3).map(|_| rng.random_range(-1.0..1.0)).collect();
+            samples[1] += (output & 1) as f32;
+            samples[2] += 2.0 * ((output >> 3) & 1) as f32;
+            let data = TraceData {
+                input: vec![plaintext],
+                key: vec![KEY],
+                ..Default::default()
+            };
+            traces.push(&samples, data).unwrap();
+        }
+        traces
+    }
+
+    #[test]
+    fn recovers_key_from_one_bit() {
+        let traces = leaking_traces(1000);
+        let target = Intermediate::AesSboxOutput(0);
+        let result = dpa(&traces, 0..3, target, &[0], &mut |_| {}).unwrap();
+
+        assert_eq!(result.num_traces, 1000);
+        assert_eq!(result.known_guess, Some(KEY as usize));
+        let best = result.ranking()[0];
+        assert_eq!(best.guess, KEY as usize);
+        assert_eq!(best.sample, 1);
+        assert!((best.difference - 1.0).abs() < 0.2, "{}", best.difference);
+
+        assert!(dpa(&traces, 0..3, target, &[], &mut |_| {}).is_err());
+    }
+
+    #[test]
+    fn averages_differences_over_bits() {
+        let traces = leaking_traces(400);
+        let target = Intermediate::AesSboxOutput(0);
+        let result = dpa(&traces, 0..3, target, &[0, 3], &mut |_| {}).unwrap();
+
+        // Difference of the partition means of one bit, from the traces themselves
+        let difference = |guess: u8, bit: u8, sample: usize| -> f64 {
+            let mut sums = [0.0; 2];
+            let mut counts = [0.0; 2];
+            for (trace, data) in traces.traces().zip(&traces.data) {
+                let set = ((SBOX[(data.input[0] ^ guess) as usize] >> bit) & 1) as usize;
+                sums[set] += trace[sample] as f64;
+                counts[set] += 1.0;
+            }
+            sums[1] / counts[1] - sums[0] / counts[0]
+        };
+
+        for guess in [0, KEY, 0xFF] {
+            for sample in 0..3 {
+                let expected = (difference(guess, 0, sample) + difference(guess, 3, sample)) / 2.0;
+                let actual = result.differences.trace(guess as usize)[sample] as f64;
+                assert!((actual - expected).abs() < 1e-5, "{}", actual);
+            }
+        }
+        // Both leaking samples peak for the key, the bit 3 one twice as high
+        let key_trace = result.differences.trace(KEY as usize);
+        assert!((key_trace[1] - 0.5).abs() < 0.15 && (key_trace[2] - 1.0).abs() < 0.15);
+        assert_eq!(result.ranking()[0].guess, KEY as usize);
+    }
+}
@@ -0,0 +1,240 @@
+use std::ops::Range;
+
+/// Columns of the cost matrix that are searched in every row
+type SearchWindow = Vec<Range<usize>>;
+
+/// Averages every pair of samples, halving the resolution
+fn reduce_by_half(x: &[f32]) -> Vec<f32> {
+    x.chunks_exact(2)
+        .map(|pair| (pair[0] + pair[1]) / 2.0)
+        .collect()
+}
+
+/// Projects a path found at half resolution onto the full resolution, widened by `radius` cells
+/// on every side.
+fn expand_window(low_path: &[(usize, usize)], n: usize, m: usize, radius: usize) -> SearchWindow {
+    let mut covered: Vec<Option<Range<usize>>> = vec![None; n];
+
+    for &(i, j) in low_path {
+        let columns = (2 * j).saturating_sub(2 * radius)..(2 * (j + radius) + 2).min(m);
+        let rows = (2 * i).saturating_sub(2 * radius)..(2 * (i + radius) + 2).min(n);
+        for range in &mut covered[rows] {
+            *range = Some(match range.take() {
+                Some(range) => range.start.min(columns.start)..range.end.max(columns.end),
+                None => columns.clone(),
+            });
+        }
+    }
+
+    // Keep every row connected to the previous one, and the corners inside the window
+    let mut window: SearchWindow = Vec::with_capacity(n);
+    for range in covered {
+        let previous = window.last().cloned().unwrap_or(0..1);
+        let mut range = range.unwrap_or(previous.clone());
+        range.start = range.start.min(previous.end - 1);
+        range.end = range.end.max(range.start + 1);
+        window.push(range);
+    }
+    window[0].start = 0;
+    window[n - 1].end = m;
+
+    window
+}
+
+/// Dynamic time warping of `x` and `y`, limited to the cells of `window`.
+///
+/// Returns the warping path as pairs of indices into `x` and `y`, from the first to the last
+/// samples, and the summed absolute difference along it.
+fn dtw(x: &[f32], y: &[f32], window: &SearchWindow) -> (Vec<(usize, usize)>, f64) {
+    let n = x.len();
+
+    // Accumulated cost of every cell of the window, row by row
+    let mut cost: Vec<Vec<f64>> = Vec::with_capacity(n);
+    let at = |cost: &Vec<Vec<f64>>, i: usize, j: usize| {
+        let range = &window[i];
+        if range.contains(&j) {
+            cost[i][j - range.start]
+        } else {
+            f64::INFINITY
+        }
+    };
+
+    for i in 0..n {
+        let range = window[i].clone();
+        let mut row = vec![f64::INFINITY; range.len()];
+
+        for j in range.clone() {
+            let distance = (x[i] - y[j]).abs() as f64;
+            let previous = if i == 0 && j == 0 {
+                0.0
+            } else {
+                let left = if j > range.start {
+                    row[j - 1 - range.start]
+                } else {
+                    f64::INFINITY
+                };
+                let (up, diagonal) = if i > 0 {
+                    let diagonal = if j > 0 {
+                        at(&cost, i - 1, j - 1)
+                    } else {
+                        f64::INFINITY
+                    };
+                    (at(&cost, i - 1, j), diagonal)
+                } else {
+                    (f64::INFINITY, f64::INFINITY)
+                };
+                left.min(up).min(diagonal)
+            };
+            row[j - range.start] = distance + previous;
+        }
+
+        cost.push(row);
+    }
+
+    // Walk back from the last cell, preferring diagonal steps on ties
+    let (mut i, mut j) = (n - 1, y.len() - 1);
+    let total = at(&cost, i, j);
+    let mut path = vec![(i, j)];
+    while i > 0 || j > 0 {
+        let diagonal = if i > 0 && j > 0 {
+            at(&cost, i - 1, j - 1)
+        } else {
+            f64::INFINITY
+        };
+        let up = if i > 0 {
+            at(&cost, i - 1, j)
+        } else {
+            f64::INFINITY
+        };
+        let left = if j > 0 {
+            at(&cost, i, j - 1)
+        } else {
+            f64::INFINITY
+        };
+
+        if diagonal <= up && diagonal <= left {
+            i -= 1;
+            j -= 1;
+        } else if up <= left {
+            i -= 1;
+        } else {
+            j -= 1;
+        }
+        path.push((i, j));
+    }
+    path.reverse();
+
+    (path, total)
+}
+
+/// FastDTW (Salvador & Chan): approximates the optimal warping path of `x` and `y` by solving at
+/// half resolution first and refining within `radius` cells of that path, in O(n * radius).
+pub fn fast_dtw(x: &[f32], y: &[f32], radius: usize) -> (Vec<(usize, usize)>, f64) {
+    let min_size = radius + 2;
+
+    if x.len() <= min_size || y.len() <= min_size {
+        let full = vec![0..y.len(); x.len()];
+        return dtw(x, y, &full);
+    }
+
+    let (low_path, _) = fast_dtw(&reduce_by_half(x), &reduce_by_half(y), radius);
+    let window = expand_window(&low_path, x.len(), y.len(), radius);
+
+    dtw(x, y, &window)
+}
+
+/// Warps `trace` onto `reference`, every reference sample getting the average of the trace
+/// samples the warping path matches it with.
+pub fn warp_onto(reference: &[f32], trace: &[f32], radius: usize) -> Vec<f32> {
+    let (path, _) = fast_dtw(reference, trace, radius);
+
+    let mut sums = vec![0.0; reference.len()];
+    let mut counts = vec![0u32; reference.len()];
+    for (i, j) in path {
+        sums[i] += trace[j];
+        counts[i] += 1;
+    }
+
+    sums.iter()
+        .zip(counts)
+        .map(|(sum, count)| sum / count as f32)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    /// Steps of a warping path must start and end at the corners and move by one cell at a time
+    fn assert_valid_path(path: &[(usize, usize)], n: usize, m: usize) {
+        assert_eq!(path.first(), Some(&(0, 0)));
+        assert_eq!(path.last(), Some(&(n - 1, m - 1)));
+        for pair in path.windows(2) {
+            let (di, dj) = (pair[1].0 - pair[0].0, pair[1].1 - pair[0].1);
+            assert!(di <= 1 && dj <= 1 && di + dj > 0, "step {:?}", pair);
+        }
+    }
+
+    #[test]
+    fn fast_dtw_matches_full_dtw() {
+        let mut rng = StdRng::seed_from_u64(9);
+        for (n, m) in [(40, 40), (37, 52), (64, 29)] {
+            // Smooth random walks, whose optimal path FastDTW finds at a small radius
+            let mut walk = |len: usize| -> Vec<f32> {
+                let mut value = 0.0;
+                (0..len)
+                    .map(|_| {
+                        value += rng.random_range(-1.0..1.0);
+                        value
+                    })
+                    .collect()
+            };
+            let (x, y) = (walk(n), walk(m));
+
+            let (_, full) = dtw(&x, &y, &vec![0..m; n]);
+            let (path, fast) = fast_dtw(&x, &y, 8);
+            assert_valid_path(&path, n, m);
+            let along_path: f64 = path.iter().map(|&(i, j)| (x[i] - y[j]).abs() as f64).sum();
+            assert!((along_path - fast).abs() < 1e-6 * fast.max(1.0));
+            // Only the full search is exact, a narrow search may miss the optimal path
+            assert!(fast >= full - 1e-9);
+            assert!(
+                fast <= 1.1 * full,
+                "{} is far from the optimum {}",
+                fast,
+                full
+            );
+        }
+
+        let x = [0.0, 1.0, 2.0, 1.0, 0.0];
+        let (path, distance) = fast_dtw(&x, &[0.0, 1.0, 1.0, 2.0, 1.0, 0.0], 1);
+        assert_eq!(distance, 0.0);
+        assert_valid_path(&path, 5, 6);
+    }
+
+    #[test]
+    fn warp_onto_undoes_time_warp() {
+        let pulse = |t: f32| (-((t - 50.0) / 6.0).powi(2)).exp();
+        let reference: Vec<f32> = (0..100).map(|i| pulse(i as f32)).collect();
+        // The trace runs slow at first and catches up after the pulse
+        let trace: Vec<f32> = (0..100)
+            .map(|i| {
+                let t = i as f32;
+                pulse(t - 8.0 * (std::f32::consts::PI * t / 100.0).sin())
+            })
+            .collect();
+
+        let warped = warp_onto(&reference, &trace, 4);
+        let error = |a: &[f32]| {
+            a.iter()
+                .zip(&reference)
+                .map(|(a, r)| (a - r).abs())
+                .fold(0.0, f32::max)
+        };
+        assert!(error(&trace) > 0.5);
+        // Trace samples fall between reference samples, the pulse changes by up to 0.15 per sample
+        assert!(error(&warped) < 0.1, "error {}", error(&warped));
+    }
+}
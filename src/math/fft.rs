@@ -0,0 +1,598 @@
+use crate::trace_set::{TimeAxis, TraceData, TraceSet, TraceSource};
+use rayon::prelude::*;
+use realfft::num_complex::Complex;
+use realfft::{ComplexToReal, RealFftPlanner, RealToComplex};
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+use std::fmt;
+use std::ops::Range;
+use std::sync::Arc;
+
+/// Number of traces read from the source at once
+const SPECTRUM_CHUNK_SIZE: usize = 256;
+
+/// Power below which spectra in decibels are cut off, keeping them finite
+const MIN_POWER: f64 = 1e-30;
+
+/// Scratch buffers for one correlation, reused between traces
+pub struct CorrelationBuffers {
+    signal: Vec<f64>,
+    spectrum: Vec<Complex<f64>>,
+    output: Vec<f64>,
+    forward_scratch: Vec<Complex<f64>>,
+    inverse_scratch: Vec<Complex<f64>>,
+}
+
+/// Normalized cross-correlation of a fixed template against signals of a fixed length.
+///
+/// The template spectrum and FFT plans are computed once, every signal then costs one forward
+/// and one inverse real FFT, so a signal of n samples is correlated in O(n log n).
+pub struct CrossCorrelator {
+    template_len: usize,
+    signal_len: usize,
+    /// Conjugated spectrum of the zero mean template, zero padded to `signal_len`
+    template_spectrum: Vec<Complex<f64>>,
+    template_norm: f64,
+    forward: Arc<dyn RealToComplex<f64>>,
+    inverse: Arc<dyn ComplexToReal<f64>>,
+}
+
+impl CrossCorrelator {
+    /// Prepares the correlation of `template` against signals of `signal_len` samples.
+    pub fn new(template: &[f32], signal_len: usize) -> Result<Self, String> {
+        if template.is_empty() || template.len() > signal_len {
+            return Err(format!(
+                "Template of {} samples doesn't fit in a signal of {} samples",
+                template.len(),
+                signal_len
+            ));
+        }
+
+        let mut planner = RealFftPlanner::<f64>::new();
+        let forward = planner.plan_fft_forward(signal_len);
+        let inverse = planner.plan_fft_inverse(signal_len);
+
+        let mean = template.iter().map(|&y| y as f64).sum::<f64>() / template.len() as f64;
+        let mut padded = forward.make_input_vec();
+        for (padded, &y) in padded.iter_mut().zip(template) {
+            *padded = y as f64 - mean;
+        }
+        let template_norm = padded.iter().map(|y| y * y).sum::<f64>().sqrt();
+        // A flat template correlates with nothing, every correlation would be NaN
+        if template_norm == 0.0 || !template_norm.is_finite() {
+            return Err("The template is flat, choose a window whose samples vary".to_string());
+        }
+
+        let mut template_spectrum = forward.make_output_vec();
+        forward
+            .process(&mut padded, &mut template_spectrum)
+            .map_err(|e| e.to_string())?;
+        for value in template_spectrum.iter_mut() {
+            *value = value.conj();
+        }
+
+        Ok(CrossCorrelator {
+            template_len: template.len(),
+            signal_len,
+            template_spectrum,
+            template_norm,
+            forward,
+            inverse,
+        })
+    }
+
+    /// Number of correlation values for every signal, one for each position of the template
+    pub fn num_lags(&self) -> usize {
+        self.signal_len - self.template_len + 1
+    }
+
+    pub fn buffers(&self) -> CorrelationBuffers {
+        CorrelationBuffers {
+            signal: self.forward.make_input_vec(),
+            spectrum: self.forward.make_output_vec(),
+            output: self.inverse.make_output_vec(),
+            forward_scratch: self.forward.make_scratch_vec(),
+            inverse_scratch: self.inverse.make_scratch_vec(),
+        }
+    }
+
+    /// Correlates the template with every window of `signal`, writing the Pearson correlation of
+    /// the window starting at sample k to `correlations[k]`.
+    ///
+    /// Windows without any variance get a correlation of zero.
+    pub fn correlate_into(
+        &self,
+        signal: &[f32],
+        buffers: &mut CorrelationBuffers,
+        correlations: &mut [f64],
+    ) -> Result<(), String> {
+        if signal.len() != self.signal_len || correlations.len() != self.num_lags() {
+            return Err(format!(
+                "Expected a signal of {} samples, got {}",
+                self.signal_len,
+                signal.len()
+            ));
+        }
+
+        // Removing the mean keeps the sliding sums below precise for signals with a large offset
+        let mean = signal.iter().map(|&y| y as f64).sum::<f64>() / signal.len() as f64;
+        for (value, &y) in buffers.signal.iter_mut().zip(signal) {
+            *value = y as f64 - mean;
+        }
+
+        // The template has zero mean, so its correlation with each window is the window covariance
+        self.forward
+            .process_with_scratch(
+                &mut buffers.signal,
+                &mut buffers.spectrum,
+                &mut buffers.forward_scratch,
+            )
+            .map_err(|e| e.to_string())?;
+        for (value, template) in buffers.spectrum.iter_mut().zip(&self.template_spectrum) {
+            *value *= template;
+        }
+        // The inverse transform rejects rounding errors in the imaginary parts that must be zero
+        buffers.spectrum[0].im = 0.0;
+        if self.signal_len.is_multiple_of(2) {
+            buffers.spectrum.last_mut().unwrap().im = 0.0;
+        }
+        self.inverse
+            .process_with_scratch(
+                &mut buffers.spectrum,
+                &mut buffers.output,
+                &mut buffers.inverse_scratch,
+            )
+            .map_err(|e| e.to_string())?;
+
+        // Sliding sums of the signal for the standard deviation of every window
+        let m = self.template_len;
+        let (mut sum, mut sum_squares) = (0.0, 0.0);
+        for &y in &signal[..m] {
+            let y = y as f64 - mean;
+            sum += y;
+            sum_squares += y * y;
+        }
+
+        // The inverse transform isn't normalized
+        let scale = 1.0 / self.signal_len as f64;
+
+        for k in 0..self.num_lags() {
+            if k > 0 {
+                let (old, new) = (signal[k - 1] as f64 - mean, signal[k + m - 1] as f64 - mean);
+                sum += new - old;
+                sum_squares += new * new - old * old;
+            }
+
+            // Anything below the rounding error of the sliding sums is a flat window
+            let variance = sum_squares - sum * sum / m as f64;
+            correlations[k] = if variance > 1e-12 * sum_squares {
+                let r = buffers.output[k] * scale / (self.template_norm * variance.sqrt());
+                r.clamp(-1.0, 1.0)
+            } else {
+                0.0
+            };
+        }
+
+        Ok(())
+    }
+}
+
+/// Correlates `template` with every window of every trace, in parallel over the traces.
+///
+/// Returns one row of `num_samples - template.len() + 1` correlations per trace.
+pub fn normalized_cross_correlation(
+    template: &[f32],
+    traces: &TraceSet,
+) -> Result<Vec<Vec<f64>>, String> {
+    let correlator = CrossCorrelator::new(template, traces.num_samples())?;
+
+    (0..traces.len())
+        .into_par_iter()
+        .map_init(
+            || correlator.buffers(),
+            |buffers, index| {
+                let mut correlations = vec![0.0; correlator.num_lags()];
+                correlator.correlate_into(traces.trace(index), buffers, &mut correlations)?;
+                Ok(correlations)
+            },
+        )
+        .collect()
+}
+
+/// Window applied to a signal before its spectrum is computed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WindowFunction {
+    Rectangular,
+    Hann,
+    Blackman,
+}
+
+impl fmt::Display for WindowFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WindowFunction::Rectangular => write!(f, "Rectangular"),
+            WindowFunction::Hann => write!(f, "Hann"),
+            WindowFunction::Blackman => write!(f, "Blackman"),
+        }
+    }
+}
+
+impl WindowFunction {
+    /// The `len` coefficients of the symmetric window
+    pub fn coefficients(&self, len: usize) -> Vec<f64> {
+        let phase = |i: usize| 2.0 * PI * i as f64 / (len.max(2) - 1) as f64;
+        (0..len)
+            .map(|i| match self {
+                WindowFunction::Rectangular => 1.0,
+                WindowFunction::Hann => 0.5 - 0.5 * phase(i).cos(),
+                WindowFunction::Blackman => {
+                    0.42 - 0.5 * phase(i).cos() + 0.08 * (2.0 * phase(i)).cos()
+                }
+            })
+            .collect()
+    }
+}
+
+/// Scratch buffers for one spectrum, reused between signals
+pub struct SpectrumBuffers {
+    signal: Vec<f64>,
+    spectrum: Vec<Complex<f64>>,
+    scratch: Vec<Complex<f64>>,
+}
+
+/// Single-sided power spectra of windowed signals of a fixed length, scaled so a sine of
+/// amplitude A has a peak of A² whatever the window.
+pub struct SpectrumAnalyzer {
+    coefficients: Vec<f64>,
+    /// Sum of the window coefficients, the gain of the window at zero frequency
+    gain: f64,
+    forward: Arc<dyn RealToComplex<f64>>,
+}
+
+impl SpectrumAnalyzer {
+    pub fn new(signal_len: usize, window: WindowFunction) -> Result<Self, String> {
+        if signal_len < 2 {
+            return Err(format!(
+                "A spectrum needs at least 2 samples, got {}",
+                signal_len
+            ));
+        }
+
+        let coefficients = window.coefficients(signal_len);
+        let gain = coefficients.iter().sum();
+        let forward = RealFftPlanner::<f64>::new().plan_fft_forward(signal_len);
+
+        Ok(SpectrumAnalyzer {
+            coefficients,
+            gain,
+            forward,
+        })
+    }
+
+    /// Number of frequency bins, from zero to half the sample rate
+    pub fn num_bins(&self) -> usize {
+        self.coefficients.len() / 2 + 1
+    }
+
+    /// Frequencies of the bins for signals with `time` as time axis
+    pub fn frequency_axis(&self, time: TimeAxis) -> TimeAxis {
+        TimeAxis::new(0.0, 1.0 / (self.coefficients.len() as f64 * time.interval))
+    }
+
+    pub fn buffers(&self) -> SpectrumBuffers {
+        SpectrumBuffers {
+            signal: self.forward.make_input_vec(),
+            spectrum: self.forward.make_output_vec(),
+            scratch: self.forward.make_scratch_vec(),
+        }
+    }
+
+    /// Writes the power of every frequency bin of `signal` to `power`
+    pub fn power_into(
+        &self,
+        signal: &[f32],
+        buffers: &mut SpectrumBuffers,
+        power: &mut [f64],
+    ) -> Result<(), String> {
+        if signal.len() != self.coefficients.len() || power.len() != self.num_bins() {
+            return Err(format!(
+                "Expected a signal of {} samples, got {}",
+                self.coefficients.len(),
+                signal.len()
+            ));
+        }
+
+        let windowed = signal.iter().zip(&self.coefficients);
+        for (value, (&y, w)) in buffers.signal.iter_mut().zip(windowed) {
+            *value = y as f64 * w;
+        }
+        self.forward
+            .process_with_scratch(
+                &mut buffers.signal,
+                &mut buffers.spectrum,
+                &mut buffers.scratch,
+            )
+            .map_err(|e| e.to_string())?;
+
+        // Every bin but zero and, for even lengths, the Nyquist bin also holds the negative
+        // frequency
+        let even = signal.len().is_multiple_of(2);
+        let nyquist = even.then_some(self.num_bins() - 1);
+        for (k, (power, value)) in power.iter_mut().zip(&buffers.spectrum).enumerate() {
+            let amplitude = value.norm() / self.gain;
+            let amplitude = if k == 0 || Some(k) == nyquist {
+                amplitude
+            } else {
+                2.0 * amplitude
+            };
+            *power = amplitude * amplitude;
+        }
+
+        Ok(())
+    }
+}
+
+/// Amplitude of a power, or the power in decibels
+fn scale_power(power: f64, decibels: bool) -> f32 {
+    if decibels {
+        (10.0 * power.max(MIN_POWER).log10()) as f32
+    } else {
+        power.sqrt() as f32
+    }
+}
+
+/// Reads the `traces` cropped to `window` in chunks and calls `consume` with the power spectra of
+/// every chunk, in parallel over the traces of a chunk.
+fn for_each_power_chunk<F>(
+    source: &dyn TraceSource,
+    traces: Range<usize>,
+    window: Range<usize>,
+    analyzer: &SpectrumAnalyzer,
+    progress: &mut dyn FnMut(f32),
+    mut consume: F,
+) -> Result<(), String>
+where
+    F: FnMut(&TraceSet, Vec<Vec<f64>>) -> Result<(), String>,
+{
+    for start in traces.clone().step_by(SPECTRUM_CHUNK_SIZE) {
+        let end = (start + SPECTRUM_CHUNK_SIZE).min(traces.end);
+        let chunk = source
+            .read_window(start..end, window.clone())
+            .map_err(|e| e.to_string())?;
+
+        let powers = (0..chunk.len())
+            .into_par_iter()
+            .map_init(
+                || analyzer.buffers(),
+                |buffers, index| {
+                    let mut power = vec![0.0; analyzer.num_bins()];
+                    analyzer.power_into(chunk.trace(index), buffers, &mut power)?;
+                    Ok(power)
+                },
+            )
+            .collect::<Result<Vec<_>, String>>()?;
+        consume(&chunk, powers)?;
+
+        progress((end - traces.start) as f32 / traces.len() as f32);
+    }
+    Ok(())
+}
+
+fn check_ranges(
+    source: &dyn TraceSource,
+    traces: &Range<usize>,
+    window: &Range<usize>,
+) -> Result<(), String> {
+    if traces.is_empty() || traces.end > source.len() {
+        return Err(format!("Invalid trace range {:?}", traces));
+    }
+    if window.is_empty() || window.end > source.num_samples() {
+        return Err(format!("Invalid sample window {:?}", window));
+    }
+    Ok(())
+}
+
+/// Amplitude spectrum of every one of the `traces` cropped to `window`, or the power spectrum in
+/// decibels, over the frequency.
+///
+/// `progress` is called with the fraction of traces processed so far.
+pub fn spectra(
+    source: &dyn TraceSource,
+    traces: Range<usize>,
+    window: Range<usize>,
+    function: WindowFunction,
+    decibels: bool,
+    progress: &mut dyn FnMut(f32),
+) -> Result<TraceSet, String> {
+    check_ranges(source, &traces, &window)?;
+    let analyzer = SpectrumAnalyzer::new(window.len(), function)?;
+
+    let mut spectra = TraceSet::new(analyzer.frequency_axis(source.time()), analyzer.num_bins());
+    for_each_power_chunk(
+        source,
+        traces,
+        window,
+        &analyzer,
+        progress,
+        |chunk, powers| {
+            if spectra.is_empty() {
+                spectra.parameters = chunk.parameters.clone();
+            }
+            for (power, data) in powers.iter().zip(&chunk.data) {
+                let values: Vec<f32> = power.iter().map(|&p| scale_power(p, decibels)).collect();
+                spectra.push(&values, data.clone())?;
+            }
+            Ok(())
+        },
+    )?;
+
+    Ok(spectra)
+}
+
+/// Mean power spectrum of the `traces` cropped to `window`, as an amplitude or in decibels
+///
+/// `progress` is called with the fraction of traces processed so far.
+pub fn average_spectrum(
+    source: &dyn TraceSource,
+    traces: Range<usize>,
+    window: Range<usize>,
+    function: WindowFunction,
+    decibels: bool,
+    progress: &mut dyn FnMut(f32),
+) -> Result<TraceSet, String> {
+    check_ranges(source, &traces, &window)?;
+    let analyzer = SpectrumAnalyzer::new(window.len(), function)?;
+    let num_traces = traces.len();
+
+    let mut sum = vec![0.0; analyzer.num_bins()];
+    for_each_power_chunk(source, traces, window, &analyzer, progress, |_, powers| {
+        for power in powers {
+            sum.iter_mut().zip(power).for_each(|(s, p)| *s += p);
+        }
+        Ok(())
+    })?;
+
+    let mut average = TraceSet::new(analyzer.frequency_axis(source.time()), analyzer.num_bins());
+    let values: Vec<f32> = sum
+        .iter()
+        .map(|&s| scale_power(s / num_traces as f64, decibels))
+        .collect();
+    let data = TraceData {
+        label: format!("Average of {} traces", num_traces),
+        ..Default::default()
+    };
+    average.push(&values, data)?;
+    Ok(average)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    /// Pearson correlation of `template` with the window of `signal` starting at every sample
+    fn direct_correlation(template: &[f32], signal: &[f32]) -> Vec<f64> {
+        let pearson = |x: &[f32], y: &[f32]| {
+            let n = x.len() as f64;
+            let mean_x = x.iter().map(|&v| v as f64).sum::<f64>() / n;
+            let mean_y = y.iter().map(|&v| v as f64).sum::<f64>() / n;
+            let (mut xy, mut xx, mut yy) = (0.0, 0.0, 0.0);
+            for (&a, &b) in x.iter().zip(y) {
+                let (a, b) = (a as f64 - mean_x, b as f64 - mean_y);
+                xy += a * b;
+                xx += a * a;
+                yy += b * b;
+            }
+            if yy == 0.0 {
+                0.0
+            } else {
+                xy / (xx * yy).sqrt()
+            }
+        };
+
+        signal
+            .windows(template.len())
+            .map(|window| pearson(template, window))
+            .collect()
+    }
+
+    #[test]
+    fn correlation_matches_direct_definition() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for signal_len in [64, 77] {
+            // A large offset and a flat stretch test the sliding sums
+            let mut signal: Vec<f32> = (0..signal_len)
+                .map(|_| 1000.0 + rng.random_range(-1.0..1.0))
+                .collect();
+            signal[40..52].fill(1000.0);
+            let template = signal[10..22].to_vec();
+
+            let traces = TraceSet::from_samples(
+                TimeAxis::default(),
+                signal_len,
+                signal.clone(),
+                vec![TraceData::default()],
+            )
+            .unwrap();
+            let fast = normalized_cross_correlation(&template, &traces).unwrap();
+
+            let direct = direct_correlation(&template, &signal);
+            assert_eq!(fast[0].len(), direct.len());
+            for (fast, direct) in fast[0].iter().zip(&direct) {
+                assert!((fast - direct).abs() < 1e-6, "{} != {}", fast, direct);
+            }
+            assert!((fast[0][10] - 1.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn rejects_flat_templates() {
+        assert!(CrossCorrelator::new(&[3.0; 8], 32).is_err());
+        assert!(CrossCorrelator::new(&[], 32).is_err());
+        assert!(CrossCorrelator::new(&[1.0, 2.0], 1).is_err());
+    }
+
+    /// Sines of amplitude 2 at 125 Hz sampled at 1 kHz with a different phase per trace, so a
+    /// window of 256 samples puts them exactly on bin 32
+    fn sines() -> TraceSet {
+        let mut traces = TraceSet::new(TimeAxis::new(0.5, 1e-3), 300);
+        for phase in [0.0, 1.0, 2.5] {
+            let samples: Vec<f32> = (0..300)
+                .map(|i| (2.0 * (2.0 * PI * 125.0 * i as f64 * 1e-3 + phase).sin()) as f32)
+                .collect();
+            traces.push(&samples, TraceData::default()).unwrap();
+        }
+        traces
+    }
+
+    /// Index of the largest value
+    fn peak(values: &[f32]) -> usize {
+        (0..values.len())
+            .max_by(|&a, &b| values[a].total_cmp(&values[b]))
+            .unwrap()
+    }
+
+    #[test]
+    fn sine_spectrum_peaks_at_its_frequency_and_amplitude() {
+        let traces = sines();
+        for (function, tolerance) in [
+            (WindowFunction::Rectangular, 1e-4),
+            (WindowFunction::Hann, 0.02),
+            (WindowFunction::Blackman, 0.02),
+        ] {
+            let amplitudes = spectra(&traces, 0..3, 10..266, function, false, &mut |_| ()).unwrap();
+            assert_eq!(amplitudes.len(), 3);
+            assert_eq!(amplitudes.num_samples(), 129);
+            assert!((amplitudes.time.x(32) - 125.0).abs() < 1e-9);
+            for spectrum in amplitudes.traces() {
+                assert_eq!(peak(spectrum), 32);
+                assert!(
+                    (spectrum[32] - 2.0).abs() < 2.0 * tolerance,
+                    "{}",
+                    spectrum[32]
+                );
+            }
+
+            let average =
+                average_spectrum(&traces, 0..3, 10..266, function, true, &mut |_| ()).unwrap();
+            assert_eq!(average.len(), 1);
+            let decibels = average.trace(0);
+            assert_eq!(peak(decibels), 32);
+            // A power of 4 is 6.02 dB
+            assert!((decibels[32] - 6.0206).abs() < 0.2, "{}", decibels[32]);
+        }
+
+        // Far from the peak the rectangular window leaves nothing
+        let amplitudes = spectra(
+            &traces,
+            0..1,
+            0..256,
+            WindowFunction::Rectangular,
+            false,
+            &mut |_| (),
+        )
+        .unwrap();
+        assert!(amplitudes.trace(0)[64] < 1e-4);
+    }
+}
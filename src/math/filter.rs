@@ -0,0 +1,654 @@
+use crate::math::fft::WindowFunction;
+use crate::trace_set::{read_chunks, TraceSet, TraceSource};
+use rayon::prelude::*;
+use realfft::num_complex::Complex;
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+use std::fmt;
+
+/// Number of traces read from the source at once while filtering
+const FILTER_CHUNK_SIZE: usize = 1024;
+
+/// Highest order of the IIR prototypes, band filters have twice as many poles
+pub const MAX_IIR_ORDER: usize = 20;
+
+/// Imaginary parts below this are rounding errors of real roots
+const REAL_ROOT_TOLERANCE: f64 = 1e-8;
+
+/// How the filter is designed
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum FilterFamily {
+    /// Maximally flat pass band
+    Butterworth,
+    /// Chebyshev type I, steeper than Butterworth at the cost of `ripple` dB in the pass band
+    Chebyshev { ripple: f64 },
+    /// Windowed sinc with `order + 1` taps, always stable but needs a much higher order
+    Fir { window: WindowFunction },
+}
+
+impl fmt::Display for FilterFamily {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilterFamily::Butterworth => write!(f, "Butterworth"),
+            FilterFamily::Chebyshev { ripple } => write!(f, "Chebyshev {} dB", ripple),
+            FilterFamily::Fir { window } => write!(f, "FIR {}", window),
+        }
+    }
+}
+
+/// Frequencies the filter keeps, in the unit of one over the time axis
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum FilterBand {
+    LowPass(f64),
+    HighPass(f64),
+    BandPass(f64, f64),
+    /// Removes the frequencies between the two edges, a notch when they are close
+    BandStop(f64, f64),
+}
+
+impl fmt::Display for FilterBand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilterBand::LowPass(cutoff) => write!(f, "low-pass {:.4e}", cutoff),
+            FilterBand::HighPass(cutoff) => write!(f, "high-pass {:.4e}", cutoff),
+            FilterBand::BandPass(low, high) => write!(f, "band-pass {:.4e}-{:.4e}", low, high),
+            FilterBand::BandStop(low, high) => write!(f, "band-stop {:.4e}-{:.4e}", low, high),
+        }
+    }
+}
+
+/// Everything needed to design a filter for a given sample rate
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FilterSpec {
+    pub family: FilterFamily,
+    pub band: FilterBand,
+    pub order: usize,
+}
+
+impl fmt::Display for FilterSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}, order {}", self.family, self.band, self.order)
+    }
+}
+
+/// One stage of a cascade, `a[0]` is always one and both have the same length
+#[derive(Clone, Debug)]
+struct Section {
+    b: Vec<f64>,
+    a: Vec<f64>,
+}
+
+impl Section {
+    /// State of the transposed direct form II after a long run of ones
+    fn step_state(&self) -> Vec<f64> {
+        let gain = self.b.iter().sum::<f64>() / self.a.iter().sum::<f64>();
+        let order = self.a.len() - 1;
+
+        let mut state = vec![0.0; order];
+        let mut next = 0.0;
+        for i in (1..=order).rev() {
+            next += self.b[i] - self.a[i] * gain;
+            state[i - 1] = next;
+        }
+        state
+    }
+
+    /// Filters `signal` in place, starting as if it had always been at its first value
+    fn run(&self, signal: &mut [f64]) {
+        let order = self.a.len() - 1;
+        let first = signal.first().copied().unwrap_or(0.0);
+        let mut state: Vec<f64> = self.step_state().iter().map(|z| z * first).collect();
+
+        for x in signal.iter_mut() {
+            let input = *x;
+            let y = self.b[0] * input + state.first().copied().unwrap_or(0.0);
+            for i in 0..order {
+                let next = if i + 1 < order { state[i + 1] } else { 0.0 };
+                state[i] = self.b[i + 1] * input - self.a[i + 1] * y + next;
+            }
+            *x = y;
+        }
+    }
+}
+
+/// A designed filter, applied forward and backward so it doesn't shift the trace in time.
+///
+/// IIR filters are kept as a cascade of second-order sections, which stays precise at orders
+/// where a single polynomial would not.
+#[derive(Clone, Debug)]
+pub struct Filter {
+    sections: Vec<Section>,
+    /// Samples the signal is extended by at each end to settle the filter
+    padding: usize,
+}
+
+impl Filter {
+    /// Designs the filter described by `spec` for traces sampled at `sample_rate`
+    pub fn design(spec: &FilterSpec, sample_rate: f64) -> Result<Self, String> {
+        let nyquist = sample_rate / 2.0;
+        let check = |frequency: f64| {
+            if frequency > 0.0 && frequency < nyquist {
+                Ok(frequency / nyquist)
+            } else {
+                Err(format!(
+                    "Frequency {:.4e} must be between zero and half the sample rate {:.4e}",
+                    frequency, nyquist
+                ))
+            }
+        };
+        // Edges as a fraction of the Nyquist frequency
+        let edges = match spec.band {
+            FilterBand::LowPass(cutoff) | FilterBand::HighPass(cutoff) => vec![check(cutoff)?],
+            FilterBand::BandPass(low, high) | FilterBand::BandStop(low, high) => {
+                if low >= high {
+                    return Err(format!(
+                        "The band must start below its end, got {:.4e} to {:.4e}",
+                        low, high
+                    ));
+                }
+                vec![check(low)?, check(high)?]
+            }
+        };
+
+        let sections = match spec.family {
+            FilterFamily::Fir { window } => {
+                if spec.order < 2 {
+                    return Err("An FIR filter needs an order of at least 2".to_string());
+                }
+                vec![fir_section(spec.band, &edges, spec.order, window)]
+            }
+            family => {
+                if spec.order == 0 || spec.order > MAX_IIR_ORDER {
+                    return Err(format!(
+                        "The order must be between 1 and {}, got {}",
+                        MAX_IIR_ORDER, spec.order
+                    ));
+                }
+                let prototype = match family {
+                    FilterFamily::Chebyshev { ripple } if ripple > 0.0 => {
+                        chebyshev_prototype(spec.order, ripple)
+                    }
+                    FilterFamily::Chebyshev { .. } => {
+                        return Err("The pass band ripple must be above 0 dB".to_string())
+                    }
+                    _ => butterworth_prototype(spec.order),
+                };
+                let analog = prototype.transform(spec.band, &edges);
+                analog.bilinear().sections()
+            }
+        };
+
+        let order: usize = sections.iter().map(|s| s.a.len() - 1).sum();
+        Ok(Filter {
+            sections,
+            padding: 3 * (order + 1),
+        })
+    }
+
+    /// Filters `trace` forward and backward, which squares the magnitude response and cancels
+    /// the phase
+    pub fn apply(&self, trace: &[f32]) -> Vec<f32> {
+        let n = trace.len();
+        if n < 2 {
+            return trace.to_vec();
+        }
+
+        // Odd extension at both ends, so the filter has settled when it reaches the trace
+        let padding = self.padding.min(n - 1);
+        let (first, last) = (trace[0] as f64, trace[n - 1] as f64);
+        let mut signal = Vec::with_capacity(n + 2 * padding);
+        signal.extend((1..=padding).rev().map(|i| 2.0 * first - trace[i] as f64));
+        signal.extend(trace.iter().map(|&y| y as f64));
+        signal.extend((1..=padding).map(|i| 2.0 * last - trace[n - 1 - i] as f64));
+
+        for section in &self.sections {
+            section.run(&mut signal);
+        }
+        signal.reverse();
+        for section in &self.sections {
+            section.run(&mut signal);
+        }
+        signal.reverse();
+
+        signal[padding..padding + n]
+            .iter()
+            .map(|&y| y as f32)
+            .collect()
+    }
+}
+
+/// Applies `filter` to every trace of `source`, in parallel over the traces of each chunk read.
+///
+/// `progress` is called with the fraction of traces filtered so far.
+pub fn filter_traces(
+    source: &dyn TraceSource,
+    filter: &Filter,
+    progress: &mut dyn FnMut(f32),
+) -> Result<TraceSet, String> {
+    let mut filtered: Option<TraceSet> = None;
+    for chunk in read_chunks(source, FILTER_CHUNK_SIZE, 0..source.num_samples()) {
+        let chunk = chunk.map_err(|e| e.to_string())?;
+
+        let samples: Vec<Vec<f32>> = (0..chunk.len())
+            .into_par_iter()
+            .map(|index| filter.apply(chunk.trace(index)))
+            .collect();
+
+        let filtered = filtered.get_or_insert_with(|| chunk.empty_like());
+        for (trace, data) in samples.iter().zip(&chunk.data) {
+            filtered.push(trace, data.clone())?;
+        }
+        progress(filtered.len() as f32 / source.len() as f32);
+    }
+
+    Ok(filtered.unwrap_or_default())
+}
+
+/// Windowed sinc with `order + 1` taps, rounded up to an odd count so every band type has a
+/// symmetric response around the center tap
+fn fir_section(band: FilterBand, edges: &[f64], order: usize, window: WindowFunction) -> Section {
+    let taps = order + 1 + order % 2;
+    let center = (taps - 1) as f64 / 2.0;
+
+    // Ideal low-pass with cutoff `edge`, a fraction of the Nyquist frequency
+    let low_pass = |edge: f64, i: usize| {
+        let m = i as f64 - center;
+        if m == 0.0 {
+            edge
+        } else {
+            (PI * edge * m).sin() / (PI * m)
+        }
+    };
+    let impulse = |i: usize| if i as f64 == center { 1.0 } else { 0.0 };
+
+    let coefficients = window.coefficients(taps);
+    let mut b: Vec<f64> = (0..taps)
+        .map(|i| {
+            let ideal = match band {
+                FilterBand::LowPass(_) => low_pass(edges[0], i),
+                FilterBand::HighPass(_) => impulse(i) - low_pass(edges[0], i),
+                FilterBand::BandPass(..) => low_pass(edges[1], i) - low_pass(edges[0], i),
+                FilterBand::BandStop(..) => {
+                    impulse(i) - low_pass(edges[1], i) + low_pass(edges[0], i)
+                }
+            };
+            ideal * coefficients[i]
+        })
+        .collect();
+
+    // Unit gain in the middle of the pass band, as a fraction of the Nyquist frequency
+    let pass = match band {
+        FilterBand::LowPass(_) | FilterBand::BandStop(..) => 0.0,
+        FilterBand::HighPass(_) => 1.0,
+        FilterBand::BandPass(..) => (edges[0] + edges[1]) / 2.0,
+    };
+    let gain: f64 = b
+        .iter()
+        .enumerate()
+        .map(|(i, h)| h * (PI * pass * (i as f64 - center)).cos())
+        .sum();
+    b.iter_mut().for_each(|h| *h /= gain);
+
+    let mut a = vec![0.0; taps];
+    a[0] = 1.0;
+    Section { b, a }
+}
+
+/// Filter as its zeros, poles and gain
+struct Zpk {
+    zeros: Vec<Complex<f64>>,
+    poles: Vec<Complex<f64>>,
+    gain: f64,
+}
+
+/// Analog low-pass prototype with a cutoff of one radian per second
+fn butterworth_prototype(order: usize) -> Zpk {
+    let poles = (0..order)
+        .map(|k| {
+            let m = (2 * k + 1) as f64 - order as f64;
+            -Complex::new(0.0, PI * m / (2 * order) as f64).exp()
+        })
+        .collect();
+
+    Zpk {
+        zeros: vec![],
+        poles,
+        gain: 1.0,
+    }
+}
+
+/// Analog Chebyshev type I low-pass prototype, whose pass band ends at one radian per second
+fn chebyshev_prototype(order: usize, ripple: f64) -> Zpk {
+    let epsilon = (10f64.powf(ripple / 10.0) - 1.0).sqrt();
+    let mu = (1.0 / epsilon).asinh() / order as f64;
+
+    let poles: Vec<Complex<f64>> = (0..order)
+        .map(|k| {
+            let m = (2 * k + 1) as f64 - order as f64;
+            -Complex::new(mu, PI * m / (2 * order) as f64).sinh()
+        })
+        .collect();
+
+    // Even orders start the pass band at the bottom of the ripple
+    let mut gain = poles.iter().map(|p| -p).product::<Complex<f64>>().re;
+    if order.is_multiple_of(2) {
+        gain /= (1.0 + epsilon * epsilon).sqrt();
+    }
+
+    Zpk {
+        zeros: vec![],
+        poles,
+        gain,
+    }
+}
+
+impl Zpk {
+    /// Turns the analog prototype into the analog `band` filter with the given digital `edges`,
+    /// prewarped for the bilinear transform
+    fn transform(self, band: FilterBand, edges: &[f64]) -> Zpk {
+        // The bilinear transform below maps s to 4 (z - 1) / (z + 1)
+        let warped: Vec<f64> = edges.iter().map(|w| 4.0 * (PI * w / 2.0).tan()).collect();
+        let degree = self.poles.len() - self.zeros.len();
+        let zero = Complex::new(0.0, 0.0);
+
+        // Ratio of the products of the negated zeros and poles
+        let inverse_gain = |zpk: &Zpk| {
+            let zeros: Complex<f64> = zpk.zeros.iter().map(|z| -z).product();
+            let poles: Complex<f64> = zpk.poles.iter().map(|p| -p).product();
+            (zeros / poles).re
+        };
+        // Both roots of x² - 2 r x + center² around every root r
+        let split = |roots: &[Complex<f64>], center: f64| -> Vec<Complex<f64>> {
+            let offsets: Vec<Complex<f64>> = roots
+                .iter()
+                .map(|r| (r * r - center * center).sqrt())
+                .collect();
+            roots
+                .iter()
+                .zip(&offsets)
+                .map(|(r, o)| r + o)
+                .chain(roots.iter().zip(&offsets).map(|(r, o)| r - o))
+                .collect()
+        };
+
+        match band {
+            FilterBand::LowPass(_) => Zpk {
+                zeros: self.zeros.iter().map(|z| z * warped[0]).collect(),
+                poles: self.poles.iter().map(|p| p * warped[0]).collect(),
+                gain: self.gain * warped[0].powi(degree as i32),
+            },
+            FilterBand::HighPass(_) => Zpk {
+                zeros: self
+                    .zeros
+                    .iter()
+                    .map(|z| warped[0] / z)
+                    .chain(std::iter::repeat_n(zero, degree))
+                    .collect(),
+                poles: self.poles.iter().map(|p| warped[0] / p).collect(),
+                gain: self.gain * inverse_gain(&self),
+            },
+            FilterBand::BandPass(..) => {
+                let center = (warped[0] * warped[1]).sqrt();
+                let width = warped[1] - warped[0];
+                let scale = |roots: &[Complex<f64>]| -> Vec<Complex<f64>> {
+                    roots.iter().map(|r| r * width / 2.0).collect()
+                };
+
+                let mut zeros = split(&scale(&self.zeros), center);
+                zeros.extend(std::iter::repeat_n(zero, degree));
+                Zpk {
+                    zeros,
+                    poles: split(&scale(&self.poles), center),
+                    gain: self.gain * width.powi(degree as i32),
+                }
+            }
+            FilterBand::BandStop(..) => {
+                let center = (warped[0] * warped[1]).sqrt();
+                let width = warped[1] - warped[0];
+                let invert = |roots: &[Complex<f64>]| -> Vec<Complex<f64>> {
+                    roots.iter().map(|r| (width / 2.0) / r).collect()
+                };
+
+                let mut zeros = split(&invert(&self.zeros), center);
+                zeros.extend(std::iter::repeat_n(Complex::new(0.0, center), degree));
+                zeros.extend(std::iter::repeat_n(Complex::new(0.0, -center), degree));
+                Zpk {
+                    zeros,
+                    poles: split(&invert(&self.poles), center),
+                    gain: self.gain * inverse_gain(&self),
+                }
+            }
+        }
+    }
+
+    /// Maps the analog filter to a digital one, s = 4 (z - 1) / (z + 1)
+    fn bilinear(self) -> Zpk {
+        let map = |root: &Complex<f64>| (4.0 + root) / (4.0 - root);
+        let degree = self.poles.len() - self.zeros.len();
+
+        let zeros_product: Complex<f64> = self.zeros.iter().map(|z| 4.0 - z).product();
+        let poles_product: Complex<f64> = self.poles.iter().map(|p| 4.0 - p).product();
+
+        Zpk {
+            zeros: self
+                .zeros
+                .iter()
+                .map(map)
+                .chain(std::iter::repeat_n(Complex::new(-1.0, 0.0), degree))
+                .collect(),
+            poles: self.poles.iter().map(map).collect(),
+            gain: self.gain * (zeros_product / poles_product).re,
+        }
+    }
+
+    /// Cascade of second-order sections of the digital filter, every pair of poles matched with
+    /// the zeros closest to them, the poles furthest from the unit circle first
+    fn sections(self) -> Vec<Section> {
+        let mut poles = quadratic_factors(&self.poles);
+        let mut zeros = quadratic_factors(&self.zeros);
+        poles.sort_by(|a, b| a.0.norm().total_cmp(&b.0.norm()));
+
+        let mut sections = Vec::with_capacity(poles.len());
+        for (pole, a) in poles {
+            let closest = (0..zeros.len())
+                .min_by(|&i, &j| {
+                    (zeros[i].0 - pole)
+                        .norm()
+                        .total_cmp(&(zeros[j].0 - pole).norm())
+                })
+                .unwrap();
+            let (_, b) = zeros.swap_remove(closest);
+            sections.push(Section { b, a });
+        }
+
+        if let Some(first) = sections.first_mut() {
+            first.b.iter_mut().for_each(|b| *b *= self.gain);
+        }
+        sections
+    }
+}
+
+/// Groups roots into real polynomials [1, c1, c2] of degree two, a conjugate pair or two real
+/// roots each, with one root padded by a zero coefficient when the count is odd. Every factor
+/// comes with one of its roots, the one with the positive imaginary part for pairs.
+fn quadratic_factors(roots: &[Complex<f64>]) -> Vec<(Complex<f64>, Vec<f64>)> {
+    let is_real = |r: &Complex<f64>| r.im.abs() <= REAL_ROOT_TOLERANCE * r.norm().max(1.0);
+
+    let mut factors: Vec<(Complex<f64>, Vec<f64>)> = roots
+        .iter()
+        .filter(|r| !is_real(r) && r.im > 0.0)
+        .map(|r| (*r, vec![1.0, -2.0 * r.re, r.norm_sqr()]))
+        .collect();
+
+    let mut real: Vec<f64> = roots.iter().filter(|r| is_real(r)).map(|r| r.re).collect();
+    real.sort_by(f64::total_cmp);
+    for pair in real.chunks(2) {
+        let factor = match pair {
+            [r1, r2] => vec![1.0, -(r1 + r2), r1 * r2],
+            [r] => vec![1.0, -r, 0.0],
+            _ => unreachable!(),
+        };
+        factors.push((Complex::new(pair[0], 0.0), factor));
+    }
+    factors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f64 = 1000.0;
+    const LEN: usize = 4000;
+    /// Samples away from both ends, where the result doesn't depend on the padding
+    const MIDDLE: std::ops::Range<usize> = 1000..3000;
+
+    fn sine(frequency: f64) -> Vec<f32> {
+        (0..LEN)
+            .map(|i| (2.0 * PI * frequency * i as f64 / SAMPLE_RATE).sin() as f32)
+            .collect()
+    }
+
+    fn design(family: FilterFamily, band: FilterBand, order: usize) -> Filter {
+        let spec = FilterSpec {
+            family,
+            band,
+            order,
+        };
+        Filter::design(&spec, SAMPLE_RATE).unwrap()
+    }
+
+    /// Gain of `filter` for a unit sine at `frequency`
+    fn gain(filter: &Filter, frequency: f64) -> f32 {
+        let filtered = filter.apply(&sine(frequency));
+        let power = filtered[MIDDLE].iter().map(|y| y * y).sum::<f32>() / MIDDLE.len() as f32;
+        (2.0 * power).sqrt()
+    }
+
+    /// Every family at a usable order, an odd one for Chebyshev so its gain is one at zero
+    /// frequency rather than at the bottom of the ripple
+    fn families() -> [(FilterFamily, usize); 3] {
+        [
+            (FilterFamily::Butterworth, 4),
+            (FilterFamily::Chebyshev { ripple: 1.0 }, 5),
+            (
+                FilterFamily::Fir {
+                    window: WindowFunction::Hann,
+                },
+                100,
+            ),
+        ]
+    }
+
+    #[test]
+    fn passes_and_stops_the_right_bands() {
+        for (family, order) in families() {
+            // Forward and backward doubles the 1 dB ripple of the Chebyshev filter
+            let passband = match family {
+                FilterFamily::Chebyshev { .. } => 0.79..1.01,
+                _ => 0.99..1.01,
+            };
+            let bands = [
+                (FilterBand::LowPass(100.0), [20.0], [300.0, 450.0]),
+                (FilterBand::HighPass(200.0), [400.0], [20.0, 50.0]),
+                (FilterBand::BandPass(100.0, 250.0), [160.0], [20.0, 450.0]),
+                (FilterBand::BandStop(100.0, 250.0), [20.0], [160.0, 170.0]),
+            ];
+            for (band, passed, stopped) in bands {
+                let filter = design(family, band, order);
+                for frequency in passed {
+                    let gain = gain(&filter, frequency);
+                    assert!(
+                        passband.contains(&gain),
+                        "{} at {}: {}",
+                        family,
+                        frequency,
+                        gain
+                    );
+                }
+                for frequency in stopped {
+                    let gain = gain(&filter, frequency);
+                    assert!(
+                        gain < 1e-3,
+                        "{} {} at {}: {}",
+                        family,
+                        band,
+                        frequency,
+                        gain
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn filtered_sine_keeps_its_phase() {
+        // A slow sine under a fast one, the low-pass should give back the slow sine unshifted
+        let slow = sine(20.0);
+        let noisy: Vec<f32> = slow.iter().zip(sine(400.0)).map(|(a, b)| a + b).collect();
+        for (family, order) in families() {
+            let filter = design(family, FilterBand::LowPass(100.0), order);
+            let filtered = filter.apply(&noisy);
+            // Scaled by the gain, which the Chebyshev ripple keeps below one
+            let gain = gain(&filter, 20.0);
+            for i in MIDDLE {
+                assert!(
+                    (filtered[i] - gain * slow[i]).abs() < 0.01,
+                    "{} at {}: {} != {}",
+                    family,
+                    i,
+                    filtered[i],
+                    gain * slow[i]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn settles_on_steps() {
+        // From 5 to 6 half way, so both ends start from a level the filter must already be at
+        let step: Vec<f32> = (0..LEN)
+            .map(|i| if i < LEN / 2 { 5.0 } else { 6.0 })
+            .collect();
+        for (family, order) in families() {
+            let filtered = design(family, FilterBand::LowPass(100.0), order).apply(&step);
+            let settled = (0..LEN).filter(|i| i.abs_diff(LEN / 2) > 200);
+            for i in settled {
+                assert!(
+                    (filtered[i] - step[i]).abs() < 1e-3,
+                    "{} at {}: {}",
+                    family,
+                    i,
+                    filtered[i]
+                );
+            }
+
+            // The step passes the middle half way between the levels
+            let middle = (filtered[LEN / 2 - 1] + filtered[LEN / 2]) / 2.0;
+            assert!((middle - 5.5).abs() < 0.05, "{}: {}", family, middle);
+
+            let filtered = design(family, FilterBand::HighPass(100.0), order).apply(&[5.0; 500]);
+            assert!(filtered.iter().all(|y| y.abs() < 1e-3), "{}", family);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_specs() {
+        let butterworth = |band, order| FilterSpec {
+            family: FilterFamily::Butterworth,
+            band,
+            order,
+        };
+        for spec in [
+            butterworth(FilterBand::LowPass(500.0), 4),
+            butterworth(FilterBand::HighPass(0.0), 4),
+            butterworth(FilterBand::BandPass(200.0, 100.0), 4),
+            butterworth(FilterBand::LowPass(100.0), MAX_IIR_ORDER + 1),
+            FilterSpec {
+                family: FilterFamily::Chebyshev { ripple: 0.0 },
+                ..butterworth(FilterBand::LowPass(100.0), 4)
+            },
+        ] {
+            assert!(Filter::design(&spec, SAMPLE_RATE).is_err(), "{}", spec);
+        }
+    }
+}
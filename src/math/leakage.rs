@@ -0,0 +1,478 @@
+use crate::math::aes::{last_round_key, INV_SBOX, SBOX, SHIFT_ROWS};
+use crate::math::des;
+use crate::trace_set::{TraceData, TraceSet};
+use rayon::prelude::*;
+
+/// How an intermediate value turns into power consumption
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Leakage {
+    HammingWeight,
+    /// The value itself
+    Identity,
+    /// One bit of the value, 0 being the least significant
+    Bit(u8),
+}
+
+impl Leakage {
+    /// Leakage of `value`, `None` for a bit past the 32 bits of the value
+    pub fn of(&self, value: u32) -> Option<f64> {
+        match self {
+            Leakage::HammingWeight => Some(value.count_ones() as f64),
+            Leakage::Identity => Some(value as f64),
+            Leakage::Bit(bit) => value.checked_shr(*bit as u32).map(|v| (v & 1) as f64),
+        }
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Leakage::HammingWeight => "HW".to_string(),
+            Leakage::Identity => "ID".to_string(),
+            Leakage::Bit(bit) => format!("bit {}", bit),
+        }
+    }
+}
+
+/// Hypothetical leakage of a trace under a guess of part of the key.
+///
+/// Implement this for other ciphers to use them in the attacks.
+pub trait LeakageModel: Send + Sync {
+    /// Short description, for titles and reports
+    fn name(&self) -> String;
+
+    /// Number of key guesses, which are numbered from 0
+    fn num_guesses(&self) -> usize;
+
+    /// Predicted leakage of a trace for `guess`. `None` when the trace data lacks the bytes the
+    /// model needs, which must not depend on the guess.
+    fn predict(&self, data: &TraceData, guess: usize) -> Option<f64>;
+
+    /// Correct guess for the key stored with a trace, if it has one
+    fn known_guess(&self, _data: &TraceData) -> Option<usize> {
+        None
+    }
+}
+
+/// Output of an AES-128 first-round S-box, from a plaintext byte and a key byte guess
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AesSboxOutput {
+    pub byte: usize,
+    pub leakage: Leakage,
+}
+
+impl LeakageModel for AesSboxOutput {
+    fn name(&self) -> String {
+        format!(
+            "AES S-box output {} byte {}",
+            self.leakage.name(),
+            self.byte
+        )
+    }
+
+    fn num_guesses(&self) -> usize {
+        256
+    }
+
+    fn predict(&self, data: &TraceData, guess: usize) -> Option<f64> {
+        let plaintext = *data.input.get(self.byte)?;
+        let output = SBOX[(plaintext ^ guess as u8) as usize];
+        self.leakage.of(output as u32)
+    }
+
+    fn known_guess(&self, data: &TraceData) -> Option<usize> {
+        data.key.get(self.byte).map(|&b| b as usize)
+    }
+}
+
+/// State register update of the last AES-128 round: the ciphertext byte against the state byte
+/// it overwrites, which is the inverse S-box output of the ciphertext byte ShiftRows moves there.
+/// Guesses are bytes of the last round key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AesLastRoundDistance {
+    pub byte: usize,
+    /// Leakage of the XOR of both values, the Hamming distance for `HammingWeight`
+    pub leakage: Leakage,
+}
+
+impl LeakageModel for AesLastRoundDistance {
+    fn name(&self) -> String {
+        format!("AES last round {} byte {}", self.leakage.name(), self.byte)
+    }
+
+    fn num_guesses(&self) -> usize {
+        256
+    }
+
+    fn predict(&self, data: &TraceData, guess: usize) -> Option<f64> {
+        let ciphertext = data.output.get(..16)?;
+        let state = INV_SBOX[(ciphertext.get(self.byte)? ^ guess as u8) as usize];
+        let overwritten_by = ciphertext[*SHIFT_ROWS.get(self.byte)?];
+        self.leakage.of((state ^ overwritten_by) as u32)
+    }
+
+    fn known_guess(&self, data: &TraceData) -> Option<usize> {
+        let key: &[u8; 16] = data.key.get(..16)?.try_into().ok()?;
+        last_round_key(key).get(self.byte).map(|&b| b as usize)
+    }
+}
+
+/// Output of one of the eight DES first-round S-boxes, guessing the 6 round key bits it mixes in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DesSboxOutput {
+    /// S-box from 0 to 7
+    pub sbox: usize,
+    pub leakage: Leakage,
+}
+
+impl LeakageModel for DesSboxOutput {
+    fn name(&self) -> String {
+        format!("DES S-box {} output {}", self.sbox + 1, self.leakage.name())
+    }
+
+    fn num_guesses(&self) -> usize {
+        64
+    }
+
+    fn predict(&self, data: &TraceData, guess: usize) -> Option<f64> {
+        let expanded = *des::first_round_expansion(data.input.get(..8)?).get(self.sbox)?;
+        let output = des::sbox(self.sbox, expanded ^ guess as u8);
+        self.leakage.of(output as u32)
+    }
+
+    fn known_guess(&self, data: &TraceData) -> Option<usize> {
+        des::first_round_key(data.key.get(..8)?)
+            .get(self.sbox)
+            .map(|&bits| bits as usize)
+    }
+}
+
+/// Built-in intermediate values, each depending on one key byte or DES S-box
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Intermediate {
+    /// First-round S-box output of an AES-128 key byte
+    AesSboxOutput(usize),
+    /// Bit flips of the last AES-128 round state register, for a last round key byte
+    AesLastRound(usize),
+    /// Output of a DES first-round S-box, numbered from 0
+    DesSboxOutput(usize),
+}
+
+impl Intermediate {
+    /// Number of bits of the intermediate value
+    pub fn num_bits(&self) -> u8 {
+        match self {
+            Intermediate::AesSboxOutput(_) | Intermediate::AesLastRound(_) => 8,
+            Intermediate::DesSboxOutput(_) => 4,
+        }
+    }
+
+    /// Key byte or S-box of the intermediate value
+    pub fn index(&self) -> usize {
+        match *self {
+            Intermediate::AesSboxOutput(index)
+            | Intermediate::AesLastRound(index)
+            | Intermediate::DesSboxOutput(index) => index,
+        }
+    }
+
+    /// Number of key bytes or S-boxes to choose from
+    pub fn num_indices(&self) -> usize {
+        match self {
+            Intermediate::AesSboxOutput(_) | Intermediate::AesLastRound(_) => 16,
+            Intermediate::DesSboxOutput(_) => 8,
+        }
+    }
+
+    /// The same kind of intermediate value for another key byte or S-box
+    pub fn with_index(&self, index: usize) -> Self {
+        let index = index.min(self.num_indices() - 1);
+        match self {
+            Intermediate::AesSboxOutput(_) => Intermediate::AesSboxOutput(index),
+            Intermediate::AesLastRound(_) => Intermediate::AesLastRound(index),
+            Intermediate::DesSboxOutput(_) => Intermediate::DesSboxOutput(index),
+        }
+    }
+
+    /// Name of the kind of intermediate value
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Intermediate::AesSboxOutput(_) => "AES S-box output",
+            Intermediate::AesLastRound(_) => "AES last round",
+            Intermediate::DesSboxOutput(_) => "DES S-box output",
+        }
+    }
+
+    pub fn model(&self, leakage: Leakage) -> Box<dyn LeakageModel> {
+        match *self {
+            Intermediate::AesSboxOutput(byte) => Box::new(AesSboxOutput { byte, leakage }),
+            Intermediate::AesLastRound(byte) => Box::new(AesLastRoundDistance { byte, leakage }),
+            Intermediate::DesSboxOutput(sbox) => Box::new(DesSboxOutput { sbox, leakage }),
+        }
+    }
+}
+
+impl std::fmt::Display for Intermediate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            // DES S-boxes are numbered from 1
+            Intermediate::DesSboxOutput(sbox) => write!(f, "DES S-box {} output", sbox + 1),
+            _ => write!(f, "{} byte {}", self.kind(), self.index()),
+        }
+    }
+}
+
+/// Sums for the correlation of every guess of a model with the traces at every sample, which
+/// traces can be added to at any time.
+///
+/// The sums of the traces are shared by every guess, so each added trace costs one prediction
+/// and one pass over its samples per guess.
+pub struct GuessAccumulator {
+    num_samples: usize,
+    num_traces: u64,
+    sum: Vec<f64>,
+    sum_squares: Vec<f64>,
+    /// Sum and sum of squares of the predictions of every guess
+    prediction_sums: Vec<(f64, f64)>,
+    /// Sum of the predictions times the traces, indexed [guess][sample]
+    cross_sums: Vec<f64>,
+}
+
+impl GuessAccumulator {
+    pub fn new(model: &dyn LeakageModel, num_samples: usize) -> Self {
+        GuessAccumulator {
+            num_samples,
+            num_traces: 0,
+            sum: vec![0.0; num_samples],
+            sum_squares: vec![0.0; num_samples],
+            prediction_sums: vec![(0.0, 0.0); model.num_guesses()],
+            cross_sums: vec![0.0; model.num_guesses() * num_samples],
+        }
+    }
+
+    /// Number of traces added that the model could predict
+    pub fn num_traces(&self) -> u64 {
+        self.num_traces
+    }
+
+    /// Adds the traces at `indices`, skipping the ones `model` can't predict.
+    pub fn add(&mut self, model: &dyn LeakageModel, traces: &TraceSet, indices: &[usize]) {
+        let predicted: Vec<usize> = indices
+            .iter()
+            .copied()
+            .filter(|&index| model.predict(traces.data(index), 0).is_some())
+            .collect();
+
+        for &index in &predicted {
+            for ((sum, sum_squares), &y) in self
+                .sum
+                .iter_mut()
+                .zip(self.sum_squares.iter_mut())
+                .zip(traces.trace(index))
+            {
+                *sum += y as f64;
+                *sum_squares += y as f64 * y as f64;
+            }
+        }
+
+        self.cross_sums
+            .par_chunks_mut(self.num_samples.max(1))
+            .zip(self.prediction_sums.par_iter_mut())
+            .enumerate()
+            .for_each(|(guess, (cross_sums, (sum_h, sum_hh)))| {
+                for &index in &predicted {
+                    let h = model.predict(traces.data(index), guess).unwrap_or(0.0);
+                    *sum_h += h;
+                    *sum_hh += h * h;
+                    for (cross_sum, &y) in cross_sums.iter_mut().zip(traces.trace(index)) {
+                        *cross_sum += h * y as f64;
+                    }
+                }
+            });
+
+        self.num_traces += predicted.len() as u64;
+    }
+
+    /// Pearson correlation of every guess at every sample, one row per guess
+    pub fn correlations(&self) -> Vec<Vec<f32>> {
+        let n = self.num_traces as f64;
+        let trace_deviation: Vec<f64> = self
+            .sum
+            .iter()
+            .zip(&self.sum_squares)
+            .map(|(sum, sum_squares)| (n * sum_squares - sum * sum).max(0.0).sqrt())
+            .collect();
+
+        self.prediction_sums
+            .par_iter()
+            .enumerate()
+            .map(|(guess, (sum_h, sum_hh))| {
+                let guess_deviation = (n * sum_hh - sum_h * sum_h).max(0.0).sqrt();
+                let cross_sums = &self.cross_sums[guess * self.num_samples..][..self.num_samples];
+
+                cross_sums
+                    .iter()
+                    .zip(&self.sum)
+                    .zip(&trace_deviation)
+                    .map(|((cross_sum, sum), deviation)| {
+                        let r = (n * cross_sum - sum_h * sum) / (guess_deviation * deviation);
+                        if r.is_finite() {
+                            r as f32
+                        } else {
+                            0.0
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Highest absolute correlation of every guess over the samples
+    pub fn peaks(&self) -> Vec<f64> {
+        self.correlations()
+            .iter()
+            .map(|row| row.iter().fold(0.0f64, |max, r| max.max(r.abs() as f64)))
+            .collect()
+    }
+}
+
+/// Correlates every key guess of `model` with the traces at every sample, giving one trace set
+/// with the correlation trace of every guess in order. Traces the model can't predict are skipped.
+pub fn guess_correlations(traces: &TraceSet, model: &dyn LeakageModel) -> Result<TraceSet, String> {
+    let mut accumulator = GuessAccumulator::new(model, traces.num_samples());
+    accumulator.add(model, traces, &(0..traces.len()).collect::<Vec<_>>());
+    if accumulator.num_traces() < 2 {
+        return Err(format!(
+            "{} can only predict {} of the traces",
+            model.name(),
+            accumulator.num_traces()
+        ));
+    }
+
+    let mut correlations = TraceSet::new(traces.time, traces.num_samples());
+    correlations
+        .parameters
+        .insert("title".to_string(), format!("Correlation {}", model.name()));
+    for (guess, correlation) in accumulator.correlations().iter().enumerate() {
+        let data = TraceData {
+            label: format!("0x{:02X}", guess),
+            ..Default::default()
+        };
+        correlations.push(correlation, data)?;
+    }
+
+    Ok(correlations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace_set::TimeAxis;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn pearson(x: &[f64], y: &[f64]) -> f64 {
+        let n = x.len() as f64;
+        let (mean_x, mean_y) = (x.iter().sum::<f64>() / n, y.iter().sum::<f64>() / n);
+        let xy: f64 = x
+            .iter()
+            .zip(y)
+            .map(|(a, b)| (a - mean_x) * (b - mean_y))
+            .sum();
+        let xx: f64 = x.iter().map(|a| (a - mean_x).powi(2)).sum();
+        let yy: f64 = y.iter().map(|b| (b - mean_y).powi(2)).sum();
+        xy / (xx * yy).sqrt()
+    }
+
+    fn random_traces(num_traces: usize, key: u8) -> TraceSet {
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut traces = TraceSet::new(TimeAxis::default(), 3);
+        for _ in 0..num_traces {
+            let plaintext: u8 = rng.random();
+            let leak = SBOX[(plaintext ^ key) as usize].count_ones() as f32;
+            let samples = [rng.random(), leak + rng.random::<f32>(), rng.random()];
+            let data = TraceData {
+                input: vec![plaintext],
+                key: vec![key],
+                ..Default::default()
+            };
+            traces.push(&samples, data).unwrap();
+        }
+        traces
+    }
+
+    #[test]
+    fn correlations_match_pearson() {
+        let traces = random_traces(200, 0x3C);
+        let model = AesSboxOutput {
+            byte: 0,
+            leakage: Leakage::HammingWeight,
+        };
+        let correlations = guess_correlations(&traces, &model).unwrap();
+        assert_eq!(correlations.len(), 256);
+
+        for guess in [0, 0x3C, 0xFF] {
+            let predictions: Vec<f64> = (0..traces.len())
+                .map(|i| model.predict(traces.data(i), guess).unwrap())
+                .collect();
+            for sample in 0..3 {
+                let samples: Vec<f64> = traces.traces().map(|t| t[sample] as f64).collect();
+                let r = pearson(&predictions, &samples);
+                assert!((correlations.trace(guess)[sample] as f64 - r).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn accumulates_in_batches() {
+        let traces = random_traces(100, 0x3C);
+        let model = AesSboxOutput {
+            byte: 0,
+            leakage: Leakage::HammingWeight,
+        };
+        let indices: Vec<usize> = (0..100).collect();
+
+        let mut whole = GuessAccumulator::new(&model, 3);
+        whole.add(&model, &traces, &indices);
+        let mut batches = GuessAccumulator::new(&model, 3);
+        for batch in indices.chunks(30) {
+            batches.add(&model, &traces, batch);
+        }
+
+        assert_eq!(batches.num_traces(), 100);
+        let (a, b) = (whole.peaks(), batches.peaks());
+        assert!(a.iter().zip(&b).all(|(a, b)| (a - b).abs() < 1e-6));
+        let best = (0..256).max_by(|&i, &j| b[i].total_cmp(&b[j])).unwrap();
+        assert_eq!(best, 0x3C);
+    }
+
+    #[test]
+    fn out_of_range_parts_predict_nothing() {
+        let data = TraceData {
+            input: vec![0x5A; 16],
+            output: vec![0xA5; 16],
+            key: vec![0x11; 16],
+            ..Default::default()
+        };
+
+        let aes = AesLastRoundDistance {
+            byte: 16,
+            leakage: Leakage::HammingWeight,
+        };
+        assert_eq!(aes.predict(&data, 0), None);
+        assert_eq!(aes.known_guess(&data), None);
+        let aes = AesLastRoundDistance { byte: 15, ..aes };
+        assert!(aes.predict(&data, 0).is_some());
+
+        let des = DesSboxOutput {
+            sbox: 8,
+            leakage: Leakage::Identity,
+        };
+        assert_eq!(des.predict(&data, 0), None);
+        assert_eq!(des.known_guess(&data), None);
+        let des = DesSboxOutput { sbox: 7, ..des };
+        assert!(des.predict(&data, 0).is_some());
+
+        assert_eq!(Leakage::Bit(31).of(1 << 31), Some(1.0));
+        assert_eq!(Leakage::Bit(32).of(u32::MAX), None);
+        assert_eq!(Leakage::Bit(255).of(1), None);
+    }
+}
@@ -0,0 +1,414 @@
+//! Small dense linear algebra for the profiled attacks
+use bincode::{Decode, Encode};
+use rand::{rng, Rng};
+use rayon::prelude::*;
+use std::ops::{Index, IndexMut};
+
+/// Rotation sweeps after which the Jacobi method stops even if it hasn't converged
+const MAX_JACOBI_SWEEPS: usize = 50;
+
+/// Extra vectors of the subspace iteration, speeding up the convergence of the last wanted ones
+const SUBSPACE_OVERSAMPLING: usize = 10;
+
+/// Multiplications by the matrix in the subspace iteration
+const SUBSPACE_ITERATIONS: usize = 30;
+
+/// Dense row-major matrix
+#[derive(Clone, Debug, PartialEq, Encode, Decode)]
+pub struct Matrix {
+    rows: usize,
+    cols: usize,
+    data: Vec<f64>,
+}
+
+impl Matrix {
+    pub fn zeros(rows: usize, cols: usize) -> Self {
+        Matrix {
+            rows,
+            cols,
+            data: vec![0.0; rows * cols],
+        }
+    }
+
+    pub fn identity(size: usize) -> Self {
+        let mut matrix = Matrix::zeros(size, size);
+        for i in 0..size {
+            matrix[(i, i)] = 1.0;
+        }
+        matrix
+    }
+
+    pub fn row(&self, row: usize) -> &[f64] {
+        &self.data[row * self.cols..(row + 1) * self.cols]
+    }
+
+    /// Adds `scale` times the outer product of `x` with itself
+    pub fn add_outer(&mut self, x: &[f64], scale: f64) {
+        for (i, &xi) in x.iter().enumerate() {
+            for (entry, &xj) in self.data[i * self.cols..][..self.cols].iter_mut().zip(x) {
+                *entry += scale * xi * xj;
+            }
+        }
+    }
+
+    /// Adds the outer products of all `xs` with themselves, one row of the matrix per thread
+    pub fn add_outer_products(&mut self, xs: &[Vec<f64>]) {
+        self.data
+            .par_chunks_mut(self.cols)
+            .enumerate()
+            .for_each(|(i, row)| {
+                for x in xs {
+                    let xi = x[i];
+                    for (entry, &xj) in row.iter_mut().zip(x) {
+                        *entry += xi * xj;
+                    }
+                }
+            });
+    }
+
+    pub fn scale(&mut self, factor: f64) {
+        self.data.iter_mut().for_each(|entry| *entry *= factor);
+    }
+
+    pub fn trace(&self) -> f64 {
+        (0..self.rows.min(self.cols)).map(|i| self[(i, i)]).sum()
+    }
+
+    pub fn mul_vec(&self, x: &[f64]) -> Vec<f64> {
+        (0..self.rows).map(|row| dot(self.row(row), x)).collect()
+    }
+}
+
+impl Index<(usize, usize)> for Matrix {
+    type Output = f64;
+
+    fn index(&self, (row, col): (usize, usize)) -> &f64 {
+        &self.data[row * self.cols + col]
+    }
+}
+
+impl IndexMut<(usize, usize)> for Matrix {
+    fn index_mut(&mut self, (row, col): (usize, usize)) -> &mut f64 {
+        &mut self.data[row * self.cols + col]
+    }
+}
+
+/// Cholesky decomposition A = L Lᵀ of a symmetric positive definite matrix
+#[derive(Clone, Debug)]
+pub struct Cholesky {
+    /// Lower triangular factor
+    l: Matrix,
+}
+
+impl Cholesky {
+    /// Decomposes `a`, failing when it isn't positive definite
+    pub fn new(a: &Matrix) -> Result<Self, String> {
+        if a.rows != a.cols {
+            return Err(format!(
+                "Expected a square matrix, got {}x{}",
+                a.rows, a.cols
+            ));
+        }
+
+        let n = a.rows;
+        let mut l = Matrix::zeros(n, n);
+        for i in 0..n {
+            for j in 0..=i {
+                let dot: f64 = (0..j).map(|k| l[(i, k)] * l[(j, k)]).sum();
+                if i == j {
+                    let pivot = a[(i, i)] - dot;
+                    if pivot <= 0.0 || !pivot.is_finite() {
+                        return Err(format!(
+                            "The matrix isn't positive definite, pivot {} is {}",
+                            i, pivot
+                        ));
+                    }
+                    l[(i, i)] = pivot.sqrt();
+                } else {
+                    l[(i, j)] = (a[(i, j)] - dot) / l[(j, j)];
+                }
+            }
+        }
+
+        Ok(Cholesky { l })
+    }
+
+    /// Natural logarithm of the determinant of A
+    pub fn log_det(&self) -> f64 {
+        (0..self.l.rows).map(|i| self.l[(i, i)].ln()).sum::<f64>() * 2.0
+    }
+
+    /// Solves L y = b by forward substitution
+    pub fn solve_lower(&self, b: &[f64]) -> Vec<f64> {
+        let mut y = vec![0.0; b.len()];
+        for i in 0..b.len() {
+            let dot: f64 = (0..i).map(|k| self.l[(i, k)] * y[k]).sum();
+            y[i] = (b[i] - dot) / self.l[(i, i)];
+        }
+        y
+    }
+
+    /// Solves Lᵀ x = y by back substitution
+    pub fn solve_upper(&self, y: &[f64]) -> Vec<f64> {
+        let mut x = y.to_vec();
+        for i in (0..x.len()).rev() {
+            let dot: f64 = (i + 1..x.len()).map(|k| self.l[(k, i)] * x[k]).sum();
+            x[i] = (x[i] - dot) / self.l[(i, i)];
+        }
+        x
+    }
+
+    /// Squared Mahalanobis norm xᵀ A⁻¹ x
+    pub fn mahalanobis(&self, x: &[f64]) -> f64 {
+        self.solve_lower(x).iter().map(|y| y * y).sum()
+    }
+}
+
+/// Eigenvalues and eigenvectors of a symmetric matrix, largest eigenvalue first
+#[derive(Clone, Debug)]
+pub struct SymmetricEigen {
+    pub values: Vec<f64>,
+    /// Unit eigenvectors, one per eigenvalue
+    pub vectors: Vec<Vec<f64>>,
+}
+
+impl SymmetricEigen {
+    /// Diagonalizes `a` with cyclic Jacobi rotations
+    pub fn new(a: &Matrix) -> Self {
+        let n = a.rows;
+        let mut a = a.clone();
+        let mut v = Matrix::identity(n);
+
+        for _ in 0..MAX_JACOBI_SWEEPS {
+            let off_diagonal: f64 = (0..n)
+                .flat_map(|p| (p + 1..n).map(move |q| (p, q)))
+                .map(|(p, q)| a[(p, q)] * a[(p, q)])
+                .sum();
+            let diagonal: f64 = (0..n).map(|i| a[(i, i)] * a[(i, i)]).sum();
+            if off_diagonal <= f64::EPSILON * f64::EPSILON * diagonal {
+                break;
+            }
+
+            for p in 0..n {
+                for q in p + 1..n {
+                    if a[(p, q)] == 0.0 {
+                        continue;
+                    }
+
+                    // Rotation zeroing a[(p, q)]
+                    let theta = (a[(q, q)] - a[(p, p)]) / (2.0 * a[(p, q)]);
+                    let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                    let c = 1.0 / (t * t + 1.0).sqrt();
+                    let s = t * c;
+
+                    for k in 0..n {
+                        let (akp, akq) = (a[(k, p)], a[(k, q)]);
+                        a[(k, p)] = c * akp - s * akq;
+                        a[(k, q)] = s * akp + c * akq;
+                    }
+                    for k in 0..n {
+                        let (apk, aqk) = (a[(p, k)], a[(q, k)]);
+                        a[(p, k)] = c * apk - s * aqk;
+                        a[(q, k)] = s * apk + c * aqk;
+                    }
+                    for k in 0..n {
+                        let (vkp, vkq) = (v[(k, p)], v[(k, q)]);
+                        v[(k, p)] = c * vkp - s * vkq;
+                        v[(k, q)] = s * vkp + c * vkq;
+                    }
+                }
+            }
+        }
+
+        let mut order: Vec<usize> = (0..n).collect();
+        order.sort_by(|&i, &j| a[(j, j)].total_cmp(&a[(i, i)]));
+
+        SymmetricEigen {
+            values: order.iter().map(|&i| a[(i, i)]).collect(),
+            vectors: order
+                .iter()
+                .map(|&i| (0..n).map(|k| v[(k, i)]).collect())
+                .collect(),
+        }
+    }
+
+    /// The `count` largest eigenvalues of a symmetric positive semi-definite matrix and their
+    /// eigenvectors, from a randomized subspace iteration. Much faster than [`SymmetricEigen::new`]
+    /// when few of many eigenvectors are wanted.
+    pub fn largest(a: &Matrix, count: usize) -> Self {
+        let n = a.rows;
+        let size = (count + SUBSPACE_OVERSAMPLING).min(n);
+        if size == 0 {
+            return SymmetricEigen {
+                values: vec![],
+                vectors: vec![],
+            };
+        }
+
+        let mut basis: Vec<Vec<f64>> = (0..size).map(|_| random_vector(n)).collect();
+        orthonormalize(&mut basis);
+        // A basis of the whole space needs no iterations
+        if size < n {
+            for _ in 0..SUBSPACE_ITERATIONS {
+                basis = basis.par_iter().map(|x| a.mul_vec(x)).collect();
+                orthonormalize(&mut basis);
+            }
+        }
+
+        // Rayleigh-Ritz: diagonalize the matrix restricted to the basis
+        let images: Vec<Vec<f64>> = basis.par_iter().map(|x| a.mul_vec(x)).collect();
+        let mut restricted = Matrix::zeros(size, size);
+        for i in 0..size {
+            for j in 0..size {
+                restricted[(i, j)] = dot(&basis[i], &images[j]);
+            }
+        }
+        let small = SymmetricEigen::new(&restricted);
+
+        let vectors = small
+            .vectors
+            .iter()
+            .take(count)
+            .map(|coefficients| {
+                let mut vector = vec![0.0; n];
+                for (c, x) in coefficients.iter().zip(&basis) {
+                    for (v, x) in vector.iter_mut().zip(x) {
+                        *v += c * x;
+                    }
+                }
+                vector
+            })
+            .collect();
+
+        SymmetricEigen {
+            values: small.values.into_iter().take(count).collect(),
+            vectors,
+        }
+    }
+}
+
+pub fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+fn random_vector(n: usize) -> Vec<f64> {
+    let mut rng = rng();
+    (0..n).map(|_| rng.random::<f64>() - 0.5).collect()
+}
+
+/// Modified Gram-Schmidt with a second pass for stability. Vectors that vanish because they
+/// depend on the previous ones are replaced by random ones.
+fn orthonormalize(vectors: &mut [Vec<f64>]) {
+    for i in 0..vectors.len() {
+        let (done, rest) = vectors.split_at_mut(i);
+        let vector = &mut rest[0];
+        let scale = dot(vector, vector).sqrt();
+
+        loop {
+            for _ in 0..2 {
+                for previous in done.iter() {
+                    let projection = dot(vector, previous);
+                    for (v, p) in vector.iter_mut().zip(previous) {
+                        *v -= projection * p;
+                    }
+                }
+            }
+
+            let norm = dot(vector, vector).sqrt();
+            if norm > 1e-10 * scale && norm > 0.0 {
+                vector.iter_mut().for_each(|v| *v /= norm);
+                break;
+            }
+            *vector = random_vector(vector.len());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matrix(rows: &[&[f64]]) -> Matrix {
+        let mut matrix = Matrix::zeros(rows.len(), rows[0].len());
+        for (i, row) in rows.iter().enumerate() {
+            for (j, &value) in row.iter().enumerate() {
+                matrix[(i, j)] = value;
+            }
+        }
+        matrix
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() <= 1e-9 * b.abs().max(1.0), "{} != {}", a, b);
+    }
+
+    #[test]
+    fn cholesky_solves_known_matrix() {
+        // L = [[2, 0, 0], [1, 3, 0], [-1, 1, 2]], so det A = 12²
+        let a = matrix(&[&[4.0, 2.0, -2.0], &[2.0, 10.0, 2.0], &[-2.0, 2.0, 6.0]]);
+        let cholesky = Cholesky::new(&a).unwrap();
+        assert_close(cholesky.log_det(), 144f64.ln());
+
+        // A [1, 2, 3] = [2, 28, 20]
+        let b = [2.0, 28.0, 20.0];
+        let x = cholesky.solve_upper(&cholesky.solve_lower(&b));
+        for (x, expected) in x.iter().zip([1.0, 2.0, 3.0]) {
+            assert_close(*x, expected);
+        }
+        assert_close(cholesky.mahalanobis(&b), dot(&b, &x));
+    }
+
+    #[test]
+    fn cholesky_rejects_indefinite_matrices() {
+        assert!(Cholesky::new(&matrix(&[&[1.0, 2.0], &[2.0, 1.0]])).is_err());
+        assert!(Cholesky::new(&matrix(&[&[1.0, 0.0], &[0.0, 0.0]])).is_err());
+        assert!(Cholesky::new(&matrix(&[&[1.0, 0.0]])).is_err());
+    }
+
+    /// Gram matrix MᵀM of a fixed pseudo-random M, symmetric positive semi-definite
+    fn gram(size: usize) -> Matrix {
+        let m: Vec<Vec<f64>> = (0..size)
+            .map(|i| {
+                (0..size)
+                    .map(|j| ((i * 7 + j * 13) % 17) as f64 - 8.0)
+                    .collect()
+            })
+            .collect();
+        let mut gram = Matrix::zeros(size, size);
+        gram.add_outer_products(&m);
+        gram
+    }
+
+    #[test]
+    fn jacobi_diagonalizes() {
+        let a = gram(6);
+        let eigen = SymmetricEigen::new(&a);
+
+        assert!(eigen.values.windows(2).all(|pair| pair[0] >= pair[1]));
+        assert_close(eigen.values.iter().sum(), a.trace());
+        for (i, (value, vector)) in eigen.values.iter().zip(&eigen.vectors).enumerate() {
+            for (av, v) in a.mul_vec(vector).iter().zip(vector) {
+                assert!((av - value * v).abs() < 1e-9 * eigen.values[0]);
+            }
+            for (j, other) in eigen.vectors.iter().enumerate() {
+                let expected = if i == j { 1.0 } else { 0.0 };
+                assert!((dot(vector, other) - expected).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn largest_matches_jacobi() {
+        let a = gram(40);
+        let all = SymmetricEigen::new(&a);
+        let largest = SymmetricEigen::largest(&a, 3);
+
+        assert_eq!(largest.values.len(), 3);
+        for i in 0..3 {
+            assert!((largest.values[i] - all.values[i]).abs() < 1e-6 * all.values[0]);
+            // Eigenvectors are only defined up to their sign
+            assert!((dot(&largest.vectors[i], &all.vectors[i]).abs() - 1.0).abs() < 1e-6);
+        }
+        assert!(SymmetricEigen::largest(&a, 0).values.is_empty());
+    }
+}
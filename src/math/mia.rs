@@ -0,0 +1,439 @@
+use crate::math::leakage::LeakageModel;
+use crate::math::MAX_ACCUMULATOR_BYTES;
+use crate::trace_set::{read_chunks, TimeAxis, TraceData, TraceSet, TraceSource};
+use rayon::prelude::*;
+use std::f64::consts::LN_2;
+use std::fmt;
+use std::ops::Range;
+
+/// Number of traces read from the source at once
+const MIA_CHUNK_SIZE: usize = 1024;
+
+/// Samples whose information is estimated between two progress updates
+const MIA_SAMPLE_BATCH: usize = 64;
+
+/// Grid points of the kernel density estimate of every sample
+const KERNEL_GRID: usize = 64;
+
+/// Kernels are cut off this many bandwidths from their center
+const KERNEL_CUTOFF: f64 = 3.0;
+
+/// How the densities of the samples are estimated
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MiaEstimator {
+    /// Histogram with the given number of bins of equal width between the extremes of a sample
+    Histogram(usize),
+    /// Gaussian kernel density on a grid, one bandwidth per sample from Silverman's rule
+    Kernel,
+}
+
+impl fmt::Display for MiaEstimator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MiaEstimator::Histogram(bins) => write!(f, "histogram of {} bins", bins),
+            MiaEstimator::Kernel => write!(f, "Gaussian kernel"),
+        }
+    }
+}
+
+impl MiaEstimator {
+    fn bins(&self) -> usize {
+        match self {
+            MiaEstimator::Histogram(bins) => (*bins).clamp(2, u8::MAX as usize + 1),
+            MiaEstimator::Kernel => KERNEL_GRID,
+        }
+    }
+}
+
+/// Mutual information in bits of the two variables counted in `table`, indexed [row][column]
+fn mutual_information(table: &[f64], columns: usize) -> f64 {
+    let total: f64 = table.iter().sum();
+    if total <= 0.0 {
+        return 0.0;
+    }
+
+    let mut column_sums = vec![0.0; columns];
+    for row in table.chunks(columns) {
+        column_sums.iter_mut().zip(row).for_each(|(s, x)| *s += x);
+    }
+
+    let mut information = 0.0;
+    for row in table.chunks(columns) {
+        let row_sum: f64 = row.iter().sum();
+        for (&joint, &column_sum) in row.iter().zip(&column_sums) {
+            if joint > 0.0 {
+                information += joint * (joint * total / (row_sum * column_sum)).ln();
+            }
+        }
+    }
+    information / total / LN_2
+}
+
+/// Truncated Gaussian kernel with a standard deviation of `width` bins, centered on its middle
+fn gaussian_kernel(width: f64) -> Vec<f64> {
+    let radius = (KERNEL_CUTOFF * width).ceil() as isize;
+    let kernel: Vec<f64> = (-radius..=radius)
+        .map(|x| (-0.5 * (x as f64 / width).powi(2)).exp())
+        .collect();
+    let sum: f64 = kernel.iter().sum();
+    kernel.into_iter().map(|k| k / sum).collect()
+}
+
+/// Smooths every row of `table` with `kernel`, keeping the mass that spills over the edges
+fn smooth_rows(table: &[f64], columns: usize, kernel: &[f64]) -> Vec<f64> {
+    let radius = (kernel.len() / 2) as isize;
+    let mut smoothed = vec![0.0; table.len()];
+    for (row, out) in table.chunks(columns).zip(smoothed.chunks_mut(columns)) {
+        for (center, &mass) in row.iter().enumerate().filter(|(_, &m)| m > 0.0) {
+            for (offset, &k) in kernel.iter().enumerate() {
+                let column = center as isize + offset as isize - radius;
+                if (0..columns as isize).contains(&column) {
+                    out[column as usize] += mass * k;
+                }
+            }
+        }
+    }
+    smoothed
+}
+
+/// A key guess with its highest mutual information
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MiaCandidate {
+    pub guess: usize,
+    /// Mutual information in bits
+    pub information: f32,
+    /// Sample of the peak, relative to the analysed window
+    pub sample: usize,
+}
+
+pub struct MiaResult {
+    pub num_traces: usize,
+    /// Mutual information trace of every guess in order, in bits
+    pub information: TraceSet,
+    /// Guess matching the key stored with the traces, if any
+    pub known_guess: Option<usize>,
+}
+
+impl MiaResult {
+    /// Guesses ordered by the height of their mutual information peak
+    pub fn ranking(&self) -> Vec<MiaCandidate> {
+        let mut candidates: Vec<MiaCandidate> = self
+            .information
+            .traces()
+            .enumerate()
+            .map(|(guess, trace)| {
+                let (sample, information) = trace
+                    .iter()
+                    .enumerate()
+                    .max_by(|(_, a), (_, b)| a.total_cmp(b))
+                    .map_or((0, 0.0), |(sample, &i)| (sample, i));
+                MiaCandidate {
+                    guess,
+                    information,
+                    sample,
+                }
+            })
+            .collect();
+        candidates.sort_by(|a, b| b.information.total_cmp(&a.information));
+        candidates
+    }
+}
+
+/// Runs mutual information analysis on the `window` samples: for every guess and sample, the
+/// mutual information between the samples and the classes `model` predicts, which are the
+/// predictions rounded to integers.
+///
+/// The source is read once for the range of every sample, then once per block of samples to
+/// quantize them, the samples of a block being held in memory as one byte each. The class of
+/// every guess for every trace is held in memory as two bytes, and fails the analysis when it
+/// leaves no room for a block within `MAX_ACCUMULATOR_BYTES`.
+///
+/// `progress` is called with the fraction of the work done so far.
+pub fn mia(
+    traces: &dyn TraceSource,
+    window: Range<usize>,
+    model: &dyn LeakageModel,
+    estimator: MiaEstimator,
+    progress: &mut dyn FnMut(f32),
+) -> Result<MiaResult, String> {
+    mia_within(
+        traces,
+        window,
+        model,
+        estimator,
+        MAX_ACCUMULATOR_BYTES,
+        progress,
+    )
+}
+
+/// [`mia`] holding at most `max_bytes` of classes and quantized samples at a time
+fn mia_within(
+    traces: &dyn TraceSource,
+    window: Range<usize>,
+    model: &dyn LeakageModel,
+    estimator: MiaEstimator,
+    max_bytes: usize,
+    progress: &mut dyn FnMut(f32),
+) -> Result<MiaResult, String> {
+    if window.is_empty() || window.end > traces.num_samples() {
+        return Err(format!("Invalid sample window {:?}", window));
+    }
+    let num_samples = window.len();
+    let num_guesses = model.num_guesses();
+    let bins = estimator.bins();
+
+    // First pass: range and spread of every sample over the traces the model predicts
+    let mut min = vec![f32::INFINITY; num_samples];
+    let mut max = vec![f32::NEG_INFINITY; num_samples];
+    let mut sum = vec![0.0; num_samples];
+    let mut sum_squares = vec![0.0; num_samples];
+    let mut num_traces = 0;
+    let mut known_guess = None;
+    let mut read = 0;
+
+    for chunk in read_chunks(traces, MIA_CHUNK_SIZE, window.clone()) {
+        let chunk = chunk.map_err(|e| e.to_string())?;
+        for index in 0..chunk.len() {
+            let data = chunk.data(index);
+            if model.predict(data, 0).is_none() {
+                continue;
+            }
+            if known_guess.is_none() {
+                known_guess = model.known_guess(data);
+            }
+            for (sample, &y) in chunk.trace(index).iter().enumerate() {
+                min[sample] = min[sample].min(y);
+                max[sample] = max[sample].max(y);
+                sum[sample] += y as f64;
+                sum_squares[sample] += y as f64 * y as f64;
+            }
+            num_traces += 1;
+        }
+        read += chunk.len();
+        progress(0.25 * read as f32 / traces.len() as f32);
+    }
+
+    if num_traces < 2 {
+        return Err(format!(
+            "{} can only predict {} of the traces",
+            model.name(),
+            num_traces
+        ));
+    }
+    let classes_size = num_guesses
+        .checked_mul(num_traces)
+        .and_then(|classes| classes.checked_mul(size_of::<u16>()))
+        .filter(|&size| size < max_bytes);
+    let Some(classes_size) = classes_size else {
+        return Err(format!(
+            "MIA of {} traces with {} guesses needs more than the {} MiB allowed, \
+             analyse fewer traces",
+            num_traces,
+            num_guesses,
+            max_bytes >> 20
+        ));
+    };
+
+    // Kernel width in grid steps, from Silverman's rule of thumb
+    let kernels: Vec<Option<Vec<f64>>> = (0..num_samples)
+        .map(|sample| match estimator {
+            MiaEstimator::Histogram(_) => None,
+            MiaEstimator::Kernel => {
+                let n = num_traces as f64;
+                let mean = sum[sample] / n;
+                let deviation = (sum_squares[sample] / n - mean * mean).max(0.0).sqrt();
+                let bandwidth = 1.06 * deviation * n.powf(-0.2);
+                let step = (max[sample] - min[sample]) as f64 / bins as f64;
+                (step > 0.0).then(|| gaussian_kernel((bandwidth / step).max(0.5)))
+            }
+        })
+        .collect();
+
+    // The class of every guess, indexed [guess][trace], filled along with the first block
+    let mut classes = vec![0u16; num_guesses * num_traces];
+    let mut num_classes = 1;
+    // Information indexed [guess][sample]
+    let mut information = vec![0.0f32; num_guesses * num_samples];
+    // The quantized samples of a block take the rest of the memory
+    let block_len = ((max_bytes - classes_size) / num_traces).clamp(1, num_samples);
+    let done = |samples: f32| 0.25 + 0.75 * samples / num_samples as f32;
+
+    for block_start in (0..num_samples).step_by(block_len) {
+        let block = block_start..(block_start + block_len).min(num_samples);
+        let first_block = block.start == 0;
+
+        // The bin of every sample of the block, indexed [sample][trace]
+        let mut quantized = vec![0u8; block.len() * num_traces];
+        let mut trace = 0;
+        read = 0;
+
+        let block_window = window.start + block.start..window.start + block.end;
+        for chunk in read_chunks(traces, MIA_CHUNK_SIZE, block_window) {
+            let chunk = chunk.map_err(|e| e.to_string())?;
+            for index in 0..chunk.len() {
+                let data = chunk.data(index);
+                if model.predict(data, 0).is_none() {
+                    continue;
+                }
+                if first_block {
+                    for guess in 0..num_guesses {
+                        let prediction = model.predict(data, guess).unwrap_or(0.0);
+                        classes[guess * num_traces + trace] =
+                            prediction.round().clamp(0.0, u16::MAX as f64) as u16;
+                    }
+                }
+                for (offset, &y) in chunk.trace(index).iter().enumerate() {
+                    let sample = block.start + offset;
+                    let width = (max[sample] - min[sample]) / bins as f32;
+                    let bin = if width > 0.0 {
+                        ((y - min[sample]) / width) as usize
+                    } else {
+                        0
+                    };
+                    quantized[offset * num_traces + trace] = bin.min(bins - 1) as u8;
+                }
+                trace += 1;
+            }
+            read += chunk.len();
+            let fraction = read as f32 / traces.len() as f32;
+            progress(done(
+                block.start as f32 + 0.5 * block.len() as f32 * fraction,
+            ));
+        }
+
+        if first_block {
+            num_classes = classes.iter().max().map_or(1, |&c| c as usize + 1);
+        }
+
+        for batch in block.clone().step_by(MIA_SAMPLE_BATCH) {
+            let batch = batch..(batch + MIA_SAMPLE_BATCH).min(block.end);
+            let values: Vec<Vec<f32>> = batch
+                .clone()
+                .into_par_iter()
+                .map(|sample| {
+                    let quantized = &quantized[(sample - block.start) * num_traces..][..num_traces];
+                    (0..num_guesses)
+                        .map(|guess| {
+                            let classes = &classes[guess * num_traces..][..num_traces];
+                            let mut table = vec![0.0; num_classes * bins];
+                            for (&class, &bin) in classes.iter().zip(quantized) {
+                                table[class as usize * bins + bin as usize] += 1.0;
+                            }
+                            let table = match &kernels[sample] {
+                                Some(kernel) => smooth_rows(&table, bins, kernel),
+                                None => table,
+                            };
+                            mutual_information(&table, bins) as f32
+                        })
+                        .collect()
+                })
+                .collect();
+
+            for (sample, values) in batch.clone().zip(values) {
+                for (guess, value) in values.into_iter().enumerate() {
+                    information[guess * num_samples + sample] = value;
+                }
+            }
+            let computed = (batch.end - block.start) as f32;
+            progress(done(
+                block.start as f32 + 0.5 * (block.len() as f32 + computed),
+            ));
+        }
+    }
+
+    let time = traces.time();
+    let mut trace_set = TraceSet::new(
+        TimeAxis::new(time.x(window.start), time.interval),
+        num_samples,
+    );
+    trace_set.parameters.insert(
+        "title".to_string(),
+        format!("MIA {} ({})", model.name(), estimator),
+    );
+    for (guess, values) in information.chunks(num_samples).enumerate() {
+        let data = TraceData {
+            label: format!("0x{:02X}", guess),
+            ..Default::default()
+        };
+        trace_set.push(values, data)?;
+    }
+
+    Ok(MiaResult {
+        num_traces,
+        information: trace_set,
+        known_guess,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::leakage::{AesSboxOutput, Leakage};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const KEY: u8 = 0xA7;
+
+    const MODEL: AesSboxOutput = AesSboxOutput {
+        byte: 0,
+        leakage: Leakage::HammingWeight,
+    };
+
+    /// Traces leaking the Hamming weight of the first S-box output at sample 2 only
+    fn leaking_traces(num_traces: usize) -> TraceSet {
+        let mut rng = StdRng::seed_from_u64(19);
+        let mut traces = TraceSet::new(TimeAxis::default(), 5);
+        for _ in 0..num_traces {
+            let data = TraceData {
+                input: vec![rng.random()],
+                key: vec![KEY],
+                ..Default::default()
+            };
+            let leak = MODEL.predict(&data, KEY as usize).unwrap() as f32;
+            let mut samples: Vec<f32> = (0..5).map(|_| rng.random_range(-1.0..1.0)).collect();
+            samples[2] += leak;
+            traces.push(&samples, data).unwrap();
+        }
+        traces
+    }
+
+    #[test]
+    fn recovers_key() {
+        let traces = leaking_traces(2000);
+        for estimator in [MiaEstimator::Histogram(16), MiaEstimator::Kernel] {
+            let result = mia(&traces, 0..5, &MODEL, estimator, &mut |_| {}).unwrap();
+            assert_eq!(result.num_traces, 2000);
+            assert_eq!(result.known_guess, Some(KEY as usize));
+            assert_eq!(result.information.len(), 256);
+
+            let best = result.ranking()[0];
+            assert_eq!(best.guess, KEY as usize, "{}", estimator);
+            assert_eq!(best.sample, 2);
+        }
+    }
+
+    #[test]
+    fn blocks_of_samples_give_the_same_information() {
+        let traces = leaking_traces(300);
+        let estimator = MiaEstimator::Histogram(8);
+        let whole = mia(&traces, 1..5, &MODEL, estimator, &mut |_| {}).unwrap();
+
+        // Room for the classes and one or three samples of quantized traces at a time
+        let classes_size = 256 * 300 * 2;
+        for max_bytes in [classes_size + 300, classes_size + 900] {
+            let mut fractions = vec![];
+            let blocks = mia_within(&traces, 1..5, &MODEL, estimator, max_bytes, &mut |f| {
+                fractions.push(f)
+            })
+            .unwrap();
+            assert_eq!(blocks.information, whole.information);
+            assert!(fractions.windows(2).all(|pair| pair[0] <= pair[1]));
+            assert_eq!(fractions.last(), Some(&1.0));
+        }
+
+        let error = mia_within(&traces, 1..5, &MODEL, estimator, classes_size, &mut |_| {})
+            .err()
+            .unwrap();
+        assert!(error.contains("MiB"), "{}", error);
+    }
+}
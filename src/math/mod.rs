@@ -0,0 +1,333 @@
+pub mod aes;
+pub mod cpa;
+pub mod des;
+pub mod dpa;
+pub mod dtw;
+pub mod fft;
+pub mod filter;
+pub mod leakage;
+pub mod linalg;
+pub mod mia;
+pub mod moments;
+pub mod pipeline;
+pub mod projection;
+pub mod rank;
+pub mod snr;
+pub mod stft;
+pub mod template;
+pub mod ttest;
+
+use crate::math::dtw::warp_onto;
+use crate::math::fft::CrossCorrelator;
+use crate::trace_set::{read_chunks, TraceSet, TraceSource};
+use bincode::{config, Decode, Encode};
+use rayon::prelude::*;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::time::Instant;
+
+/// Largest memory the sums of a streaming analysis may take, in bytes. The analyses hold sums
+/// for every sample of their window, so this bounds the window they accept.
+pub(crate) const MAX_ACCUMULATOR_BYTES: usize = 2 << 30;
+
+/// Number of traces read from the source at once while shifting
+const ALIGN_CHUNK_SIZE: usize = 1024;
+
+/// Moves the samples `shift` places to the right, or to the left when negative. Samples shifted
+/// out of the trace are dropped and the vacated ones are set to zero.
+fn shift_samples(data: &[f32], shift: i64) -> Result<Vec<f32>, String> {
+    if data.is_empty() {
+        return Err("Cannot shift an empty trace".to_string());
+    }
+
+    let n = data.len();
+    let distance = usize::try_from(shift.unsigned_abs()).map_or(n, |d| d.min(n));
+    let mut shifted = vec![0.0; n];
+    if shift >= 0 {
+        shifted[distance..].copy_from_slice(&data[..n - distance]);
+    } else {
+        shifted[..n - distance].copy_from_slice(&data[distance..]);
+    }
+
+    Ok(shifted)
+}
+
+/// Lag and correlation of the best match, where `correlations[k]` is the correlation at lag
+/// `first_lag + k`
+fn best_lag(correlations: &[f64], first_lag: i64) -> (i64, f64) {
+    let mut best = (0i64, f64::NEG_INFINITY);
+    for (k, &r) in correlations.iter().enumerate() {
+        let lag = first_lag + k as i64;
+        // Ties go to the smallest shift
+        if r > best.1 || (r == best.1 && lag.abs() < best.0.abs()) {
+            best = (lag, r);
+        }
+    }
+    best
+}
+
+/// Shift and correlation found for one trace by `static_align`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TraceShift {
+    pub trace: usize,
+    /// Number of samples the trace is shifted by, positive to the right
+    pub shift: i64,
+    /// Correlation with the reference window after shifting
+    pub correlation: f64,
+    /// Whether the correlation reached the threshold, traces below it are dropped
+    pub kept: bool,
+}
+
+pub struct StaticAlignment {
+    /// Traces that reached the correlation threshold, shifted onto the reference
+    pub traces: TraceSet,
+    /// One entry for every input trace, in order
+    pub report: Vec<TraceShift>,
+}
+
+/// Shifts every trace so that the samples best matching the `sample_selection` window of the
+/// target trace line up with it.
+///
+/// Every shift up to `max_distance` samples in either direction is tried, and the one with the
+/// highest correlation to the target window is kept. The samples a trace is shifted away from
+/// are set to zero. Traces whose best correlation is below `correlation_threshold` are left out
+/// of the aligned trace set but still listed in the report.
+///
+/// The correlations of all shifts of a trace are computed at once with `fft::CrossCorrelator`,
+/// reading the source a chunk of traces at a time. A flat target window is an error.
+pub fn static_align(
+    target_trace: usize,
+    traces: &dyn TraceSource,
+    sample_selection: Range<usize>,
+    max_distance: usize,
+    correlation_threshold: f64,
+) -> Result<StaticAlignment, String> {
+    if target_trace >= traces.len() {
+        return Err(format!("There is no trace {}", target_trace));
+    }
+    if sample_selection.is_empty() || sample_selection.end > traces.num_samples() {
+        return Err(format!("Invalid sample selection {:?}", sample_selection));
+    }
+
+    let source = traces;
+    let start = Instant::now();
+
+    // Only the samples the search can reach are read from the source
+    let search_start = sample_selection.start.saturating_sub(max_distance);
+    let search_end = (sample_selection.end + max_distance).min(source.num_samples());
+    let target = source
+        .read_window(target_trace..target_trace + 1, sample_selection.clone())
+        .map_err(|e| e.to_string())?;
+
+    let correlator = CrossCorrelator::new(target.trace(0), search_end - search_start)?;
+
+    // Correlation k is the window starting k samples into the search range
+    let first_lag = search_start as i64 - sample_selection.start as i64;
+
+    // Best (lag, correlation) of every trace
+    let mut best = Vec::with_capacity(source.len());
+    for chunk in read_chunks(source, ALIGN_CHUNK_SIZE, search_start..search_end) {
+        let chunk = chunk.map_err(|e| e.to_string())?;
+
+        let chunk_best = (0..chunk.len())
+            .into_par_iter()
+            .map_init(
+                || (correlator.buffers(), vec![0.0; correlator.num_lags()]),
+                |(buffers, correlations), index| {
+                    correlator.correlate_into(chunk.trace(index), buffers, correlations)?;
+                    Ok(best_lag(correlations, first_lag))
+                },
+            )
+            .collect::<Result<Vec<_>, String>>()?;
+        best.extend(chunk_best);
+    }
+    best[target_trace] = (0, 1.0);
+
+    // A trace matching at lag l is shifted by -l to line up with the target
+    let report: Vec<TraceShift> = best
+        .into_iter()
+        .enumerate()
+        .map(|(trace, (lag, correlation))| TraceShift {
+            trace,
+            shift: -lag,
+            correlation,
+            kept: correlation >= correlation_threshold,
+        })
+        .collect();
+
+    let mut aligned: Option<TraceSet> = None;
+    let mut index = 0;
+    for chunk in read_chunks(source, ALIGN_CHUNK_SIZE, 0..source.num_samples()) {
+        let chunk = chunk.map_err(|e| e.to_string())?;
+        let aligned = aligned.get_or_insert_with(|| chunk.empty_like());
+
+        for (trace, data) in chunk.traces().zip(&chunk.data) {
+            let shift = report[index];
+            if shift.kept {
+                aligned.push(&shift_samples(trace, shift.shift)?, data.clone())?;
+            }
+            index += 1;
+        }
+    }
+
+    log::info!("Static Align Elapsed Time: {:?}", start.elapsed());
+
+    Ok(StaticAlignment {
+        traces: aligned.unwrap_or_default(),
+        report,
+    })
+}
+
+/// Writes the shift and correlation of every trace found by `static_align` as CSV
+pub fn write_shift_report(report: &[TraceShift], file_path: &str) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::Writer::from_path(file_path)?;
+    writer.write_record(["trace", "shift", "correlation", "kept"])?;
+
+    for shift in report {
+        writer.write_record([
+            shift.trace.to_string(),
+            shift.shift.to_string(),
+            shift.correlation.to_string(),
+            shift.kept.to_string(),
+        ])?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Warps the `sample_selection` window of every trace onto the same window of the target trace
+/// with FastDTW, for traces with clock jitter or random delays that a single shift can't fix.
+///
+/// `radius` is the number of cells FastDTW searches around the path found at half resolution,
+/// larger values are closer to the exact warping but slower. The result only holds the window,
+/// with every sample the average of the trace samples matched to that reference sample.
+pub fn elastic_align(
+    target_trace: usize,
+    traces: &dyn TraceSource,
+    sample_selection: Range<usize>,
+    radius: usize,
+    progress: &mut dyn FnMut(f32),
+) -> Result<TraceSet, String> {
+    if target_trace >= traces.len() {
+        return Err(format!("There is no trace {}", target_trace));
+    }
+    if sample_selection.is_empty() || sample_selection.end > traces.num_samples() {
+        return Err(format!("Invalid sample selection {:?}", sample_selection));
+    }
+
+    let start = Instant::now();
+
+    let reference = traces
+        .read_window(target_trace..target_trace + 1, sample_selection.clone())
+        .map_err(|e| e.to_string())?;
+    let reference = reference.trace(0);
+
+    let mut warped: Option<TraceSet> = None;
+    for chunk in read_chunks(traces, ALIGN_CHUNK_SIZE, sample_selection) {
+        let chunk = chunk.map_err(|e| e.to_string())?;
+
+        let samples: Vec<Vec<f32>> = (0..chunk.len())
+            .into_par_iter()
+            .map(|index| warp_onto(reference, chunk.trace(index), radius))
+            .collect();
+
+        let warped = warped.get_or_insert_with(|| chunk.empty_like());
+        for (trace, data) in samples.iter().zip(&chunk.data) {
+            warped.push(trace, data.clone())?;
+        }
+        progress(warped.len() as f32 / traces.len() as f32);
+    }
+
+    log::info!("Elastic Align Elapsed Time: {:?}", start.elapsed());
+
+    Ok(warped.unwrap_or_default())
+}
+
+fn invalid_data(message: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Writes `value` behind a magic number and a format version, so files of other kinds or
+/// versions are refused when read back
+pub(crate) fn write_versioned<T: Encode>(
+    file_path: &str,
+    magic: &[u8; 8],
+    version: u32,
+    value: &T,
+) -> io::Result<()> {
+    let encoded = bincode::encode_to_vec(value, config::standard()).map_err(invalid_data)?;
+
+    let mut bytes = Vec::with_capacity(magic.len() + 4 + encoded.len());
+    bytes.extend_from_slice(magic);
+    bytes.extend_from_slice(&version.to_le_bytes());
+    bytes.extend_from_slice(&encoded);
+    fs::write(file_path, bytes)
+}
+
+/// Reads a value written by [`write_versioned`], `kind` naming the file kind in errors
+pub(crate) fn read_versioned<T: Decode<()>>(
+    file_path: &str,
+    magic: &[u8; 8],
+    version: u32,
+    kind: &str,
+) -> io::Result<T> {
+    let bytes = fs::read(file_path)?;
+    if bytes.len() < magic.len() + 4 || &bytes[..magic.len()] != magic {
+        return Err(invalid_data(format!("Not a {} file", kind)));
+    }
+    let found = u32::from_le_bytes(bytes[magic.len()..magic.len() + 4].try_into().unwrap());
+    if found != version {
+        return Err(invalid_data(format!(
+            "Unsupported {} version {}",
+            kind, found
+        )));
+    }
+
+    let (value, _) = bincode::decode_from_slice(&bytes[magic.len() + 4..], config::standard())
+        .map_err(invalid_data)?;
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace_set::{TimeAxis, TraceData};
+
+    #[test]
+    fn shifts_without_wrapping() {
+        let data = [1.0, 2.0, 3.0, 4.0];
+        assert_eq!(shift_samples(&data, 1).unwrap(), [0.0, 1.0, 2.0, 3.0]);
+        assert_eq!(shift_samples(&data, -2).unwrap(), [3.0, 4.0, 0.0, 0.0]);
+        assert_eq!(shift_samples(&data, 0).unwrap(), data);
+        assert_eq!(shift_samples(&data, i64::MIN).unwrap(), [0.0; 4]);
+        assert!(shift_samples(&[], 1).is_err());
+    }
+
+    #[test]
+    fn static_align_recovers_shifts() {
+        let pulse = |at: usize| -> Vec<f32> {
+            (0..64)
+                .map(|i| (-((i as f32 - at as f32) / 3.0).powi(2)).exp())
+                .collect()
+        };
+        let mut traces = TraceSet::new(TimeAxis::default(), 64);
+        for at in [30, 33, 26] {
+            traces.push(&pulse(at), TraceData::default()).unwrap();
+        }
+
+        let alignment = static_align(0, &traces, 20..40, 8, 0.9).unwrap();
+        let shifts: Vec<i64> = alignment.report.iter().map(|s| s.shift).collect();
+        assert_eq!(shifts, [0, -3, 4]);
+        assert_eq!(alignment.traces.len(), 3);
+        assert_eq!(alignment.traces.trace(1)[20..40], pulse(30)[20..40]);
+
+        // A flat target window would make every correlation NaN and drop every other trace
+        let mut flat = traces.empty_like();
+        flat.push(&[1.0; 64], TraceData::default()).unwrap();
+        flat.push(&pulse(30), TraceData::default()).unwrap();
+        assert!(static_align(0, &flat, 20..40, 8, 0.9).is_err());
+    }
+}
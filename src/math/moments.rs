@@ -0,0 +1,240 @@
+/// Binomial coefficient, for the small orders of the moment updates
+fn binomial(n: usize, k: usize) -> f64 {
+    (0..k).fold(1.0, |c, i| c * (n - i) as f64 / (i + 1) as f64)
+}
+
+/// Running mean and central sums Σ(x - mean)^p at every sample, updated one trace at a time so
+/// that millions of traces don't lose precision.
+///
+/// The updates and merges follow Schneider & Moradi, "Leakage Assessment Methodology", which
+/// keeps the central sums for p = 2..=`max_moment` exact in one pass.
+#[derive(Clone, Debug)]
+pub struct OnlineMoments {
+    count: u64,
+    max_moment: usize,
+    mean: Vec<f64>,
+    /// Central sums indexed [sample][p - 2]
+    central_sums: Vec<f64>,
+}
+
+impl OnlineMoments {
+    pub fn new(num_samples: usize, max_moment: usize) -> Self {
+        let max_moment = max_moment.max(2);
+        OnlineMoments {
+            count: 0,
+            max_moment,
+            mean: vec![0.0; num_samples],
+            central_sums: vec![0.0; num_samples * (max_moment - 1)],
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn add(&mut self, trace: &[f32]) {
+        let n_a = self.count as f64;
+        self.count += 1;
+        let n = self.count as f64;
+        let moments = self.max_moment;
+
+        for ((mean, sums), &y) in self
+            .mean
+            .iter_mut()
+            .zip(self.central_sums.chunks_exact_mut(moments - 1))
+            .zip(trace)
+        {
+            let delta = y as f64 - *mean;
+            *mean += delta / n;
+            if n_a == 0.0 {
+                continue;
+            }
+
+            // Higher sums use the lower ones before they are updated
+            for p in (2..=moments).rev() {
+                let mut sum = sums[p - 2];
+                for k in 1..=p - 2 {
+                    sum += binomial(p, k) * sums[p - k - 2] * (-delta / n).powi(k as i32);
+                }
+                sum += (n_a / n * delta).powi(p as i32) * (1.0 - (-1.0 / n_a).powi(p as i32 - 1));
+                sums[p - 2] = sum;
+            }
+        }
+    }
+
+    /// Combines the moments of two disjoint groups of traces
+    pub fn merge(&mut self, other: &OnlineMoments) {
+        if other.count == 0 {
+            return;
+        }
+        if self.count == 0 {
+            *self = other.clone();
+            return;
+        }
+
+        let (n_a, n_b) = (self.count as f64, other.count as f64);
+        let n = n_a + n_b;
+        let moments = self.max_moment;
+
+        for (((mean, sums), other_mean), other_sums) in self
+            .mean
+            .iter_mut()
+            .zip(self.central_sums.chunks_exact_mut(moments - 1))
+            .zip(&other.mean)
+            .zip(other.central_sums.chunks_exact(moments - 1))
+        {
+            let delta = other_mean - *mean;
+            *mean += delta * n_b / n;
+
+            for p in (2..=moments).rev() {
+                let mut sum = sums[p - 2] + other_sums[p - 2];
+                for k in 1..=p - 2 {
+                    sum += binomial(p, k)
+                        * delta.powi(k as i32)
+                        * ((-n_b / n).powi(k as i32) * sums[p - k - 2]
+                            + (n_a / n).powi(k as i32) * other_sums[p - k - 2]);
+                }
+                sum += (n_a * n_b / n * delta).powi(p as i32)
+                    * (1.0 / n_b.powi(p as i32 - 1) - (-1.0 / n_a).powi(p as i32 - 1));
+                sums[p - 2] = sum;
+            }
+        }
+        self.count += other.count;
+    }
+
+    pub fn mean(&self) -> &[f64] {
+        &self.mean
+    }
+
+    /// Central moment E[(x - mean)^p] at `sample`
+    pub fn central_moment(&self, sample: usize, p: usize) -> f64 {
+        self.central_sums[sample * (self.max_moment - 1) + p - 2] / self.count as f64
+    }
+
+    /// Mean and variance at every sample of the preprocessed traces a univariate t-test of
+    /// `order` compares: the traces for order 1, the squared centered traces for order 2, and the
+    /// standardized traces raised to `order` above that.
+    pub fn statistics(&self, order: usize) -> Vec<(f64, f64)> {
+        let n = self.count as f64;
+
+        (0..self.mean.len())
+            .map(|sample| match order {
+                1 => (
+                    self.mean[sample],
+                    self.central_moment(sample, 2) * n / (n - 1.0).max(1.0),
+                ),
+                2 => {
+                    let cm2 = self.central_moment(sample, 2);
+                    (cm2, self.central_moment(sample, 4) - cm2 * cm2)
+                }
+                _ => {
+                    let cm2 = self.central_moment(sample, 2);
+                    let cm = self.central_moment(sample, order);
+                    (
+                        cm / cm2.powf(order as f64 / 2.0),
+                        (self.central_moment(sample, 2 * order) - cm * cm) / cm2.powi(order as i32),
+                    )
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    /// Central moment of order `p` computed from the mean in a second pass
+    fn two_pass_moment(values: &[f64], p: i32) -> f64 {
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        values.iter().map(|v| (v - mean).powi(p)).sum::<f64>() / values.len() as f64
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() <= 1e-9 * b.abs().max(1.0), "{} != {}", a, b);
+    }
+
+    #[test]
+    fn moments_match_two_pass_reference() {
+        let mut rng = StdRng::seed_from_u64(3);
+        // A large offset loses precision in naive power sums
+        let traces: Vec<[f32; 2]> = (0..1000)
+            .map(|_| {
+                let y: f32 = rng.random_range(-1.0..1.0);
+                [1000.0 + y, y * y * y]
+            })
+            .collect();
+
+        // One group added trace by trace, and the same traces added in two merged halves
+        let mut online = OnlineMoments::new(2, 6);
+        let mut first = OnlineMoments::new(2, 6);
+        let mut second = OnlineMoments::new(2, 6);
+        for (i, trace) in traces.iter().enumerate() {
+            online.add(trace);
+            let half = if i < 300 { &mut first } else { &mut second };
+            half.add(trace);
+        }
+        first.merge(&second);
+
+        for sample in 0..2 {
+            let values: Vec<f64> = traces.iter().map(|t| t[sample] as f64).collect();
+            let mean = values.iter().sum::<f64>() / values.len() as f64;
+            for moments in [&online, &first] {
+                assert_eq!(moments.count(), 1000);
+                assert_close(moments.mean()[sample], mean);
+                for p in 2..=6 {
+                    assert_close(
+                        moments.central_moment(sample, p),
+                        two_pass_moment(&values, p as i32),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Mean and population variance, the moments of the preprocessed traces
+    fn mean_and_variance(values: &[f64]) -> (f64, f64) {
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        (mean, two_pass_moment(values, 2))
+    }
+
+    #[test]
+    fn higher_order_statistics_match_preprocessed_traces() {
+        let mut rng = StdRng::seed_from_u64(4);
+        let values: Vec<f64> = (0..500)
+            .map(|_| {
+                let y: f64 = rng.random_range(-1.0..1.0);
+                // Skewed, so the odd moments don't vanish
+                50.0 + y + 0.5 * y * y
+            })
+            .collect();
+
+        let mut moments = OnlineMoments::new(1, 6);
+        for &value in &values {
+            moments.add(&[value as f32]);
+        }
+        // The reference uses the f32 samples the moments were computed from
+        let values: Vec<f64> = values.iter().map(|&v| v as f32 as f64).collect();
+        let (mean, variance) = mean_and_variance(&values);
+
+        // Order 2 squares the centered traces
+        let centered: Vec<f64> = values.iter().map(|v| (v - mean).powi(2)).collect();
+        let (expected_mean, expected_variance) = mean_and_variance(&centered);
+        let (mean_2, variance_2) = moments.statistics(2)[0];
+        assert_close(mean_2, expected_mean);
+        assert_close(variance_2, expected_variance);
+
+        // Order 3 cubes the standardized traces
+        let standardized: Vec<f64> = values
+            .iter()
+            .map(|v| ((v - mean) / variance.sqrt()).powi(3))
+            .collect();
+        let (expected_mean, expected_variance) = mean_and_variance(&standardized);
+        let (mean_3, variance_3) = moments.statistics(3)[0];
+        assert_close(mean_3, expected_mean);
+        assert_close(variance_3, expected_variance);
+        assert!(mean_3.abs() > 0.1);
+    }
+}
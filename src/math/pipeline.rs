@@ -0,0 +1,657 @@
+use crate::loaders::container::{default_block_size, ChunkedWriter};
+use crate::math::dtw::warp_onto;
+use crate::math::fft::CrossCorrelator;
+use crate::math::filter::{Filter, FilterSpec};
+use crate::math::{best_lag, shift_samples};
+use crate::trace_set::{read_chunks, SampleCoding, TimeAxis, TraceSet, TraceSource};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::ops::Range;
+
+/// Number of traces read from the source at once while running a pipeline
+const PIPELINE_CHUNK_SIZE: usize = 1024;
+
+/// Prefix of the trace set parameters recording the steps that produced the traces
+const PARAMETER_PREFIX: &str = "pipeline.";
+
+/// How every trace is rescaled by `Transform::Normalize`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Normalization {
+    /// Subtract the mean of the trace
+    Mean,
+    /// Subtract the mean and divide by the standard deviation of the trace
+    ZScore,
+    /// Scale the trace to the range from zero to one
+    MinMax,
+}
+
+impl fmt::Display for Normalization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Normalization::Mean => write!(f, "Mean removal"),
+            Normalization::ZScore => write!(f, "Z-score"),
+            Normalization::MinMax => write!(f, "Min-max"),
+        }
+    }
+}
+
+/// One preprocessing step of a pipeline.
+///
+/// Sample indices refer to the traces as they come out of the previous step, trace indices to
+/// the traces of the input set.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum Transform {
+    /// Keeps only the `samples` window
+    Crop { samples: Range<usize> },
+    /// Zero-phase filter, see `filter::Filter`
+    Filter { spec: FilterSpec },
+    /// Shifts every trace onto the `window` of the `reference` trace, see `math::static_align`
+    StaticAlign {
+        reference: usize,
+        window: Range<usize>,
+        max_shift: usize,
+        /// Traces below this correlation are dropped
+        threshold: f64,
+    },
+    /// Warps the `window` of every trace onto the `reference` trace with FastDTW, keeping only
+    /// the window, see `math::elastic_align`
+    ElasticAlign {
+        reference: usize,
+        window: Range<usize>,
+        radius: usize,
+    },
+    /// Averages every `factor` samples into one, dropping the samples left over at the end
+    Resample { factor: usize },
+    /// Rescales every trace on its own
+    Normalize { method: Normalization },
+}
+
+impl fmt::Display for Transform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Transform::Crop { samples } => {
+                write!(f, "Crop samples {} to {}", samples.start, samples.end)
+            }
+            Transform::Filter { spec } => write!(f, "Filter {}", spec),
+            Transform::StaticAlign {
+                reference,
+                window,
+                max_shift,
+                threshold,
+            } => write!(
+                f,
+                "Static align on trace {} samples {} to {}, max shift {}, threshold {}",
+                reference, window.start, window.end, max_shift, threshold
+            ),
+            Transform::ElasticAlign {
+                reference,
+                window,
+                radius,
+            } => write!(
+                f,
+                "Elastic align on trace {} samples {} to {}, radius {}",
+                reference, window.start, window.end, radius
+            ),
+            Transform::Resample { factor } => write!(f, "Resample by {}", factor),
+            Transform::Normalize { method } => write!(f, "Normalize ({})", method),
+        }
+    }
+}
+
+/// A transform ready to run on the traces coming out of the previous stage
+enum Stage {
+    Crop(Range<usize>),
+    Filter(Filter),
+    StaticAlign {
+        reference: usize,
+        correlator: CrossCorrelator,
+        /// Samples the reference window is searched in
+        search: Range<usize>,
+        /// Lag of the first correlation, relative to the reference window
+        first_lag: i64,
+        threshold: f64,
+    },
+    ElasticAlign {
+        /// Window of the processed reference trace
+        reference: Vec<f32>,
+        window: Range<usize>,
+        radius: usize,
+    },
+    Resample(usize),
+    Normalize(Normalization),
+}
+
+impl Stage {
+    /// Processes trace `index` of the input set, returning `None` when it's dropped
+    fn apply(&self, index: usize, trace: Vec<f32>) -> Result<Option<Vec<f32>>, String> {
+        let trace = match self {
+            Stage::Crop(samples) => trace[samples.clone()].to_vec(),
+            Stage::Filter(filter) => filter.apply(&trace),
+            Stage::StaticAlign {
+                reference,
+                correlator,
+                search,
+                first_lag,
+                threshold,
+            } => {
+                if index == *reference {
+                    return Ok(Some(trace));
+                }
+                let mut correlations = vec![0.0; correlator.num_lags()];
+                correlator.correlate_into(
+                    &trace[search.clone()],
+                    &mut correlator.buffers(),
+                    &mut correlations,
+                )?;
+                let (lag, correlation) = best_lag(&correlations, *first_lag);
+                if correlation < *threshold {
+                    return Ok(None);
+                }
+                shift_samples(&trace, -lag)?
+            }
+            Stage::ElasticAlign {
+                reference,
+                window,
+                radius,
+            } => warp_onto(reference, &trace[window.clone()], *radius),
+            Stage::Resample(factor) => trace
+                .chunks_exact(*factor)
+                .map(|group| group.iter().sum::<f32>() / *factor as f32)
+                .collect(),
+            Stage::Normalize(method) => normalize(trace, *method),
+        };
+        Ok(Some(trace))
+    }
+}
+
+fn normalize(mut trace: Vec<f32>, method: Normalization) -> Vec<f32> {
+    let n = trace.len().max(1) as f64;
+    let mean = trace.iter().map(|&y| y as f64).sum::<f64>() / n;
+
+    let (offset, scale) = match method {
+        Normalization::Mean => (mean, 1.0),
+        Normalization::ZScore => {
+            let variance = trace
+                .iter()
+                .map(|&y| (y as f64 - mean).powi(2))
+                .sum::<f64>()
+                / n;
+            (mean, variance.sqrt())
+        }
+        Normalization::MinMax => {
+            let min = trace.iter().copied().fold(f32::INFINITY, f32::min) as f64;
+            let max = trace.iter().copied().fold(f32::NEG_INFINITY, f32::max) as f64;
+            (min, max - min)
+        }
+    };
+    // Flat traces are only shifted
+    let scale = if scale > 0.0 { scale } else { 1.0 };
+
+    for y in trace.iter_mut() {
+        *y = ((*y as f64 - offset) / scale) as f32;
+    }
+    trace
+}
+
+/// The stages of a pipeline with the shape of the traces they produce
+struct Prepared {
+    stages: Vec<Stage>,
+    time: TimeAxis,
+    num_samples: usize,
+}
+
+impl Prepared {
+    fn apply(&self, index: usize, mut trace: Vec<f32>) -> Result<Option<Vec<f32>>, String> {
+        for stage in &self.stages {
+            match stage.apply(index, trace)? {
+                Some(next) => trace = next,
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(trace))
+    }
+}
+
+/// A sequence of transforms run on every trace, which can be saved and run again on other
+/// trace sets.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Pipeline {
+    pub steps: Vec<Transform>,
+}
+
+impl Pipeline {
+    /// Writes the pipeline as JSON
+    pub fn save(&self, file_path: &str) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        fs::write(file_path, json)
+    }
+
+    pub fn load(file_path: &str) -> io::Result<Self> {
+        let json = fs::read_to_string(file_path)?;
+        serde_json::from_str(&json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Checks every step against the traces of `source` and prepares it, reading and processing
+    /// the reference traces of the alignment steps
+    fn prepare(&self, source: &dyn TraceSource) -> Result<Prepared, String> {
+        let mut prepared = Prepared {
+            stages: Vec::with_capacity(self.steps.len()),
+            time: source.time(),
+            num_samples: source.num_samples(),
+        };
+
+        for (number, step) in self.steps.iter().enumerate() {
+            let check_window = |window: &Range<usize>, num_samples: usize| {
+                if window.is_empty() || window.end > num_samples {
+                    Err(format!(
+                        "Step {} ({}) needs samples {} to {} but the traces have {}",
+                        number + 1,
+                        step,
+                        window.start,
+                        window.end,
+                        num_samples
+                    ))
+                } else {
+                    Ok(())
+                }
+            };
+            // Reference trace as it comes out of the stages before this step
+            let read_reference = |prepared: &Prepared, reference: usize| {
+                if reference >= source.len() {
+                    return Err(format!("There is no trace {}", reference));
+                }
+                let trace = source
+                    .read_traces(reference..reference + 1)
+                    .map_err(|e| e.to_string())?;
+                prepared
+                    .apply(reference, trace.trace(0).to_vec())?
+                    .ok_or_else(|| {
+                        format!(
+                            "Reference trace {} of step {} is dropped by an earlier step",
+                            reference,
+                            number + 1
+                        )
+                    })
+            };
+
+            let stage = match step {
+                Transform::Crop { samples } => {
+                    check_window(samples, prepared.num_samples)?;
+                    prepared.time.offset = prepared.time.x(samples.start);
+                    prepared.num_samples = samples.len();
+                    Stage::Crop(samples.clone())
+                }
+                Transform::Filter { spec } => {
+                    Stage::Filter(Filter::design(spec, 1.0 / prepared.time.interval)?)
+                }
+                Transform::StaticAlign {
+                    reference,
+                    window,
+                    max_shift,
+                    threshold,
+                } => {
+                    check_window(window, prepared.num_samples)?;
+                    let trace = read_reference(&prepared, *reference)?;
+
+                    let search = window.start.saturating_sub(*max_shift)
+                        ..(window.end + max_shift).min(prepared.num_samples);
+                    Stage::StaticAlign {
+                        reference: *reference,
+                        correlator: CrossCorrelator::new(&trace[window.clone()], search.len())?,
+                        first_lag: search.start as i64 - window.start as i64,
+                        search,
+                        threshold: *threshold,
+                    }
+                }
+                Transform::ElasticAlign {
+                    reference,
+                    window,
+                    radius,
+                } => {
+                    check_window(window, prepared.num_samples)?;
+                    let trace = read_reference(&prepared, *reference)?;
+
+                    prepared.time.offset = prepared.time.x(window.start);
+                    prepared.num_samples = window.len();
+                    Stage::ElasticAlign {
+                        reference: trace[window.clone()].to_vec(),
+                        window: window.clone(),
+                        radius: *radius,
+                    }
+                }
+                Transform::Resample { factor } => {
+                    if *factor == 0 || *factor > prepared.num_samples {
+                        return Err(format!(
+                            "Step {} can't resample {} samples by {}",
+                            number + 1,
+                            prepared.num_samples,
+                            factor
+                        ));
+                    }
+                    // Every new sample sits in the middle of the samples it averages
+                    let time = prepared.time;
+                    prepared.time = TimeAxis::new(
+                        time.offset + (factor - 1) as f64 / 2.0 * time.interval,
+                        time.interval * *factor as f64,
+                    );
+                    prepared.num_samples /= factor;
+                    Stage::Resample(*factor)
+                }
+                Transform::Normalize { method } => Stage::Normalize(*method),
+            };
+            prepared.stages.push(stage);
+        }
+
+        Ok(prepared)
+    }
+
+    /// Adds the steps to `parameters`, after the steps of any pipeline run on the traces before
+    pub fn record(&self, parameters: &mut BTreeMap<String, String>) -> Result<(), String> {
+        let earlier = parameters
+            .keys()
+            .filter(|key| key.starts_with(PARAMETER_PREFIX))
+            .count();
+
+        for (number, step) in self.steps.iter().enumerate() {
+            let json = serde_json::to_string(step).map_err(|e| e.to_string())?;
+            parameters.insert(
+                format!("{}{}", PARAMETER_PREFIX, earlier + number + 1),
+                json,
+            );
+        }
+        Ok(())
+    }
+
+    /// Runs the pipeline on `source` a chunk of traces at a time, in parallel over the traces of
+    /// a chunk, and calls `consume` with the traces that came through every step.
+    ///
+    /// The output is stored as floats and its parameters record the steps. `progress` is called
+    /// with the fraction of traces processed so far.
+    pub fn run_chunks<F>(
+        &self,
+        source: &dyn TraceSource,
+        progress: &mut dyn FnMut(f32),
+        mut consume: F,
+    ) -> Result<(), String>
+    where
+        F: FnMut(TraceSet) -> Result<(), String>,
+    {
+        let prepared = self.prepare(source)?;
+
+        let mut first = 0;
+        for chunk in read_chunks(source, PIPELINE_CHUNK_SIZE, 0..source.num_samples()) {
+            let chunk = chunk.map_err(|e| e.to_string())?;
+
+            let traces = (0..chunk.len())
+                .into_par_iter()
+                .map(|index| prepared.apply(first + index, chunk.trace(index).to_vec()))
+                .collect::<Result<Vec<_>, String>>()?;
+
+            let mut output = TraceSet::new(prepared.time, prepared.num_samples);
+            output.coding = SampleCoding::Float;
+            output.parameters = chunk.parameters.clone();
+            self.record(&mut output.parameters)?;
+            for (trace, data) in traces.iter().zip(&chunk.data) {
+                if let Some(trace) = trace {
+                    output.push(trace, data.clone())?;
+                }
+            }
+            consume(output)?;
+
+            first += chunk.len();
+            progress(first as f32 / source.len() as f32);
+        }
+
+        Ok(())
+    }
+
+    /// Runs the pipeline on every trace of `source`, keeping the output in memory
+    pub fn run(
+        &self,
+        source: &dyn TraceSource,
+        progress: &mut dyn FnMut(f32),
+    ) -> Result<TraceSet, String> {
+        let mut output: Option<TraceSet> = None;
+        self.run_chunks(source, progress, |chunk| match &mut output {
+            Some(output) => output.append(&chunk),
+            None => {
+                output = Some(chunk);
+                Ok(())
+            }
+        })?;
+
+        Ok(output.unwrap_or_default())
+    }
+
+    /// Runs the pipeline on every trace of `source`, streaming the output into a chunked
+    /// container so neither the input nor the output has to fit in memory
+    pub fn run_to_file(
+        &self,
+        source: &dyn TraceSource,
+        file_path: &str,
+        progress: &mut dyn FnMut(f32),
+    ) -> Result<(), String> {
+        if source.is_empty() {
+            return Err("There are no traces to process".to_string());
+        }
+
+        let mut writer: Option<ChunkedWriter> = None;
+        self.run_chunks(source, progress, |chunk| {
+            let writer = match &mut writer {
+                Some(writer) => writer,
+                None => {
+                    let block_size = default_block_size(chunk.num_samples(), chunk.coding);
+                    writer.insert(
+                        ChunkedWriter::create(file_path, &chunk, block_size)
+                            .map_err(|e| e.to_string())?,
+                    )
+                }
+            };
+            writer.write_traces(&chunk).map_err(|e| e.to_string())
+        })?;
+
+        match writer {
+            Some(writer) => writer.finish().map_err(|e| e.to_string()),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loaders::container::ChunkedTraceFile;
+    use crate::math::fft::WindowFunction;
+    use crate::math::filter::{FilterBand, FilterFamily};
+    use crate::trace_set::TraceData;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    /// More than a chunk of noisy bumps jittered by a few samples, with every seventh trace flat
+    fn test_traces() -> TraceSet {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut traces = TraceSet::new(TimeAxis::new(1.0, 0.5), 64);
+        traces
+            .parameters
+            .insert("title".to_string(), "bumps".to_string());
+        for i in 0..PIPELINE_CHUNK_SIZE + 500 {
+            let center = 28.0 + (i % 5) as f32;
+            let samples: Vec<f32> = (0..64)
+                .map(|j| match i % 7 {
+                    6 => 0.25,
+                    _ => (-(j as f32 - center).powi(2) / 8.0).exp() + rng.random_range(-0.05..0.05),
+                })
+                .collect();
+            let data = TraceData {
+                input: vec![i as u8],
+                ..Default::default()
+            };
+            traces.push(&samples, data).unwrap();
+        }
+        traces
+    }
+
+    fn test_pipeline() -> Pipeline {
+        Pipeline {
+            steps: vec![
+                Transform::Crop { samples: 4..60 },
+                Transform::Filter {
+                    spec: FilterSpec {
+                        family: FilterFamily::Fir {
+                            window: WindowFunction::Hann,
+                        },
+                        band: FilterBand::LowPass(0.5),
+                        order: 8,
+                    },
+                },
+                Transform::StaticAlign {
+                    reference: 0,
+                    window: 16..36,
+                    max_shift: 4,
+                    threshold: 0.5,
+                },
+                Transform::ElasticAlign {
+                    reference: 1,
+                    window: 8..48,
+                    radius: 2,
+                },
+                Transform::Resample { factor: 3 },
+                Transform::Normalize {
+                    method: Normalization::ZScore,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pipeline.json");
+        let path = path.to_str().unwrap();
+
+        let pipeline = test_pipeline();
+        pipeline.save(path).unwrap();
+        assert_eq!(Pipeline::load(path).unwrap(), pipeline);
+
+        fs::write(path, r#"{"steps": [{"step": "twist"}]}"#).unwrap();
+        let error = Pipeline::load(path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn prepare_follows_the_time_axis() {
+        let traces = test_traces();
+        let pipeline = Pipeline {
+            steps: vec![
+                Transform::Crop { samples: 10..50 },
+                Transform::Resample { factor: 4 },
+                Transform::ElasticAlign {
+                    reference: 0,
+                    window: 2..8,
+                    radius: 1,
+                },
+            ],
+        };
+
+        let prepared = Pipeline {
+            steps: pipeline.steps[..1].to_vec(),
+        }
+        .prepare(&traces)
+        .unwrap();
+        assert_eq!(prepared.time, TimeAxis::new(6.0, 0.5));
+        assert_eq!(prepared.num_samples, 40);
+
+        // The first new sample is the mean of the samples at 6.0 to 7.5
+        let prepared = Pipeline {
+            steps: pipeline.steps[..2].to_vec(),
+        }
+        .prepare(&traces)
+        .unwrap();
+        assert_eq!(prepared.time, TimeAxis::new(6.75, 2.0));
+        assert_eq!(prepared.num_samples, 10);
+
+        let prepared = pipeline.prepare(&traces).unwrap();
+        assert_eq!(prepared.time, TimeAxis::new(10.75, 2.0));
+        assert_eq!(prepared.num_samples, 6);
+
+        let output = pipeline.run(&traces, &mut |_| ()).unwrap();
+        assert_eq!(output.time, prepared.time);
+        assert_eq!(output.num_samples(), 6);
+        assert_eq!(output.len(), traces.len());
+
+        // Windows are checked against the samples left by the steps before
+        let too_long = Pipeline {
+            steps: vec![
+                Transform::Crop { samples: 10..50 },
+                Transform::Crop { samples: 0..41 },
+            ],
+        };
+        assert!(too_long.prepare(&traces).is_err());
+    }
+
+    #[test]
+    fn run_and_run_to_file_agree() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("processed.sct");
+        let path = path.to_str().unwrap();
+        let traces = test_traces();
+        let pipeline = test_pipeline();
+
+        let output = pipeline.run(&traces, &mut |_| ()).unwrap();
+        // The flat traces don't correlate with the reference
+        let flat = (0..traces.len()).filter(|i| i % 7 == 6).count();
+        assert_eq!(output.len(), traces.len() - flat);
+        assert_eq!(output.data[6].input, vec![7]);
+        assert_eq!(output.coding, SampleCoding::Float);
+
+        let mut fractions = Vec::new();
+        pipeline
+            .run_to_file(&traces, path, &mut |fraction| fractions.push(fraction))
+            .unwrap();
+        assert_eq!(fractions.last(), Some(&1.0));
+
+        let file = ChunkedTraceFile::open(path).unwrap();
+        assert_eq!(file.read_traces(0..file.len()).unwrap(), output);
+    }
+
+    #[test]
+    fn record_numbers_steps_after_earlier_pipelines() {
+        let mut parameters = BTreeMap::new();
+        parameters.insert("title".to_string(), "bumps".to_string());
+        let first = Pipeline {
+            steps: vec![Transform::Crop { samples: 0..8 }],
+        };
+        first.record(&mut parameters).unwrap();
+
+        let second = Pipeline {
+            steps: vec![
+                Transform::Resample { factor: 2 },
+                Transform::Normalize {
+                    method: Normalization::Mean,
+                },
+            ],
+        };
+        second.record(&mut parameters).unwrap();
+
+        let keys: Vec<&str> = parameters.keys().map(|key| key.as_str()).collect();
+        assert_eq!(keys, ["pipeline.1", "pipeline.2", "pipeline.3", "title"]);
+        let step: Transform = serde_json::from_str(&parameters["pipeline.3"]).unwrap();
+        assert_eq!(step, second.steps[1]);
+
+        // Running a pipeline records it after the steps already in the input's parameters
+        let mut traces = test_traces();
+        traces.parameters = parameters;
+        let output = second.run(&traces, &mut |_| ()).unwrap();
+        assert_eq!(
+            output.parameters["pipeline.5"],
+            traces.parameters["pipeline.3"]
+        );
+        assert_eq!(output.parameters["title"], "bumps");
+    }
+}
@@ -0,0 +1,389 @@
+use crate::math::leakage::LeakageModel;
+use crate::math::linalg::{dot, Cholesky, Matrix, SymmetricEigen};
+use crate::math::template::TemplateBuilder;
+use crate::math::{read_versioned, write_versioned};
+use crate::trace_set::{read_chunks, TimeAxis, TraceData, TraceSet, TraceSource};
+use bincode::{Decode, Encode};
+use rayon::prelude::*;
+use std::io;
+use std::ops::Range;
+
+const MAGIC: &[u8; 8] = b"SCPROJEC";
+const VERSION: u32 = 1;
+
+/// Number of traces read from the source at once
+const PROJECTION_CHUNK_SIZE: usize = 1024;
+
+/// Added to the diagonal of the within-class covariance of LDA, relative to its mean diagonal,
+/// so samples without noise don't make it singular
+const LDA_RIDGE: f64 = 1e-9;
+
+/// A linear map from a window of the samples to a few components
+#[derive(Clone, Debug, Encode, Decode)]
+pub struct Projection {
+    /// How the components were found, e.g. "PCA"
+    pub method: String,
+    /// First sample of the window the projection applies to
+    pub first_sample: usize,
+    /// Mean trace over the window, subtracted before projecting
+    pub mean: Vec<f64>,
+    /// Components as long as the window, most important first
+    pub components: Vec<Vec<f64>>,
+    /// Variance along every component for PCA, ratio of the scatter between classes to the
+    /// scatter within classes for LDA
+    pub eigenvalues: Vec<f64>,
+    /// Sum of all eigenvalues, including those of the components left out
+    pub total: f64,
+}
+
+impl Projection {
+    pub fn save(&self, file_path: &str) -> io::Result<()> {
+        write_versioned(file_path, MAGIC, VERSION, self)
+    }
+
+    pub fn load(file_path: &str) -> io::Result<Self> {
+        read_versioned(file_path, MAGIC, VERSION, "projection")
+    }
+
+    /// Samples the projection applies to
+    pub fn window(&self) -> Range<usize> {
+        self.first_sample..self.first_sample + self.mean.len()
+    }
+
+    /// Components of one trace cropped to the window
+    pub fn apply(&self, trace: &[f32]) -> Vec<f32> {
+        let centered: Vec<f64> = trace
+            .iter()
+            .zip(&self.mean)
+            .map(|(&x, m)| x as f64 - m)
+            .collect();
+        self.components
+            .iter()
+            .map(|component| dot(component, &centered) as f32)
+            .collect()
+    }
+
+    /// Explained fraction of every component and their cumulative sum, over the component number
+    pub fn explained_variance(&self) -> Result<TraceSet, String> {
+        let fractions: Vec<f32> = self
+            .eigenvalues
+            .iter()
+            .map(|&value| (value / self.total) as f32)
+            .collect();
+        let cumulative: Vec<f32> = fractions
+            .iter()
+            .scan(0.0, |sum, fraction| {
+                *sum += fraction;
+                Some(*sum)
+            })
+            .collect();
+
+        let mut trace_set = TraceSet::new(TimeAxis::new(1.0, 1.0), fractions.len());
+        trace_set
+            .parameters
+            .insert("title".to_string(), "Explained variance".to_string());
+        for (label, values) in [("Component", fractions), ("Cumulative", cumulative)] {
+            let data = TraceData {
+                label: label.to_string(),
+                ..Default::default()
+            };
+            trace_set.push(&values, data)?;
+        }
+        Ok(trace_set)
+    }
+
+    /// One trace per component, on the time axis `time` of the projected traces
+    pub fn component_traces(&self, time: TimeAxis) -> Result<TraceSet, String> {
+        let axis = TimeAxis::new(time.x(self.first_sample), time.interval);
+        let mut trace_set = TraceSet::new(axis, self.mean.len());
+        for (index, component) in self.components.iter().enumerate() {
+            let values: Vec<f32> = component.iter().map(|&v| v as f32).collect();
+            let data = TraceData {
+                label: format!("Component {}", index + 1),
+                ..Default::default()
+            };
+            trace_set.push(&values, data)?;
+        }
+        Ok(trace_set)
+    }
+}
+
+/// Principal components of the `window` samples of every trace of the source, keeping the
+/// `count` directions of largest variance.
+///
+/// `progress` is called with the fraction of traces processed so far.
+pub fn pca(
+    traces: &dyn TraceSource,
+    window: Range<usize>,
+    count: usize,
+    progress: &mut dyn FnMut(f32),
+) -> Result<Projection, String> {
+    check_window(traces, &window)?;
+    let n = window.len();
+
+    // Sums relative to the first trace keep the products small
+    let mut shift: Option<Vec<f64>> = None;
+    let mut sum = vec![0.0; n];
+    let mut products = Matrix::zeros(n, n);
+    let mut read = 0;
+
+    for chunk in read_chunks(traces, PROJECTION_CHUNK_SIZE, window.clone()) {
+        let chunk = chunk.map_err(|e| e.to_string())?;
+        let shift = shift.get_or_insert_with(|| to_f64(chunk.trace(0)));
+
+        let shifted: Vec<Vec<f64>> = chunk
+            .traces()
+            .map(|trace| {
+                trace
+                    .iter()
+                    .zip(shift.iter())
+                    .map(|(&x, s)| x as f64 - s)
+                    .collect()
+            })
+            .collect();
+        for x in &shifted {
+            sum.iter_mut().zip(x).for_each(|(s, x)| *s += x);
+        }
+        products.add_outer_products(&shifted);
+
+        read += chunk.len();
+        progress(read as f32 / traces.len() as f32);
+    }
+
+    if read < 2 {
+        return Err("PCA needs at least two traces".to_string());
+    }
+    let shift = shift.unwrap_or_default();
+
+    let mut covariance = products;
+    covariance.add_outer(&sum, -1.0 / read as f64);
+    covariance.scale(1.0 / (read - 1) as f64);
+    let eigen = SymmetricEigen::largest(&covariance, count.min(n));
+
+    Ok(Projection {
+        method: "PCA".to_string(),
+        first_sample: window.start,
+        mean: sum
+            .iter()
+            .zip(&shift)
+            .map(|(s, shift)| s / read as f64 + shift)
+            .collect(),
+        components: eigen.vectors,
+        eigenvalues: eigen.values,
+        total: covariance.trace(),
+    })
+}
+
+/// Fisher linear discriminants of the `window` samples of every trace of the source, separating
+/// the classes `model` predicts for the key stored with each trace. At most one less than the
+/// number of classes of the `count` components are kept. The components are scaled to unit
+/// variance within the classes.
+///
+/// `progress` is called with the fraction of traces processed so far.
+pub fn lda(
+    traces: &dyn TraceSource,
+    window: Range<usize>,
+    model: &dyn LeakageModel,
+    count: usize,
+    progress: &mut dyn FnMut(f32),
+) -> Result<Projection, String> {
+    check_window(traces, &window)?;
+    let n = window.len();
+
+    let mut builder = TemplateBuilder::new(window.clone().collect());
+    let mut read = 0;
+    for chunk in read_chunks(traces, PROJECTION_CHUNK_SIZE, window.clone()) {
+        let chunk = chunk.map_err(|e| e.to_string())?;
+        builder.add(&chunk, window.start, model);
+        read += chunk.len();
+        progress(read as f32 / traces.len() as f32);
+    }
+    let classes = builder.build(model)?;
+    let count = count.min(n).min(classes.num_classes().saturating_sub(1));
+
+    let mut within = classes.covariance;
+    let ridge = LDA_RIDGE * within.trace() / n as f64;
+    for i in 0..n {
+        within[(i, i)] += ridge;
+    }
+    let cholesky = Cholesky::new(&within)?;
+
+    let num_traces: u64 = classes.counts.iter().sum();
+    let mut mean = vec![0.0; n];
+    for (class_mean, &count) in classes.means.iter().zip(&classes.counts) {
+        if let Some(class_mean) = class_mean {
+            for (m, c) in mean.iter_mut().zip(class_mean) {
+                *m += c * count as f64 / num_traces as f64;
+            }
+        }
+    }
+
+    // The scatter between classes, whitened by the scatter within: L⁻¹ S_b L⁻ᵀ
+    let mut between = Matrix::zeros(n, n);
+    for (class_mean, &count) in classes.means.iter().zip(&classes.counts) {
+        if let Some(class_mean) = class_mean {
+            let deviation: Vec<f64> = class_mean.iter().zip(&mean).map(|(c, m)| c - m).collect();
+            between.add_outer(
+                &cholesky.solve_lower(&deviation),
+                count as f64 / num_traces as f64,
+            );
+        }
+    }
+
+    let eigen = SymmetricEigen::largest(&between, count);
+
+    Ok(Projection {
+        method: format!("LDA {}", model.name()),
+        first_sample: window.start,
+        mean,
+        components: eigen
+            .vectors
+            .iter()
+            .map(|vector| cholesky.solve_upper(vector))
+            .collect(),
+        eigenvalues: eigen.values,
+        total: between.trace(),
+    })
+}
+
+/// Projects every trace of the source, giving a trace set with one sample per component
+///
+/// `progress` is called with the fraction of traces processed so far.
+pub fn project(
+    traces: &dyn TraceSource,
+    projection: &Projection,
+    progress: &mut dyn FnMut(f32),
+) -> Result<TraceSet, String> {
+    let window = projection.window();
+    check_window(traces, &window)?;
+
+    let mut projected = TraceSet::new(TimeAxis::new(1.0, 1.0), projection.components.len());
+    let mut read = 0;
+
+    for chunk in read_chunks(traces, PROJECTION_CHUNK_SIZE, window) {
+        let chunk = chunk.map_err(|e| e.to_string())?;
+        if read == 0 {
+            projected.parameters = chunk.parameters.clone();
+            projected
+                .parameters
+                .insert("projection".to_string(), projection.method.clone());
+        }
+
+        let rows: Vec<Vec<f32>> = (0..chunk.len())
+            .into_par_iter()
+            .map(|index| projection.apply(chunk.trace(index)))
+            .collect();
+        for (row, data) in rows.iter().zip(&chunk.data) {
+            projected.push(row, data.clone())?;
+        }
+
+        read += chunk.len();
+        progress(read as f32 / traces.len() as f32);
+    }
+
+    Ok(projected)
+}
+
+fn check_window(traces: &dyn TraceSource, window: &Range<usize>) -> Result<(), String> {
+    if window.is_empty() || window.end > traces.num_samples() {
+        return Err(format!(
+            "Invalid sample window {:?} for traces of {} samples",
+            window,
+            traces.num_samples()
+        ));
+    }
+    Ok(())
+}
+
+fn to_f64(trace: &[f32]) -> Vec<f64> {
+    trace.iter().map(|&x| x as f64).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::leakage::{AesSboxOutput, Leakage};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const MODEL: AesSboxOutput = AesSboxOutput {
+        byte: 0,
+        leakage: Leakage::Bit(0),
+    };
+
+    /// Traces whose first two samples share a large noise, with one bit of the first S-box
+    /// output added to the second sample only, and a third sample of small noise
+    fn leaking_traces(num_traces: usize) -> TraceSet {
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut traces = TraceSet::new(TimeAxis::default(), 4);
+        for _ in 0..num_traces {
+            let data = TraceData {
+                input: vec![rng.random()],
+                key: vec![0x2B],
+                ..Default::default()
+            };
+            let leak = MODEL.predict(&data, 0x2B).unwrap() as f32;
+            let shared: f32 = rng.random_range(-5.0..5.0);
+            let samples = [
+                0.0,
+                10.0 + shared + rng.random_range(-0.1..0.1),
+                shared + leak + rng.random_range(-0.1..0.1),
+                rng.random_range(-0.1..0.1),
+            ];
+            traces.push(&samples, data).unwrap();
+        }
+        traces
+    }
+
+    #[test]
+    fn pca_finds_direction_of_largest_variance() {
+        let traces = leaking_traces(2000);
+        let projection = pca(&traces, 1..4, 2, &mut |_| {}).unwrap();
+
+        assert_eq!(projection.window(), 1..4);
+        assert!((projection.mean[0] - 10.0).abs() < 0.5);
+        assert_eq!(projection.components.len(), 2);
+        // The shared noise moves the first two samples together
+        let first = &projection.components[0];
+        assert!((first[0] - first[1]).abs() < 0.05 && first[2].abs() < 0.05);
+        assert!((dot(first, first) - 1.0).abs() < 1e-9);
+        assert!(projection.eigenvalues[0] > 10.0 * projection.eigenvalues[1]);
+        assert!(projection.total >= projection.eigenvalues.iter().sum::<f64>());
+
+        let projected = project(&traces, &projection, &mut |_| {}).unwrap();
+        assert_eq!(projected.len(), traces.len());
+        assert_eq!(projected.num_samples(), 2);
+        assert_eq!(projected.parameters["projection"], "PCA");
+    }
+
+    #[test]
+    fn lda_separates_classes() {
+        let traces = leaking_traces(2000);
+        let projection = lda(&traces, 1..4, &MODEL, 3, &mut |_| {}).unwrap();
+
+        // Two classes have a single discriminant, which cancels the shared noise
+        assert_eq!(projection.components.len(), 1);
+        let component = &projection.components[0];
+        assert!((component[0] + component[1]).abs() < 0.05 * component[1].abs());
+
+        let projected = project(&traces, &projection, &mut |_| {}).unwrap();
+        let mut sums = [0.0; 2];
+        let mut counts = [0; 2];
+        for index in 0..projected.len() {
+            let class = MODEL.predict(projected.data(index), 0x2B).unwrap() as usize;
+            sums[class] += projected.trace(index)[0] as f64;
+            counts[class] += 1;
+        }
+        // Components have unit variance within the classes, so the means are far apart
+        let separation = (sums[1] / counts[1] as f64 - sums[0] / counts[0] as f64).abs();
+        assert!(separation > 5.0, "class means only {} apart", separation);
+    }
+
+    #[test]
+    fn rejects_invalid_windows() {
+        let traces = leaking_traces(10);
+        assert!(pca(&traces, 2..2, 1, &mut |_| {}).is_err());
+        assert!(pca(&traces, 0..5, 1, &mut |_| {}).is_err());
+        assert!(lda(&traces, 3..5, &MODEL, 1, &mut |_| {}).is_err());
+    }
+}
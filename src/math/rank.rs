@@ -0,0 +1,413 @@
+use crate::math::leakage::{GuessAccumulator, LeakageModel};
+use crate::trace_set::{TimeAxis, TraceData, TraceSet, TraceSource};
+use rand::rng;
+use rand::seq::SliceRandom;
+use std::error::Error;
+use std::ops::Range;
+
+/// Bins of the log-probability histograms when none are asked for
+pub const DEFAULT_RANK_BINS: usize = 1024;
+
+/// Coarser bins for the many estimates of the rank curves
+const CURVE_RANK_BINS: usize = 256;
+
+/// Highest |r| turned into a probability, keeping the Fisher transform finite
+const MAX_CORRELATION: f64 = 1.0 - 1e-9;
+
+/// Position of the correct key among all keys, as powers of two
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyRank {
+    pub log2_rank: f64,
+    /// Bounds of the estimate, from the rounding of the log-probabilities to bins
+    pub log2_lower: f64,
+    pub log2_upper: f64,
+}
+
+/// Scales scores to log-probabilities that sum to one
+pub fn normalize_log_probabilities(scores: &[f64]) -> Vec<f64> {
+    let max = scores.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let log_sum = max + scores.iter().map(|s| (s - max).exp()).sum::<f64>().ln();
+    scores.iter().map(|s| s - log_sum).collect()
+}
+
+/// Log-probabilities of guesses from the correlation peak of each, over `num_traces` traces.
+///
+/// Uses the log-likelihood ratio of the Fisher transform of every correlation against the
+/// correlation of a wrong guess, which is zero.
+pub fn correlation_log_probabilities(correlations: &[f64], num_traces: usize) -> Vec<f64> {
+    let scale = num_traces.saturating_sub(3) as f64 / 2.0;
+    let scores: Vec<f64> = correlations
+        .iter()
+        .map(|r| scale * r.abs().min(MAX_CORRELATION).atanh().powi(2))
+        .collect();
+    normalize_log_probabilities(&scores)
+}
+
+/// Estimates the rank of `key` from the log-probabilities of every guess of every key part,
+/// indexed [part][guess], by convolving a histogram of the log-probabilities of every part.
+///
+/// Rank 1 means the key is the most likely one.
+pub fn estimate_key_rank(
+    log_probabilities: &[Vec<f64>],
+    key: &[usize],
+    bins: usize,
+) -> Result<KeyRank, String> {
+    if log_probabilities.len() != key.len() {
+        return Err(format!(
+            "Got scores of {} key parts for a key of {}",
+            log_probabilities.len(),
+            key.len()
+        ));
+    }
+    if let Some(part) = (0..key.len()).find(|&part| key[part] >= log_probabilities[part].len()) {
+        return Err(format!("Key part {} has no score for its guess", part));
+    }
+
+    let finite = || log_probabilities.iter().flatten().filter(|p| p.is_finite());
+    let min = finite().cloned().fold(f64::INFINITY, f64::min);
+    let max = finite().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let bins = bins.max(1);
+    let width = ((max - min) / bins as f64).max(f64::MIN_POSITIVE);
+    let bin = |p: f64| {
+        if p.is_finite() {
+            (((p - min) / width) as usize).min(bins - 1)
+        } else {
+            0
+        }
+    };
+
+    // Number of keys in every bin of the summed log-probabilities
+    let mut keys = vec![1.0];
+    for part in log_probabilities {
+        let mut histogram = vec![0.0; bins];
+        for &p in part {
+            histogram[bin(p)] += 1.0;
+        }
+        keys = convolve(&keys, &histogram);
+    }
+
+    let key_bin = key
+        .iter()
+        .zip(log_probabilities)
+        .map(|(&guess, part)| bin(part[guess]))
+        .sum::<usize>();
+    // Every part can be off by one bin
+    let margin = key.len();
+    let keys_from = |first_bin: usize| keys[first_bin.min(keys.len())..].iter().sum::<f64>();
+
+    Ok(KeyRank {
+        log2_rank: keys_from(key_bin).max(1.0).log2(),
+        log2_lower: keys_from(key_bin + margin).max(1.0).log2(),
+        log2_upper: keys_from(key_bin.saturating_sub(margin)).max(1.0).log2(),
+    })
+}
+
+fn convolve(a: &[f64], b: &[f64]) -> Vec<f64> {
+    let mut result = vec![0.0; a.len() + b.len() - 1];
+    for (i, &x) in a.iter().enumerate().filter(|(_, &x)| x != 0.0) {
+        for (r, &y) in result[i..].iter_mut().zip(b) {
+            *r += x * y;
+        }
+    }
+    result
+}
+
+/// Reads a score table with one row per key part and one column per guess. A first row that
+/// isn't numeric is skipped as a header.
+pub fn read_score_csv(file_path: &str) -> Result<Vec<Vec<f64>>, Box<dyn Error>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_path(file_path)?;
+
+    let mut table = vec![];
+    for (row, record) in reader.records().enumerate() {
+        let record = record?;
+        let scores: Result<Vec<f64>, _> = record.iter().map(|f| f.trim().parse()).collect();
+        match scores {
+            Ok(scores) => table.push(scores),
+            Err(_) if row == 0 => continue,
+            Err(e) => return Err(format!("Row {}: {}", row + 1, e).into()),
+        }
+    }
+
+    if table.is_empty() {
+        return Err("The file has no scores".into());
+    }
+    Ok(table)
+}
+
+/// Guessing entropy and success rate of a full key against the number of traces
+pub struct RankCurves {
+    /// Number of traces of the first step, and between steps
+    pub step: usize,
+    /// Mean log2 rank of the key at every step
+    pub guessing_entropy: Vec<f64>,
+    /// Fraction of experiments ranking the key first at every step
+    pub success_rate: Vec<f64>,
+}
+
+impl RankCurves {
+    pub fn num_traces(&self) -> impl Iterator<Item = usize> + '_ {
+        (1..=self.guessing_entropy.len()).map(|step| step * self.step)
+    }
+
+    /// Both curves as trace sets over the number of traces, guessing entropy first
+    pub fn trace_sets(&self) -> Result<(TraceSet, TraceSet), String> {
+        let curve = |name: &str, values: &[f64]| {
+            let axis = TimeAxis::new(self.step as f64, self.step as f64);
+            let mut trace_set = TraceSet::new(axis, values.len());
+            trace_set
+                .parameters
+                .insert("title".to_string(), name.to_string());
+            let values: Vec<f32> = values.iter().map(|&v| v as f32).collect();
+            let data = TraceData {
+                label: name.to_string(),
+                ..Default::default()
+            };
+            trace_set.push(&values, data).map(|_| trace_set)
+        };
+
+        Ok((
+            curve("Guessing entropy (bits)", &self.guessing_entropy)?,
+            curve("Success rate", &self.success_rate)?,
+        ))
+    }
+
+    /// Writes the curves as CSV, one row per step
+    pub fn write_csv(&self, file_path: &str) -> Result<(), Box<dyn Error>> {
+        let mut writer = csv::Writer::from_path(file_path)?;
+        writer.write_record(["traces", "guessing_entropy", "success_rate"])?;
+
+        for ((traces, entropy), success) in self
+            .num_traces()
+            .zip(&self.guessing_entropy)
+            .zip(&self.success_rate)
+        {
+            writer.write_record([traces.to_string(), entropy.to_string(), success.to_string()])?;
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Runs a correlation distinguisher with one model per key part on growing random subsets of
+/// the first `max_traces` traces, `steps` subsets per experiment, and averages the rank of the
+/// key stored with the traces over `experiments` experiments.
+///
+/// Every subset gets the correlations `guess_correlations` would give it, but they are grown with
+/// one [`GuessAccumulator`] per model as the subset grows instead of being recomputed from all of
+/// its traces at every step.
+///
+/// `progress` is called with the fraction of subsets done so far.
+pub fn rank_curves(
+    traces: &dyn TraceSource,
+    window: Range<usize>,
+    models: &[Box<dyn LeakageModel>],
+    max_traces: usize,
+    steps: usize,
+    experiments: usize,
+    progress: &mut dyn FnMut(f32),
+) -> Result<RankCurves, String> {
+    if window.is_empty() || window.end > traces.num_samples() {
+        return Err(format!("Invalid sample window {:?}", window));
+    }
+    let max_traces = max_traces.min(traces.len());
+    let step = max_traces / steps.max(1);
+    if step < 2 || experiments == 0 {
+        return Err(format!(
+            "Can't make {} steps of at least 2 traces out of {}",
+            steps, max_traces
+        ));
+    }
+
+    let all = traces
+        .read_window(0..max_traces, window)
+        .map_err(|e| e.to_string())?;
+    let key = models
+        .iter()
+        .map(|model| model.known_guess(all.data(0)))
+        .collect::<Option<Vec<usize>>>()
+        .ok_or("The traces have no known key to rank")?;
+
+    let mut entropy = vec![0.0; steps];
+    let mut successes = vec![0; steps];
+    let mut order: Vec<usize> = (0..max_traces).collect();
+
+    for experiment in 0..experiments {
+        order.shuffle(&mut rng());
+
+        // The subsets of an experiment grow along the same order, so each step only adds the
+        // traces since the previous one
+        let mut accumulators: Vec<GuessAccumulator> = models
+            .iter()
+            .map(|model| GuessAccumulator::new(model.as_ref(), all.num_samples()))
+            .collect();
+
+        for (index, added) in order[..steps * step].chunks(step).enumerate() {
+            let log_probabilities = models
+                .iter()
+                .zip(accumulators.iter_mut())
+                .map(|(model, accumulator)| {
+                    accumulator.add(model.as_ref(), &all, added);
+                    let num_traces = accumulator.num_traces() as usize;
+                    if num_traces < 2 {
+                        return Err(format!(
+                            "{} can only predict {} of the traces",
+                            model.name(),
+                            num_traces
+                        ));
+                    }
+                    Ok(correlation_log_probabilities(
+                        &accumulator.peaks(),
+                        num_traces,
+                    ))
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+
+            let rank = estimate_key_rank(&log_probabilities, &key, CURVE_RANK_BINS)?;
+            entropy[index] += rank.log2_rank / experiments as f64;
+            let first = log_probabilities
+                .iter()
+                .zip(&key)
+                .all(|(part, &guess)| part.iter().all(|&p| p <= part[guess]));
+            if first {
+                successes[index] += 1;
+            }
+
+            progress((experiment * steps + index + 1) as f32 / (experiments * steps) as f32);
+        }
+    }
+
+    Ok(RankCurves {
+        step,
+        guessing_entropy: entropy,
+        success_rate: successes
+            .iter()
+            .map(|&s| s as f64 / experiments as f64)
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::aes::SBOX;
+    use crate::math::leakage::{AesSboxOutput, Leakage};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    /// Number of keys at least as likely as `key`, found by enumerating every key
+    fn exact_rank(log_probabilities: &[Vec<f64>], key: &[usize]) -> usize {
+        let score = |guesses: &[usize]| -> f64 {
+            guesses
+                .iter()
+                .zip(log_probabilities)
+                .map(|(&guess, part)| part[guess])
+                .sum()
+        };
+        let key_score = score(key);
+
+        let num_keys: usize = log_probabilities.iter().map(Vec::len).product();
+        (0..num_keys)
+            .filter(|&index| {
+                let mut rest = index;
+                let guesses: Vec<usize> = log_probabilities
+                    .iter()
+                    .map(|part| {
+                        let guess = rest % part.len();
+                        rest /= part.len();
+                        guess
+                    })
+                    .collect();
+                score(&guesses) >= key_score
+            })
+            .count()
+    }
+
+    #[test]
+    fn rank_matches_enumeration() {
+        let mut rng = StdRng::seed_from_u64(11);
+        for _ in 0..20 {
+            let log_probabilities: Vec<Vec<f64>> = (0..3)
+                .map(|_| {
+                    let scores: Vec<f64> = (0..8).map(|_| rng.random_range(0.0..5.0)).collect();
+                    normalize_log_probabilities(&scores)
+                })
+                .collect();
+            let key: Vec<usize> = (0..3).map(|_| rng.random_range(0..8)).collect();
+
+            let exact = (exact_rank(&log_probabilities, &key) as f64).log2();
+            let estimate = estimate_key_rank(&log_probabilities, &key, 4096).unwrap();
+            assert!(
+                estimate.log2_lower <= exact + 1e-9,
+                "{:?} {}",
+                estimate,
+                exact
+            );
+            assert!(
+                estimate.log2_upper >= exact - 1e-9,
+                "{:?} {}",
+                estimate,
+                exact
+            );
+            assert!(
+                (estimate.log2_rank - exact).abs() < 0.5,
+                "{:?} {}",
+                estimate,
+                exact
+            );
+        }
+    }
+
+    #[test]
+    fn best_key_ranks_first() {
+        let log_probabilities = vec![
+            normalize_log_probabilities(&[3.0, 0.0, 1.0]),
+            normalize_log_probabilities(&[0.0, 2.0]),
+        ];
+        let rank = estimate_key_rank(&log_probabilities, &[0, 1], DEFAULT_RANK_BINS).unwrap();
+        assert_eq!(rank.log2_rank, 0.0);
+        assert!(estimate_key_rank(&log_probabilities, &[0], DEFAULT_RANK_BINS).is_err());
+        assert!(estimate_key_rank(&log_probabilities, &[3, 0], DEFAULT_RANK_BINS).is_err());
+    }
+
+    #[test]
+    fn rank_curves_converge() {
+        let mut rng = StdRng::seed_from_u64(13);
+        let key = [0x2Bu8, 0x7E];
+        let mut traces = TraceSet::new(TimeAxis::default(), 2);
+        for _ in 0..200 {
+            let plaintext: Vec<u8> = (0..2).map(|_| rng.random()).collect();
+            let samples: Vec<f32> = (0..2)
+                .map(|i| {
+                    let leak = SBOX[(plaintext[i] ^ key[i]) as usize].count_ones() as f32;
+                    leak + rng.random_range(-1.0..1.0)
+                })
+                .collect();
+            let data = TraceData {
+                input: plaintext,
+                key: key.to_vec(),
+                ..Default::default()
+            };
+            traces.push(&samples, data).unwrap();
+        }
+
+        let models: Vec<Box<dyn LeakageModel>> = (0..2)
+            .map(|byte| {
+                let model = AesSboxOutput {
+                    byte,
+                    leakage: Leakage::HammingWeight,
+                };
+                Box::new(model) as Box<dyn LeakageModel>
+            })
+            .collect();
+        let curves = rank_curves(&traces, 0..2, &models, 200, 4, 3, &mut |_| {}).unwrap();
+
+        assert_eq!(curves.num_traces().collect::<Vec<_>>(), [50, 100, 150, 200]);
+        assert_eq!(curves.success_rate[3], 1.0);
+        assert!(curves.guessing_entropy[3] < 0.5);
+        assert!(curves.guessing_entropy[0] >= curves.guessing_entropy[3]);
+    }
+}
@@ -0,0 +1,360 @@
+use crate::math::leakage::{AesSboxOutput, Leakage, LeakageModel};
+use crate::math::moments::OnlineMoments;
+use crate::math::MAX_ACCUMULATOR_BYTES;
+use crate::trace_set::{read_chunks, TimeAxis, TraceData, TraceSet, TraceSource};
+use rayon::prelude::*;
+use std::error::Error;
+use std::ops::Range;
+
+/// Number of traces read from the source at once
+const SNR_CHUNK_SIZE: usize = 1024;
+
+/// Most classes a class function can sort traces into
+pub const MAX_CLASSES: usize = 256;
+
+/// Intermediate value the traces are grouped by, computed from the trace data
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SnrClass {
+    PlaintextByte(usize),
+    CiphertextByte(usize),
+    /// First-round S-box output of a byte, using the key stored with the traces
+    SboxOutput(usize),
+    /// Hamming weight of the first-round S-box output of a byte
+    SboxOutputHammingWeight(usize),
+}
+
+impl SnrClass {
+    /// Class of a trace, `None` when its data doesn't hold the needed bytes
+    pub fn class_of(&self, data: &TraceData) -> Option<usize> {
+        // The S-box classes are the predictions of the correct key guess
+        let sbox_output = |byte: usize, leakage: Leakage| {
+            let model = AesSboxOutput { byte, leakage };
+            model
+                .predict(data, model.known_guess(data)?)
+                .map(|value| value as usize)
+        };
+
+        match *self {
+            SnrClass::PlaintextByte(byte) => data.input.get(byte).map(|&b| b as usize),
+            SnrClass::CiphertextByte(byte) => data.output.get(byte).map(|&b| b as usize),
+            SnrClass::SboxOutput(byte) => sbox_output(byte, Leakage::Identity),
+            SnrClass::SboxOutputHammingWeight(byte) => sbox_output(byte, Leakage::HammingWeight),
+        }
+    }
+}
+
+/// Streaming mean and variance of every class, at every sample.
+pub struct ClassAccumulator {
+    classes: Vec<OnlineMoments>,
+}
+
+impl ClassAccumulator {
+    /// Prepares the moments of `MAX_CLASSES` classes, failing when they would take more than
+    /// `MAX_ACCUMULATOR_BYTES` of memory.
+    pub fn new(num_samples: usize) -> Result<Self, String> {
+        // Every class keeps a mean and a central sum of squares per sample
+        let fits = (MAX_CLASSES * 2 * size_of::<f64>())
+            .checked_mul(num_samples)
+            .is_some_and(|size| size <= MAX_ACCUMULATOR_BYTES);
+        if !fits {
+            return Err(format!(
+                "SNR on {} samples needs more than the {} MiB allowed, select a smaller window",
+                num_samples,
+                MAX_ACCUMULATOR_BYTES >> 20
+            ));
+        }
+
+        Ok(ClassAccumulator {
+            classes: vec![OnlineMoments::new(num_samples, 2); MAX_CLASSES],
+        })
+    }
+
+    /// Adds a batch of traces, skipping the ones `class` returns `None` for.
+    pub fn add(&mut self, traces: &TraceSet, class: impl Fn(&TraceData) -> Option<usize>) {
+        let mut members: Vec<Vec<usize>> = vec![vec![]; MAX_CLASSES];
+        for (index, data) in traces.data.iter().enumerate() {
+            if let Some(class) = class(data).filter(|&class| class < MAX_CLASSES) {
+                members[class].push(index);
+            }
+        }
+
+        self.classes
+            .par_iter_mut()
+            .zip(members)
+            .for_each(|(moments, members)| {
+                for index in members {
+                    moments.add(traces.trace(index));
+                }
+            });
+    }
+
+    /// Number of traces in every class
+    pub fn counts(&self) -> Vec<u64> {
+        self.classes.iter().map(|moments| moments.count()).collect()
+    }
+
+    /// Variance of the class means and mean of the class variances at every sample, weighting
+    /// every class by its number of traces
+    fn signal_and_noise(&self, num_samples: usize) -> Vec<(f64, f64)> {
+        let classes: Vec<&OnlineMoments> = self.classes.iter().filter(|c| c.count() > 0).collect();
+        let total = classes.iter().map(|c| c.count()).sum::<u64>() as f64;
+
+        (0..num_samples)
+            .map(|sample| {
+                let mean = classes
+                    .iter()
+                    .map(|c| c.count() as f64 * c.mean()[sample])
+                    .sum::<f64>()
+                    / total;
+                let signal = classes
+                    .iter()
+                    .map(|c| c.count() as f64 * (c.mean()[sample] - mean).powi(2))
+                    .sum::<f64>()
+                    / total;
+                let noise = classes
+                    .iter()
+                    .map(|c| c.count() as f64 * c.central_moment(sample, 2))
+                    .sum::<f64>()
+                    / total;
+                (signal, noise)
+            })
+            .collect()
+    }
+}
+
+pub struct SnrResult {
+    /// Var(E[X|class]) / E[Var(X|class)] as its only trace
+    pub snr: TraceSet,
+    /// Var(E[X|class]) / Var(X) as its only trace
+    pub nicv: TraceSet,
+    /// Number of traces in every class
+    pub counts: Vec<u64>,
+}
+
+/// Computes the SNR and NICV of the `window` samples, grouping the traces with `class`.
+///
+/// `progress` is called with the fraction of traces processed so far.
+pub fn snr(
+    traces: &dyn TraceSource,
+    window: Range<usize>,
+    class: SnrClass,
+    progress: &mut dyn FnMut(f32),
+) -> Result<SnrResult, String> {
+    if window.is_empty() || window.end > traces.num_samples() {
+        return Err(format!("Invalid sample window {:?}", window));
+    }
+
+    let mut accumulator = ClassAccumulator::new(window.len())?;
+    let mut processed = 0;
+
+    for chunk in read_chunks(traces, SNR_CHUNK_SIZE, window.clone()) {
+        let chunk = chunk.map_err(|e| e.to_string())?;
+        accumulator.add(&chunk, |data| class.class_of(data));
+
+        processed += chunk.len();
+        progress(processed as f32 / traces.len() as f32);
+    }
+
+    let counts = accumulator.counts();
+    if counts.iter().filter(|&&count| count > 0).count() < 2 {
+        return Err(format!(
+            "{:?} sorts the traces into less than two classes",
+            class
+        ));
+    }
+
+    let (snr, nicv): (Vec<f32>, Vec<f32>) = accumulator
+        .signal_and_noise(window.len())
+        .into_iter()
+        .map(|(signal, noise)| {
+            let ratio = |a: f64, b: f64| if b > 0.0 { (a / b) as f32 } else { 0.0 };
+            (ratio(signal, noise), ratio(signal, signal + noise))
+        })
+        .unzip();
+
+    let time = traces.time();
+    let time = TimeAxis::new(time.x(window.start), time.interval);
+    let single_trace = |name: &str, values: &[f32]| {
+        let mut trace_set = TraceSet::new(time, window.len());
+        trace_set
+            .parameters
+            .insert("title".to_string(), format!("{} of {:?}", name, class));
+        let data = TraceData {
+            label: name.to_string(),
+            ..Default::default()
+        };
+        trace_set.push(values, data).map(|_| trace_set)
+    };
+
+    Ok(SnrResult {
+        snr: single_trace("SNR", &snr)?,
+        nicv: single_trace("NICV", &nicv)?,
+        counts,
+    })
+}
+
+/// A point of interest: a local peak of a leakage trace
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PointOfInterest {
+    /// Sample index in the analysed trace
+    pub sample: usize,
+    pub value: f32,
+}
+
+/// Picks the `count` highest samples of `trace` that are at least `min_distance` samples apart,
+/// so that one wide peak doesn't fill the whole list.
+pub fn top_peaks(trace: &[f32], count: usize, min_distance: usize) -> Vec<PointOfInterest> {
+    let mut order: Vec<usize> = (0..trace.len()).collect();
+    order.sort_by(|&a, &b| trace[b].total_cmp(&trace[a]));
+
+    let mut peaks: Vec<PointOfInterest> = Vec::with_capacity(count);
+    for sample in order {
+        if peaks.len() >= count {
+            break;
+        }
+        if peaks
+            .iter()
+            .all(|p| p.sample.abs_diff(sample) >= min_distance.max(1))
+        {
+            peaks.push(PointOfInterest {
+                sample,
+                value: trace[sample],
+            });
+        }
+    }
+
+    peaks
+}
+
+/// Writes points of interest as CSV, with sample indices offset by `first_sample` and their time
+pub fn write_poi_csv(
+    pois: &[PointOfInterest],
+    first_sample: usize,
+    time: &TimeAxis,
+    file_path: &str,
+) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::Writer::from_path(file_path)?;
+    writer.write_record(["sample", "time", "value"])?;
+
+    for poi in pois {
+        let sample = first_sample + poi.sample;
+        writer.write_record([
+            sample.to_string(),
+            time.x(sample).to_string(),
+            poi.value.to_string(),
+        ])?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Reads the sample indices of points of interest written by `write_poi_csv`
+pub fn read_poi_csv(file_path: &str) -> Result<Vec<usize>, Box<dyn Error>> {
+    let mut reader = csv::Reader::from_path(file_path)?;
+    let column = reader
+        .headers()?
+        .iter()
+        .position(|header| header == "sample")
+        .ok_or("The file has no sample column")?;
+
+    let mut samples = vec![];
+    for record in reader.records() {
+        let record = record?;
+        samples.push(record.get(column).unwrap_or_default().trim().parse()?);
+    }
+    Ok(samples)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn snr_and_nicv_match_two_pass_reference() {
+        // The first sample leaks the plaintext byte, the second is noise only
+        let mut rng = StdRng::seed_from_u64(13);
+        let mut traces = TraceSet::new(TimeAxis::default(), 2);
+        for _ in 0..400 {
+            let value: u8 = rng.random_range(0..4);
+            let samples = [
+                100.0 + value as f32 + rng.random_range(-2.0..2.0),
+                rng.random_range(-2.0..2.0),
+            ];
+            let data = TraceData {
+                input: vec![value],
+                ..Default::default()
+            };
+            traces.push(&samples, data).unwrap();
+        }
+
+        let result = snr(&traces, 0..2, SnrClass::PlaintextByte(0), &mut |_| {}).unwrap();
+        assert_eq!(result.counts.iter().sum::<u64>(), 400);
+        assert_eq!(result.counts.iter().filter(|&&c| c > 0).count(), 4);
+
+        for sample in 0..2 {
+            let classes: Vec<Vec<f64>> = (0..4)
+                .map(|value| {
+                    (0..traces.len())
+                        .filter(|&i| traces.data(i).input[0] == value)
+                        .map(|i| traces.trace(i)[sample] as f64)
+                        .collect()
+                })
+                .collect();
+            let mean = |values: &[f64]| values.iter().sum::<f64>() / values.len() as f64;
+            let variance = |values: &[f64]| {
+                let m = mean(values);
+                values.iter().map(|v| (v - m).powi(2)).sum::<f64>() / values.len() as f64
+            };
+            let all: Vec<f64> = classes.concat();
+            let total_mean = mean(&all);
+            let weight = |class: &Vec<f64>| class.len() as f64 / all.len() as f64;
+            let signal: f64 = classes
+                .iter()
+                .map(|c| weight(c) * (mean(c) - total_mean).powi(2))
+                .sum();
+            let noise: f64 = classes.iter().map(|c| weight(c) * variance(c)).sum();
+
+            let expected_snr = signal / noise;
+            let expected_nicv = signal / variance(&all);
+            let snr = result.snr.trace(0)[sample] as f64;
+            let nicv = result.nicv.trace(0)[sample] as f64;
+            assert!((snr - expected_snr).abs() < 1e-5 * expected_snr.max(1e-3));
+            assert!((nicv - expected_nicv).abs() < 1e-5 * expected_nicv.max(1e-3));
+        }
+        assert!(result.snr.trace(0)[0] > 10.0 * result.snr.trace(0)[1]);
+    }
+
+    #[test]
+    fn needs_two_classes_and_a_small_window() {
+        let mut traces = TraceSet::new(TimeAxis::default(), 1);
+        for _ in 0..3 {
+            let data = TraceData {
+                input: vec![7],
+                ..Default::default()
+            };
+            traces.push(&[1.0], data).unwrap();
+        }
+        assert!(snr(&traces, 0..1, SnrClass::PlaintextByte(0), &mut |_| {}).is_err());
+
+        assert!(ClassAccumulator::new(1000).is_ok());
+        assert!(ClassAccumulator::new(1 << 30).is_err());
+        assert!(ClassAccumulator::new(usize::MAX / 8).is_err());
+    }
+
+    #[test]
+    fn top_peaks_keep_their_distance() {
+        let trace = [0.0, 5.0, 4.9, 1.0, 0.0, 3.0, 0.0, 0.0, 4.0, 0.0];
+
+        let samples = |peaks: Vec<PointOfInterest>| -> Vec<usize> {
+            peaks.iter().map(|p| p.sample).collect()
+        };
+        assert_eq!(samples(top_peaks(&trace, 3, 1)), [1, 2, 8]);
+        assert_eq!(samples(top_peaks(&trace, 3, 2)), [1, 8, 5]);
+        assert_eq!(samples(top_peaks(&trace, 10, 4)), [1, 8]);
+        assert_eq!(top_peaks(&trace, 1, 0)[0].value, 5.0);
+        assert!(top_peaks(&[], 3, 1).is_empty());
+    }
+}
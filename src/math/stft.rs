@@ -0,0 +1,140 @@
+use crate::math::fft::{SpectrumAnalyzer, WindowFunction};
+use crate::trace_set::TimeAxis;
+use rayon::prelude::*;
+
+/// Power below which the spectrogram is cut off, keeping it finite in decibels
+const MIN_POWER: f64 = 1e-30;
+
+/// Power of a trace over time and frequency
+#[derive(Clone, Debug)]
+pub struct Spectrogram {
+    /// Center times of the frames
+    pub time: TimeAxis,
+    /// Frequencies of the bins
+    pub frequency: TimeAxis,
+    pub num_frames: usize,
+    pub num_bins: usize,
+    /// Power in decibels, indexed [frame][bin]
+    pub power: Vec<f32>,
+}
+
+impl Spectrogram {
+    pub fn frame(&self, frame: usize) -> &[f32] {
+        &self.power[frame * self.num_bins..(frame + 1) * self.num_bins]
+    }
+
+    /// Lowest and highest power, ignoring the cut off
+    pub fn power_range(&self) -> (f32, f32) {
+        let floor = (10.0 * MIN_POWER.log10()) as f32;
+        self.power
+            .iter()
+            .filter(|&&p| p > floor)
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), &p| {
+                (min.min(p), max.max(p))
+            })
+    }
+}
+
+/// Short-time Fourier transform of `trace`, sampled on `time`: the power spectrum in decibels of
+/// every frame of `window_len` samples, `hop` samples apart.
+pub fn stft(
+    trace: &[f32],
+    time: TimeAxis,
+    window_len: usize,
+    hop: usize,
+    function: WindowFunction,
+) -> Result<Spectrogram, String> {
+    if hop == 0 {
+        return Err("The hop must be at least one sample".to_string());
+    }
+    if window_len > trace.len() {
+        return Err(format!(
+            "A window of {} samples doesn't fit in a trace of {}",
+            window_len,
+            trace.len()
+        ));
+    }
+
+    let analyzer = SpectrumAnalyzer::new(window_len, function)?;
+    let num_bins = analyzer.num_bins();
+    let num_frames = (trace.len() - window_len) / hop + 1;
+
+    let mut power = vec![0.0f32; num_frames * num_bins];
+    power
+        .par_chunks_mut(num_bins)
+        .enumerate()
+        .try_for_each_init(
+            || (analyzer.buffers(), vec![0.0; num_bins]),
+            |(buffers, frame_power), (frame, out)| {
+                let start = frame * hop;
+                analyzer.power_into(&trace[start..start + window_len], buffers, frame_power)?;
+                for (out, &p) in out.iter_mut().zip(frame_power.iter()) {
+                    *out = (10.0 * p.max(MIN_POWER).log10()) as f32;
+                }
+                Ok::<(), String>(())
+            },
+        )?;
+
+    // Frames are placed at their center
+    let center = time.offset + (window_len - 1) as f64 / 2.0 * time.interval;
+    Ok(Spectrogram {
+        time: TimeAxis::new(center, hop as f64 * time.interval),
+        frequency: analyzer.frequency_axis(time),
+        num_frames,
+        num_bins,
+        power,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    #[test]
+    fn tones_land_in_their_frames_and_bins() {
+        // 125 Hz for the first half second and 250 Hz for the second, sampled at 1 kHz, so
+        // windows of 64 samples put them on bins 8 and 16
+        let time = TimeAxis::new(2.0, 1e-3);
+        let trace: Vec<f32> = (0..1024)
+            .map(|i| {
+                let frequency = if i < 512 { 125.0 } else { 250.0 };
+                (2.0 * PI * frequency * i as f64 * 1e-3).sin() as f32
+            })
+            .collect();
+
+        let spectrogram = stft(&trace, time, 64, 32, WindowFunction::Rectangular).unwrap();
+        assert_eq!(spectrogram.num_frames, 31);
+        assert_eq!(spectrogram.num_bins, 33);
+        assert!((spectrogram.frequency.x(8) - 125.0).abs() < 1e-9);
+        assert!((spectrogram.time.x(0) - 2.0315).abs() < 1e-9);
+        assert!((spectrogram.time.x(16) - 2.5435).abs() < 1e-9);
+
+        let peak = |frame: &[f32]| {
+            (0..frame.len())
+                .max_by(|&a, &b| frame[a].total_cmp(&frame[b]))
+                .unwrap()
+        };
+        for frame in 0..spectrogram.num_frames {
+            let start = frame * 32;
+            let expected = match start + 64 {
+                end if end <= 512 => 8,
+                _ if start >= 512 => 16,
+                // Frames straddling the change hold both tones
+                _ => continue,
+            };
+            let power = spectrogram.frame(frame);
+            assert_eq!(peak(power), expected, "frame {}", frame);
+            // A unit sine has a power of 1, 0 dB
+            assert!(power[expected].abs() < 1e-3, "{}", power[expected]);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_windows() {
+        let trace = [0.0; 16];
+        assert!(stft(&trace, TimeAxis::default(), 8, 0, WindowFunction::Hann).is_err());
+        assert!(stft(&trace, TimeAxis::default(), 17, 1, WindowFunction::Hann).is_err());
+        assert!(stft(&trace, TimeAxis::default(), 1, 1, WindowFunction::Hann).is_err());
+    }
+}
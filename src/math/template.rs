@@ -0,0 +1,368 @@
+use crate::math::leakage::LeakageModel;
+use crate::math::linalg::{Cholesky, Matrix};
+use crate::math::{read_versioned, write_versioned};
+use crate::trace_set::{read_chunks, TraceSet, TraceSource};
+use bincode::{Decode, Encode};
+use std::f64::consts::PI;
+use std::io;
+
+const MAGIC: &[u8; 8] = b"SCTEMPLT";
+const VERSION: u32 = 1;
+
+/// Number of traces read from the source at once
+const TEMPLATE_CHUNK_SIZE: usize = 1024;
+
+/// Gaussian templates: the mean at the points of interest of every class of an intermediate
+/// value, with one covariance matrix pooled over all classes.
+#[derive(Clone, Debug, Encode, Decode)]
+pub struct Templates {
+    /// Name of the leakage model the classes come from
+    pub model: String,
+    /// Sample indices of the points of interest
+    pub pois: Vec<usize>,
+    /// Mean of every class, `None` for classes without profiling traces
+    pub means: Vec<Option<Vec<f64>>>,
+    /// Number of profiling traces of every class
+    pub counts: Vec<u64>,
+    pub covariance: Matrix,
+}
+
+impl Templates {
+    pub fn save(&self, file_path: &str) -> io::Result<()> {
+        write_versioned(file_path, MAGIC, VERSION, self)
+    }
+
+    pub fn load(file_path: &str) -> io::Result<Self> {
+        read_versioned(file_path, MAGIC, VERSION, "template")
+    }
+
+    /// Number of classes with a template
+    pub fn num_classes(&self) -> usize {
+        self.means.iter().filter(|mean| mean.is_some()).count()
+    }
+}
+
+/// One-pass accumulators for building templates from profiling traces with a known key.
+pub struct TemplateBuilder {
+    pois: Vec<usize>,
+    /// Values of the first trace at the points of interest, subtracted from every trace to keep
+    /// the sums of products small
+    shift: Option<Vec<f64>>,
+    /// Sum of the shifted traces of every class
+    class_sums: Vec<Vec<f64>>,
+    class_counts: Vec<u64>,
+    /// Sum of the outer products of the shifted traces
+    products: Matrix,
+}
+
+impl TemplateBuilder {
+    pub fn new(pois: Vec<usize>) -> Self {
+        let num_pois = pois.len();
+        TemplateBuilder {
+            pois,
+            shift: None,
+            class_sums: vec![],
+            class_counts: vec![],
+            products: Matrix::zeros(num_pois, num_pois),
+        }
+    }
+
+    /// Adds traces whose samples start at sample `offset`, sorting them into the classes `model`
+    /// predicts for the key stored with each trace. Traces without a prediction are skipped.
+    pub fn add(&mut self, traces: &TraceSet, offset: usize, model: &dyn LeakageModel) {
+        for index in 0..traces.len() {
+            let data = traces.data(index);
+            let Some(class) = model
+                .known_guess(data)
+                .and_then(|guess| model.predict(data, guess))
+            else {
+                continue;
+            };
+
+            let trace = traces.trace(index);
+            let values: Vec<f64> = self
+                .pois
+                .iter()
+                .map(|&poi| trace[poi - offset] as f64)
+                .collect();
+            let shift = self.shift.get_or_insert_with(|| values.clone());
+            let shifted: Vec<f64> = values
+                .iter()
+                .zip(shift.iter())
+                .map(|(v, s)| v - s)
+                .collect();
+
+            let class = class as usize;
+            if class >= self.class_sums.len() {
+                self.class_sums
+                    .resize(class + 1, vec![0.0; self.pois.len()]);
+                self.class_counts.resize(class + 1, 0);
+            }
+            for (sum, x) in self.class_sums[class].iter_mut().zip(&shifted) {
+                *sum += x;
+            }
+            self.class_counts[class] += 1;
+            self.products.add_outer(&shifted, 1.0);
+        }
+    }
+
+    pub fn build(&self, model: &dyn LeakageModel) -> Result<Templates, String> {
+        let num_traces: u64 = self.class_counts.iter().sum();
+        let num_classes = self.class_counts.iter().filter(|&&count| count > 0).count();
+        if num_traces <= num_classes as u64 {
+            return Err(format!(
+                "{} profiling traces in {} classes are too few for a covariance",
+                num_traces, num_classes
+            ));
+        }
+
+        // The scatter within the classes is the total scatter minus the scatter of the means
+        let mut covariance = self.products.clone();
+        for (sum, &count) in self.class_sums.iter().zip(&self.class_counts) {
+            if count > 0 {
+                covariance.add_outer(sum, -1.0 / count as f64);
+            }
+        }
+        covariance.scale(1.0 / (num_traces - num_classes as u64) as f64);
+
+        let shift = self.shift.as_ref().ok_or("No profiling traces")?;
+        let means = self
+            .class_sums
+            .iter()
+            .zip(&self.class_counts)
+            .map(|(sum, &count)| {
+                (count > 0).then(|| {
+                    sum.iter()
+                        .zip(shift)
+                        .map(|(sum, shift)| sum / count as f64 + shift)
+                        .collect()
+                })
+            })
+            .collect();
+
+        Ok(Templates {
+            model: model.name(),
+            pois: self.pois.clone(),
+            means,
+            counts: self.class_counts.clone(),
+            covariance,
+        })
+    }
+}
+
+/// Builds templates at the `pois` from every trace of the source.
+///
+/// `progress` is called with the fraction of traces processed so far.
+pub fn build_templates(
+    traces: &dyn TraceSource,
+    pois: &[usize],
+    model: &dyn LeakageModel,
+    progress: &mut dyn FnMut(f32),
+) -> Result<Templates, String> {
+    let window = poi_window(pois, traces.num_samples())?;
+    let mut builder = TemplateBuilder::new(pois.to_vec());
+    let mut read = 0;
+
+    for chunk in read_chunks(traces, TEMPLATE_CHUNK_SIZE, window.clone()) {
+        let chunk = chunk.map_err(|e| e.to_string())?;
+        builder.add(&chunk, window.start, model);
+        read += chunk.len();
+        progress(read as f32 / traces.len() as f32);
+    }
+
+    builder.build(model)
+}
+
+/// Samples from the first to the last point of interest
+fn poi_window(pois: &[usize], num_samples: usize) -> Result<std::ops::Range<usize>, String> {
+    let (Some(&first), Some(&last)) = (pois.iter().min(), pois.iter().max()) else {
+        return Err("Choose at least one point of interest".to_string());
+    };
+    if last >= num_samples {
+        return Err(format!(
+            "Point of interest {} is past the {} samples of the traces",
+            last, num_samples
+        ));
+    }
+    Ok(first..last + 1)
+}
+
+pub struct TemplateResult {
+    pub num_traces: usize,
+    /// Log-likelihood of every guess, summed over the attack traces
+    pub log_likelihoods: Vec<f64>,
+    /// Guess matching the key stored with the attack traces, if any
+    pub known_guess: Option<usize>,
+}
+
+impl TemplateResult {
+    /// Guesses with their log-likelihood, most likely first
+    pub fn ranking(&self) -> Vec<(usize, f64)> {
+        let mut ranking: Vec<(usize, f64)> =
+            self.log_likelihoods.iter().cloned().enumerate().collect();
+        ranking.sort_by(|a, b| b.1.total_cmp(&a.1));
+        ranking
+    }
+}
+
+/// Matches the attack traces of the source against `templates`: every guess of `model` gets the
+/// log-likelihood of every trace under the template of the class it predicts, summed over the
+/// traces. A guess predicting a class without a template is ruled out.
+///
+/// `progress` is called with the fraction of traces processed so far.
+pub fn match_templates(
+    traces: &dyn TraceSource,
+    templates: &Templates,
+    model: &dyn LeakageModel,
+    progress: &mut dyn FnMut(f32),
+) -> Result<TemplateResult, String> {
+    if templates.model != model.name() {
+        return Err(format!(
+            "The templates were built for {}, not {}",
+            templates.model,
+            model.name()
+        ));
+    }
+
+    let window = poi_window(&templates.pois, traces.num_samples())?;
+    let cholesky = Cholesky::new(&templates.covariance)?;
+    let normalization = -0.5 * (cholesky.log_det() + templates.pois.len() as f64 * (2.0 * PI).ln());
+
+    let mut log_likelihoods = vec![0.0; model.num_guesses()];
+    let mut known_guess = None;
+    let mut num_traces = 0;
+    let mut read = 0;
+
+    for chunk in read_chunks(traces, TEMPLATE_CHUNK_SIZE, window.clone()) {
+        let chunk = chunk.map_err(|e| e.to_string())?;
+
+        for index in 0..chunk.len() {
+            let data = chunk.data(index);
+            if model.predict(data, 0).is_none() {
+                continue;
+            }
+            if known_guess.is_none() {
+                known_guess = model.known_guess(data);
+            }
+
+            let trace = chunk.trace(index);
+            let values: Vec<f64> = templates
+                .pois
+                .iter()
+                .map(|&poi| trace[poi - window.start] as f64)
+                .collect();
+
+            // Every class needs one Mahalanobis distance, however many guesses predict it
+            let class_likelihoods: Vec<f64> = templates
+                .means
+                .iter()
+                .map(|mean| match mean {
+                    Some(mean) => {
+                        let deviation: Vec<f64> =
+                            values.iter().zip(mean).map(|(x, m)| x - m).collect();
+                        normalization - 0.5 * cholesky.mahalanobis(&deviation)
+                    }
+                    None => f64::NEG_INFINITY,
+                })
+                .collect();
+
+            for (guess, log_likelihood) in log_likelihoods.iter_mut().enumerate() {
+                let class = model.predict(data, guess).unwrap_or(0.0) as usize;
+                *log_likelihood += class_likelihoods
+                    .get(class)
+                    .copied()
+                    .unwrap_or(f64::NEG_INFINITY);
+            }
+            num_traces += 1;
+        }
+
+        read += chunk.len();
+        progress(read as f32 / traces.len() as f32);
+    }
+
+    if num_traces == 0 {
+        return Err(format!("{} can't predict any attack trace", model.name()));
+    }
+
+    Ok(TemplateResult {
+        num_traces,
+        log_likelihoods,
+        known_guess,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::leakage::{AesSboxOutput, Leakage};
+    use crate::trace_set::{TimeAxis, TraceData};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const KEY: u8 = 0x2B;
+
+    /// Traces leaking the Hamming weight of the first S-box output at samples 1 and 3, with noise
+    /// shared between them so the covariance matters
+    fn leaking_traces(num_traces: usize, seed: u64) -> TraceSet {
+        let model = AesSboxOutput {
+            byte: 0,
+            leakage: Leakage::HammingWeight,
+        };
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut traces = TraceSet::new(TimeAxis::default(), 4);
+        for _ in 0..num_traces {
+            let data = TraceData {
+                input: vec![rng.random()],
+                key: vec![KEY],
+                ..Default::default()
+            };
+            let leak = model.predict(&data, KEY as usize).unwrap() as f32;
+            let shared: f32 = rng.random_range(-2.0..2.0);
+            let samples = [
+                rng.random_range(-1.0..1.0),
+                leak + shared,
+                rng.random_range(-1.0..1.0),
+                0.5 * leak + shared + rng.random_range(-0.5..0.5),
+            ];
+            traces.push(&samples, data).unwrap();
+        }
+        traces
+    }
+
+    #[test]
+    fn templates_recover_key() {
+        let model = AesSboxOutput {
+            byte: 0,
+            leakage: Leakage::HammingWeight,
+        };
+        let templates =
+            build_templates(&leaking_traces(5000, 1), &[1, 3], &model, &mut |_| {}).unwrap();
+        assert_eq!(templates.num_classes(), 9);
+        assert_eq!(templates.counts.iter().sum::<u64>(), 5000);
+        // Class 4 leaks 4 at sample 1 and 2 at sample 3, plus noise of mean 0
+        let mean = templates.means[4].as_ref().unwrap();
+        assert!((mean[0] - 4.0).abs() < 0.2 && (mean[1] - 2.0).abs() < 0.2);
+
+        let result =
+            match_templates(&leaking_traces(50, 2), &templates, &model, &mut |_| {}).unwrap();
+        assert_eq!(result.num_traces, 50);
+        assert_eq!(result.known_guess, Some(KEY as usize));
+        assert_eq!(result.ranking()[0].0, KEY as usize);
+    }
+
+    #[test]
+    fn rejects_templates_of_other_models() {
+        let model = AesSboxOutput {
+            byte: 0,
+            leakage: Leakage::HammingWeight,
+        };
+        let templates =
+            build_templates(&leaking_traces(100, 1), &[1, 3], &model, &mut |_| {}).unwrap();
+        let other = AesSboxOutput {
+            byte: 0,
+            leakage: Leakage::Identity,
+        };
+        assert!(match_templates(&leaking_traces(10, 2), &templates, &other, &mut |_| {}).is_err());
+        assert!(build_templates(&leaking_traces(10, 1), &[4], &model, &mut |_| {}).is_err());
+    }
+}
@@ -0,0 +1,254 @@
+use crate::math::moments::OnlineMoments;
+use crate::trace_set::{read_chunks, TimeAxis, TraceData, TraceSet, TraceSource};
+use rayon::prelude::*;
+use std::collections::BTreeSet;
+use std::ops::Range;
+
+/// |t| above which a sample is considered leaking by TVLA
+pub const TVLA_THRESHOLD: f64 = 4.5;
+
+/// Number of traces read from the source at once
+const TTEST_CHUNK_SIZE: usize = 1024;
+
+/// Univariate Welch's t-tests between two groups of traces, at every sample and for every order
+/// up to `max_order`.
+pub struct WelchTTest {
+    pub max_order: usize,
+    pub groups: [OnlineMoments; 2],
+}
+
+impl WelchTTest {
+    pub fn new(num_samples: usize, max_order: usize) -> Self {
+        let max_order = max_order.max(1);
+        WelchTTest {
+            max_order,
+            groups: [
+                OnlineMoments::new(num_samples, 2 * max_order),
+                OnlineMoments::new(num_samples, 2 * max_order),
+            ],
+        }
+    }
+
+    /// Adds a batch of traces, `group` returning 0 or 1 for the traces to test and `None` for
+    /// the traces to skip.
+    pub fn add(&mut self, traces: &TraceSet, group: impl Fn(&TraceData) -> Option<usize> + Sync) {
+        let num_samples = traces.num_samples();
+        let max_moment = 2 * self.max_order;
+        let empty = || {
+            [
+                OnlineMoments::new(num_samples, max_moment),
+                OnlineMoments::new(num_samples, max_moment),
+            ]
+        };
+
+        let batch = (0..traces.len())
+            .into_par_iter()
+            .fold(empty, |mut groups, index| {
+                if let Some(group) = group(traces.data(index)) {
+                    groups[group].add(traces.trace(index));
+                }
+                groups
+            })
+            .reduce(empty, |mut a, b| {
+                a[0].merge(&b[0]);
+                a[1].merge(&b[1]);
+                a
+            });
+
+        self.groups[0].merge(&batch[0]);
+        self.groups[1].merge(&batch[1]);
+    }
+
+    /// Welch's t of `order` at every sample, zero where both groups have no variance
+    pub fn t_values(&self, order: usize) -> Vec<f32> {
+        let [a, b] = &self.groups;
+        let (n_a, n_b) = (a.count() as f64, b.count() as f64);
+
+        a.statistics(order)
+            .iter()
+            .zip(b.statistics(order))
+            .map(|((mean_a, var_a), (mean_b, var_b))| {
+                let t = (mean_a - mean_b) / (var_a / n_a + var_b / n_b).sqrt();
+                if t.is_finite() {
+                    t as f32
+                } else {
+                    0.0
+                }
+            })
+            .collect()
+    }
+}
+
+pub struct TTestResult {
+    /// Holds the t-trace of every order, first order first
+    pub t: TraceSet,
+    /// Number of traces in each group
+    pub counts: [u64; 2],
+}
+
+impl TTestResult {
+    /// Number of samples where |t| of `order` exceeds `threshold`
+    pub fn leaking_samples(&self, order: usize, threshold: f64) -> usize {
+        self.t
+            .trace(order - 1)
+            .iter()
+            .filter(|t| t.abs() as f64 > threshold)
+            .count()
+    }
+
+    pub fn max_abs_t(&self, order: usize) -> f32 {
+        self.t
+            .trace(order - 1)
+            .iter()
+            .fold(0.0, |max, t| max.max(t.abs()))
+    }
+}
+
+/// Every trace label of the source, sorted
+pub fn distinct_labels(traces: &dyn TraceSource) -> Result<Vec<String>, String> {
+    let mut labels = BTreeSet::new();
+
+    // An empty window only reads the trace data
+    for chunk in read_chunks(traces, TTEST_CHUNK_SIZE * 64, 0..0) {
+        let chunk = chunk.map_err(|e| e.to_string())?;
+        labels.extend(chunk.data.iter().map(|data| data.label.clone()));
+    }
+
+    Ok(labels.into_iter().collect())
+}
+
+/// Runs Welch's t-test on the `window` samples, between the traces labelled `first_label` and
+/// those labelled `second_label`. Traces with any other label are ignored.
+///
+/// Every order up to `max_order` is tested in the same pass, giving one t-trace per order.
+///
+/// `progress` is called with the fraction of traces processed so far.
+pub fn welch_t_test(
+    traces: &dyn TraceSource,
+    window: Range<usize>,
+    first_label: &str,
+    second_label: &str,
+    max_order: usize,
+    progress: &mut dyn FnMut(f32),
+) -> Result<TTestResult, String> {
+    if window.is_empty() || window.end > traces.num_samples() {
+        return Err(format!("Invalid sample window {:?}", window));
+    }
+
+    let mut test = WelchTTest::new(window.len(), max_order);
+    let mut processed = 0;
+
+    for chunk in read_chunks(traces, TTEST_CHUNK_SIZE, window.clone()) {
+        let chunk = chunk.map_err(|e| e.to_string())?;
+        test.add(&chunk, |data| {
+            if data.label == first_label {
+                Some(0)
+            } else if data.label == second_label {
+                Some(1)
+            } else {
+                None
+            }
+        });
+
+        processed += chunk.len();
+        progress(processed as f32 / traces.len() as f32);
+    }
+
+    let counts = [test.groups[0].count(), test.groups[1].count()];
+    if counts.contains(&0) {
+        return Err(format!(
+            "Both groups need traces, found {} labelled '{}' and {} labelled '{}'",
+            counts[0], first_label, counts[1], second_label
+        ));
+    }
+
+    let time = traces.time();
+    let mut t = TraceSet::new(
+        TimeAxis::new(time.x(window.start), time.interval),
+        window.len(),
+    );
+    t.parameters.insert(
+        "title".to_string(),
+        format!("Welch t-test '{}' vs '{}'", first_label, second_label),
+    );
+    for order in 1..=test.max_order {
+        t.push(
+            &test.t_values(order),
+            TraceData {
+                label: format!("Order {}", order),
+                ..Default::default()
+            },
+        )?;
+    }
+
+    Ok(TTestResult { t, counts })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn first_order_t_matches_welch_formula() {
+        let groups = [vec![1.0f32, 2.0, 4.0, 7.0], vec![3.0, 5.0, 6.0, 9.0, 10.0]];
+        let mut traces = TraceSet::new(TimeAxis::default(), 1);
+        for (group, values) in groups.iter().enumerate() {
+            for &value in values {
+                let data = TraceData {
+                    label: group.to_string(),
+                    ..Default::default()
+                };
+                traces.push(&[value], data).unwrap();
+            }
+        }
+
+        let result = welch_t_test(&traces, 0..1, "0", "1", 2, &mut |_| {}).unwrap();
+        assert_eq!(result.counts, [4, 5]);
+
+        // Sample mean and unbiased variance of each group
+        let [(mean_a, var_a, n_a), (mean_b, var_b, n_b)] = groups.map(|values| {
+            let n = values.len() as f64;
+            let mean = values.iter().map(|&v| v as f64).sum::<f64>() / n;
+            let var = values
+                .iter()
+                .map(|&v| (v as f64 - mean).powi(2))
+                .sum::<f64>()
+                / (n - 1.0);
+            (mean, var, n)
+        });
+        let t = (mean_a - mean_b) / (var_a / n_a + var_b / n_b).sqrt();
+        assert!((result.t.trace(0)[0] as f64 - t).abs() < 1e-5);
+        assert_eq!(result.t.len(), 2);
+    }
+
+    #[test]
+    fn second_order_detects_variance_difference() {
+        // Same mean, the second group spread twice as wide
+        let mut rng = StdRng::seed_from_u64(2);
+        let mut traces = TraceSet::new(TimeAxis::default(), 1);
+        for i in 0..2000 {
+            let group = i % 2;
+            let value = rng.random_range(-1.0..1.0) * (1 + group) as f32;
+            let data = TraceData {
+                label: group.to_string(),
+                ..Default::default()
+            };
+            traces.push(&[value], data).unwrap();
+        }
+
+        let result = welch_t_test(&traces, 0..1, "0", "1", 3, &mut |_| {}).unwrap();
+        assert_eq!(result.t.len(), 3);
+        assert!(result.max_abs_t(1) < 4.5);
+        assert!(result.max_abs_t(2) > 20.0);
+        assert_eq!(result.leaking_samples(2, 4.5), 1);
+    }
+
+    #[test]
+    fn needs_both_groups() {
+        let mut traces = TraceSet::new(TimeAxis::default(), 1);
+        traces.push(&[1.0], TraceData::default()).unwrap();
+        assert!(welch_t_test(&traces, 0..1, "", "other", 1, &mut |_| {}).is_err());
+    }
+}
@@ -1,6 +1,6 @@
 mod plot_selection;
+pub mod spectrogram_plotter;
 mod trace_plot;
+#[allow(clippy::module_inception)]
 pub mod trace_plotter;
 mod util;
-
-type Trace = Vec<(f64, f64)>;
@@ -1,4 +1,4 @@
-use crate::trace_plotter::Trace;
+use crate::trace_set::TimeAxis;
 use eframe::epaint::{Color32, Stroke};
 use egui_plot::{PlotBounds, PlotPoint, PlotPoints, PlotResponse, PlotUi, Polygon};
 use std::ops::Range;
@@ -91,13 +91,21 @@ impl PlotSelection {
         }
     }
 
-    pub fn get_selected_data_range_indices(&self, selected_trace: &Trace) -> Option<Range<usize>> {
+    pub fn get_selected_data_range_indices(
+        &self,
+        time: &TimeAxis,
+        num_samples: usize,
+    ) -> Option<Range<usize>> {
         if let (Some(start), Some(end)) = (self.start_pos, self.end_pos) {
             let start_x = start.x.min(end.x);
             let end_x = start.x.max(end.x);
 
-            let start_idx = selected_trace.iter().position(|&(x, _)| x >= start_x)?;
-            let end_idx = selected_trace.iter().position(|&(x, _)| x >= end_x)?;
+            let start_idx = time.index_of(start_x);
+            let end_idx = time.index_of(end_x);
+
+            if end_idx >= num_samples {
+                return None;
+            }
 
             Some(start_idx..end_idx + 1)
         } else {
@@ -105,6 +113,13 @@ impl PlotSelection {
         }
     }
 
+    /// Selects the x range from `start_x` to `end_x` over the full height, as if it was dragged
+    pub(crate) fn select_x(&mut self, start_x: f64, end_x: f64) {
+        let (min_y, max_y) = (self.plot_bounds.min()[1], self.plot_bounds.max()[1]);
+        self.start_pos = Some(PlotPoint::new(start_x, min_y));
+        self.end_pos = Some(PlotPoint::new(end_x, max_y));
+    }
+
     pub(crate) fn get_plot_bounds(&self) -> PlotBounds {
         self.plot_bounds
     }
@@ -0,0 +1,269 @@
+use crate::math::fft::WindowFunction;
+use crate::math::stft::{stft, Spectrogram};
+use crate::trace_plotter::plot_selection::PlotSelection;
+use crate::trace_plotter::trace_plotter::TracePlotter;
+use crate::trace_set::TraceSource;
+use egui::{
+    Color32, ColorImage, ComboBox, Context, DragValue, Key, TextureHandle, TextureOptions, Ui,
+    Vec2, Vec2b, Window,
+};
+use egui_plot::{Plot, PlotBounds, PlotImage, PlotPoint, PlotResponse};
+use std::ops::Range;
+use std::sync::Arc;
+
+/// Frames are merged, keeping the highest power, so the image stays below this width
+const MAX_IMAGE_COLUMNS: usize = 4096;
+
+/// Powers further than this below the peak get the coldest color
+const DYNAMIC_RANGE_DB: f32 = 100.0;
+
+/// Colors from the lowest to the highest power
+const HEAT_COLORS: [[u8; 3]; 5] = [
+    [0, 0, 4],
+    [87, 16, 110],
+    [188, 55, 84],
+    [249, 142, 9],
+    [252, 255, 164],
+];
+
+/// Heatmap of the power of one trace over time and frequency. The time axis is the axis of the
+/// trace, so a region selected here is a sample range of the trace plotter it came from.
+pub struct SpectrogramPlotter {
+    /// Title of the plotter the trace comes from
+    source_title: String,
+    source: Arc<dyn TraceSource>,
+    trace: usize,
+    window_len: usize,
+    hop: usize,
+    function: WindowFunction,
+    spectrogram: Option<Spectrogram>,
+    texture: Option<TextureHandle>,
+    plot_selection: PlotSelection,
+    error: Option<String>,
+}
+
+impl SpectrogramPlotter {
+    /// Opens the spectrogram of the first trace shown in `trace_plotter`
+    pub fn new(trace_plotter: &TracePlotter) -> Self {
+        let source = trace_plotter.source().clone();
+        let num_samples = source.num_samples();
+
+        let mut plotter = SpectrogramPlotter {
+            source_title: trace_plotter.title().to_string(),
+            trace: trace_plotter.selected_plot_range().start,
+            window_len: 256.min(num_samples),
+            hop: 64.min(num_samples).max(1),
+            function: WindowFunction::Hann,
+            spectrogram: None,
+            texture: None,
+            plot_selection: PlotSelection::new(PlotBounds::from_min_max([0.0, 0.0], [1.0, 1.0])),
+            error: None,
+            source,
+        };
+        plotter.compute();
+        plotter
+    }
+
+    /// Renders the window, returning the title of the source plotter and a sample range when the
+    /// selection should be pushed to it.
+    pub fn render(&mut self, ctx: &Context, open: &mut bool) -> Option<(String, Range<usize>)> {
+        let mut selection = None;
+
+        Window::new(format!("Spectrogram: {}", self.source_title))
+            .open(open)
+            .show(ctx, |ui| {
+                self.render_options(ui);
+
+                if let Some(error) = &self.error {
+                    ui.colored_label(Color32::RED, error);
+                }
+
+                self.render_plot(ui);
+
+                let range = self.plot_selection.get_selected_data_range_indices(
+                    &self.source.time(),
+                    self.source.num_samples(),
+                );
+                ui.horizontal(|ui| {
+                    if let Some(range) = range {
+                        ui.label(format!(
+                            "Selected samples {} to {}, Points: {}",
+                            range.start,
+                            range.end,
+                            range.len()
+                        ));
+                        if ui
+                            .button("Select in trace plotter")
+                            .on_hover_text("Select these samples in the plotter of the trace")
+                            .clicked()
+                        {
+                            selection = Some((self.source_title.clone(), range));
+                        }
+                    } else {
+                        ui.label("Drag over a region to select its samples");
+                    }
+                });
+            });
+
+        selection
+    }
+
+    fn render_options(&mut self, ui: &mut Ui) {
+        let num_samples = self.source.num_samples();
+        let num_traces = self.source.len();
+
+        ui.horizontal(|ui| {
+            ui.label("Trace:");
+            ui.add(DragValue::new(&mut self.trace).range(0..=num_traces.saturating_sub(1)));
+            ui.label("Window:");
+            ui.add(DragValue::new(&mut self.window_len).range(2..=num_samples.max(2)));
+            ui.label("Hop:");
+            ui.add(DragValue::new(&mut self.hop).range(1..=num_samples.max(1)));
+
+            ComboBox::from_id_source("spectrogram_window")
+                .selected_text(self.function.to_string())
+                .show_ui(ui, |ui| {
+                    for function in [
+                        WindowFunction::Rectangular,
+                        WindowFunction::Hann,
+                        WindowFunction::Blackman,
+                    ] {
+                        ui.selectable_value(&mut self.function, function, function.to_string());
+                    }
+                });
+
+            if ui.button("Update").clicked() {
+                self.compute();
+            }
+        });
+    }
+
+    fn render_plot(&mut self, ui: &mut Ui) {
+        let plot = Plot::new("spectrogram_plot")
+            .width(1000.0)
+            .view_aspect(2.0)
+            .allow_zoom(false)
+            .allow_drag(false)
+            .allow_scroll(false)
+            .auto_bounds(Vec2b::FALSE)
+            .allow_double_click_reset(false)
+            .allow_boxed_zoom(false);
+
+        if ui.input(|input| input.key_pressed(Key::Enter)) {
+            self.plot_selection.select_zoom();
+        }
+        if ui.input(|input| input.key_pressed(Key::Escape)) {
+            self.plot_selection.revert_zoom();
+        }
+
+        if self.texture.is_none() {
+            self.texture = self.spectrogram.as_ref().map(|spectrogram| {
+                ui.ctx()
+                    .load_texture("spectrogram", heatmap(spectrogram), TextureOptions::NEAREST)
+            });
+        }
+
+        let plot_response: PlotResponse<()> = plot.show(ui, |plot_ui| {
+            plot_ui.set_plot_bounds(self.plot_selection.get_plot_bounds());
+
+            if let (Some(spectrogram), Some(texture)) = (&self.spectrogram, &self.texture) {
+                // Every frame and bin covers one step around its center
+                let (time, frequency) = (spectrogram.time, spectrogram.frequency);
+                let width = spectrogram.num_frames as f64 * time.interval;
+                let height = spectrogram.num_bins as f64 * frequency.interval;
+                let center = PlotPoint::new(
+                    time.offset - time.interval / 2.0 + width / 2.0,
+                    frequency.offset - frequency.interval / 2.0 + height / 2.0,
+                );
+                plot_ui.image(PlotImage::new(
+                    texture,
+                    center,
+                    Vec2::new(width as f32, height as f32),
+                ));
+            }
+
+            self.plot_selection.draw_selection_box(plot_ui);
+        });
+        self.plot_selection.update_selection(plot_response);
+    }
+
+    fn compute(&mut self) {
+        self.texture = None;
+        self.error = None;
+
+        let trace = match self.source.read_traces(self.trace..self.trace + 1) {
+            Ok(trace) if !trace.is_empty() => trace,
+            Ok(_) => {
+                self.error = Some(format!("There is no trace {}", self.trace));
+                return;
+            }
+            Err(e) => {
+                self.error = Some(e.to_string());
+                return;
+            }
+        };
+
+        match stft(
+            trace.trace(0),
+            trace.time,
+            self.window_len,
+            self.hop,
+            self.function,
+        ) {
+            Ok(spectrogram) => {
+                let time = self.source.time();
+                let nyquist = spectrogram.frequency.x(spectrogram.num_bins - 1);
+                let bounds = PlotBounds::from_min_max(
+                    [time.x(0), 0.0],
+                    [time.x(self.source.num_samples().saturating_sub(1)), nyquist],
+                );
+                self.plot_selection = PlotSelection::new(bounds);
+                self.spectrogram = Some(spectrogram);
+            }
+            Err(e) => self.error = Some(e),
+        }
+    }
+}
+
+/// Image of the spectrogram, time from left to right and frequency from bottom to top
+fn heatmap(spectrogram: &Spectrogram) -> ColorImage {
+    let merged = spectrogram.num_frames.div_ceil(MAX_IMAGE_COLUMNS);
+    let columns = spectrogram.num_frames.div_ceil(merged);
+    let rows = spectrogram.num_bins;
+
+    let (_, max) = spectrogram.power_range();
+    let min = max - DYNAMIC_RANGE_DB;
+
+    let mut pixels = vec![Color32::BLACK; columns * rows];
+    for column in 0..columns {
+        let frames = column * merged..((column + 1) * merged).min(spectrogram.num_frames);
+        for bin in 0..rows {
+            let power = frames
+                .clone()
+                .map(|frame| spectrogram.frame(frame)[bin])
+                .fold(f32::NEG_INFINITY, f32::max);
+            pixels[(rows - 1 - bin) * columns + column] = heat_color((power - min) / (max - min));
+        }
+    }
+
+    ColorImage {
+        size: [columns, rows],
+        pixels,
+    }
+}
+
+/// Color of a power scaled to 0 for the lowest and 1 for the highest
+fn heat_color(value: f32) -> Color32 {
+    let position = value.clamp(0.0, 1.0) * (HEAT_COLORS.len() - 1) as f32;
+    let index = (position as usize).min(HEAT_COLORS.len() - 2);
+    let fraction = position - index as f32;
+
+    let [r, g, b] = [0, 1, 2].map(|channel| {
+        let (from, to) = (
+            HEAT_COLORS[index][channel] as f32,
+            HEAT_COLORS[index + 1][channel] as f32,
+        );
+        (from + (to - from) * fraction) as u8
+    });
+    Color32::from_rgb(r, g, b)
+}
@@ -1,36 +1,37 @@
-use crate::trace_plotter::Trace;
+use crate::trace_set::TraceSet;
 use egui_plot::{Line, PlotPoints, PlotUi};
 
 #[derive(Clone, Debug)]
 pub(crate) struct TracePlot {
-    pub trace: Trace,
+    /// Index of the plotted trace in the trace set
+    pub index: usize,
 }
 
 impl TracePlot {
-    pub(crate) fn draw_trace(&mut self, plot_ui: &mut PlotUi, max_visible_points_per_trace: usize) {
+    pub(crate) fn draw_trace(
+        &mut self,
+        plot_ui: &mut PlotUi,
+        trace_set: &TraceSet,
+        max_visible_points_per_trace: usize,
+    ) {
         let plot_bounds = plot_ui.plot_bounds();
-        let trace = &self.trace;
+        let time = trace_set.time;
+        let trace = trace_set.trace(self.index);
 
         let min_x = plot_bounds.min()[0];
         let max_x = plot_bounds.max()[0];
 
-        // Filter points that are within the current x-axis bounds
-        // Include the next point outside the bounds if available
-        let mut visible_points: Vec<[f64; 2]> = Vec::new();
-        let mut add_next_point = true;
-        let mut add_prev_point = true;
-
-        for &(x, y) in trace {
-            if x >= min_x && x <= max_x {
-                visible_points.push([x, y]);
-                add_next_point = true;
-            } else if x > max_x && add_next_point {
-                visible_points.push([x, y]);
-                add_next_point = false;
-            } else if x < min_x && add_prev_point {
-                visible_points.insert(0, [x, y]);
-                add_prev_point = false;
-            }
+        // Only the samples within the current x-axis bounds are visited,
+        // including the previous and next point outside the bounds if available
+        let start = time.index_of(min_x).saturating_sub(1).min(trace.len());
+        let end = time.index_of(max_x).saturating_add(1).min(trace.len());
+
+        let visible_points: Vec<[f64; 2]> = (start..end)
+            .map(|i| [time.x(i), trace[i] as f64])
+            .collect();
+
+        if visible_points.is_empty() {
+            return;
         }
 
         let total_points = visible_points.len();
@@ -56,7 +57,7 @@ impl TracePlot {
         plot_ui.line(line);
     }
 
-    pub(crate) fn new(trace: Trace) -> Self {
-        TracePlot { trace }
+    pub(crate) fn new(index: usize) -> Self {
+        TracePlot { index }
     }
 }
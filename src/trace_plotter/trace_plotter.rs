@@ -1,21 +1,31 @@
 use crate::trace_plotter::plot_selection::PlotSelection;
 use crate::trace_plotter::trace_plot::TracePlot;
 use crate::trace_plotter::util::calculate_bounds;
-use egui::{Area, ComboBox, Context, Id, Key, Ui, UiKind, Vec2b, Window};
+use crate::trace_set::{TraceSet, TraceSource};
+use egui::{Area, Color32, ComboBox, Context, Id, Key, Ui, UiKind, Vec2b, Window};
 use egui_plot::{
-    Legend, Plot, PlotResponse, PlotUi,
+    HLine, Legend, LineStyle, Plot, PlotResponse, PlotUi,
 };
+use log::error;
 use std::ops::Range;
-use egui::debug_text::print;
+use std::sync::Arc;
 
 const MAX_NUMB_OF_POINTS: usize = 100_000;
 
-#[derive(Clone, Debug)]
+/// Number of traces read from the source to calculate the default plot bounds
+const BOUNDS_TRACES: usize = 16;
+
+#[derive(Clone)]
 pub struct TracePlotter {
     title: String,
-    traces: Vec<TracePlot>,
+    source: Arc<dyn TraceSource>,
+    /// Traces read from the source, covering `loaded_range`
+    loaded: TraceSet,
+    loaded_range: Range<usize>,
     selected_plot_range: Range<usize>,
     plot_selection: PlotSelection,
+    /// Values marked with a horizontal line, such as t-test thresholds
+    threshold_lines: Vec<f64>,
     currently_selected: bool,
 }
 
@@ -30,7 +40,7 @@ impl TracePlotter {
         window.open(open).show(ctx, |ui| {
             // Handle key inputs to change the selected plot range
             if ctx.input(|i| i.key_pressed(Key::ArrowUp))
-                && self.selected_plot_range.end < self.traces.len()
+                && self.selected_plot_range.end < self.source.len()
                 && self.currently_selected
             {
                 self.selected_plot_range =
@@ -53,7 +63,7 @@ impl TracePlotter {
                 let start_response = ComboBox::from_label("")
                     .selected_text(format!("Plot {}", self.selected_plot_range.start + 1))
                     .show_ui(ui, |ui| {
-                        for (i, _) in self.traces.iter().enumerate() {
+                        for i in 0..self.source.len() {
                             if ui
                                 .selectable_value(
                                     &mut self.selected_plot_range.start,
@@ -75,7 +85,7 @@ impl TracePlotter {
                 let end_response = ComboBox::from_label(" ")
                     .selected_text(format!("Plot {}", self.selected_plot_range.end))
                     .show_ui(ui, |ui| {
-                        for (i, _) in self.traces.iter().enumerate() {
+                        for i in 0..self.source.len() {
                             if ui
                                 .selectable_value(
                                     &mut self.selected_plot_range.end,
@@ -106,7 +116,7 @@ impl TracePlotter {
 
             if let Some(range) = self
                 .plot_selection
-                .get_selected_data_range_indices(&self.traces.first().unwrap().trace)
+                .get_selected_data_range_indices(&self.source.time(), self.source.num_samples())
             {
                 ui.label(format!(
                     "Selected range start: {:.2}, end: {:.2}, Points: {}",
@@ -139,6 +149,13 @@ impl TracePlotter {
 
 
             self.plot_traces(plot_ui);
+            for &y in &self.threshold_lines {
+                plot_ui.hline(
+                    HLine::new(y)
+                        .color(Color32::RED)
+                        .style(LineStyle::dashed_loose()),
+                );
+            }
             self.plot_selection.draw_selection_box(plot_ui);
 
         });
@@ -146,11 +163,36 @@ impl TracePlotter {
     }
 
     fn plot_traces(&mut self, plot_ui: &mut PlotUi) {
+        self.load_selected_traces();
+
         let num_of_shown_traces = self.selected_plot_range.len();
         let max_visible_points_per_trace = MAX_NUMB_OF_POINTS / num_of_shown_traces.max(1);
 
         for i in self.selected_plot_range.clone() {
-            self.traces[i].draw_trace(plot_ui, max_visible_points_per_trace);
+            if self.loaded_range.contains(&i) {
+                TracePlot::new(i - self.loaded_range.start).draw_trace(
+                    plot_ui,
+                    &self.loaded,
+                    max_visible_points_per_trace,
+                );
+            }
+        }
+    }
+
+    /// Reads the selected traces from the source if they aren't loaded yet
+    fn load_selected_traces(&mut self) {
+        let selected =
+            self.selected_plot_range.start..self.selected_plot_range.end.min(self.source.len());
+        if selected.start >= self.loaded_range.start && selected.end <= self.loaded_range.end {
+            return;
+        }
+
+        match self.source.read_traces(selected.clone()) {
+            Ok(loaded) => {
+                self.loaded = loaded;
+                self.loaded_range = selected;
+            }
+            Err(e) => error!("Failed to read traces {:?}: {:?}", selected, e),
         }
     }
     fn process_zoom_input(&mut self, ui: &Ui) {
@@ -171,7 +213,7 @@ impl TracePlotter {
             if modifiers.command {
                 // Control key is held, expand or shrink only one side
                 if scroll_delta.y > 0.0 {
-                    if self.selected_plot_range.end < self.traces.len() {
+                    if self.selected_plot_range.end < self.source.len() {
                         self.selected_plot_range =
                             self.selected_plot_range.start..self.selected_plot_range.end + 1;
                     }
@@ -183,7 +225,7 @@ impl TracePlotter {
                 }
             } else {
                 // Expand or shrink both sides
-                if scroll_delta.y > 0.0 && self.selected_plot_range.end < self.traces.len() {
+                if scroll_delta.y > 0.0 && self.selected_plot_range.end < self.source.len() {
                     self.selected_plot_range =
                         self.selected_plot_range.start + 1..self.selected_plot_range.end + 1;
                 } else if scroll_delta.y < 0.0 && self.selected_plot_range.start > 0 {
@@ -194,16 +236,76 @@ impl TracePlotter {
         }
     }
 
-    pub(crate) fn new(trace_data: Vec<Vec<(f64, f64)>>, title: String) -> Self {
-        let bounds = calculate_bounds(&trace_data);
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn source(&self) -> &Arc<dyn TraceSource> {
+        &self.source
+    }
+
+    /// Shows the given traces instead of only the first one
+    pub(crate) fn with_plot_range(mut self, range: Range<usize>) -> Self {
+        self.selected_plot_range =
+            range.start..range.end.min(self.source.len()).max(range.start + 1);
+        self
+    }
+
+    /// Draws a horizontal line at every value, keeping them inside the default bounds
+    pub(crate) fn with_threshold_lines(mut self, lines: Vec<f64>) -> Self {
+        let mut bounds = calculate_bounds(&self.loaded);
+        for &y in &lines {
+            bounds.extend_with_y(y * 1.1);
+        }
+        self.plot_selection = PlotSelection::new(bounds);
+        self.threshold_lines = lines;
+        self
+    }
+
+    /// Traces that are currently shown
+    pub fn selected_plot_range(&self) -> Range<usize> {
+        self.selected_plot_range.clone()
+    }
+
+    /// Sample indices covered by the selection box, if there is one
+    pub fn selected_sample_range(&self) -> Option<Range<usize>> {
+        self.plot_selection
+            .get_selected_data_range_indices(&self.source.time(), self.source.num_samples())
+    }
+
+    /// Draws the selection box over the given samples, as if they were selected in the plot
+    pub(crate) fn select_samples(&mut self, samples: Range<usize>) {
+        if samples.is_empty() {
+            return;
+        }
+        let time = self.source.time();
+        self.plot_selection
+            .select_x(time.x(samples.start), time.x(samples.end - 1));
+    }
+
+    /// Whether the plotter window is currently the top layer
+    pub fn is_selected(&self) -> bool {
+        self.currently_selected
+    }
 
-        let traces: Vec<TracePlot> = trace_data.into_iter().map(TracePlot::new).collect();
+    pub(crate) fn new(source: Arc<dyn TraceSource>, title: String) -> Self {
+        // Only the first traces are read, the source may not fit in memory
+        let first_traces = source
+            .read_traces(0..source.len().min(BOUNDS_TRACES))
+            .unwrap_or_else(|e| {
+                error!("Failed to read traces: {:?}", e);
+                TraceSet::new(source.time(), source.num_samples())
+            });
+        let bounds = calculate_bounds(&first_traces);
 
         TracePlotter {
             title,
-            traces,
+            loaded_range: 0..first_traces.len(),
+            loaded: first_traces,
+            source,
             selected_plot_range: 0..1,
             plot_selection: PlotSelection::new(bounds),
+            threshold_lines: vec![],
             currently_selected: false,
         }
     }
@@ -1,25 +1,19 @@
+use crate::trace_set::TraceSet;
 use egui_plot::PlotBounds;
 
-pub(crate) fn calculate_bounds(trace_data: &Vec<Vec<(f64, f64)>>) -> PlotBounds {
-    let mut min_x = f64::INFINITY;
-    let mut max_x = f64::NEG_INFINITY;
+pub(crate) fn calculate_bounds(trace_set: &TraceSet) -> PlotBounds {
+    let min_x = trace_set.time.x(0);
+    let max_x = trace_set.time.x(trace_set.num_samples().saturating_sub(1));
     let mut min_y = f64::INFINITY;
     let mut max_y = f64::NEG_INFINITY;
 
-    for trace in trace_data {
-        for &(x, y) in trace {
-            if x < min_x {
-                min_x = x;
-            }
-            if x > max_x {
-                max_x = x;
-            }
-            if y < min_y {
-                min_y = y;
-            }
-            if y > max_y {
-                max_y = y;
-            }
+    for &y in trace_set.samples() {
+        let y = y as f64;
+        if y < min_y {
+            min_y = y;
+        }
+        if y > max_y {
+            max_y = y;
         }
     }
 
@@ -0,0 +1,417 @@
+use bincode::{Decode, Encode};
+use std::collections::BTreeMap;
+use std::io;
+use std::ops::Range;
+
+/// Data recorded alongside a single trace during acquisition.
+#[derive(Clone, Debug, Default, PartialEq, Encode, Decode)]
+pub struct TraceData {
+    /// Bytes sent to the target, usually the plaintext.
+    pub input: Vec<u8>,
+    /// Bytes returned by the target, usually the ciphertext.
+    pub output: Vec<u8>,
+    /// Key used for this trace, if known.
+    pub key: Vec<u8>,
+    /// Free-form label, e.g. the group of a fixed-vs-random acquisition.
+    pub label: String,
+}
+
+/// Largest distance of a time value from the evenly spaced axis, in intervals, accepted by
+/// `TimeAxis::from_times`
+const TIME_TOLERANCE: f64 = 1e-3;
+
+/// Evenly spaced time axis shared by every trace in a set.
+#[derive(Clone, Copy, Debug, PartialEq, Encode, Decode)]
+pub struct TimeAxis {
+    /// Time of the first sample.
+    pub offset: f64,
+    /// Time between two consecutive samples.
+    pub interval: f64,
+}
+
+impl Default for TimeAxis {
+    fn default() -> Self {
+        TimeAxis {
+            offset: 0.0,
+            interval: 1.0,
+        }
+    }
+}
+
+impl TimeAxis {
+    pub fn new(offset: f64, interval: f64) -> Self {
+        TimeAxis { offset, interval }
+    }
+
+    /// Axis through a column of increasing time values, failing when they aren't evenly spaced
+    pub fn from_times(times: &[f64]) -> Result<Self, String> {
+        let axis = match times {
+            [] => return Ok(TimeAxis::default()),
+            [offset] => return Ok(TimeAxis::new(*offset, 1.0)),
+            [first, .., last] => TimeAxis::new(*first, (last - first) / (times.len() - 1) as f64),
+        };
+        if axis.interval <= 0.0 || !axis.interval.is_finite() {
+            return Err("The time values don't increase".to_string());
+        }
+
+        let tolerance = TIME_TOLERANCE * axis.interval;
+        match times
+            .iter()
+            .enumerate()
+            .find(|(index, &time)| (time - axis.x(*index)).abs() > tolerance)
+        {
+            Some((index, time)) => Err(format!(
+                "The time values aren't evenly spaced, {} at sample {} should be {}",
+                time,
+                index,
+                axis.x(index)
+            )),
+            None => Ok(axis),
+        }
+    }
+
+    /// Time of the sample at `index`.
+    pub fn x(&self, index: usize) -> f64 {
+        self.offset + index as f64 * self.interval
+    }
+
+    /// Index of the first sample at or after `x`, which may be past the end of a trace. Every
+    /// sample of an axis without a positive interval is at `offset`.
+    pub fn index_of(&self, x: f64) -> usize {
+        if self.interval <= 0.0 || !self.interval.is_finite() {
+            return if x <= self.offset { 0 } else { usize::MAX };
+        }
+        ((x - self.offset) / self.interval).ceil().max(0.0) as usize
+    }
+}
+
+/// How samples are stored on disk. In memory samples are always `f32`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Encode, Decode)]
+pub enum SampleCoding {
+    Byte,
+    Short,
+    Int,
+    #[default]
+    Float,
+}
+
+impl SampleCoding {
+    pub fn size(&self) -> usize {
+        match self {
+            SampleCoding::Byte => 1,
+            SampleCoding::Short => 2,
+            SampleCoding::Int | SampleCoding::Float => 4,
+        }
+    }
+
+    /// Converts samples to little-endian raw values, dividing by `scale` for integer codings.
+    pub fn encode(&self, samples: &[f32], scale: f32) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(samples.len() * self.size());
+
+        match self {
+            SampleCoding::Byte => {
+                for &sample in samples {
+                    bytes.push(((sample / scale).round() as i8) as u8);
+                }
+            }
+            SampleCoding::Short => {
+                for &sample in samples {
+                    bytes.extend_from_slice(&((sample / scale).round() as i16).to_le_bytes());
+                }
+            }
+            SampleCoding::Int => {
+                for &sample in samples {
+                    bytes.extend_from_slice(&((sample / scale).round() as i32).to_le_bytes());
+                }
+            }
+            SampleCoding::Float => {
+                for &sample in samples {
+                    bytes.extend_from_slice(&sample.to_le_bytes());
+                }
+            }
+        }
+
+        bytes
+    }
+
+    /// Converts little-endian raw values back to samples, multiplying by `scale` for integer codings.
+    pub fn decode(&self, bytes: &[u8], scale: f32) -> Vec<f32> {
+        match self {
+            SampleCoding::Byte => bytes.iter().map(|&b| b as i8 as f32 * scale).collect(),
+            SampleCoding::Short => bytes
+                .chunks_exact(2)
+                .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 * scale)
+                .collect(),
+            SampleCoding::Int => bytes
+                .chunks_exact(4)
+                .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 * scale)
+                .collect(),
+            SampleCoding::Float => bytes
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect(),
+        }
+    }
+}
+
+/// A set of traces sharing one time axis, with the data that was processed in each trace.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TraceSet {
+    /// Time axis shared by all traces.
+    pub time: TimeAxis,
+    /// Coding used when the samples are written to disk.
+    pub coding: SampleCoding,
+    /// Value of one integer step when `coding` is an integer coding.
+    pub scale: f32,
+    /// Per-trace data, one entry per trace.
+    pub data: Vec<TraceData>,
+    /// Acquisition parameters such as sample rate, probe position or scope settings.
+    pub parameters: BTreeMap<String, String>,
+    num_samples: usize,
+    /// Sample matrix stored row-major, one row of `num_samples` per trace.
+    samples: Vec<f32>,
+}
+
+impl TraceSet {
+    pub fn new(time: TimeAxis, num_samples: usize) -> Self {
+        TraceSet {
+            time,
+            scale: 1.0,
+            num_samples,
+            ..Default::default()
+        }
+    }
+
+    /// Builds a trace set from an existing sample matrix with `num_samples` columns.
+    pub fn from_samples(
+        time: TimeAxis,
+        num_samples: usize,
+        samples: Vec<f32>,
+        data: Vec<TraceData>,
+    ) -> Result<Self, String> {
+        if samples.len() != num_samples * data.len() {
+            return Err(format!(
+                "Expected {} samples for {} traces but got {}",
+                num_samples * data.len(),
+                data.len(),
+                samples.len()
+            ));
+        }
+
+        Ok(TraceSet {
+            data,
+            samples,
+            ..TraceSet::new(time, num_samples)
+        })
+    }
+
+    /// Appends a trace, returning an error if its length doesn't match the other traces.
+    pub fn push(&mut self, samples: &[f32], data: TraceData) -> Result<(), String> {
+        if samples.len() != self.num_samples {
+            return Err(format!(
+                "Trace has {} samples but the trace set has {}",
+                samples.len(),
+                self.num_samples
+            ));
+        }
+
+        self.samples.extend_from_slice(samples);
+        self.data.push(data);
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn num_samples(&self) -> usize {
+        self.num_samples
+    }
+
+    pub fn trace(&self, index: usize) -> &[f32] {
+        &self.samples[index * self.num_samples..(index + 1) * self.num_samples]
+    }
+
+    pub fn traces(&self) -> impl Iterator<Item = &[f32]> {
+        (0..self.len()).map(move |i| self.trace(i))
+    }
+
+    pub fn data(&self, index: usize) -> &TraceData {
+        &self.data[index]
+    }
+
+    /// The whole sample matrix, row-major.
+    pub fn samples(&self) -> &[f32] {
+        &self.samples
+    }
+
+    /// Returns a new trace set containing the given traces, cropped to the `window` samples.
+    pub fn select(&self, traces: Range<usize>, window: Range<usize>) -> TraceSet {
+        let mut samples = Vec::with_capacity(traces.len() * window.len());
+        for index in traces.clone() {
+            samples.extend_from_slice(&self.trace(index)[window.clone()]);
+        }
+
+        TraceSet {
+            time: TimeAxis::new(self.time.x(window.start), self.time.interval),
+            coding: self.coding,
+            scale: self.scale,
+            data: self.data[traces].to_vec(),
+            parameters: self.parameters.clone(),
+            num_samples: window.len(),
+            samples,
+        }
+    }
+
+    /// Returns an empty trace set with the same time axis, coding and parameters.
+    pub fn empty_like(&self) -> TraceSet {
+        self.select(0..0, 0..self.num_samples)
+    }
+
+    /// Appends every trace of `other`, which must have the same number of samples.
+    pub fn append(&mut self, other: &TraceSet) -> Result<(), String> {
+        if other.num_samples != self.num_samples {
+            return Err(format!(
+                "Trace set has {} samples but {} were expected",
+                other.num_samples, self.num_samples
+            ));
+        }
+
+        self.samples.extend_from_slice(&other.samples);
+        self.data.extend_from_slice(&other.data);
+        Ok(())
+    }
+}
+
+/// Random access to traces that may not all be held in memory, such as a memory-mapped file.
+pub trait TraceSource: Send + Sync {
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn num_samples(&self) -> usize;
+
+    fn time(&self) -> TimeAxis;
+
+    /// Reads the given traces, keeping only the samples in `window`.
+    fn read_window(&self, traces: Range<usize>, window: Range<usize>) -> io::Result<TraceSet>;
+
+    fn read_traces(&self, traces: Range<usize>) -> io::Result<TraceSet> {
+        self.read_window(traces, 0..self.num_samples())
+    }
+}
+
+impl TraceSource for TraceSet {
+    fn len(&self) -> usize {
+        TraceSet::len(self)
+    }
+
+    fn num_samples(&self) -> usize {
+        self.num_samples
+    }
+
+    fn time(&self) -> TimeAxis {
+        self.time
+    }
+
+    fn read_window(&self, traces: Range<usize>, window: Range<usize>) -> io::Result<TraceSet> {
+        check_read_ranges(self, &traces, &window)?;
+        Ok(self.select(traces, window))
+    }
+}
+
+/// Fails with `InvalidInput` unless `traces` and `window` are increasing ranges within `source`,
+/// for implementations of [`TraceSource::read_window`].
+pub fn check_read_ranges(
+    source: &dyn TraceSource,
+    traces: &Range<usize>,
+    window: &Range<usize>,
+) -> io::Result<()> {
+    if traces.start > traces.end || window.start > window.end {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Reversed trace range {:?} or sample window {:?}",
+                traces, window
+            ),
+        ));
+    }
+    if traces.end > source.len() || window.end > source.num_samples() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Requested traces are out of range",
+        ));
+    }
+    Ok(())
+}
+
+/// Reads `source` in chunks of `chunk_size` traces, cropped to the `window` samples.
+pub fn read_chunks(
+    source: &dyn TraceSource,
+    chunk_size: usize,
+    window: Range<usize>,
+) -> impl Iterator<Item = io::Result<TraceSet>> + '_ {
+    let chunk_size = chunk_size.max(1);
+
+    (0..source.len())
+        .step_by(chunk_size)
+        .map(move |start| {
+            source.read_window(start..(start + chunk_size).min(source.len()), window.clone())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_of_rounds_up() {
+        let time = TimeAxis::new(1.0, 0.5);
+        assert_eq!(time.index_of(0.0), 0);
+        assert_eq!(time.index_of(1.0), 0);
+        assert_eq!(time.index_of(1.2), 1);
+        assert_eq!(time.index_of(2.0), 2);
+    }
+
+    #[test]
+    fn index_of_without_interval() {
+        let time = TimeAxis::new(1.0, 0.0);
+        assert_eq!(time.index_of(1.0), 0);
+        assert_eq!(time.index_of(2.0), usize::MAX);
+        assert_eq!(TimeAxis::new(0.0, f64::NAN).index_of(-1.0), 0);
+    }
+
+    #[test]
+    fn time_axis_from_times() {
+        assert_eq!(
+            TimeAxis::from_times(&[2.0, 2.5, 3.0, 3.5]),
+            Ok(TimeAxis::new(2.0, 0.5))
+        );
+        assert!(TimeAxis::from_times(&[0.0, 1.0, 3.0]).is_err());
+        assert!(TimeAxis::from_times(&[1.0, 1.0, 1.0]).is_err());
+        assert!(TimeAxis::from_times(&[3.0, 2.0, 1.0]).is_err());
+    }
+
+    #[test]
+    #[allow(clippy::reversed_empty_ranges)]
+    fn read_window_rejects_bad_ranges() {
+        let mut traces = TraceSet::new(TimeAxis::default(), 3);
+        traces.push(&[1.0, 2.0, 3.0], TraceData::default()).unwrap();
+        traces.push(&[4.0, 5.0, 6.0], TraceData::default()).unwrap();
+
+        assert_eq!(
+            traces.read_window(1..2, 1..3).unwrap().samples(),
+            [5.0, 6.0]
+        );
+        for (rows, window) in [(0..1, 2..1), (2..1, 0..3), (0..3, 0..3), (0..2, 0..4)] {
+            let error = traces.read_window(rows, window).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+}
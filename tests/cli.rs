@@ -0,0 +1,64 @@
+//! Runs the command line tools of the binary on generated trace files
+#![cfg(feature = "cli")]
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use softcore_sc_analysis::loaders::write_trace_file;
+use softcore_sc_analysis::trace_set::{TimeAxis, TraceData, TraceSet};
+use std::path::Path;
+use std::process::Command;
+
+/// Noisy traces labelled "fixed" or "random", the fixed ones offset by `leak` at sample 20
+fn write_labelled_traces(path: &Path, leak: f32) {
+    let mut rng = StdRng::seed_from_u64(1);
+    let mut traces = TraceSet::new(TimeAxis::default(), 50);
+    for i in 0..400 {
+        let fixed = i % 2 == 0;
+        let mut samples: Vec<f32> = (0..50).map(|_| rng.random_range(-1.0..1.0)).collect();
+        if fixed {
+            samples[20] += leak;
+        }
+        let data = TraceData {
+            label: if fixed { "fixed" } else { "random" }.to_string(),
+            ..Default::default()
+        };
+        traces.push(&samples, data).unwrap();
+    }
+    write_trace_file(&traces, path.to_str().unwrap(), false).unwrap();
+}
+
+/// Exit code of a t-test of `input`, with `args` after the files
+fn ttest(input: &Path, output: &Path, args: &[&str]) -> Option<i32> {
+    Command::new(env!("CARGO_BIN_EXE_softcore_sc_analysis"))
+        .arg("ttest")
+        .args([input, output])
+        .args(args)
+        .output()
+        .unwrap()
+        .status
+        .code()
+}
+
+#[test]
+fn ttest_fails_on_leakage_with_code_3() {
+    let dir = tempfile::tempdir().unwrap();
+    let (leaking, quiet) = (dir.path().join("leaking.sct"), dir.path().join("quiet.sct"));
+    let output = dir.path().join("t.sct");
+    write_labelled_traces(&leaking, 2.0);
+    write_labelled_traces(&quiet, 0.0);
+
+    assert_eq!(ttest(&leaking, &output, &["--fail-on-leakage"]), Some(3));
+    assert!(output.exists());
+    assert_eq!(ttest(&leaking, &output, &[]), Some(0));
+    assert_eq!(ttest(&quiet, &output, &["--fail-on-leakage"]), Some(0));
+
+    // Leakage outside the window isn't reported
+    let window = ["--fail-on-leakage", "--window", "0..20"];
+    assert_eq!(ttest(&leaking, &output, &window), Some(0));
+
+    // Errors keep their own codes, 2 for bad arguments and 1 for failed commands
+    let reversed = ["--fail-on-leakage", "--window", "20..0"];
+    assert_eq!(ttest(&leaking, &output, &reversed), Some(2));
+    let missing = dir.path().join("missing.sct");
+    assert_eq!(ttest(&missing, &output, &["--fail-on-leakage"]), Some(1));
+}